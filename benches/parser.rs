@@ -0,0 +1,113 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rogcat::parser::{Parser, PARSERS};
+
+/// Lines of the supported formats and a line that no parser accepts
+const LINES: &[(&str, &str)] = &[
+    (
+        "threadtime",
+        "03-01 10:00:00.004   1004   2004 E InputDispatcher: display some longer word here 0x7fff1234 value",
+    ),
+    (
+        "threadtime_year",
+        "2024-03-01 10:00:00.004123   1004   2004 E InputDispatcher: display some longer word here 0x7fff1234",
+    ),
+    ("mindroid", "I/ActivityManager(  585): Start proc com.android.settings for broadcast"),
+    (
+        "json",
+        r#"{"timestamp":"03-01 10:00:00.004000000","message":"display some longer word here","level":"Error","tags":["InputDispatcher"],"process":"1004","thread":"2004","raw":"-"}"#,
+    ),
+    (
+        "syslog",
+        "<34>1 2024-03-01T10:00:00.004Z host app 1004 ID47 - display some longer word here 0x7fff1234",
+    ),
+    ("unparsable", "--------- beginning of main with some more text that is not a record"),
+];
+
+/// Parse a single line of each format with the default parser order. The parser is
+/// adaptive and has settled on the format after the first iteration.
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(1));
+    for (name, line) in LINES {
+        let mut parser = Parser::default();
+        group.bench_function(*name, |b| {
+            b.iter_batched(
+                || (*line).to_owned(),
+                |line| black_box(parser.parse(line)),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Reject a line that is not in the format of the parser. Mixed inputs and lines
+/// that no parser accepts pass through all parsers.
+fn reject(c: &mut Criterion) {
+    let line = "--------- beginning of main with some more text that is not a record";
+    let mut group = c.benchmark_group("reject");
+    group.throughput(Throughput::Elements(1));
+    for name in PARSERS {
+        let mut parser = Parser::new(&[name], false);
+        group.bench_function(*name, |b| b.iter(|| black_box(parser.try_parse(line))));
+    }
+    group.finish();
+}
+
+/// Parse a replayed file of mixed threadtime records and garbled lines
+fn replay(c: &mut Criterion) {
+    let lines = (0..10_000)
+        .map(|n| match n % 100 {
+            0 => "--------- beginning of crash".to_owned(),
+            _ => format!(
+                "03-01 10:{:02}:{:02}.{:03}  {:5}  {:5} {} Tag{}: message number {} of the replay",
+                n / 60_000 % 60,
+                n / 1000 % 60,
+                n % 1000,
+                1000 + n % 7,
+                2000 + n % 13,
+                ["V", "D", "I", "W", "E"][n % 5],
+                n % 17,
+                n
+            ),
+        })
+        .collect::<Vec<_>>();
+    let mut group = c.benchmark_group("replay");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("threadtime", |b| {
+        b.iter_batched(
+            || lines.clone(),
+            |lines| {
+                let mut parser = Parser::default();
+                for line in lines {
+                    black_box(parser.parse(line));
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, reject, replay);
+criterion_main!(benches);
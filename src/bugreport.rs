@@ -0,0 +1,192 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Split a dumpstate output into one file per section
+
+use crate::error::RogcatError;
+use failure::Error;
+use rogcat::parser::bugreport_section;
+use serde::Serialize;
+use std::{
+    fs::{DirBuilder, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use zip::ZipArchive;
+
+/// Name of the machine readable index written to the extract directory
+pub const INDEX: &str = "index.json";
+
+/// Section of a bugreport e.g `SYSTEM LOG`
+#[derive(Debug, Serialize)]
+pub struct Section {
+    pub name: String,
+    /// Command that produced the section e.g `logcat -v threadtime -d *:v`
+    pub command: Option<String>,
+    /// File name relative to the extract directory
+    pub file: String,
+    /// Line of the section header in the report starting at 1
+    pub line: usize,
+    pub lines: usize,
+}
+
+/// Writes the sections of a dumpstate output fed line by line to `dir`
+pub struct Extractor {
+    dir: PathBuf,
+    sections: Vec<Section>,
+    writer: Option<BufWriter<File>>,
+    line: usize,
+}
+
+impl Extractor {
+    pub fn new(dir: &Path) -> Result<Extractor, Error> {
+        DirBuilder::new()
+            .recursive(true)
+            .create(dir)
+            .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", dir.display(), e)))?;
+        Ok(Extractor {
+            dir: dir.to_owned(),
+            sections: Vec::new(),
+            writer: None,
+            line: 0,
+        })
+    }
+
+    pub fn line(&mut self, line: &str) -> Result<(), Error> {
+        self.line += 1;
+        // The duration line at the end of a section is kept with it
+        match header(line).filter(|title| !title.contains("was the duration of")) {
+            Some(title) => {
+                let (name, command) = match bugreport_section(title) {
+                    Ok((_, (name, command))) => (name.trim().to_owned(), Some(command)),
+                    Err(_) => (title.to_owned(), None),
+                };
+                self.open(name, command)
+            }
+            None => {
+                if self.writer.is_none() {
+                    // Build and device information preceding the first section
+                    self.open("dumpstate".to_owned(), None)?;
+                }
+                let writer = self.writer.as_mut().expect("Missing section writer");
+                writeln!(writer, "{}", line)
+                    .map_err(|e| RogcatError::Io(format!("Failed to write: {}", e)))?;
+                if let Some(section) = self.sections.last_mut() {
+                    section.lines += 1;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the index and return the sections
+    pub fn finish(mut self) -> Result<Vec<Section>, Error> {
+        self.close()?;
+        let index = self.dir.join(INDEX);
+        let file = File::create(&index)
+            .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", index.display(), e)))?;
+        serde_json::to_writer_pretty(file, &self.sections)?;
+        Ok(self.sections)
+    }
+
+    fn open(&mut self, name: String, command: Option<String>) -> Result<(), Error> {
+        self.close()?;
+        let file = format!("{:03}-{}.txt", self.sections.len(), slug(&name));
+        let path = self.dir.join(&file);
+        let writer = File::create(&path)
+            .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", path.display(), e)))?;
+        self.writer = Some(BufWriter::new(writer));
+        self.sections.push(Section {
+            name,
+            command,
+            file,
+            line: self.line,
+            lines: 0,
+        });
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(mut writer) = self.writer.take() {
+            writer
+                .flush()
+                .map_err(|e| RogcatError::Io(format!("Failed to write: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Split the report `file` into `dir`. The dumpstate output of zipped reports is the
+/// largest text file of the archive.
+pub fn extract(file: &Path, dir: &Path) -> Result<Vec<Section>, Error> {
+    let open = || {
+        File::open(file)
+            .map_err(|e| RogcatError::Io(format!("Failed to open {}: {}", file.display(), e)))
+    };
+    let mut extractor = Extractor::new(dir)?;
+    if file.extension().is_some_and(|e| e == "zip") {
+        let mut archive = ZipArchive::new(open()?)?;
+        let index = (0..archive.len())
+            .filter_map(|i| {
+                archive
+                    .by_index(i)
+                    .ok()
+                    .filter(|e| e.name().ends_with(".txt"))
+                    .map(|e| (e.size(), i))
+            })
+            .max()
+            .map(|(_, i)| i)
+            .ok_or_else(|| RogcatError::Config(format!("No report found in {}", file.display())))?;
+        feed(BufReader::new(archive.by_index(index)?), &mut extractor)?;
+    } else {
+        feed(BufReader::new(open()?), &mut extractor)?;
+    }
+    extractor.finish()
+}
+
+fn feed<R: BufRead>(reader: R, extractor: &mut Extractor) -> Result<(), Error> {
+    for line in reader.split(b'\n') {
+        let line = line.map_err(|e| RogcatError::Io(format!("Failed to read: {}", e)))?;
+        extractor.line(String::from_utf8_lossy(&line).trim_end_matches('\r'))?;
+    }
+    Ok(())
+}
+
+/// Title of a section header like `------ SYSTEM LOG (logcat -v threadtime -d *:v) ------`
+fn header(line: &str) -> Option<&str> {
+    line.strip_prefix("------ ")
+        .and_then(|l| l.strip_suffix(" ------"))
+        .filter(|title| !title.is_empty())
+}
+
+/// Lowercase alphanumeric file name of a section name
+fn slug(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "section".to_owned()
+    } else {
+        slug.chars().take(64).collect()
+    }
+}
@@ -19,9 +19,9 @@
 // SOFTWARE.
 
 use crate::utils;
-use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, SubCommand};
+use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, ArgGroup, SubCommand};
 use lazy_static::lazy_static;
-use rogcat::record::Level;
+use rogcat::parser::PARSERS;
 
 lazy_static! {
     static ref ABOUT: String = {
@@ -39,11 +39,58 @@ pub fn cli() -> App<'static, 'static> {
           .version(crate_version!())
           .author(crate_authors!())
           .about(ABOUT.as_str())
+          // Configuration
+          .arg(Arg::with_name("config")
+               .long("config")
+               .takes_value(true)
+               .value_name("FILE")
+               .global(true)
+               .help("Manually specify config file (overrules ROGCAT_CONFIG)"))
+          .arg(Arg::with_name("verbose")
+               .long("verbose")
+               .global(true)
+               .help("Print the used configuration files"))
+
+          // Parser
+          .arg(Arg::with_name("parser-order")
+               .long("parser-order")
+               .takes_value(true)
+               .possible_values(&["fixed", "adaptive"])
+               .default_value("adaptive")
+               .global(true)
+               .help("Try format parsers in a fixed order or move successful parsers to the front"))
+          .arg(Arg::with_name("input-format")
+               .long("input-format")
+               .takes_value(true)
+               .possible_values(PARSERS)
+               .conflicts_with("disable-parser")
+               .global(true)
+               .help("Parse the input with one format parser only. The format of stdin is detected from the first lines otherwise"))
+          .arg(Arg::with_name("boot-time")
+               .long("boot-time")
+               .takes_value(true)
+               .value_name("SECONDS")
+               .validator(|t| t.parse::<f64>().map(|_| ()).map_err(|e| e.to_string()))
+               .help("Boot time in seconds since the epoch e.g the btime of /proc/stat. Converts kernel uptime stamps like [114416.534450] \
+                      to the wall clock time. Queried from the device when capturing logcat"))
+          .arg(Arg::with_name("disable-parser")
+               .long("disable-parser")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .possible_values(PARSERS)
+               .global(true)
+               .help("Disable a format parser"))
+
           // Restart
           .arg(Arg::with_name("restart")
                .long("restart")
-               .conflicts_with_all(&["dump", "input", "tail"])
+               .conflicts_with_all(&["dump", "inputs", "tail"])
                .help("Restart command on exit"))
+          .arg(Arg::with_name("resume")
+               .long("resume")
+               .conflicts_with_all(&["dump", "inputs", "COMMAND", "fuchsia", "last", "tail"])
+               .help("Continue logcat at the last record seen by the previous run on the same device"))
 
           // Logcat specific
           .arg(Arg::with_name("buffer")
@@ -51,25 +98,58 @@ pub fn cli() -> App<'static, 'static> {
                .long("buffer")
                .multiple(true)
                .takes_value(true)
-               .conflicts_with_all(&["input", "COMMAND"])
+               .conflicts_with_all(&["inputs", "COMMAND"])
                .help("Select specific logd buffers. Defaults to main, events, kernel and crash"))
           .arg(Arg::with_name("dev")
                .short("-s")
                .long("serial")
                .takes_value(true)
                .multiple(false)
-               .help("Forwards the device selector to adb"))
+               .help("Select the device by serial or by a part of its model, product or device name e.g pixel"))
+          .arg(Arg::with_name("adb-path")
+               .long("adb-path")
+               .takes_value(true)
+               .value_name("PATH")
+               .global(true)
+               .help("adb binary to use instead of the one on PATH"))
+          .arg(Arg::with_name("adb-arg")
+               .long("adb-arg")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .allow_hyphen_values(true)
+               .value_name("ARG")
+               .global(true)
+               .help("Argument passed to every adb invocation e.g --adb-arg -H --adb-arg remote-host"))
+          .arg(Arg::with_name("no-adb")
+               .long("no-adb")
+               .global(true)
+               .help("Run logcat, log and getprop directly instead of through adb e.g in Termux or a root shell on the device. Detected on Android without adb"))
+          .arg(Arg::with_name("resolve-names")
+               .long("resolve-names")
+               .conflicts_with_all(&["inputs", "COMMAND"])
+               .help("Resolve process ids to process names with ps on the device"))
+          .arg(Arg::with_name("symbols-dir")
+               .long("symbols-dir")
+               .takes_value(true)
+               .value_name("DIR")
+               .help("Symbolize native backtraces with the unstripped libraries in DIR"))
+          .arg(Arg::with_name("symbolizer")
+               .long("symbolizer")
+               .takes_value(true)
+               .requires("symbols-dir")
+               .help("Symbolizer used for --symbols-dir e.g addr2line. Defaults to llvm-symbolizer or addr2line"))
           .arg(Arg::with_name("last")
                .short("L")
                .long("last")
-               .conflicts_with_all(&["input", "fuchsia", "COMMAND"])
+               .conflicts_with_all(&["inputs", "fuchsia", "COMMAND"])
                .help("Dump the logs prior to the last reboot"))
 
           // Dump, head, tail
           .arg(Arg::with_name("dump")
                .short("d")
                .long("dump")
-               .conflicts_with_all(&["input", "COMMAND", "restart"])
+               .conflicts_with_all(&["inputs", "COMMAND", "restart"])
                .help("Dump the log and then exit (don't block)"))
           .arg(Arg::with_name("head")
                .short("H")
@@ -77,48 +157,145 @@ pub fn cli() -> App<'static, 'static> {
                .takes_value(true)
                .conflicts_with_all(&["tail", "restart"])
                .help( "Read n records and exit"))
+          .arg(Arg::with_name("skip")
+               .long("skip")
+               .takes_value(true)
+               .value_name("N")
+               .conflicts_with("range")
+               .help("Skip the first N records"))
+          .arg(Arg::with_name("range")
+               .long("range")
+               .takes_value(true)
+               .value_name("START..END")
+               .help("Write only the records with an index from START up to but excluding END e.g 1000..2000, 1000.. or ..2000. The first record has index 0"))
+          .arg(Arg::with_name("for")
+               .long("for")
+               .takes_value(true)
+               .value_name("DURATION")
+               .help("Stop after DURATION (e.g 90s, 5m, 1h) from the first record"))
           .arg(Arg::with_name("tail")
                .long("tail")
                .takes_value(true)
-               .conflicts_with_all(&["input", "COMMAND", "restart"])
-               .help("Dump only the most recent <COUNT> lines (implies --dump)"))
+               .conflicts_with_all(&["COMMAND", "restart", "follow", "watch"])
+               .help("Dump only the most recent <COUNT> lines (implies --dump). Writes the last <COUNT> records of --input files"))
+          .arg(Arg::with_name("first-per-tag")
+               .long("first-per-tag")
+               .takes_value(true)
+               .value_name("N")
+               .conflicts_with("last-per-tag")
+               .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+               .help("Output only the first N records of each tag"))
+          .arg(Arg::with_name("last-per-tag")
+               .long("last-per-tag")
+               .takes_value(true)
+               .value_name("N")
+               .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+               .help("Output only the last N records of each tag at the end of the input"))
+
+          .arg(Arg::with_name("notify")
+               .long("notify")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("REGEX")
+               .help("Raise a desktop notification with tag and message when a message matches REGEX"))
+          .arg(Arg::with_name("on-match")
+               .long("on-match")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("REGEX:COMMAND")
+               .help("Run COMMAND in a shell when a message matches REGEX. The record is passed in the ROGCAT_TAG, ROGCAT_MESSAGE, ROGCAT_LEVEL, ROGCAT_PID, ROGCAT_TID, ROGCAT_TIMESTAMP and ROGCAT_RAW environment variables"))
+          .arg(Arg::with_name("flight-recorder")
+               .long("flight-recorder")
+               .takes_value(true)
+               .value_name("SIZE")
+               .requires("output")
+               .help("Keep the last SIZE records (e.g 10000) or DURATION (e.g 5m) in memory and write them to --output on a trigger, SIGUSR1 or ctrl-\\"))
+          .arg(Arg::with_name("trigger")
+               .long("trigger")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("REGEX")
+               .requires("flight-recorder")
+               .help("Dump the flight recorder when a message matches REGEX"))
 
           // Format
           .arg(Arg::with_name("format")
                .long("format")
                .takes_value(true)
-               .possible_values(&["csv", "html", "human", "json", "raw"]).help("Output format. Defaults to human on stdout and raw on file output"))
+               .possible_values(&["csv", "html", "human", "json", "passthrough", "raw"]).help("Output format. Defaults to human on stdout and raw on file output. passthrough prints the lines unchanged with highlights colored"))
+          .arg(Arg::with_name("format-file")
+               .long("format-file")
+               .takes_value(true)
+               .possible_values(&["csv", "html", "human", "json", "raw"])
+               .help("Format of the output file. The records are displayed on the terminal with --format as well"))
+
+          .arg(Arg::with_name("csv-columns")
+               .long("csv-columns")
+               .takes_value(true)
+               .help("Comma separated list of columns emitted by the csv format with a header line. \
+                      Available columns: timestamp, level, tag, process, thread, message, raw, process_name, source, buffer, fields"))
 
           // Display options
           .arg(Arg::with_name("color")
                .long("color")
                .takes_value(true)
                .possible_values(&["auto", "always", "never"])
-               .conflicts_with_all(&["highlight", "output"])
-               .help("Terminal coloring option"))
+               .conflicts_with("highlight")
+               .help("Terminal coloring option. Use always to write ANSI colors into human output files"))
           .arg(Arg::with_name("highlight")
                .short("h")
                .long("highlight")
                .takes_value(true)
                .multiple(true)
-               .conflicts_with_all(&["output"])
                .help( "Highlight messages that match this pattern in RE2. The prefix '!' inverts the match"))
           .arg(Arg::with_name("no-dimm")
                .long("no-dimm")
-               .conflicts_with("output")
                .help("Use white as dimm color"))
           .arg(Arg::with_name("bright_colors")
                .long("bright-colors")
-               .conflicts_with("output")
                .help("Use intense colors in terminal output"))
           .arg(Arg::with_name("hide-timestamp")
                .long("hide-timestamp")
-               .conflicts_with("output")
                .help("Hide timestamp in terminal output"))
           .arg(Arg::with_name("show-date")
                .long("show-date")
-               .conflicts_with("output")
                .help("Show month and day in terminal output"))
+          .arg(Arg::with_name("show-buffer")
+               .long("show-buffer")
+               .help("Show the logcat buffer of records in terminal output"))
+          .arg(Arg::with_name("wide")
+               .long("wide")
+               .help("Print each record on one line regardless of the terminal width. Implies --no-multiline"))
+
+          .arg(Arg::with_name("null")
+               .long("null")
+               .short("0")
+               .conflicts_with("output")
+               .help("Terminate records with NUL instead of newline e.g for fzf --read0 or xargs -0"))
+          .arg(Arg::with_name("no-multiline")
+               .long("no-multiline")
+               .conflicts_with("output")
+               .help("Escape newlines within records as \\n"))
+          .arg(Arg::with_name("gap-indicator")
+               .long("gap-indicator")
+               .takes_value(true)
+               .value_name("DURATION")
+               .validator(|d| utils::parse_duration(&d).map(|_| ()).map_err(|e| e.to_string()))
+               .help("Print a separator if the time between two records exceeds DURATION (e.g 500ms) in human output"))
+          .arg(Arg::with_name("interactive")
+               .long("interactive")
+               .conflicts_with_all(&["output", "format"])
+               .help("Pause with space, scroll back with arrow keys and page up/down, edit filters with / and quit with q"))
+          .arg(Arg::with_name("catch-up-condensed")
+               .long("catch-up-condensed")
+               .conflicts_with("output")
+               .help("Render records without colors and wrapping while the terminal is behind the capture"))
+          .arg(Arg::with_name("no-internal")
+               .long("no-internal")
+               .help("Do not inject internal events (restarts, file rotation...) tagged ROGCAT into the output"))
 
           // Input, Output
           .arg(Arg::with_name("input")
@@ -126,33 +303,124 @@ pub fn cli() -> App<'static, 'static> {
                .long("input")
                .takes_value(true)
                .multiple(true)
-               .help( "Read from file instead of command. Use 'serial://COM0@115200,8N1 or similiar for reading a serial port"))
+               .help( "Read from file instead of command. Directories and patterns like 'logs/**/*.log' are expanded. Use 'serial://COM0@115200,8N1 or similiar for reading a serial port"))
+          .arg(Arg::with_name("input-dir")
+               .long("input-dir")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("DIR")
+               .help("Read all files of DIR in alphanumerical order"))
+          .arg(Arg::with_name("watch")
+               .long("watch")
+               .requires("input-dir")
+               .conflicts_with_all(&["input", "mmap"])
+               .help("Keep watching --input-dir for new files and follow them"))
+          .arg(Arg::with_name("follow")
+               .long("follow")
+               .requires("input")
+               .conflicts_with_all(&["mmap", "watch"])
+               .help("Keep reading --input files when they grow and reopen them when they are rotated"))
+          .arg(Arg::with_name("glob")
+               .long("glob")
+               .takes_value(true)
+               .requires("inputs")
+               .help("Only read files of --input-dir and directories passed with --input matching this pattern e.g '*.log'"))
+          .arg(Arg::with_name("recursive")
+               .long("recursive")
+               .requires("inputs")
+               .conflicts_with("watch")
+               .help("Read the subdirectories of --input-dir and directories passed with --input"))
+          .group(ArgGroup::with_name("inputs")
+               .args(&["input", "input-dir"])
+               .multiple(true))
+          .arg(Arg::with_name("merge-by-time")
+               .long("merge-by-time")
+               .requires("inputs")
+               .conflicts_with_all(&["jobs", "follow", "watch"])
+               .help("Interleave the records of the input files by timestamp instead of reading the files one after the other"))
+          .arg(Arg::with_name("jobs")
+               .short("j")
+               .long("jobs")
+               .takes_value(true)
+               .value_name("N")
+               .requires("inputs")
+               .validator(|n| match n.parse::<usize>() {
+                   Ok(0) | Err(_) => Err("Invalid number of jobs".to_owned()),
+                   Ok(_) => Ok(()),
+               })
+               .help("Parse and filter --input files on N threads. The order of the records is kept"))
+          .arg(Arg::with_name("mmap")
+               .long("mmap")
+               .requires("inputs")
+               .help("Memory map input files instead of reading them. Faster on large files"))
+          .arg(Arg::with_name("framing")
+               .long("framing")
+               .takes_value(true)
+               .possible_values(&["len32", "newline", "binary"])
+               .conflicts_with_all(&["inputs", "fuchsia"])
+               .help("Framing of stdin and tcp input. 'len32' expects each record prefixed with a big endian u32 length. 'binary' reads the logcat binary format and captures with 'adb exec-out logcat -B' if no command is passed. Defaults to newline"))
+          .arg(Arg::with_name("event-tags")
+               .long("event-tags")
+               .takes_value(true)
+               .value_name("FILE")
+               .help("Event log tags to describe events e.g of --framing binary. Pulled from the device when capturing logcat"))
+          .arg(Arg::with_name("connect-timeout")
+               .long("connect-timeout")
+               .takes_value(true)
+               .value_name("DURATION")
+               .help("Timeout for resolving and connecting tcp sources (e.g 5s). Defaults to 10s"))
           .arg(Arg::with_name("output")
                .short("o")
                .long("output")
                .takes_value(true)
-               .conflicts_with("color")
-               .help("Write output to file"))
+               .help("Write output to file or forward it to a collector e.g tcp://collector:6514 or udp://collector:514"))
           .arg(Arg::with_name("overwrite")
                .long("overwrite")
-               .requires("output")
                .help("Overwrite output file if present"))
           .arg(Arg::with_name("records-per-file")
                .short("n")
                .long("records-per-file")
                .takes_value(true)
-               .requires("output")
                .help( "Write n records per file. Use k, M, G suffixes or a plain number"))
           .arg(Arg::with_name("filename-format")
                .long("filename-format")
                .short("a")
                .takes_value(true)
-               .requires("output")
                .possible_values(&["single", "enumerate", "date"])
                .help( "Select a format for output file names. By passing 'single' the filename provided with the '-o' option is used (default).\
                          'enumerate' appends a file sequence number after the filename passed with '-o' option whenever a new file is created \
                          (see 'records-per-file' option). 'date' will prefix the output filename with the current local date when a new file is created"))
 
+          // Statistics
+          .arg(Arg::with_name("stats")
+               .long("stats")
+               .takes_value(true)
+               .value_name("FILE")
+               .help("Write record counts per level and tag as json to FILE at exit"))
+          .arg(Arg::with_name("stats-baseline")
+               .long("stats-baseline")
+               .takes_value(true)
+               .value_name("FILE")
+               .help("Compare the record counts at exit with a baseline written with --stats and print significant changes"))
+          .arg(Arg::with_name("stats-threshold")
+               .long("stats-threshold")
+               .takes_value(true)
+               .value_name("PERCENT")
+               .requires("stats-baseline")
+               .help("Report changes to --stats-baseline of at least PERCENT and exit with 1 if there are any"))
+          .arg(Arg::with_name("stats-file")
+               .long("stats-file")
+               .takes_value(true)
+               .value_name("FILE")
+               .help("Append the record rate and counts per level and tag of every --stats-interval as json line to FILE"))
+          .arg(Arg::with_name("stats-interval")
+               .long("stats-interval")
+               .takes_value(true)
+               .value_name("DURATION")
+               .requires("stats-file")
+               .help("Interval of the statistics written to --stats-file e.g 30s, 5m or 1h. Defaults to 5m"))
+
           // Profiles
           .arg(Arg::with_name("profiles-path")
                .short("P")
@@ -166,24 +434,133 @@ pub fn cli() -> App<'static, 'static> {
                .help("Select profile"))
 
 
+          // Rewrite
+          .arg(Arg::with_name("rewrite")
+               .long("rewrite")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("s/REGEX/REPLACEMENT/FLAGS")
+               .help("Rewrite messages with a sed like substitution e.g to redact tokens. Supported flags are g and i"))
+          .arg(Arg::with_name("hash-fields")
+               .long("hash-fields")
+               .takes_value(true)
+               .value_name("FIELDS")
+               .help("Replace fields with salted hashes e.g process,thread,tag,message or capture groups with message:/REGEX/"))
+          .arg(Arg::with_name("hash-salt")
+               .long("hash-salt")
+               .takes_value(true)
+               .requires("hash-fields")
+               .help("Salt of --hash-fields to correlate hashes of several runs. Defaults to a random salt"))
+
           // Filter
+          .arg(Arg::with_name("annotate")
+               .long("annotate")
+               .takes_value(true)
+               .value_name("FILE")
+               .help("Annotate records with messages matching the regexes of a toml file e.g with links"))
           .arg(Arg::with_name("level")
                .short("l")
                .long("level")
                .takes_value(true)
-               .possible_values(Level::values()).help("Minimum level"))
+               .help("Minimum level e.g info or I, a custom level of the profile or a severity like 45"))
           .arg(Arg::with_name("filter")
                .long("filter")
                .short("f")
                .takes_value(true)
                .multiple(true)
                .help("Regex filter on tag, pid, thread and message."))
+          .arg(Arg::with_name("since")
+               .long("since")
+               .takes_value(true)
+               .value_name("TIME")
+               .help("Drop records before TIME e.g 10:31:00 or 03-25 10:31:00.500"))
+          .arg(Arg::with_name("until")
+               .long("until")
+               .takes_value(true)
+               .value_name("TIME")
+               .help("Drop records after TIME e.g 10:45:00 or 03-25 10:45:00.500"))
+          .arg(Arg::with_name("explain")
+               .long("explain")
+               .help("Print the number of records rejected per filter at the end"))
+          .arg(Arg::with_name("pid")
+               .long("pid")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .help("Process id or range like 100-200 filter. The prefix '!' inverts the match"))
+          .arg(Arg::with_name("logcat-pid")
+               .long("logcat-pid")
+               .takes_value(true)
+               .value_name("PID")
+               .conflicts_with_all(&["inputs", "fuchsia", "COMMAND"])
+               .validator(|pid| pid.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+               .help("Let logcat on the device select the records of process PID. Saves adb bandwidth compared to --pid"))
+          .arg(Arg::with_name("logcat-uid")
+               .long("logcat-uid")
+               .takes_value(true)
+               .value_name("UID")
+               .multiple(true)
+               .number_of_values(1)
+               .conflicts_with_all(&["inputs", "fuchsia", "COMMAND"])
+               .validator(|uid| uid.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+               .help("Let logcat on the device select the records of the user id UID. Requires Android 9 or newer"))
+          .arg(Arg::with_name("tid")
+               .long("tid")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .help("Thread id or range like 100-200 filter. The prefix '!' inverts the match"))
+          .arg(Arg::with_name("source")
+               .long("source")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("REGEX")
+               .help("Source filter e.g device serial, input file or can interface. The prefix '!' inverts the match"))
+          .arg(Arg::with_name("buffer-filter")
+               .long("buffer-filter")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("REGEX")
+               .help("Logcat buffer filter e.g main or crash. The prefix '!' inverts the match"))
+          .arg(Arg::with_name("field")
+               .long("field")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("KEY=REGEX")
+               .help("Structured field filter e.g pid=123. Patterns of the same key are alternatives, all keys must match. The prefix '!' inverts the match. Implies --extract-fields"))
+          .arg(Arg::with_name("extract-fields")
+               .long("extract-fields")
+               .help("Extract key=value pairs of messages into structured fields"))
+          .arg(Arg::with_name("can-id")
+               .long("can-id")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .help("CAN frame id or range like 0x100-0x1FF filter. The prefix '!' inverts the match"))
+          .arg(Arg::with_name("isotp")
+               .long("isotp")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("REQUEST:RESPONSE")
+               .help("Reassemble ISO-TP messages between a tester and an ECU id like 0x7E0:0x7E8 of CAN sources and decode UDS services"))
           .arg(Arg::with_name("filter-case-insensitive")
                .long("Filter")
                .short("F")
                .takes_value(true)
                .multiple(true)
                .help("Same as -f/--filter but case insensitive"))
+          .arg(Arg::with_name("filter-file")
+               .long("filter-file")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("FILE")
+               .help("Read filters from FILE. One pattern per line optionally prefixed with 'tag:' or 'message:'. Lines starting with '#' are comments and 'include FILE' reads another file"))
           .arg(Arg::with_name("message")
                .short("m")
                .long("message")
@@ -200,20 +577,43 @@ pub fn cli() -> App<'static, 'static> {
                .short("t")
                .long("tag")
                .takes_value(true)
-               .multiple(true).help("Tag filters in RE2. The prefix '!' inverts the match"))
+               .multiple(true).help("Tag filters in RE2. The prefix '!' inverts the match. group:NAME matches the tags of a profile tag group"))
           .arg(Arg::with_name("tag-case-insensitive")
                .short("T")
                .long("Tag")
                .takes_value(true)
                .multiple(true)
                .help("Same as -t/--tag but case insensitive"))
+          .arg(Arg::with_name("tag-exact")
+               .long("tag-exact")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("TAG")
+               .help("Tag filter without regex. Matches tags equal to TAG. The prefix '!' inverts the match"))
+          .arg(Arg::with_name("tags-from")
+               .long("tags-from")
+               .takes_value(true)
+               .value_name("FILE")
+               .help("Read --tag-exact filters from FILE. One tag per line, lines starting with '#' are ignored"))
+          .arg(Arg::with_name("msg-exact")
+               .long("msg-exact")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .value_name("TEXT")
+               .help("Message filter without regex. Matches messages containing TEXT. The prefix '!' inverts the match"))
 
           // Fuchsia
           .arg(Arg::with_name("fuchsia")
                .long("ffx")
                .takes_value(false)
-               .conflicts_with_all(&["buffer", "dev", "input", "COMMAND", "restart", "tail"])
+               .conflicts_with_all(&["buffer", "dev", "inputs", "COMMAND", "restart", "tail"])
                .help("Use ffx log instead of adb logcat"))
+          .arg(Arg::with_name("fuchsia-json")
+               .long("ffx-json")
+               .requires("fuchsia")
+               .help("Read the structured json output of ffx. Pid, tid and the keys of records are available for --field"))
 
           // Command
           .arg(Arg::with_name("COMMAND")
@@ -223,14 +623,65 @@ pub fn cli() -> App<'static, 'static> {
                     .about("Capture bugreport. This is only works for Android versions < 7.")
                     .arg(Arg::with_name("zip").short("z").long("zip").help("Zip report"))
                     .arg(Arg::with_name("overwrite").long("overwrite").help("Overwrite report file if present"))
+                    .arg(Arg::with_name("dev")
+                         .short("-s")
+                         .long("serial")
+                         .takes_value(true)
+                         .help("Select the device by serial or by a part of its model, product or device name e.g pixel"))
+                    .arg(Arg::with_name("extract")
+                         .short("x")
+                         .long("extract")
+                         .takes_value(true)
+                         .value_name("DIR")
+                         .help("Write each section of the report e.g SYSTEM LOG to a file in DIR with an index.json"))
+                    .arg(Arg::with_name("input")
+                         .short("i")
+                         .long("input")
+                         .takes_value(true)
+                         .requires("extract")
+                         .conflicts_with_all(&["file", "zip"])
+                         .help("Extract a previously captured report (text or zip) instead of capturing one"))
                     .arg(Arg::with_name("file").help("Output file name - defaults to <now>-bugreport")))
-          // Completions
-          .subcommand(SubCommand::with_name("completions")
-                    .about("Generates completion scripts")
-                    .arg(Arg::with_name("shell")
+          // Convert
+          .subcommand(SubCommand::with_name("convert")
+                    .about("Convert log files between formats")
+                    .arg(Arg::with_name("input")
+                         .short("i")
+                         .long("input")
+                         .takes_value(true)
+                         .multiple(true)
+                         .required(true)
+                         .help("Files to convert"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
                          .required(true)
-                         .possible_values(&["bash", "fish", "zsh"])
-                         .help("The shell to generate the script for")))
+                         .help("Output file"))
+                    .arg(Arg::with_name("format")
+                         .long("format")
+                         .takes_value(true)
+                         .possible_values(&["csv", "json", "raw"])
+                         .help("Output format. Defaults to the output file extension or raw"))
+                    .arg(Arg::with_name("overwrite").long("overwrite").help("Overwrite output file if present"))
+                    .arg(Arg::with_name("verify")
+                         .long("verify")
+                         .help("Parse every written record and fail if any field is not reproduced exactly")))
+          // Completions and man pages
+          .subcommand(SubCommand::with_name("generate")
+                    .about("Generates completion scripts and man pages")
+                    .alias("completions")
+                    .arg(Arg::with_name("dir")
+                         .short("d")
+                         .long("dir")
+                         .takes_value(true)
+                         .value_name("DIR")
+                         .help("Write files to DIR instead of stdout. Man pages are written for each subcommand"))
+                    .arg(Arg::with_name("target")
+                         .required(true)
+                         .multiple(true)
+                         .possible_values(&["bash", "elvish", "fig", "fish", "man", "nushell", "powershell", "zsh"])
+                         .help("The shell to generate the script for or \"man\". Multiple targets require --dir")))
           // Clear android log buffer
           .subcommand(SubCommand::with_name("clear")
                     .about("Clear logd buffers")
@@ -243,6 +694,29 @@ pub fn cli() -> App<'static, 'static> {
           // List adb devices
           .subcommand(SubCommand::with_name("devices")
                     .about("List available devices"))
+          // adb over the network
+          .subcommand(SubCommand::with_name("connect")
+                    .about("Connect to a device with adb connect and capture its logs once it is online")
+                    .arg(Arg::with_name("timeout")
+                         .long("timeout")
+                         .takes_value(true)
+                         .value_name("DURATION")
+                         .help("Time to wait for the device to come online. Defaults to 30s"))
+                    .arg(Arg::with_name("no-stream")
+                         .long("no-stream")
+                         .help("Exit once the device is online"))
+                    .arg(Arg::with_name("ADDRESS")
+                         .required(true)
+                         .help("Address of the device like 192.168.1.23 or 192.168.1.23:5555")))
+          .subcommand(SubCommand::with_name("tcpip")
+                    .about("Restart adbd of a usb device in tcp mode and print the address to connect to")
+                    .arg(Arg::with_name("dev")
+                         .short("-s")
+                         .long("serial")
+                         .takes_value(true)
+                         .help("Select the device by serial or by a part of its model, product or device name e.g pixel"))
+                    .arg(Arg::with_name("PORT")
+                         .help("Port of adbd. Defaults to 5555")))
           // Log to logd
           .subcommand(SubCommand::with_name("log")
                     .about("Add log message(s) log buffer")
@@ -257,5 +731,183 @@ pub fn cli() -> App<'static, 'static> {
                          .takes_value(true)
                          .possible_values(&[ "trace", "debug", "info", "warn", "error", "fatal", "assert", "T", "D", "I", "W", "E", "F", "A" ],)
                          .help("Log on level"))
+                    .arg(Arg::with_name("buffer")
+                         .short("b")
+                         .long("buffer")
+                         .takes_value(true)
+                         .possible_values(&["main", "system", "radio", "events", "crash"])
+                         .help("Log buffer to write to. Defaults to main"))
+                    .arg(Arg::with_name("kv")
+                         .long("kv")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("KEY=VALUE")
+                         .help("Append key=value pairs to the message. Values with whitespace are quoted"))
                     .arg_from_usage("[MESSAGE] 'Log message. Pass \"-\" to read from stdin'."))
+          // System properties
+          .subcommand(SubCommand::with_name("props")
+                    .about("Print, save and diff the system properties of a device")
+                    .arg(Arg::with_name("dev")
+                         .short("-s")
+                         .long("serial")
+                         .takes_value(true)
+                         .help("Select the device by serial or by a part of its model, product or device name e.g pixel"))
+                    .arg(Arg::with_name("save")
+                         .long("save")
+                         .takes_value(true)
+                         .value_name("NAME")
+                         .help("Save the properties as snapshot NAME"))
+                    .arg(Arg::with_name("diff")
+                         .long("diff")
+                         .takes_value(true)
+                         .value_name("NAME")
+                         .help("Print the properties added, removed or changed since the snapshot NAME"))
+                    .arg(Arg::with_name("color")
+                         .long("color")
+                         .takes_value(true)
+                         .possible_values(&["auto", "always", "never"])
+                         .help("Terminal coloring option")))
+          // Crashes of saved logs
+          .subcommand(SubCommand::with_name("crashes")
+                    .about("Print Java exceptions, native crashes and ANRs of log files")
+                    .arg(Arg::with_name("input")
+                         .short("i")
+                         .long("input")
+                         .takes_value(true)
+                         .multiple(true)
+                         .required(true)
+                         .help("Files to analyze"))
+                    .arg(Arg::with_name("output-dir")
+                         .short("o")
+                         .long("output-dir")
+                         .takes_value(true)
+                         .value_name("DIR")
+                         .help("Write each crash to a numbered file in DIR"))
+                    .arg(Arg::with_name("symbols-dir")
+                         .long("symbols-dir")
+                         .takes_value(true)
+                         .value_name("DIR")
+                         .help("Symbolize native backtraces with the unstripped libraries in DIR"))
+                    .arg(Arg::with_name("symbolizer")
+                         .long("symbolizer")
+                         .takes_value(true)
+                         .requires("symbols-dir")
+                         .help("Symbolizer used for --symbols-dir e.g addr2line. Defaults to llvm-symbolizer or addr2line"))
+                    .arg(Arg::with_name("color")
+                         .long("color")
+                         .takes_value(true)
+                         .possible_values(&["auto", "always", "never"])
+                         .help("Terminal coloring option")))
+          // Search of rotated captures
+          .subcommand(SubCommand::with_name("grep")
+                    .about("Search the rotated captures of a directory and print the matches with file and line")
+                    .arg(Arg::with_name("filter")
+                         .required(true)
+                         .multiple(true)
+                         .value_name("PATTERN")
+                         .help("Regex filter on tag, pid, thread and message. The prefix '!' inverts the match"))
+                    .arg(Arg::with_name("dir")
+                         .short("d")
+                         .long("dir")
+                         .takes_value(true)
+                         .default_value(".")
+                         .help("Directory with the captures. Subdirectories are searched as well"))
+                    .arg(Arg::with_name("captures-since")
+                         .long("since")
+                         .takes_value(true)
+                         .value_name("DATE")
+                         .validator(|d| utils::parse_capture_date(&d, false).map(|_| ()).map_err(|e| e.to_string()))
+                         .help("Skip captures that ended before DATE e.g 2024-03-01 or \"2024-03-01 10:30\""))
+                    .arg(Arg::with_name("captures-until")
+                         .long("until")
+                         .takes_value(true)
+                         .value_name("DATE")
+                         .validator(|d| utils::parse_capture_date(&d, true).map(|_| ()).map_err(|e| e.to_string()))
+                         .help("Skip captures that started after DATE"))
+                    .arg(Arg::with_name("tag")
+                         .short("t")
+                         .long("tag")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("Tag filters in RE2. The prefix '!' inverts the match"))
+                    .arg(Arg::with_name("message")
+                         .short("m")
+                         .long("message")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("Message filters in RE2. The prefix '!' inverts the match"))
+                    .arg(Arg::with_name("level")
+                         .short("l")
+                         .long("level")
+                         .takes_value(true)
+                         .help("Minimum level e.g info or I, a custom level of the profile or a severity like 45"))
+                    .arg(Arg::with_name("jobs")
+                         .short("j")
+                         .long("jobs")
+                         .takes_value(true)
+                         .validator(|n| match n.parse::<usize>() {
+                             Ok(0) | Err(_) => Err("Invalid number of jobs".to_owned()),
+                             Ok(_) => Ok(()),
+                         })
+                         .help("Number of files searched in parallel. Defaults to the number of CPUs"))
+                    .arg(Arg::with_name("color")
+                         .long("color")
+                         .takes_value(true)
+                         .possible_values(&["auto", "always", "never"])
+                         .help("Terminal coloring option")))
+          // Statistics of saved logs
+          .subcommand(SubCommand::with_name("stats")
+                    .about("Print record counts, rate and the most frequent messages of log files")
+                    .arg(Arg::with_name("input")
+                         .short("i")
+                         .long("input")
+                         .takes_value(true)
+                         .multiple(true)
+                         .required(true)
+                         .help("Files to analyze"))
+                    .arg(Arg::with_name("bucket")
+                         .long("bucket")
+                         .takes_value(true)
+                         .value_name("DURATION")
+                         .validator(|d| utils::parse_duration(&d).map(|_| ()).map_err(|e| e.to_string()))
+                         .help("Time bucket of the rate e.g 1s or 5m. Defaults to 1m"))
+                    .arg(Arg::with_name("top")
+                         .short("n")
+                         .long("top")
+                         .takes_value(true)
+                         .default_value("10")
+                         .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                         .help("Number of most frequent messages to print")))
+          // Terminal ui
+          .subcommand(SubCommand::with_name("tui")
+                    .about("Full screen terminal ui with level toggles, search, pause and export")
+                    .arg(Arg::with_name("buffer")
+                         .short("b")
+                         .long("buffer")
+                         .multiple(true)
+                         .takes_value(true)
+                         .conflicts_with_all(&["input", "COMMAND"])
+                         .help("Select specific logd buffers. Defaults to main, events, kernel and crash"))
+                    .arg(Arg::with_name("dev")
+                         .short("-s")
+                         .long("serial")
+                         .takes_value(true)
+                         .help("Select the device by serial or by a part of its model, product or device name e.g pixel"))
+                    .arg(Arg::with_name("input")
+                         .short("i")
+                         .long("input")
+                         .takes_value(true)
+                         .multiple(true)
+                         .conflicts_with("COMMAND")
+                         .help("Read from file instead of command"))
+                    .arg(Arg::with_name("COMMAND")
+                         .help("Optional command to run and capture stdout and stdderr from")))
+          .subcommand(SubCommand::with_name("view")
+                    .about("Page a file written with --format human --color always")
+                    .arg(Arg::with_name("FILE")
+                         .required(true)
+                         .help("File to view")))
 }
@@ -0,0 +1,49 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Console capabilities. Legacy Windows consoles interpret escape codes only after
+//! virtual terminal processing is enabled and do not report their size to `term_size`.
+
+use atty::Stream;
+use ratatui::crossterm::terminal;
+
+/// Check if the console interprets ANSI escape codes. On Windows this tries to
+/// enable virtual terminal processing first.
+#[cfg(windows)]
+pub fn ansi() -> bool {
+    ratatui::crossterm::ansi_support::supports_ansi()
+}
+
+/// Check if the console interprets ANSI escape codes
+#[cfg(not(windows))]
+pub fn ansi() -> bool {
+    true
+}
+
+/// Width of the console stdout is attached to. Uses the console API on Windows.
+pub fn width() -> Option<usize> {
+    if !atty::is(Stream::Stdout) {
+        return None;
+    }
+    terminal::size()
+        .ok()
+        .map(|(columns, _)| usize::from(columns))
+        .filter(|columns| *columns > 0)
+}
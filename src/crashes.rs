@@ -0,0 +1,156 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Detection of Java exceptions, native crashes and ANRs that span multiple records
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::Record;
+use std::fmt::{self, Display, Formatter};
+
+lazy_static! {
+    static ref EXCEPTION: Regex = Regex::new(r"^[A-Za-z_$][\w$]*(\.[\w$]+)+(: .*)?$").unwrap();
+    static ref SIGNAL: Regex = Regex::new(r"signal \d+ \(SIG\w+\)").unwrap();
+}
+
+/// Maximum gap between two records of a crash in seconds
+const MAX_GAP: i64 = 1;
+
+/// Kind of a crash
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Java,
+    Native,
+    Anr,
+}
+
+impl Kind {
+    /// Kind of the crash started by `record` if any
+    fn detect(record: &Record) -> Option<Kind> {
+        let tagged = |tag: &str| record.tags.iter().any(|t| t == tag);
+        if tagged("AndroidRuntime") && record.message.contains("FATAL EXCEPTION") {
+            Some(Kind::Java)
+        } else if tagged("DEBUG") && record.message.starts_with("*** *** ***") {
+            Some(Kind::Native)
+        } else if tagged("ActivityManager") && record.message.starts_with("ANR in ") {
+            Some(Kind::Anr)
+        } else {
+            None
+        }
+    }
+
+    /// Short name used for file names
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Java => "java",
+            Kind::Native => "native",
+            Kind::Anr => "anr",
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Kind::Java => write!(f, "Java exception"),
+            Kind::Native => write!(f, "Native crash"),
+            Kind::Anr => write!(f, "ANR"),
+        }
+    }
+}
+
+/// Records of one crash
+#[derive(Debug)]
+pub struct Crash {
+    pub kind: Kind,
+    pub records: Vec<Record>,
+}
+
+impl Crash {
+    /// True if `record` is logged by the same process, thread and tag
+    fn continues(&self, record: &Record) -> bool {
+        let first = &self.records[0];
+        first.process == record.process
+            && first.thread == record.thread
+            && first.tags == record.tags
+    }
+
+    /// True if `record` is more than `MAX_GAP` after the last record of the crash
+    fn expired(&self, record: &Record) -> bool {
+        let last = self.records.last().and_then(|r| r.timestamp.as_ref());
+        match (last, record.timestamp.as_ref()) {
+            (Some(last), Some(ts)) if last.is_valid() && ts.is_valid() => {
+                (ts.tm - last.tm).num_seconds() > MAX_GAP
+            }
+            _ => false,
+        }
+    }
+
+    /// Summary e.g the exception, the signal or the ANR line
+    pub fn title(&self) -> String {
+        let messages = self.records.iter().map(|r| r.message.trim());
+        match self.kind {
+            Kind::Java => messages.skip(1).find(|m| EXCEPTION.is_match(m)),
+            Kind::Native => messages.skip(1).find(|m| SIGNAL.is_match(m)),
+            Kind::Anr => None,
+        }
+        .unwrap_or_else(|| self.records[0].message.trim())
+        .to_owned()
+    }
+}
+
+/// Groups records into crashes
+#[derive(Default)]
+pub struct Detector {
+    open: Vec<Crash>,
+}
+
+impl Detector {
+    /// Add `record` and return the crashes that are complete
+    pub fn add(&mut self, record: Record) -> Vec<Crash> {
+        let (mut done, open) = self
+            .open
+            .drain(..)
+            .partition::<Vec<_>, _>(|c| c.expired(&record));
+        self.open = open;
+
+        let kind = Kind::detect(&record);
+        if let Some(n) = self.open.iter().position(|c| c.continues(&record)) {
+            if kind.is_none() {
+                self.open[n].records.push(record);
+                return done;
+            }
+            done.push(self.open.remove(n));
+        }
+
+        if let Some(kind) = kind {
+            self.open.push(Crash {
+                kind,
+                records: vec![record],
+            });
+        }
+        done
+    }
+
+    /// Return the remaining crashes at the end of the input
+    pub fn finish(self) -> Vec<Crash> {
+        self.open
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Diagnostic report written if rogcat panics
+
+use crate::{interactive, utils};
+use lazy_static::lazy_static;
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+/// Number of raw input lines in the report
+const LINES: usize = 100;
+
+lazy_static! {
+    static ref LAST_LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::with_capacity(LINES));
+}
+
+/// Write one report only e.g if several threads panic
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Remember `line` for the diagnostic report
+pub fn line(line: &str) {
+    if let Ok(mut lines) = LAST_LINES.lock() {
+        if lines.len() == LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_owned());
+    }
+}
+
+/// Install a panic hook that writes a diagnostic report and prints its path
+pub fn install() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        interactive::restore();
+        default(info);
+        if REPORTED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        match write_report(info) {
+            Ok(file) => eprintln!(
+                "Please attach the diagnostic report {} to the bug report",
+                file.display()
+            ),
+            Err(e) => eprintln!("Failed to write diagnostic report: {e}"),
+        }
+    }));
+}
+
+fn write_report(info: &PanicHookInfo) -> io::Result<PathBuf> {
+    let filename = env::temp_dir().join(format!("rogcat-panic-{}.txt", process::id()));
+    let mut report = BufWriter::new(File::create(&filename)?);
+
+    writeln!(report, "rogcat {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(report, "Arguments: {:?}", env::args().collect::<Vec<_>>())?;
+    writeln!(
+        report,
+        "Config directory: {}",
+        utils::config_dir().display()
+    )?;
+    for var in &["ROGCAT_CONFIG", "ROGCAT_PROFILES"] {
+        if let Some(value) = env::var_os(var) {
+            writeln!(report, "{}: {}", var, value.to_string_lossy())?;
+        }
+    }
+    writeln!(report)?;
+    writeln!(report, "{info}")?;
+    writeln!(report, "{}", Backtrace::force_capture())?;
+
+    // The panic may have happened while the lines are updated
+    if let Ok(lines) = LAST_LINES.try_lock() {
+        writeln!(report, "Last {} input lines:", lines.len())?;
+        for line in lines.iter() {
+            writeln!(report, "{line}")?;
+        }
+    }
+    report.flush()?;
+    Ok(filename)
+}
@@ -0,0 +1,103 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Errors with a distinct exit code per kind for scripts wrapping rogcat
+
+use failure::{Error, Fail};
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+    num::ParseIntError,
+};
+
+/// Exit code of errors that are not classified
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code of invalid arguments, configuration or profiles
+pub const EXIT_CONFIG: i32 = 2;
+/// Exit code of failures of adb, the device or another source
+pub const EXIT_DEVICE: i32 = 3;
+/// Exit code of failures to read inputs or write outputs
+pub const EXIT_IO: i32 = 4;
+/// Exit code of inputs that cannot be parsed
+pub const EXIT_PARSE: i32 = 5;
+
+#[derive(Debug)]
+pub enum RogcatError {
+    /// Invalid arguments, configuration or profiles
+    Config(String),
+    /// adb, the device or another source failed
+    Device(String),
+    /// Reading an input or writing an output failed
+    Io(String),
+    /// An input cannot be parsed
+    Parse(String),
+}
+
+impl RogcatError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RogcatError::Config(_) => EXIT_CONFIG,
+            RogcatError::Device(_) => EXIT_DEVICE,
+            RogcatError::Io(_) => EXIT_IO,
+            RogcatError::Parse(_) => EXIT_PARSE,
+        }
+    }
+}
+
+impl Display for RogcatError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RogcatError::Config(message)
+            | RogcatError::Device(message)
+            | RogcatError::Io(message)
+            | RogcatError::Parse(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Fail for RogcatError {}
+
+/// Exit code of `error`. Errors of libraries are classified by their type.
+pub fn exit_code(error: &Error) -> i32 {
+    for cause in error.iter_chain() {
+        if let Some(e) = cause.downcast_ref::<RogcatError>() {
+            return e.exit_code();
+        } else if cause.downcast_ref::<io::Error>().is_some()
+            || cause.downcast_ref::<csv::Error>().is_some()
+        {
+            return EXIT_IO;
+        } else if cause.downcast_ref::<regex::Error>().is_some()
+            || cause.downcast_ref::<toml::de::Error>().is_some()
+            || cause.downcast_ref::<config::ConfigError>().is_some()
+            || cause.downcast_ref::<clap::Error>().is_some()
+            || cause.downcast_ref::<ParseIntError>().is_some()
+            || cause.downcast_ref::<url::ParseError>().is_some()
+        {
+            return EXIT_CONFIG;
+        } else if cause
+            .downcast_ref::<rogcat::parser::ParserError>()
+            .is_some()
+            || cause.downcast_ref::<serde_json::Error>().is_some()
+        {
+            return EXIT_PARSE;
+        }
+    }
+    EXIT_FAILURE
+}
@@ -18,11 +18,22 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::LogSink;
+use crate::{
+    error::RogcatError,
+    forwarder::{self, Forwarder},
+    internal,
+    profiles::Profile,
+    progress::Progress,
+    terminal::Human,
+    utils::{self, format_options},
+    LogSink,
+};
 use clap::ArgMatches;
 use failure::{err_msg, format_err, Error};
-use futures::{Async, AsyncSink, Poll, Sink, StartSend};
-use indicatif::{ProgressBar, ProgressStyle};
+use futures::{
+    sync::mpsc::{channel, Sender},
+    try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream,
+};
 use regex::Regex;
 use rogcat::record::{Format, Record};
 use std::{
@@ -30,9 +41,20 @@ use std::{
     io::Write,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::{self, JoinHandle},
 };
+use termcolor::Buffer;
 use time::{now, strftime};
 
+/// Number of records buffered for the writer thread
+const WRITE_QUEUE: usize = 64 * 1024;
+
+/// Number of records queued for the writer thread
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+/// Maximum number of records queued for the writer thread
+static MAX_QUEUED: AtomicUsize = AtomicUsize::new(0);
+
 /// Filename format
 #[derive(Clone)]
 enum FilenameFormat {
@@ -47,73 +69,338 @@ struct Textfile {
     format: Format,
 }
 
+/// Human readable file. ANSI colors are written with `--color always`.
+struct HumanFile {
+    file: File,
+    human: Human,
+    ansi: bool,
+}
+
+/// Output file or collector url and its options
+pub struct Output {
+    path: String,
+    format: Option<String>,
+    records_per_file: Option<String>,
+}
+
+impl Output {
+    /// Output of the command line
+    fn from_args(args: &ArgMatches) -> Option<Output> {
+        args.value_of("output").map(|path| Output {
+            path: path.to_owned(),
+            format: file_format(args).map(str::to_owned),
+            records_per_file: args.value_of("records-per-file").map(str::to_owned),
+        })
+    }
+
+    /// Output of the command line or the default output of `profile` if `-o` isn't passed.
+    /// `--format` and `--records-per-file` take precedence over the profile settings.
+    pub fn new(args: &ArgMatches, profile: &Profile) -> Result<Option<Output>, Error> {
+        if let Some(output) = Output::from_args(args) {
+            return Ok(Some(output));
+        }
+        let path = match profile.output {
+            Some(ref path) => expand(path)?,
+            None => return Ok(None),
+        };
+        let format = file_format(args)
+            .or(profile.format.as_deref())
+            .map(str::to_owned);
+        if let Some(ref format) = format {
+            Format::from_str(format).map_err(|_| {
+                RogcatError::Config(format!("Invalid format {} in profile", format))
+            })?;
+        }
+        let records_per_file = args
+            .value_of("records-per-file")
+            .or(profile.records_per_file.as_deref())
+            .map(str::to_owned);
+        Ok(Some(Output {
+            path,
+            format,
+            records_per_file,
+        }))
+    }
+
+    fn format(&self, args: &ArgMatches) -> Result<Format, Error> {
+        let format = self
+            .format
+            .as_deref()
+            .and_then(|f| Format::from_str(f).ok())
+            .unwrap_or(Format::Raw);
+        format_options(format, args)
+    }
+}
+
+/// Format of the output file. `--format` applies to the terminal if `--format-file` is passed.
+fn file_format<'a>(args: &'a ArgMatches) -> Option<&'a str> {
+    if args.is_present("format-file") {
+        args.value_of("format-file")
+    } else {
+        args.value_of("format")
+    }
+}
+
+/// Expand a leading `~` to the home directory and `%date%` to the current date and time
+fn expand(path: &str) -> Result<String, Error> {
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = directories::BaseDirs::new()
+                .map(|dirs| dirs.home_dir().display().to_string())
+                .ok_or_else(|| RogcatError::Config("Failed to find home directory".to_owned()))?;
+            format!("{}{}", home, rest)
+        }
+        _ => path.to_owned(),
+    };
+    if path.contains("%date%") {
+        Ok(path.replace("%date%", &strftime("%F-%H_%M_%S", &now())?))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Creates the writer for the next output file
+type Create<T> = Box<dyn Fn(&Path) -> Result<T, Error> + Send>;
+
 struct FileWriter<T> {
     current_filename: PathBuf,
     file_size: usize,
     filename: PathBuf,
     filename_format: FilenameFormat,
     index: usize,
-    format: Format,
-    progress: ProgressBar,
+    progress: Progress,
+    create: Create<T>,
     writer: Option<Box<T>>,
 }
 
 trait Writer {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Self, Error>
-    where
-        Self: Sized;
     fn write(&mut self, record: &Record, index: usize) -> Result<(), Error>;
     fn flush(&mut self) -> Result<(), Error> {
         Ok(())
     }
 }
 
-/// Crate a new log sink for given arguments
-pub fn try_from(args: &ArgMatches) -> Result<LogSink, Error> {
-    let format = args
-        .value_of("format")
-        .and_then(|f| Format::from_str(f).ok())
-        .unwrap_or(Format::Raw);
+/// Crate a new log sink for given arguments. Records are written on a
+/// dedicated thread to keep a slow disk or network from stalling the ingestion.
+pub fn try_from(args: &ArgMatches, profile: &Profile) -> Result<LogSink, Error> {
+    let output = Output::new(args, profile)?
+        .ok_or_else(|| RogcatError::Config("Invalid output filename!".to_owned()))?;
+    let format = output.format(args)?;
+
+    if let Some(url) = forwarder::url(&output.path) {
+        let forwarder = Forwarder::new(args, profile, format, url)?;
+        return Ok(Box::new(WriterThread::spawn(Box::new(forwarder))?));
+    }
+
+    Ok(Box::new(WriterThread::spawn(match format {
+        Format::Csv(_) | Format::Json | Format::Passthrough | Format::Raw => {
+            let create = Box::new(move |filename: &Path| Textfile::create(filename, &format));
+            Box::new(FileWriter::<Textfile>::new(args, &output, create)?) as LogSink
+        }
+        Format::Html => {
+            let create = Box::new(html::Html::create);
+            Box::new(FileWriter::<html::Html>::new(args, &output, create)?) as LogSink
+        }
+        Format::Human => {
+            let create = human_file(args, profile, format);
+            Box::new(FileWriter::<HumanFile>::new(args, &output, create)?) as LogSink
+        }
+    })?))
+}
 
-    Ok(match format {
-        Format::Csv | Format::Json | Format::Raw => {
-            Box::new(FileWriter::<Textfile>::from_args(args, format)?) as LogSink
+/// Write `records` to a new file named like `--output` with the current date prefixed e.g
+/// the dumps of the flight recorder. Returns the name of the written file.
+pub fn dump(args: &ArgMatches, profile: &Profile, records: &[Record]) -> Result<PathBuf, Error> {
+    let output = Output::from_args(args)
+        .ok_or_else(|| RogcatError::Config("Invalid output filename!".to_owned()))?;
+    let format = output.format(args)?;
+    match format {
+        Format::Csv(_) | Format::Json | Format::Passthrough | Format::Raw => {
+            let create = Box::new(move |filename: &Path| Textfile::create(filename, &format));
+            FileWriter::<Textfile>::new(args, &output, create)?.dump(records)
+        }
+        Format::Html => FileWriter::<html::Html>::new(args, &output, Box::new(html::Html::create))?
+            .dump(records),
+        Format::Human => {
+            let create = human_file(args, profile, format);
+            FileWriter::<HumanFile>::new(args, &output, create)?.dump(records)
         }
-        Format::Html => Box::new(FileWriter::<html::Html>::from_args(args, format)?) as LogSink,
-        Format::Human => panic!("Unsupported format human in output file"),
+    }
+}
+
+/// Creates human readable files
+fn human_file(args: &ArgMatches, profile: &Profile, format: Format) -> Create<HumanFile> {
+    let human = Human::from(args, profile, format).detach();
+    let ansi = args.value_of("color") == Some("always");
+    Box::new(move |filename: &Path| -> Result<HumanFile, Error> {
+        Ok(HumanFile {
+            file: create_file(filename)?,
+            human: human.clone(),
+            ansi,
+        })
     })
 }
 
-impl Writer for Textfile {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Textfile, Error> {
-        let file = File::create(filename).map_err(|e| {
-            format_err!("Failed to create output file {}: {}", filename.display(), e)
-        })?;
+/// Maximum number of records that were queued for the writer thread
+pub fn max_queued() -> usize {
+    MAX_QUEUED.load(Ordering::Relaxed)
+}
+
+/// Sink that queues records for a sink driven by a dedicated thread
+struct WriterThread {
+    tx: Option<Sender<Record>>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl WriterThread {
+    fn spawn(sink: LogSink) -> Result<WriterThread, Error> {
+        let (tx, rx) = channel(WRITE_QUEUE);
+        let thread = thread::Builder::new()
+            .name("writer".into())
+            .spawn(move || {
+                rx.map_err(|_| err_msg("Writer queue failed"))
+                    .inspect(|_| {
+                        QUEUED.fetch_sub(1, Ordering::Relaxed);
+                    })
+                    .forward(sink)
+                    .wait()
+                    .map(drop)
+            })
+            .map_err(|e| format_err!("Failed to start writer thread: {}", e))?;
+        Ok(WriterThread {
+            tx: Some(tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Wait for the writer thread and return its result
+    fn join(&mut self) -> Result<(), Error> {
+        self.tx.take();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(err_msg("Writer thread panicked"))),
+            None => Ok(()),
+        }
+    }
+
+    /// Error of a writer thread that stopped receiving records
+    fn failed(&mut self) -> Error {
+        self.join()
+            .err()
+            .unwrap_or_else(|| err_msg("Writer thread stopped"))
+    }
+}
+
+impl Sink for WriterThread {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        let tx = match self.tx {
+            Some(ref mut tx) => tx,
+            None => return Err(err_msg("Writer thread stopped")),
+        };
+        // Count before sending: the writer thread may take the record
+        // before start_send returns
+        let queued = QUEUED.fetch_add(1, Ordering::Relaxed) + 1;
+        match tx.start_send(record) {
+            Ok(AsyncSink::Ready) => {
+                MAX_QUEUED.fetch_max(queued, Ordering::Relaxed);
+                Ok(AsyncSink::Ready)
+            }
+            Ok(not_ready) => {
+                QUEUED.fetch_sub(1, Ordering::Relaxed);
+                Ok(not_ready)
+            }
+            Err(_) => {
+                QUEUED.fetch_sub(1, Ordering::Relaxed);
+                Err(self.failed())
+            }
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        let result = match self.tx {
+            Some(ref mut tx) => tx.poll_complete(),
+            None => return Ok(Async::Ready(())),
+        };
+        result.map_err(|_| self.failed())
+    }
+
+    /// Write all queued records and close the files
+    fn close(&mut self) -> Poll<(), Error> {
+        try_ready!(self.poll_complete());
+        self.join().map(Async::Ready)
+    }
+}
+
+impl Drop for WriterThread {
+    /// Write the queued records e.g when the processing is cancelled
+    fn drop(&mut self) {
+        if let Err(e) = self.join() {
+            eprintln!("{e}");
+        }
+    }
+}
+
+fn create_file(filename: &Path) -> Result<File, Error> {
+    File::create(filename).map_err(|e| {
+        RogcatError::Io(format!(
+            "Failed to create output file {}: {}",
+            filename.display(),
+            e
+        ))
+        .into()
+    })
+}
+
+impl Textfile {
+    fn create(filename: &Path, format: &Format) -> Result<Textfile, Error> {
+        let mut file = create_file(filename)?;
+        if let Some(header) = format.header()? {
+            file.write_all(header.as_bytes())
+                .and_then(|_| file.write_all(b"\n"))
+                .map_err(|e| RogcatError::Io(format!("Failed to write: {}", e)))?;
+        }
         Ok(Textfile {
             file,
             format: format.clone(),
         })
     }
+}
 
+impl Writer for Textfile {
     fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
         self.file
             .write(self.format.fmt_record(record)?.as_bytes())
-            .map_err(|e| format_err!("Failed to write: {}", e))?;
+            .map_err(|e| RogcatError::Io(format!("Failed to write: {}", e)))?;
         self.file
             .write(b"\n")
-            .map_err(|e| format_err!("Failed to write: {}", e))?;
+            .map_err(|e| RogcatError::Io(format!("Failed to write: {}", e)))?;
         Ok(())
     }
 }
 
+impl Writer for HumanFile {
+    fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
+        let buffer = if self.ansi {
+            Buffer::ansi()
+        } else {
+            Buffer::no_color()
+        };
+        let buffer = self.human.render(record.clone(), buffer)?;
+        self.file
+            .write_all(buffer.as_slice())
+            .map_err(|e| RogcatError::Io(format!("Failed to write: {}", e)).into())
+    }
+}
+
 impl<'a, T: Writer> FileWriter<T> {
-    pub fn from_args(args: &ArgMatches<'a>, format: Format) -> Result<Self, Error> {
-        let filename = args
-            .value_of("output")
-            .map(PathBuf::from)
-            .ok_or_else(|| err_msg("Invalid output filename!"))?;
+    fn new(args: &ArgMatches<'a>, output: &Output, create: Create<T>) -> Result<Self, Error> {
+        let filename = PathBuf::from(&output.path);
 
-        let records_per_file = args.value_of("records-per-file").and_then(|l| {
+        let records_per_file = output.records_per_file.as_deref().and_then(|l| {
             Regex::new(r"^(\d+)([kMG])$")
                 .unwrap()
                 .captures(l)
@@ -149,26 +436,21 @@ impl<'a, T: Writer> FileWriter<T> {
             }
         };
 
-        let progress = {
-            let (pb, chars, template) = if let Some(n) = records_per_file {
-                (
-                    ProgressBar::new(n as u64),
-                    "•• ",
-                    "{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim}/{len:.dim} {elapsed_precise:.dim} [{bar:40.yellow/green}] ({eta:.dim})",
-                )
-            } else {
-                (
-                    ProgressBar::new(u64::MAX),
-                    " • ",
-                    "{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}",
-                )
-            };
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template(template)
-                    .progress_chars(chars),
-            );
-            pb
+        // The progress would garble the records on the terminal
+        let progress = if args.is_present("format-file") {
+            Progress::Hidden
+        } else if let Some(n) = records_per_file {
+            Progress::new(
+                Some(n as u64),
+                "{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim}/{len:.dim} {elapsed_precise:.dim} [{bar:40.yellow/green}] ({eta:.dim})",
+                "•• ",
+            )
+        } else {
+            Progress::new(
+                None,
+                "{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}",
+                " • ",
+            )
         };
 
         Ok(FileWriter {
@@ -177,36 +459,63 @@ impl<'a, T: Writer> FileWriter<T> {
             filename,
             filename_format,
             index: 0,
-            format,
             progress,
+            create,
             writer: None,
         })
     }
 
+    /// Write `records` to one new file with the date prefixed and return its name
+    fn dump(mut self, records: &[Record]) -> Result<PathBuf, Error> {
+        self.filename_format = FilenameFormat::Date(false, usize::MAX);
+        self.progress = Progress::Hidden;
+        for record in records {
+            self.write(record)?;
+        }
+        let filename = self.current_filename.clone();
+        FileWriter::flush(&mut self)?;
+        Ok(filename)
+    }
+
     fn next_file(&self) -> Result<PathBuf, Error> {
         match self.filename_format {
             FilenameFormat::Single(overwrite) => {
                 if self.filename.exists() && !overwrite {
-                    Err(format_err!(
+                    Err(RogcatError::Config(format!(
                         "{} exists. Use overwrite flag to force!",
                         self.filename.display()
                     ))
+                    .into())
                 } else {
+                    let dir = self.filename.parent().unwrap_or_else(|| Path::new(""));
+                    if !dir.as_os_str().is_empty() && !dir.is_dir() {
+                        DirBuilder::new().recursive(true).create(dir).map_err(|e| {
+                            RogcatError::Io(format!(
+                                "Failed to create outfile parent directory {}: {}",
+                                dir.display(),
+                                e
+                            ))
+                        })?;
+                    }
                     Ok(self.filename.clone())
                 }
             }
             FilenameFormat::Enumerate(_overwrite, _) => {
                 if self.filename.as_path().is_dir() {
-                    return Err(format_err!(
+                    return Err(RogcatError::Config(format!(
                         "Output file {} is a directory",
                         self.filename.display()
-                    ));
+                    ))
+                    .into());
                 }
 
                 let dir = self.filename.parent().unwrap_or_else(|| Path::new(""));
                 if !dir.is_dir() {
                     DirBuilder::new().recursive(true).create(dir).map_err(|e| {
-                        format_err!("Failed to create outfile parent directory: {:?}", e)
+                        RogcatError::Io(format!(
+                            "Failed to create outfile parent directory: {:?}",
+                            e
+                        ))
                     })?
                 }
 
@@ -215,9 +524,9 @@ impl<'a, T: Writer> FileWriter<T> {
                     name = PathBuf::from(format!(
                         "{}-{:03}",
                         name.file_stem()
-                            .ok_or_else(|| err_msg("Invalid path"))?
+                            .ok_or_else(|| RogcatError::Config("Invalid path".to_owned()))?
                             .to_str()
-                            .ok_or_else(|| err_msg("Invalid path"))?,
+                            .ok_or_else(|| RogcatError::Config("Invalid path".to_owned()))?,
                         index
                     ));
                     if let Some(extension) = self.filename.extension() {
@@ -245,11 +554,11 @@ impl<'a, T: Writer> FileWriter<T> {
                     let dir = self.filename.parent().unwrap_or_else(|| Path::new(""));
                     if !dir.is_dir() {
                         DirBuilder::new().recursive(true).create(dir).map_err(|e| {
-                            format_err!(
+                            RogcatError::Io(format!(
                                 "Failed to create outfile parent directory {}: {}",
                                 dir.display(),
                                 e
-                            )
+                            ))
                         })?;
                     }
 
@@ -260,9 +569,9 @@ impl<'a, T: Writer> FileWriter<T> {
                     let filename = self
                         .filename
                         .file_name()
-                        .ok_or_else(|| err_msg("Invalid path"))?
+                        .ok_or_else(|| RogcatError::Config("Invalid path".to_owned()))?
                         .to_str()
-                        .ok_or_else(|| err_msg("Invalid path"))?;
+                        .ok_or_else(|| RogcatError::Config("Invalid path".to_owned()))?;
                     let candidate = PathBuf::from(format!("{now}{enumeration}_{filename}"));
                     let candidate = dir.join(candidate);
                     if !overwrite && candidate.exists() {
@@ -284,7 +593,10 @@ impl<'a, T: Writer> FileWriter<T> {
             }
             None => {
                 self.current_filename = self.next_file()?;
-                let mut writer = T::with_file_format(&self.current_filename, &self.format)?;
+                if self.index > 0 {
+                    internal::emit(format!("Rotating to {}", self.current_filename.display()));
+                }
+                let mut writer = (self.create)(&self.current_filename)?;
                 let message = format!("Writing {}", self.current_filename.display());
                 self.progress.set_message(&message);
                 writer.write(record, self.index)?;
@@ -312,8 +624,6 @@ impl<'a, T: Writer> FileWriter<T> {
         if let Some(ref mut writer) = self.writer {
             writer.flush()?;
         }
-        self.progress
-            .set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
         self.progress
             .finish_with_message(&format!("Dumped {} records", self.index));
         self.file_size = 0;
@@ -327,6 +637,10 @@ impl<T: Writer> Sink for FileWriter<T> {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        // Invalid timestamps are written as missing
+        if let Some(timestamp) = record.timestamp.as_ref().filter(|t| !t.is_valid()) {
+            utils::invalid_timestamp(timestamp);
+        }
         self.write(&record).map(|_| AsyncSink::Ready)
     }
 
@@ -336,32 +650,209 @@ impl<T: Writer> Sink for FileWriter<T> {
 }
 
 mod html {
-    use super::Writer;
+    use super::{create_file, Writer};
     use crc::{crc32, Hasher32};
     use failure::{format_err, Error};
     use handlebars::{
         to_json, Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext,
         RenderError,
     };
-    use rogcat::record::{Format, Record};
+    use rogcat::record::{Level, Record, Timestamp};
     use serde::Serialize;
     use serde_json::value::{Map, Value as Json};
     use std::{
+        collections::HashMap,
+        fmt::Write as _,
         fs::File,
-        path::{Path, PathBuf},
-        str,
+        io::{BufWriter, Write},
+        path::Path,
     };
 
+    /// Number of records per embedded page
+    const PAGE_RECORDS: usize = 1000;
+    /// Number of tags listed in the summary
+    const TOP_TAGS: usize = 10;
+    /// Number of errors linked in the summary
+    const FIRST_ERRORS: usize = 10;
+    /// Maximum number of bars of the rate chart. Longer captures use buckets of several minutes.
+    const CHART_BARS: i64 = 720;
+    const CHART_BAR_WIDTH: i64 = 2;
+    const CHART_HEIGHT: u64 = 60;
+
+    /// Record as embedded in the page: index, timestamp, tags, process, process name,
+    /// thread, level, message, annotations and source
+    #[derive(Serialize)]
+    struct Row<'a>(
+        usize,
+        Option<&'a Timestamp>,
+        String,
+        &'a str,
+        Option<&'a str>,
+        &'a str,
+        String,
+        &'a str,
+        &'a [String],
+        Option<&'a str>,
+    );
+
+    #[derive(Serialize)]
+    struct Count {
+        name: String,
+        /// Level letter used as css class
+        class: String,
+        count: usize,
+    }
+
+    /// Link to a record of the table
     #[derive(Serialize)]
-    struct HtmlRecord {
+    struct Jump {
         index: usize,
-        record: Record,
+        class: String,
+        tag: String,
+        message: String,
+    }
+
+    /// Triage header of the report
+    #[derive(Serialize)]
+    struct SummaryView {
+        records: usize,
+        levels: Vec<Count>,
+        tags: Vec<Count>,
+        /// Inline svg of the records per minute
+        chart: Option<String>,
+        /// First error, fatal and assert records
+        errors: Vec<Jump>,
+        /// Number of embedded pages
+        pages: usize,
+    }
+
+    /// Counts collected while the records are written
+    #[derive(Default)]
+    struct Summary {
+        records: usize,
+        levels: Vec<(Level, usize)>,
+        tags: HashMap<String, usize>,
+        /// Records per minute since the epoch
+        minutes: HashMap<i64, u64>,
+        /// Minute and time of the first valid timestamp
+        first: Option<(i64, time::Tm)>,
+        errors: Vec<Jump>,
+    }
+
+    impl Summary {
+        fn add(&mut self, index: usize, record: &Record) {
+            self.records += 1;
+            match self.levels.iter_mut().find(|(l, _)| *l == record.level) {
+                Some((_, count)) => *count += 1,
+                None => self.levels.push((record.level.clone(), 1)),
+            }
+            for tag in &record.tags {
+                match self.tags.get_mut(tag) {
+                    Some(count) => *count += 1,
+                    None => {
+                        self.tags.insert(tag.clone(), 1);
+                    }
+                }
+            }
+            if let Some(timestamp) = record.timestamp.as_ref().filter(|t| t.is_valid()) {
+                let minute = timestamp.to_timespec().sec.div_euclid(60);
+                *self.minutes.entry(minute).or_default() += 1;
+                if self.first.is_none() {
+                    self.first = Some((minute, timestamp.tm));
+                }
+            }
+            if record.level >= Level::Error && self.errors.len() < FIRST_ERRORS {
+                self.errors.push(Jump {
+                    index,
+                    class: record.level.to_string(),
+                    tag: record.tags.join(" "),
+                    message: record.message.clone(),
+                });
+            }
+        }
+
+        fn view(&mut self, pages: usize) -> SummaryView {
+            let mut levels = self.levels.clone();
+            levels.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            let mut tags = self.tags.iter().collect::<Vec<_>>();
+            tags.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+            SummaryView {
+                records: self.records,
+                levels: levels
+                    .into_iter()
+                    .map(|(level, count)| Count {
+                        name: format!("{level:?}"),
+                        class: level.to_string(),
+                        count,
+                    })
+                    .collect(),
+                tags: tags
+                    .into_iter()
+                    .take(TOP_TAGS)
+                    .map(|(name, count)| Count {
+                        name: name.clone(),
+                        class: String::new(),
+                        count: *count,
+                    })
+                    .collect(),
+                chart: self.chart(),
+                errors: std::mem::take(&mut self.errors),
+                pages,
+            }
+        }
+
+        /// Bar chart of the records per minute from the first to the last timestamp
+        fn chart(&self) -> Option<String> {
+            let (first, start) = self.first?;
+            let last = self.minutes.keys().copied().max()?;
+            let minutes = (last - first) / CHART_BARS + 1;
+
+            let mut bars = vec![0u64; ((last - first) / minutes + 1) as usize];
+            for (m, count) in &self.minutes {
+                if *m >= first {
+                    bars[((m - first) / minutes) as usize] += count;
+                }
+            }
+            let max = bars.iter().copied().max().unwrap_or(1).max(1);
+
+            let mut svg = format!(
+                "<svg class=\"chart\" width=\"{}\" height=\"{}\">",
+                bars.len() as i64 * CHART_BAR_WIDTH,
+                CHART_HEIGHT
+            );
+            for (i, count) in bars.iter().enumerate() {
+                let height = count * CHART_HEIGHT / max;
+                let t = start + time::Duration::minutes(i as i64 * minutes);
+                write!(
+                    svg,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"><title>{:02}-{:02} {:02}:{:02} {:.1} records/min</title></rect>",
+                    i as i64 * CHART_BAR_WIDTH,
+                    CHART_HEIGHT - height,
+                    CHART_BAR_WIDTH,
+                    height,
+                    t.tm_mon + 1,
+                    t.tm_mday,
+                    t.tm_hour,
+                    t.tm_min,
+                    *count as f64 / minutes as f64
+                )
+                .ok()?;
+            }
+            svg.push_str("</svg>");
+            Some(svg)
+        }
     }
 
-    /// Simple static html file
+    /// Single html file. Records are written in pages of embedded json while they arrive and
+    /// rendered by the browser one page at a time. The summary is appended at the end.
     pub struct Html {
-        filename: PathBuf,
-        records: Vec<HtmlRecord>,
+        file: Option<BufWriter<File>>,
+        /// Json rows of the current page
+        page: String,
+        page_records: usize,
+        pages: usize,
+        summary: Summary,
     }
 
     impl Html {
@@ -399,48 +890,107 @@ mod html {
             Ok(())
         }
 
-        fn flush(&mut self) -> Result<(), Error> {
+        /// Write the buffered rows as one page
+        fn write_page(&mut self) -> Result<(), Error> {
+            if self.page_records == 0 {
+                return Ok(());
+            }
+            let file = self
+                .file
+                .as_mut()
+                .ok_or_else(|| format_err!("Html output is finished"))?;
+            writeln!(
+                file,
+                "<script type=\"application/json\" class=\"page\">[{}]</script>",
+                self.page
+            )?;
+            self.page.clear();
+            self.page_records = 0;
+            self.pages += 1;
+            Ok(())
+        }
+
+        /// Write the last page, the summary and the script
+        fn finish(&mut self) -> Result<(), Error> {
+            if self.file.is_none() {
+                return Ok(());
+            }
+            self.write_page()?;
+            let mut file = match self.file.take() {
+                Some(file) => file,
+                None => return Ok(()),
+            };
             let mut hb = Handlebars::new();
             let mut data: Map<String, Json> = Map::new();
-            data.insert("records".to_owned(), to_json(&self.records));
-            let mut output_file = File::create(&self.filename)?;
+            data.insert("summary".to_owned(), to_json(self.summary.view(self.pages)));
             hb.register_helper("color", Box::new(Self::color_helper));
-            hb.register_template_string("t1", HTML_TEMPLATE)?;
-            hb.render_to_write("t1", &data, &mut output_file)
-                .map_err(|e| format_err!("Rednering error: {}", e))
-                .map(|_| ())
+            hb.register_template_string("summary", HTML_SUMMARY)?;
+            hb.render_to_write("summary", &data, &mut file)
+                .map_err(|e| format_err!("Rednering error: {}", e))?;
+            file.write_all(HTML_SCRIPT.as_bytes())?;
+            file.flush().map_err(Into::into)
         }
     }
 
-    impl Writer for Html {
-        fn with_file_format(filename: &Path, _: &Format) -> Result<Html, Error> {
+    impl Html {
+        pub fn create(filename: &Path) -> Result<Html, Error> {
+            let mut file = BufWriter::new(create_file(filename)?);
+            file.write_all(HTML_HEAD.as_bytes())?;
             Ok(Html {
-                filename: filename.to_owned(),
-                records: Vec::new(),
+                file: Some(file),
+                page: String::new(),
+                page_records: 0,
+                pages: 0,
+                summary: Summary::default(),
             })
         }
+    }
 
+    impl Writer for Html {
         fn write(&mut self, record: &Record, index: usize) -> Result<(), Error> {
-            self.records.push(HtmlRecord {
+            self.summary.add(index, record);
+            let row = Row(
                 index,
-                record: record.clone(),
-            });
+                record.timestamp.as_ref(),
+                record.tags.join(" "),
+                &record.process,
+                record.process_name.as_deref(),
+                &record.thread,
+                record.level.to_string(),
+                &record.message,
+                &record.annotations,
+                record.source.as_deref(),
+            );
+            if self.page_records > 0 {
+                self.page.push(',');
+            }
+            // A "</script>" in a message must not end the embedding script
+            self.page
+                .push_str(&serde_json::to_string(&row)?.replace('<', "\\u003c"));
+            self.page_records += 1;
+            if self.page_records == PAGE_RECORDS {
+                self.write_page()?;
+            }
             Ok(())
         }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            self.finish()
+        }
     }
 
     impl Drop for Html {
         fn drop(&mut self) {
-            self.flush().ok();
+            self.finish().ok();
         }
     }
 
-    const HTML_TEMPLATE: &str = r#"
-<!doctype HTML>
+    const HTML_HEAD: &str = r##"<!doctype HTML>
+<meta charset="utf-8">
 <title>Rogcat</title>
 <link href='http://fonts.googleapis.com/css?family=Source+Code+Pro' rel='stylesheet' type='text/css'>
 <style>
-body {background: black; color: #BBBBBB; font-family: 'Source Code Pro', Monaco, monospace; font-size: 12px}
+body {background: black; color: #BBBBBB; font-family: 'Source Code Pro', Monaco, monospace; font-size: 12px; display: flex; flex-direction: column}
 .green, .I {color: #A8FF60}
 .white {color: #EEEEEE}
 .red, .E, .A, .F {color: #FF6C60}
@@ -449,7 +999,8 @@ body {background: black; color: #BBBBBB; font-family: 'Source Code Pro', Monaco,
 .blue {color: #96CBFE}
 .cyan {color: #C6C5FE}
 .magenta {color: #FF73FD}
-tr.hover { background: #260041 }
+.annotation {color: #4F4F4F}
+a, a.annotation {color: #96CBFE}
 table {
     border-spacing: 0;
     width: 100%;
@@ -461,9 +1012,12 @@ td {
     padding-right: 2ex;
     white-space: nowrap;
 }
-tr:hover {
+tr:hover, tr.target {
     color: yellow;
 }
+tr.target {
+    background: #260041;
+}
 td.level-D {
     color: white;
     background: #555;
@@ -476,44 +1030,217 @@ td.level-W {
     color: black;
     background: #FFFFB6;
 }
-td.level-E {
+td.level-E, td.level-A, td.level-F {
     color: black;
     background: #FF6C60;
 }
-td.level-A {
-    color: black;
-    background: #FF6C60;
+#records tr td:first-child + td + td, #records tr td:first-child + td + td + td + td {
+    text-align: right
 }
-td.level-F {
-    color: black;
-    background: #FF6C60;
+.controls {
+    display: flex;
+    gap: 2ex;
+    align-items: center;
+    margin-bottom: 1em;
 }
-table tr td:first-child + td + td {
-    text-align: right
+input, button {
+    background: #222;
+    color: #BBBBBB;
+    border: 1px solid #4F4F4F;
+    font-family: inherit;
 }
-table tr td:first-child + td + td + td {
+.summary {
+    order: -1;
+    display: flex;
+    flex-wrap: wrap;
+    gap: 4ex;
+    margin-bottom: 2em;
 }
-table tr td:first-child + td + td + td + td {
-    text-align: right
+.summary table {
+    width: auto;
+}
+.summary td {
+    padding-left: 0;
+}
+.summary tr.toggle, .summary tr.tag {
+    cursor: pointer;
 }
-table tr td:first-child + td + td + td + td + td {
+.summary tr.off {
+    opacity: 0.3;
+}
+.chart rect {
+    fill: #96CBFE;
+}
+.chart rect:hover {
+    fill: yellow;
 }
 </style>
 
-<table>
+<div class="controls">
+<input id="search" type="search" placeholder="Search" size="40">
+<input id="tag" type="search" placeholder="Tag" size="20">
+<button id="first">&laquo;</button>
+<button id="prev">&lsaquo;</button>
+<span id="position"></span>
+<button id="next">&rsaquo;</button>
+<button id="last">&raquo;</button>
+</div>
+<table id="records"></table>
+"##;
 
-{{#each records as |t| ~}}
-    <tr>
-    <td>{{t.index}}</td>
-    <td>{{t.record.timestamp}}</td>
-    <td><a>{{color t.record.tag}}</a></td>
-    <td>{{color t.record.process}}</td>
-    <td>{{color t.record.thread}}</td>
-    <td class="level-{{t.record.level}}">{{t.record.level}}</td>
-    <td>{{t.record.message}}</td>
-    </tr>
+    const HTML_SUMMARY: &str = r##"
+<div class="summary">
+<div>
+<h3>Levels</h3>
+<table>
+{{#each summary.levels as |l| ~}}
+<tr class="toggle" data-level="{{l.class}}" title="Show or hide {{l.name}}"><td class="{{l.class}}">{{l.name}}</td><td>{{l.count}}</td></tr>
+{{/each~}}
+<tr><td>Total</td><td>{{summary.records}}</td></tr>
+</table>
+</div>
+<div>
+<h3>Top tags</h3>
+<table>
+{{#each summary.tags as |t| ~}}
+<tr class="tag" data-tag="{{t.name}}" title="Filter by {{t.name}}"><td>{{color t.name}}</td><td>{{t.count}}</td></tr>
+{{/each~}}
+</table>
+</div>
+{{#if summary.chart ~}}
+<div>
+<h3>Records per minute</h3>
+{{{summary.chart}}}
+</div>
+{{/if~}}
+{{#if summary.errors ~}}
+<div>
+<h3>First errors</h3>
+<table>
+{{#each summary.errors as |e| ~}}
+<tr><td><a href="#r{{e.index}}">#{{e.index}}</a></td><td class="{{e.class}}">{{e.tag}}: {{e.message}}</td></tr>
 {{/each~}}
-
 </table>
-"#;
+</div>
+{{/if~}}
+</div>
+"##;
+
+    const HTML_SCRIPT: &str = r##"
+<script>
+(() => {
+  const PAGE = 1000;
+  const sources = Array.from(document.querySelectorAll("script.page"));
+  const parsed = [];
+  const page = n => parsed[n] || (parsed[n] = JSON.parse(sources[n].textContent));
+  const state = {page: 0, search: "", tag: "", hidden: new Set(), filtered: null};
+
+  const escape = s => String(s).replace(/[&<>"]/g, c => ({"&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;"})[c]);
+  const CRC = Array.from({length: 256}, (_, n) => {
+    for (let k = 0; k < 8; k++) n = n & 1 ? 0xEDB88320 ^ (n >>> 1) : n >>> 1;
+    return n >>> 0;
+  });
+  const colors = new Map();
+  const color = v => {
+    if (v === "" || v === "0") return '<span style="color:grey">' + escape(v) + "</span>";
+    let c = colors.get(v);
+    if (!c) {
+      let h = 0xFFFFFFFF;
+      for (const b of new TextEncoder().encode(v)) h = CRC[(h ^ b) & 0xFF] ^ (h >>> 8);
+      h = (h ^ 0xFFFFFFFF) >>> 0;
+      const hex = n => n.toString(16).padStart(2, "0");
+      c = "#" + hex(h & 0xFF) + hex((h >>> 8) & 0xFF) + hex((h >>> 16) & 0xFF);
+      colors.set(v, c);
+    }
+    return '<span style="color:' + c + '">' + escape(v) + "</span>";
+  };
+  const annotation = a => /^https?:\/\//.test(a)
+    ? ' <a class="annotation" href="' + escape(a) + '">' + escape(a) + "</a>"
+    : ' <span class="annotation">' + escape(a) + "</span>";
+  const row = ([index, timestamp, tag, process, name, thread, level, message, annotations, source]) =>
+    '<tr id="r' + index + '"><td>' + index + "</td><td>" +
+    (source ? '<span style="color:grey">' + escape(source) + "</span> " : "") + escape(timestamp || "") + "</td><td>" + color(tag) +
+    "</td><td>" + color(process) + (name ? " " + escape(name) : "") + "</td><td>" + color(thread) +
+    '</td><td class="level-' + level + '">' + level + "</td><td>" + escape(message) + annotations.map(annotation).join("") +
+    "</td></tr>";
+
+  const filtering = () => state.search || state.tag || state.hidden.size;
+  const filtered = () => {
+    if (!state.filtered) {
+      const search = state.search.toLowerCase();
+      const tag = state.tag.toLowerCase();
+      state.filtered = sources.flatMap((_, n) => page(n)).filter(r =>
+        !state.hidden.has(r[6]) &&
+        (!tag || r[2].toLowerCase().includes(tag)) &&
+        (!search || r[7].toLowerCase().includes(search) || r[2].toLowerCase().includes(search)));
+    }
+    return state.filtered;
+  };
+  const pages = () => filtering() ? Math.max(1, Math.ceil(filtered().length / PAGE)) : Math.max(1, sources.length);
+  const rows = () => filtering() ? filtered().slice(state.page * PAGE, (state.page + 1) * PAGE) : (sources.length ? page(state.page) : []);
+
+  const render = () => {
+    state.page = Math.min(Math.max(0, state.page), pages() - 1);
+    document.getElementById("records").innerHTML = rows().map(row).join("");
+    const count = filtering() ? " (" + filtered().length + " matching)" : "";
+    document.getElementById("position").textContent = "Page " + (state.page + 1) + " of " + pages() + count;
+  };
+  const refilter = () => {
+    state.filtered = null;
+    state.page = 0;
+    render();
+  };
+
+  // Show the page of a record linked with #r<index>
+  const jump = () => {
+    const target = /^#r(\d+)$/.exec(location.hash);
+    if (!target || !sources.length) return;
+    const index = Number(target[1]);
+    let position = filtering() ? filtered().findIndex(r => r[0] === index) : -1;
+    if (position < 0) {
+      state.search = state.tag = "";
+      state.hidden.clear();
+      document.getElementById("search").value = document.getElementById("tag").value = "";
+      document.querySelectorAll(".summary tr.toggle").forEach(t => t.classList.remove("off"));
+      state.filtered = null;
+      position = index - page(0)[0][0];
+    }
+    state.page = Math.floor(position / PAGE);
+    render();
+    const tr = document.getElementById("r" + index);
+    if (tr) {
+      document.querySelectorAll("tr.target").forEach(t => t.classList.remove("target"));
+      tr.classList.add("target");
+      tr.scrollIntoView({block: "center"});
+    }
+  };
+
+  let typing;
+  const input = (id, key) => document.getElementById(id).addEventListener("input", e => {
+    clearTimeout(typing);
+    typing = setTimeout(() => { state[key] = e.target.value; refilter(); }, 300);
+  });
+  input("search", "search");
+  input("tag", "tag");
+  const step = f => () => { state.page = f(state.page); render(); };
+  document.getElementById("first").onclick = step(() => 0);
+  document.getElementById("prev").onclick = step(p => p - 1);
+  document.getElementById("next").onclick = step(p => p + 1);
+  document.getElementById("last").onclick = step(() => pages() - 1);
+  document.querySelectorAll(".summary tr.toggle").forEach(tr => tr.onclick = () => {
+    const level = tr.dataset.level;
+    if (state.hidden.has(level)) state.hidden.delete(level); else state.hidden.add(level);
+    tr.classList.toggle("off");
+    refilter();
+  });
+  document.querySelectorAll(".summary tr.tag").forEach(tr => tr.onclick = () => {
+    document.getElementById("tag").value = state.tag = tr.dataset.tag;
+    refilter();
+  });
+  window.addEventListener("hashchange", jump);
+  render();
+  jump();
+})();
+</script>
+"##;
 }
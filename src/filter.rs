@@ -18,99 +18,351 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::profiles::Profile;
+use crate::{error::RogcatError, profiles::Profile, utils::parse_number};
+use aho_corasick::AhoCorasick;
 use clap::ArgMatches;
 use failure::Error;
-use regex::{RegexSet, RegexSetBuilder};
-use rogcat::record::{Level, Record};
+use lazy_static::lazy_static;
+use regex::{Regex, RegexSet};
+use rogcat::record::{self, Record, Timestamp};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::read_to_string,
+    iter::once,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-/// Configured filters
-#[derive(Debug)]
+lazy_static! {
+    /// Filters added and removed at runtime in the interactive mode
+    static ref LIVE: RwLock<Live> = RwLock::new(Live::default());
+}
+
+/// Maximum nesting of `include` in filter files
+const FILTER_FILE_DEPTH: usize = 16;
+
+/// Time after the first record to warn if all records were filtered out
+const ALL_FILTERED_WARNING_DELAY: Duration = Duration::from_secs(10);
+
+/// Configured filters. Clones share the rejection counts.
+#[derive(Clone, Debug)]
 pub struct Filter {
-    level: Option<Level>,
-    has_positive: bool,
-    has_negative: bool,
-    filter: FilterSet,
-    filter_case_insensitive: FilterSet,
-    message: FilterSet,
-    message_case_insensitive: FilterSet,
-    tag: FilterSet,
-    tag_case_insensitive: FilterSet,
-}
-
-pub fn from_args_profile(args: &ArgMatches, profile: &Profile) -> Result<Filter, Error> {
+    /// Minimum severity per tag. The tag "*" applies to all other tags.
+    levels: HashMap<String, u32>,
+    patterns: Patterns,
+    pid: IdSet,
+    tid: IdSet,
+    can_id: IdSet,
+    source: SourceSet,
+    buffer: SourceSet,
+    fields: FieldSet,
+    window: TimeWindow,
+    stats: Arc<FilterStats>,
+}
+
+/// Number of records rejected per filter
+#[derive(Debug, Default)]
+pub struct FilterStats {
+    total: AtomicUsize,
+    level: AtomicUsize,
+    id: AtomicUsize,
+    source: AtomicUsize,
+    time: AtomicUsize,
+    unmatched: AtomicUsize,
+    excluded: AtomicUsize,
+    /// Time of the first record
+    first: Mutex<Option<Instant>>,
+    warned: AtomicBool,
+    /// Number of configured filters per kind
+    configured: String,
+    /// Print the rejections at the end
+    explain: bool,
+}
+
+/// Describe the number of configured filters per kind e.g "2 tag, 1 message"
+fn configured(
+    args: &ArgMatches,
+    profile: &Profile,
+    levels: &HashMap<String, u32>,
+    exact_tags: usize,
+    filter_files: &FilterFiles,
+) -> String {
+    let count = |arg: &str, profile: &[String]| {
+        args.values_of(arg).map_or(0, |v| v.count()) + profile.len()
+    };
+    let mut configured = [
+        ("filter", count("filter", &profile.filter)),
+        (
+            "case insensitive filter",
+            count("filter-case-insensitive", &profile.filter_case_insensitive),
+        ),
+        ("tag", count("tag", &profile.tag)),
+        (
+            "case insensitive tag",
+            count("tag-case-insensitive", &profile.tag_case_insensitive),
+        ),
+        ("exact tag", exact_tags),
+        (
+            "filter file",
+            filter_files.filter.len() + filter_files.tag.len() + filter_files.message.len(),
+        ),
+        ("message", count("message", &profile.message)),
+        (
+            "case insensitive message",
+            count(
+                "message-case-insensitive",
+                &profile.message_case_insensitive,
+            ),
+        ),
+        ("exact message", count("msg-exact", &[])),
+        ("pid", count("pid", &profile.pid)),
+        ("tid", count("tid", &profile.tid)),
+        ("can id", count("can-id", &[])),
+        ("source", count("source", &[])),
+        ("buffer", count("buffer-filter", &[])),
+        ("field", count("field", &[])),
+        ("level", levels.len()),
+        (
+            "time window",
+            usize::from(args.is_present("since") || args.is_present("until")),
+        ),
+    ]
+    .iter()
+    .filter(|(_, n)| *n > 0)
+    .map(|(kind, n)| format!("{n} {kind}"))
+    .collect::<Vec<_>>();
+    if configured.is_empty() {
+        configured.push("none".into());
+    }
+    configured.join(", ")
+}
+
+/// Tags of `--tag-exact` and the lines of `--tags-from`
+fn exact_tags(args: &ArgMatches) -> Result<Vec<String>, Error> {
+    let mut tags = args
+        .values_of("tag-exact")
+        .unwrap_or_default()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    if let Some(file) = args.value_of("tags-from") {
+        let content = read_to_string(file)
+            .map_err(|e| RogcatError::Config(format!("Failed to read {}: {}", file, e)))?;
+        tags.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(ToOwned::to_owned),
+        );
+    }
+    Ok(tags)
+}
+
+/// Replace tag patterns like `group:NAME` with a pattern matching the tags of the group
+fn tag_groups<'a, I: Iterator<Item = &'a str>>(
+    patterns: I,
+    profile: &Profile,
+) -> Result<Vec<String>, Error> {
+    patterns
+        .map(|pattern| {
+            let (negation, name) = match pattern.strip_prefix('!') {
+                Some(pattern) => ("!", pattern),
+                None => ("", pattern),
+            };
+            match name.strip_prefix("group:") {
+                Some(name) => {
+                    let tags = profile.tag_groups.get(name).ok_or_else(|| {
+                        RogcatError::Config(format!("Unknown tag group {}", name))
+                    })?;
+                    let tags = tags.iter().map(|t| regex::escape(t)).collect::<Vec<_>>();
+                    Ok(format!("{}^(?:{})$", negation, tags.join("|")))
+                }
+                None => Ok(pattern.to_owned()),
+            }
+        })
+        .collect()
+}
+
+/// Patterns read from filter files
+#[derive(Default)]
+struct FilterFiles {
+    filter: Vec<String>,
+    tag: Vec<String>,
+    message: Vec<String>,
+}
+
+impl FilterFiles {
+    /// Read `--filter-file` and the filter files of `profile`
+    fn new(args: &ArgMatches, profile: &Profile) -> Result<FilterFiles, Error> {
+        let mut files = FilterFiles::default();
+        for file in args
+            .values_of("filter-file")
+            .unwrap_or_default()
+            .chain(profile.filter_files.iter().map(String::as_str))
+        {
+            files.read(Path::new(file), 0)?;
+        }
+        Ok(files)
+    }
+
+    /// Read one pattern per line. Patterns are prefixed with `tag:` or `message:` to apply
+    /// to one field only and a leading `!` excludes. Empty lines and lines starting with `#`
+    /// are skipped. `include FILE` reads `FILE` relative to the including file.
+    fn read(&mut self, file: &Path, depth: usize) -> Result<(), Error> {
+        if depth > FILTER_FILE_DEPTH {
+            return Err(RogcatError::Config(format!(
+                "Reached include limit while reading {}",
+                file.display()
+            ))
+            .into());
+        }
+        let content = read_to_string(file).map_err(|e| {
+            RogcatError::Config(format!("Failed to read {}: {}", file.display(), e))
+        })?;
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(include) = line.strip_prefix("include ") {
+                let dir = file.parent().unwrap_or_else(|| Path::new("."));
+                self.read(&dir.join(include.trim()), depth + 1)?;
+                continue;
+            }
+            let (line, negation) = match line.strip_prefix('!') {
+                Some(line) => (line, "!"),
+                None => (line, ""),
+            };
+            let (patterns, pattern) = match line.split_once(':') {
+                Some(("tag", pattern)) => (&mut self.tag, pattern),
+                Some(("message", pattern)) => (&mut self.message, pattern),
+                _ => (&mut self.filter, line),
+            };
+            patterns.push(format!("{negation}{pattern}"));
+        }
+        Ok(())
+    }
+}
+
+pub fn from_args_profile<'a>(args: &'a ArgMatches, profile: &'a Profile) -> Result<Filter, Error> {
     // Level is filtered by ffx in case of fuchsia.
     let level = (!args.is_present("fuchsia"))
-        .then(|| args.value_of("level").map(Level::from))
+        .then(|| args.value_of("level"))
         .flatten();
+    let severity = |level: &str| {
+        record::severity(level)
+            .ok_or_else(|| RogcatError::Config(format!("Unknown level {}", level)))
+    };
+    // The level given on the command line overrules the profile wildcard
+    let mut levels = profile
+        .levels
+        .iter()
+        .map(|(tag, level)| Ok((tag.clone(), severity(level)?)))
+        .collect::<Result<HashMap<_, _>, Error>>()?;
+    if let Some(level) = level {
+        levels.insert("*".to_owned(), severity(level)?);
+    }
 
-    let filter = args
-        .values_of("filter")
-        .unwrap_or_default()
-        .chain(profile.filter.iter().map(String::as_str))
-        .collect::<Vec<_>>();
-    let filter_case_insensitive = args
-        .values_of("filter-case-insensitive")
-        .unwrap_or_default()
-        .chain(profile.filter_case_insensitive.iter().map(String::as_str))
-        .collect::<Vec<_>>();
+    let exact_tags = exact_tags(args)?;
+    let filter_files = FilterFiles::new(args, profile)?;
+    let stats = Arc::new(FilterStats {
+        configured: configured(args, profile, &levels, exact_tags.len(), &filter_files),
+        explain: args.is_present("explain"),
+        ..Default::default()
+    });
 
-    let tag = args
-        .values_of("tag")
-        .unwrap_or_default()
-        .chain(filter.iter().copied()) // Include the filter pattern as tag filter
-        .chain(profile.filter.iter().map(String::as_str)) // Include the filter pattern from the profile as tag filter
-        .chain(profile.tag.iter().map(String::as_str));
-    let tag_case_insensitive = args
-        .values_of("tag-case-insensitive")
-        .unwrap_or_default()
-        .chain(filter_case_insensitive.iter().copied()) // Include the filter pattern as tag filter
-        .chain(profile.filter_case_insensitive.iter().map(String::as_str)) // Include the filter pattern from the profile as tag filter
-        .chain(profile.tag_case_insensitive.iter().map(String::as_str));
+    let values = |arg: &str, profile: &'a [String]| {
+        args.values_of(arg)
+            .unwrap_or_default()
+            .chain(profile.iter().map(String::as_str))
+    };
+    let mut patterns = PatternsBuilder::default();
+    // Filter patterns apply to the tag, message, pid, tid and process name
+    patterns.add(values("filter", &profile.filter), false, FIELD_ALL);
+    patterns.add(
+        values("filter-case-insensitive", &profile.filter_case_insensitive),
+        true,
+        FIELD_ALL,
+    );
+    let tag = tag_groups(values("tag", &profile.tag), profile)?;
+    patterns.add(tag.iter().map(String::as_str), false, FIELD_TAG);
+    let tag_case_insensitive = tag_groups(
+        values("tag-case-insensitive", &profile.tag_case_insensitive),
+        profile,
+    )?;
+    patterns.add(
+        tag_case_insensitive.iter().map(String::as_str),
+        true,
+        FIELD_TAG,
+    );
+    patterns.add(values("message", &profile.message), false, FIELD_MESSAGE);
+    patterns.add(
+        values(
+            "message-case-insensitive",
+            &profile.message_case_insensitive,
+        ),
+        true,
+        FIELD_MESSAGE,
+    );
+    patterns.add(
+        filter_files.filter.iter().map(String::as_str),
+        false,
+        FIELD_ALL,
+    );
+    let filter_files_tag = tag_groups(filter_files.tag.iter().map(String::as_str), profile)?;
+    patterns.add(
+        filter_files_tag.iter().map(String::as_str),
+        false,
+        FIELD_TAG,
+    );
+    patterns.add(
+        filter_files.message.iter().map(String::as_str),
+        false,
+        FIELD_MESSAGE,
+    );
+    // Literals are not compiled into the regex set
+    patterns.add_exact(exact_tags.iter().map(String::as_str), FIELD_TAG);
+    patterns.add_exact(
+        args.values_of("msg-exact").unwrap_or_default(),
+        FIELD_MESSAGE,
+    );
+    let patterns = patterns.build()?;
 
-    let message = args
-        .values_of("message")
-        .unwrap_or_default()
-        .chain(filter.iter().copied()) // Include the filter pattern as message filter
-        .chain(profile.filter.iter().map(String::as_str)) // Include the filter pattern from the profile as message filter
-        .chain(profile.message.iter().map(String::as_str));
-    let message_case_insensitive = args
-        .values_of("message-case-insensitive")
-        .unwrap_or_default()
-        .chain(filter_case_insensitive.iter().copied()) // Include the filter pattern as tag filter
-        .chain(profile.filter_case_insensitive.iter().map(String::as_str)) // Include the filter pattern from the profile as tag filter
-        .chain(profile.message_case_insensitive.iter().map(String::as_str));
-
-    let filter = FilterSet::new(filter.iter().copied(), true)?;
-    let filter_case_insensitive = FilterSet::new(filter_case_insensitive.iter().copied(), false)?;
-    let tag = FilterSet::new(tag, true)?;
-    let tag_case_insensitive = FilterSet::new(tag_case_insensitive, false)?;
-    let message = FilterSet::new(message, true)?;
-    let message_case_insensitive = FilterSet::new(message_case_insensitive, false)?;
-
-    let has_positive = filter.has_positive()
-        || filter_case_insensitive.has_positive()
-        || tag.has_positive()
-        || tag_case_insensitive.has_positive()
-        || message.has_positive()
-        || message_case_insensitive.has_positive();
-    let has_negative = filter.has_negative()
-        || filter_case_insensitive.has_negative()
-        || tag.has_negative()
-        || tag_case_insensitive.has_negative()
-        || message.has_negative()
-        || message_case_insensitive.has_negative();
+    let pid = IdSet::new(
+        args.values_of("pid")
+            .unwrap_or_default()
+            .chain(profile.pid.iter().map(String::as_str)),
+    )?;
+    let tid = IdSet::new(
+        args.values_of("tid")
+            .unwrap_or_default()
+            .chain(profile.tid.iter().map(String::as_str)),
+    )?;
+
+    let can_id = IdSet::new(args.values_of("can-id").unwrap_or_default())?;
+    let source = SourceSet::new(args.values_of("source").unwrap_or_default())?;
+    let buffer = SourceSet::new(args.values_of("buffer-filter").unwrap_or_default())?;
+    let fields = FieldSet::new(args.values_of("field").unwrap_or_default())?;
+
+    let window = TimeWindow {
+        since: args.value_of("since").map(TimeBound::new).transpose()?,
+        until: args.value_of("until").map(TimeBound::new).transpose()?,
+    };
 
     let filter = Filter {
-        level,
-        has_positive,
-        has_negative,
-        filter,
-        filter_case_insensitive,
-        message,
-        message_case_insensitive,
-        tag,
-        tag_case_insensitive,
+        levels,
+        patterns,
+        pid,
+        tid,
+        can_id,
+        source,
+        buffer,
+        fields,
+        window,
+        stats,
     };
 
     Ok(filter)
@@ -118,105 +370,774 @@ pub fn from_args_profile(args: &ArgMatches, profile: &Profile) -> Result<Filter,
 
 impl Filter {
     pub fn filter(&self, record: &Record) -> bool {
-        if let Some(ref level) = self.level {
-            if record.level < *level {
-                return false;
+        let rejection = self.rejection(record);
+        self.stats.add(rejection);
+        rejection.is_none()
+    }
+
+    /// True if `record` passes without counting it in the rejection stats
+    pub fn matches(&self, record: &Record) -> bool {
+        self.rejection(record).is_none()
+    }
+
+    /// Handle to the rejection counts
+    pub fn stats(&self) -> Arc<FilterStats> {
+        self.stats.clone()
+    }
+
+    /// Rebuild the filters from `args` and a reloaded `profile`. The rejection counts are kept.
+    pub fn reload(&mut self, args: &ArgMatches, profile: &Profile) -> Result<(), Error> {
+        let stats = self.stats.clone();
+        *self = from_args_profile(args, profile)?;
+        self.stats = stats;
+        Ok(())
+    }
+
+    /// Counter of the filter that rejects `record` if any
+    fn rejection(&self, record: &Record) -> Option<Rejection> {
+        let level = record
+            .tags
+            .iter()
+            .find_map(|tag| self.levels.get(tag))
+            .or_else(|| self.levels.get("*"));
+        if let Some(level) = level {
+            if record.severity() < *level {
+                return Some(Rejection::Level);
             }
         }
 
-        if self.has_positive || self.has_negative {
-            let positive = !self.has_positive || self.matches_positive(record);
-            let negative = self.has_negative && self.matches_negative(record);
-            positive && !negative
+        if !self.pid.matches(&record.process)
+            || !self.tid.matches(&record.thread)
+            || !self.can_id.contains(record.can_id.map(u64::from))
+        {
+            return Some(Rejection::Id);
+        }
+
+        if !self.source.matches(record.source.as_deref())
+            || !self.buffer.matches(record.buffer.as_deref())
+        {
+            return Some(Rejection::Source);
+        }
+
+        if !self.window.contains(record.timestamp.as_ref()) {
+            return Some(Rejection::Time);
+        }
+
+        match self.fields.matches(&record.fields) {
+            (false, _) => return Some(Rejection::Unmatched),
+            (_, true) => return Some(Rejection::Excluded),
+            _ => (),
+        }
+
+        let (positive, negative) = self.patterns.matches(record);
+        if !positive {
+            Some(Rejection::Unmatched)
+        } else if negative {
+            Some(Rejection::Excluded)
         } else {
-            true
-        }
-    }
-
-    fn matches_positive(&self, record: &Record) -> bool {
-        self.filter.match_positive(&record.process)
-            || self.filter.match_positive(&record.thread)
-            || self.filter_case_insensitive.match_positive(&record.process)
-            || self.filter_case_insensitive.match_positive(&record.thread)
-            || self.tag.match_positive_iter(record.tags.iter())
-            || self
-                .tag_case_insensitive
-                .match_positive_iter(record.tags.iter())
-            || self.message.match_positive(&record.message)
-            || self
-                .message_case_insensitive
-                .match_positive(&record.message)
-    }
-
-    fn matches_negative(&self, record: &Record) -> bool {
-        self.filter.match_negative(&record.process)
-            || self.filter.match_negative(&record.thread)
-            || self.filter_case_insensitive.match_negative(&record.process)
-            || self.filter_case_insensitive.match_negative(&record.thread)
-            || self.tag.match_negative_iter(record.tags.iter())
-            || self
-                .tag_case_insensitive
-                .match_negative_iter(record.tags.iter())
-            || self.message.match_negative(&record.message)
-            || self
-                .message_case_insensitive
-                .match_negative(&record.message)
-    }
-}
-
-#[derive(Debug)]
-struct FilterSet {
-    positive: RegexSet,
-    negative: RegexSet,
-}
-
-impl FilterSet {
-    fn new<'a, T: Iterator<Item = &'a str>>(
-        regex: T,
-        case_sensitive: bool,
-    ) -> Result<FilterSet, Error> {
+            live_rejection(record)
+        }
+    }
+}
+
+/// Filters edited at runtime
+#[derive(Default)]
+struct Live {
+    filters: Vec<String>,
+    patterns: Option<Patterns>,
+}
+
+/// Add the live filter `filter` or remove it if present. Filters are `tag:REGEX`,
+/// `message:REGEX` or a `REGEX` matched like `--filter`. A leading `!` excludes matches.
+pub fn toggle_live(filter: &str) -> Result<(), Error> {
+    let mut live = LIVE.write().expect("Failed to get live filter lock");
+    let mut filters = live.filters.clone();
+    match filters.iter().position(|f| f == filter) {
+        Some(index) => {
+            filters.remove(index);
+        }
+        None => filters.push(filter.to_owned()),
+    }
+
+    let mut patterns = PatternsBuilder::default();
+    for filter in &filters {
+        let (pattern, fields) = match filter.split_once(':') {
+            Some(("tag", pattern)) => (pattern, FIELD_TAG),
+            Some(("message", pattern)) => (pattern, FIELD_MESSAGE),
+            _ => (filter.as_str(), FIELD_ALL),
+        };
+        patterns.add(once(pattern), false, fields);
+    }
+    live.patterns = if filters.is_empty() {
+        None
+    } else {
+        Some(patterns.build()?)
+    };
+    live.filters = filters;
+    Ok(())
+}
+
+/// Filters added at runtime
+pub fn live_filters() -> Vec<String> {
+    LIVE.read()
+        .expect("Failed to get live filter lock")
+        .filters
+        .clone()
+}
+
+/// True if `record` passes the filters added at runtime
+pub fn live_matches(record: &Record) -> bool {
+    live_rejection(record).is_none()
+}
+
+fn live_rejection(record: &Record) -> Option<Rejection> {
+    let live = LIVE.read().expect("Failed to get live filter lock");
+    let (positive, negative) = live.patterns.as_ref()?.matches(record);
+    if !positive {
+        Some(Rejection::Unmatched)
+    } else if negative {
+        Some(Rejection::Excluded)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Rejection {
+    Level,
+    Id,
+    Source,
+    Time,
+    Unmatched,
+    Excluded,
+}
+
+impl FilterStats {
+    fn add(&self, rejection: Option<Rejection>) {
+        let total = self.total.fetch_add(1, Ordering::Relaxed) + 1;
+        let counter = match rejection {
+            Some(Rejection::Level) => &self.level,
+            Some(Rejection::Id) => &self.id,
+            Some(Rejection::Source) => &self.source,
+            Some(Rejection::Time) => &self.time,
+            Some(Rejection::Unmatched) => &self.unmatched,
+            Some(Rejection::Excluded) => &self.excluded,
+            None => return,
+        };
+        let rejected = counter.fetch_add(1, Ordering::Relaxed) + 1;
+
+        // Warn once if nothing passed for a while
+        if rejected == total && !self.warned.load(Ordering::Relaxed) {
+            let mut first = self.first.lock().expect("Failed to get filter lock");
+            match *first {
+                Some(first) if first.elapsed() > ALL_FILTERED_WARNING_DELAY => self.warn(),
+                Some(_) => (),
+                None => *first = Some(Instant::now()),
+            }
+        }
+    }
+
+    fn rejected(&self) -> usize {
+        self.level.load(Ordering::Relaxed)
+            + self.id.load(Ordering::Relaxed)
+            + self.source.load(Ordering::Relaxed)
+            + self.time.load(Ordering::Relaxed)
+            + self.unmatched.load(Ordering::Relaxed)
+            + self.excluded.load(Ordering::Relaxed)
+    }
+
+    fn warn(&self) {
+        if self.warned.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        eprintln!(
+            "Warning: all {} records were filtered out. Configured filters: {}. Check the selected profile or run with --explain",
+            self.total.load(Ordering::Relaxed),
+            self.configured
+        );
+    }
+
+    /// Warn if all records were filtered out and print the rejections if requested
+    pub fn finish(&self) {
+        let total = self.total.load(Ordering::Relaxed);
+        if total > 0 && self.rejected() == total {
+            self.warn();
+        }
+        if self.explain {
+            eprintln!(
+                "Filtered {} of {} records: {} below level, {} pid or tid, {} other source or buffer, {} outside time window, {} not matching, {} excluded. Configured filters: {}",
+                self.rejected(),
+                total,
+                self.level.load(Ordering::Relaxed),
+                self.id.load(Ordering::Relaxed),
+                self.source.load(Ordering::Relaxed),
+                self.time.load(Ordering::Relaxed),
+                self.unmatched.load(Ordering::Relaxed),
+                self.excluded.load(Ordering::Relaxed),
+                self.configured
+            );
+        }
+    }
+}
+
+/// Process or thread ids and id ranges like `100-200`. The prefix '!' excludes.
+#[derive(Clone, Debug)]
+struct IdSet {
+    positive: Vec<(u64, u64)>,
+    negative: Vec<(u64, u64)>,
+}
+
+impl IdSet {
+    fn new<'a, T: Iterator<Item = &'a str>>(ids: T) -> Result<IdSet, Error> {
         let mut positive = Vec::new();
         let mut negative = Vec::new();
 
-        for r in regex {
-            if let Some(r) = r.strip_prefix('!') {
-                negative.push(r);
+        for id in ids {
+            let (set, range) = match id.strip_prefix('!') {
+                Some(range) => (&mut negative, range),
+                None => (&mut positive, id),
+            };
+            let parse = |id: &str| {
+                parse_number(id)
+                    .ok_or_else(|| RogcatError::Config(format!("Invalid id filter: {}", id)))
+            };
+            let range = match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                None => {
+                    let id = parse(range)?;
+                    (id, id)
+                }
+            };
+            set.push(range);
+        }
+
+        Ok(IdSet { positive, negative })
+    }
+
+    /// True if `id` is in one of the positive ranges (if any) and not in a negative one
+    fn matches(&self, id: &str) -> bool {
+        self.contains(id.trim().parse().ok())
+    }
+
+    /// True if `id` is in one of the positive ranges (if any) and not in a negative one.
+    /// Records without id only pass if there are no positive ranges.
+    fn contains(&self, id: Option<u64>) -> bool {
+        if self.positive.is_empty() && self.negative.is_empty() {
+            return true;
+        }
+        let contains =
+            |ranges: &[(u64, u64)], id| ranges.iter().any(|(s, e)| (*s..=*e).contains(&id));
+        match id {
+            Some(id) => {
+                (self.positive.is_empty() || contains(&self.positive, id))
+                    && !contains(&self.negative, id)
+            }
+            None => self.positive.is_empty(),
+        }
+    }
+}
+
+/// Patterns matched against the source of records. The prefix '!' excludes.
+#[derive(Clone, Debug)]
+struct SourceSet {
+    positive: Option<RegexSet>,
+    negative: Option<RegexSet>,
+}
+
+impl SourceSet {
+    fn new<'a, T: Iterator<Item = &'a str>>(patterns: T) -> Result<SourceSet, Error> {
+        let (negative, positive): (Vec<_>, Vec<_>) = patterns.partition(|p| p.starts_with('!'));
+        let negative = negative.iter().map(|p| &p[1..]).collect::<Vec<_>>();
+        let set = |patterns: &[&str]| -> Result<Option<RegexSet>, Error> {
+            if patterns.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(RegexSet::new(patterns)?))
+            }
+        };
+        Ok(SourceSet {
+            positive: set(&positive)?,
+            negative: set(&negative)?,
+        })
+    }
+
+    /// True if `source` matches a positive pattern (if any) and no negative one.
+    /// Records without source only pass if there are no positive patterns.
+    fn matches(&self, source: Option<&str>) -> bool {
+        match source {
+            Some(source) => {
+                self.positive.as_ref().is_none_or(|p| p.is_match(source))
+                    && !self.negative.as_ref().is_some_and(|n| n.is_match(source))
+            }
+            None => self.positive.is_none(),
+        }
+    }
+}
+
+/// Patterns like `pid=123` matched against the structured fields of records. The prefix '!'
+/// excludes. Patterns of the same key are alternatives, all keys must match.
+#[derive(Clone, Debug, Default)]
+struct FieldSet {
+    positive: BTreeMap<String, Vec<Regex>>,
+    negative: Vec<(String, Regex)>,
+}
+
+impl FieldSet {
+    fn new<'a, T: Iterator<Item = &'a str>>(patterns: T) -> Result<FieldSet, Error> {
+        let mut set = FieldSet::default();
+        for pattern in patterns {
+            let (negate, p) = match pattern.strip_prefix('!') {
+                Some(p) => (true, p),
+                None => (false, pattern),
+            };
+            let (key, regex) = p.split_once('=').ok_or_else(|| {
+                RogcatError::Config(format!("Invalid field filter {}. Use KEY=REGEX", pattern))
+            })?;
+            let regex = Regex::new(regex)?;
+            if negate {
+                set.negative.push((key.to_owned(), regex));
             } else {
-                positive.push(r);
+                set.positive.entry(key.to_owned()).or_default().push(regex);
             }
         }
+        Ok(set)
+    }
+
+    /// Returns if all keys of the positive patterns match and if a negative pattern matches.
+    /// Records without a field fail its positive patterns.
+    fn matches(&self, fields: &BTreeMap<String, String>) -> (bool, bool) {
+        let positive = self.positive.iter().all(|(key, regexes)| {
+            fields
+                .get(key)
+                .is_some_and(|value| regexes.iter().any(|r| r.is_match(value)))
+        });
+        let negative = self
+            .negative
+            .iter()
+            .any(|(key, regex)| fields.get(key).is_some_and(|value| regex.is_match(value)));
+        (positive, negative)
+    }
+}
 
-        let positive = RegexSetBuilder::new(positive)
-            .case_insensitive(!case_sensitive)
-            .build()?;
-        let negative = RegexSetBuilder::new(negative)
-            .case_insensitive(!case_sensitive)
-            .build()?;
+/// Records with a timestamp between `since` and `until`. Records without timestamp pass.
+#[derive(Clone, Debug)]
+struct TimeWindow {
+    since: Option<TimeBound>,
+    until: Option<TimeBound>,
+}
+
+/// Time of day with an optional date like `10:31`, `10:31:00.250` or `03-25 10:31:00`
+#[derive(Clone, Debug)]
+struct TimeBound {
+    /// Month (starting at 0 like `Tm`) and day
+    date: Option<(i32, i32)>,
+    /// Hour, minute, second and nanoseconds
+    time: (i32, i32, i32, i32),
+}
+
+impl TimeBound {
+    fn new(s: &str) -> Result<TimeBound, Error> {
+        let invalid = || {
+            RogcatError::Config(format!(
+                "Invalid time: {}. Use e.g 10:31:00 or 03-25 10:31:00",
+                s
+            ))
+        };
+        let number = |s: &str| s.trim().parse::<i32>().map_err(|_| invalid());
 
-        Ok(FilterSet { positive, negative })
+        let (date, time) = match s.trim().split_once(' ') {
+            Some((date, time)) => {
+                // Skip an optional year
+                let date = date.rsplitn(3, '-').collect::<Vec<_>>();
+                if date.len() < 2 {
+                    return Err(invalid().into());
+                }
+                (Some((number(date[1])? - 1, number(date[0])?)), time)
+            }
+            None => (None, s),
+        };
+
+        let (time, nanos) = match time.split_once('.') {
+            Some((time, fraction)) => {
+                let digits = fraction.trim().len();
+                if digits == 0 || digits > 9 {
+                    return Err(invalid().into());
+                }
+                (time, number(fraction)? * 10i32.pow(9 - digits as u32))
+            }
+            None => (time, 0),
+        };
+        let mut time = time.split(':').map(number);
+        let hour = time.next().ok_or_else(invalid)??;
+        let minute = time.next().ok_or_else(invalid)??;
+        let second = time.next().transpose()?.unwrap_or(0);
+        if time.next().is_some() {
+            return Err(invalid().into());
+        }
+
+        Ok(TimeBound {
+            date,
+            time: (hour, minute, second, nanos),
+        })
     }
 
-    fn has_positive(&self) -> bool {
-        !self.positive.is_empty()
+    /// Compare `timestamp` to this bound. The date is only compared if given.
+    fn compare(&self, timestamp: &Timestamp) -> std::cmp::Ordering {
+        let time = (
+            timestamp.tm_hour,
+            timestamp.tm_min,
+            timestamp.tm_sec,
+            timestamp.tm_nsec,
+        );
+        match self.date {
+            Some(date) => ((timestamp.tm_mon, timestamp.tm_mday), time).cmp(&(date, self.time)),
+            None => time.cmp(&self.time),
+        }
     }
+}
 
-    fn has_negative(&self) -> bool {
-        !self.negative.is_empty()
+impl TimeWindow {
+    fn contains(&self, timestamp: Option<&Timestamp>) -> bool {
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => return true,
+        };
+        let after = |since: &TimeBound| since.compare(timestamp).is_ge();
+        let before = |until: &TimeBound| until.compare(timestamp).is_le();
+        match (&self.since, &self.until) {
+            // A window of times of day like 23:00 to 01:00 wraps around midnight
+            (Some(since), Some(until))
+                if since.date.is_none() && until.date.is_none() && since.time > until.time =>
+            {
+                after(since) || before(until)
+            }
+            (since, until) => since.as_ref().is_none_or(after) && until.as_ref().is_none_or(before),
+        }
     }
+}
+
+/// Fields a pattern applies to
+const FIELD_ID: u8 = 1;
+const FIELD_TAG: u8 = 2;
+const FIELD_MESSAGE: u8 = 4;
+const FIELD_ALL: u8 = FIELD_ID | FIELD_TAG | FIELD_MESSAGE;
+
+/// Unique patterns and the fields they apply to. A pattern given several times e.g as filter
+/// and tag is compiled and matched once per field.
+#[derive(Clone, Debug)]
+struct Patterns {
+    set: RegexSet,
+    /// Fields per pattern in which a match passes a record
+    positive: Vec<u8>,
+    /// Fields per pattern in which a match excludes a record
+    negative: Vec<u8>,
+    /// Fields with at least one positive pattern
+    positive_fields: u8,
+    /// Tags compared for equality. Passing and excluding per tag.
+    exact_tags: HashMap<String, (bool, bool)>,
+    /// Substrings searched in messages
+    exact_messages: Option<AhoCorasick>,
+    /// Passing and excluding per message substring
+    exact_message_kinds: Vec<(bool, bool)>,
+}
 
-    fn match_positive<T: AsRef<str>>(&self, item: T) -> bool {
-        !self.positive.is_empty() && self.positive.is_match(item.as_ref())
+#[derive(Default)]
+struct PatternsBuilder {
+    patterns: Vec<String>,
+    positive: Vec<u8>,
+    negative: Vec<u8>,
+    index: HashMap<String, usize>,
+    exact_tags: HashMap<String, (bool, bool)>,
+    exact_messages: Vec<String>,
+    exact_message_kinds: Vec<(bool, bool)>,
+}
+
+impl PatternsBuilder {
+    fn add<'a, T: Iterator<Item = &'a str>>(
+        &mut self,
+        patterns: T,
+        case_insensitive: bool,
+        fields: u8,
+    ) {
+        for pattern in patterns {
+            let (pattern, negative) = match pattern.strip_prefix('!') {
+                Some(pattern) => (pattern, true),
+                None => (pattern, false),
+            };
+            let pattern = if case_insensitive {
+                format!("(?i){pattern}")
+            } else {
+                pattern.to_owned()
+            };
+            let index = match self.index.get(&pattern) {
+                Some(index) => *index,
+                None => {
+                    self.index.insert(pattern.clone(), self.patterns.len());
+                    self.patterns.push(pattern);
+                    self.positive.push(0);
+                    self.negative.push(0);
+                    self.patterns.len() - 1
+                }
+            };
+            if negative {
+                self.negative[index] |= fields;
+            } else {
+                self.positive[index] |= fields;
+            }
+        }
     }
 
-    fn match_positive_iter<I: Iterator<Item = T>, T: AsRef<str>>(&self, mut iter: I) -> bool {
-        !self.positive.is_empty() && iter.any(|i| self.positive.is_match(i.as_ref()))
+    /// Add literals that are matched without regex. Tags are compared for equality and
+    /// messages are searched for the literal.
+    fn add_exact<'a, T: Iterator<Item = &'a str>>(&mut self, literals: T, field: u8) {
+        for literal in literals {
+            let (literal, negative) = match literal.strip_prefix('!') {
+                Some(literal) => (literal, true),
+                None => (literal, false),
+            };
+            let kind = if field == FIELD_TAG {
+                self.exact_tags.entry(literal.to_owned()).or_default()
+            } else {
+                match self.exact_messages.iter().position(|m| m == literal) {
+                    Some(index) => &mut self.exact_message_kinds[index],
+                    None => {
+                        self.exact_messages.push(literal.to_owned());
+                        self.exact_message_kinds.push((false, false));
+                        self.exact_message_kinds.last_mut().unwrap()
+                    }
+                }
+            };
+            if negative {
+                kind.1 = true;
+            } else {
+                kind.0 = true;
+            }
+        }
+    }
+
+    fn build(self) -> Result<Patterns, Error> {
+        let mut positive_fields = self.positive.iter().fold(0, |fields, f| fields | f);
+        if self.exact_tags.values().any(|(positive, _)| *positive) {
+            positive_fields |= FIELD_TAG;
+        }
+        if self
+            .exact_message_kinds
+            .iter()
+            .any(|(positive, _)| *positive)
+        {
+            positive_fields |= FIELD_MESSAGE;
+        }
+        let exact_messages = if self.exact_messages.is_empty() {
+            None
+        } else {
+            Some(AhoCorasick::new(&self.exact_messages)?)
+        };
+        Ok(Patterns {
+            set: RegexSet::new(&self.patterns)?,
+            positive_fields,
+            positive: self.positive,
+            negative: self.negative,
+            exact_tags: self.exact_tags,
+            exact_messages,
+            exact_message_kinds: self.exact_message_kinds,
+        })
+    }
+}
+
+impl Patterns {
+    /// Returns if `record` matches a positive pattern (or there is none) and a negative pattern
+    fn matches(&self, record: &Record) -> (bool, bool) {
+        if self.set.is_empty() && self.exact_tags.is_empty() && self.exact_messages.is_none() {
+            return (true, false);
+        }
+
+        let mut positive = self.positive_fields == 0;
+        let mut negative = false;
+
+        for tag in &record.tags {
+            if let Some((p, n)) = self.exact_tags.get(tag) {
+                positive |= p;
+                negative |= n;
+            }
+        }
+        if let Some(ref messages) = self.exact_messages {
+            for m in messages.find_overlapping_iter(&record.message) {
+                let (p, n) = self.exact_message_kinds[m.pattern().as_usize()];
+                positive |= p;
+                negative |= n;
+                if positive && negative {
+                    break;
+                }
+            }
+        }
+        if self.set.is_empty() || (positive && negative) {
+            return (positive, negative);
+        }
+
+        let ids = once(&record.process)
+            .chain(once(&record.thread))
+            .chain(record.process_name.as_ref())
+            .map(|id| (FIELD_ID, id));
+        let tags = record.tags.iter().map(|tag| (FIELD_TAG, tag));
+        let message = (FIELD_MESSAGE, &record.message);
+        for (field, value) in ids.chain(tags).chain(once(message)) {
+            for index in self.set.matches(value).iter() {
+                positive |= self.positive[index] & field != 0;
+                negative |= self.negative[index] & field != 0;
+            }
+            if positive && negative {
+                break;
+            }
+        }
+        (positive, negative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(month: i32, day: i32, hour: i32, minute: i32) -> Timestamp {
+        Timestamp::new(time::Tm {
+            tm_mon: month,
+            tm_mday: day,
+            tm_hour: hour,
+            tm_min: minute,
+            ..time::empty_tm()
+        })
+    }
+
+    #[test]
+    fn time_bound() {
+        let bound = TimeBound::new("10:31").unwrap();
+        assert_eq!(bound.date, None);
+        assert_eq!(bound.time, (10, 31, 0, 0));
+
+        let bound = TimeBound::new("10:31:02.25").unwrap();
+        assert_eq!(bound.time, (10, 31, 2, 250_000_000));
+
+        // Months start at 0 like in Tm
+        let bound = TimeBound::new("03-25 10:31:00").unwrap();
+        assert_eq!(bound.date, Some((2, 25)));
+        let bound = TimeBound::new("2024-12-31 23:59").unwrap();
+        assert_eq!(bound.date, Some((11, 31)));
+        assert_eq!(bound.time, (23, 59, 0, 0));
+
+        for invalid in &[
+            "",
+            "10",
+            "x:10",
+            "10:31:00:00",
+            "10:31.",
+            "25 10:31",
+            "10:31.1234567890",
+        ] {
+            assert!(TimeBound::new(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn time_window() {
+        let window = |since: &str, until: &str| TimeWindow {
+            since: Some(TimeBound::new(since).unwrap()),
+            until: Some(TimeBound::new(until).unwrap()),
+        };
+
+        let day = window("10:00", "12:00");
+        assert!(day.contains(Some(&timestamp(0, 1, 10, 0))));
+        assert!(day.contains(Some(&timestamp(0, 1, 12, 0))));
+        assert!(!day.contains(Some(&timestamp(0, 1, 12, 1))));
+        assert!(day.contains(None));
+
+        let night = window("23:00", "01:00");
+        assert!(night.contains(Some(&timestamp(0, 1, 23, 30))));
+        assert!(night.contains(Some(&timestamp(0, 2, 0, 30))));
+        assert!(!night.contains(Some(&timestamp(0, 2, 12, 0))));
+
+        let dated = window("03-25 10:00", "03-26 10:00");
+        assert!(!dated.contains(Some(&timestamp(2, 24, 12, 0))));
+        assert!(dated.contains(Some(&timestamp(2, 25, 12, 0))));
+        assert!(!dated.contains(Some(&timestamp(3, 25, 12, 0))));
+    }
+
+    fn record(tag: &str, message: &str) -> Record {
+        Record {
+            tags: vec![tag.to_owned()],
+            message: message.to_owned(),
+            process: "100".to_owned(),
+            thread: "101".to_owned(),
+            ..Record::default()
+        }
+    }
+
+    #[test]
+    fn patterns_shared() {
+        let mut builder = PatternsBuilder::default();
+        builder.add(["foo", "!bar"].iter().copied(), false, FIELD_ALL);
+        builder.add(["foo", "!bar"].iter().copied(), false, FIELD_TAG);
+        builder.add(["baz"].iter().copied(), false, FIELD_MESSAGE);
+        let patterns = builder.build().unwrap();
+
+        // Patterns given as filter and tag are compiled once
+        assert_eq!(patterns.set.len(), 3);
+        assert_eq!(patterns.positive, vec![FIELD_ALL, 0, FIELD_MESSAGE]);
+        assert_eq!(patterns.negative, vec![0, FIELD_ALL, 0]);
+        assert_eq!(patterns.positive_fields, FIELD_ALL);
+    }
+
+    #[test]
+    fn patterns_fields() {
+        let mut builder = PatternsBuilder::default();
+        builder.add(["^Tag$", "!^Noisy$"].iter().copied(), false, FIELD_TAG);
+        builder.add(["crash", "!ignore"].iter().copied(), true, FIELD_MESSAGE);
+        let patterns = builder.build().unwrap();
+
+        assert_eq!(patterns.matches(&record("Tag", "hello")), (true, false));
+        assert_eq!(patterns.matches(&record("Other", "CRASH")), (true, false));
+        assert_eq!(patterns.matches(&record("Other", "Tag")), (false, false));
+        assert_eq!(patterns.matches(&record("Noisy", "hello")), (false, true));
+        assert_eq!(patterns.matches(&record("Tag", "Ignore")), (true, true));
+    }
+
+    #[test]
+    fn patterns_ids() {
+        let mut builder = PatternsBuilder::default();
+        builder.add(["^101$"].iter().copied(), false, FIELD_ALL);
+        let patterns = builder.build().unwrap();
+
+        assert_eq!(patterns.matches(&record("Tag", "hello")), (true, false));
+        let mut other = record("Tag", "hello");
+        other.thread = "102".to_owned();
+        assert_eq!(patterns.matches(&other), (false, false));
+    }
+
+    #[test]
+    fn patterns_exact() {
+        let mut builder = PatternsBuilder::default();
+        builder.add_exact(["Tag", "!Noisy"].iter().copied(), FIELD_TAG);
+        builder.add_exact(["a.c", "!skip"].iter().copied(), FIELD_MESSAGE);
+        let patterns = builder.build().unwrap();
+
+        assert!(patterns.set.is_empty());
+        assert_eq!(patterns.matches(&record("Tag", "hello")), (true, false));
+        assert_eq!(patterns.matches(&record("Tag2", "hello")), (false, false));
+        assert_eq!(patterns.matches(&record("Other", "xa.cx")), (true, false));
+        // Literals are not regular expressions
+        assert_eq!(patterns.matches(&record("Other", "abc")), (false, false));
+        assert_eq!(patterns.matches(&record("Noisy", "a.c")), (true, true));
+        assert_eq!(patterns.matches(&record("Tag", "skip")), (true, true));
     }
 
-    fn match_negative<T: AsRef<str>>(&self, item: T) -> bool {
-        !self.negative.is_empty() && self.negative.is_match(item.as_ref())
+    #[test]
+    fn patterns_empty() {
+        let patterns = PatternsBuilder::default().build().unwrap();
+        assert_eq!(patterns.matches(&record("Tag", "hello")), (true, false));
     }
 
-    fn match_negative_iter<I: Iterator<Item = T>, T: AsRef<str>>(&self, mut iter: I) -> bool {
-        !self.negative.is_empty() && iter.any(|i| self.negative.is_match(i.as_ref()))
+    #[test]
+    fn patterns_invalid() {
+        let mut builder = PatternsBuilder::default();
+        builder.add(["("].iter().copied(), false, FIELD_ALL);
+        assert!(builder.build().is_err());
     }
 }
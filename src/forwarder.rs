@@ -0,0 +1,204 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Forward records to a tcp or udp collector e.g `--output tcp://collector:6514`
+
+use crate::{error::RogcatError, profiles::Profile, terminal::Human};
+use clap::ArgMatches;
+use failure::Error;
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use rogcat::record::{Format, Record};
+use std::{
+    cmp::min,
+    io::Write,
+    net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    thread,
+    time::Duration,
+};
+use termcolor::Buffer;
+use url::Url;
+
+/// Timeout of a single connection attempt
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the first reconnect. Doubled after each failed attempt.
+const BACKOFF_MIN: Duration = Duration::from_millis(250);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// Size of the buffered records that triggers a write to a tcp collector
+const FLUSH_SIZE: usize = 64 * 1024;
+
+/// Network url of `output` if it's a collector
+pub fn url(output: &str) -> Option<Url> {
+    Url::parse(output)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "tcp" | "udp"))
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// Serialize records and send them to a collector. Tcp records are newline terminated,
+/// udp records are sent as one datagram each. The connection is reestablished with an
+/// increasing delay if it fails.
+pub struct Forwarder {
+    url: Url,
+    format: Format,
+    human: Option<Human>,
+    connection: Option<Connection>,
+    backoff: Duration,
+    /// Serialized records that are not yet sent
+    pending: Vec<u8>,
+}
+
+impl Forwarder {
+    pub fn new(
+        args: &ArgMatches,
+        profile: &Profile,
+        format: Format,
+        url: Url,
+    ) -> Result<Self, Error> {
+        if format == Format::Html {
+            return Err(
+                RogcatError::Config("The html format cannot be forwarded".to_owned()).into(),
+            );
+        }
+        if url.host_str().is_none() || url.port().is_none() {
+            return Err(RogcatError::Config(format!(
+                "Invalid collector {}. Use e.g tcp://collector:6514",
+                url
+            ))
+            .into());
+        }
+        let human =
+            (format == Format::Human).then(|| Human::from(args, profile, format.clone()).detach());
+        Ok(Forwarder {
+            url,
+            format,
+            human,
+            connection: None,
+            backoff: BACKOFF_MIN,
+            pending: Vec::new(),
+        })
+    }
+
+    fn connect(&self) -> Result<Connection, Error> {
+        let addrs = self.url.to_socket_addrs()?;
+        let mut error = RogcatError::Io("Failed to resolve address".to_owned());
+        for addr in addrs {
+            let connection = if self.url.scheme() == "udp" {
+                let local: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                UdpSocket::bind(local)
+                    .and_then(|socket| socket.connect(addr).map(|_| socket))
+                    .map(Connection::Udp)
+            } else {
+                TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map(Connection::Tcp)
+            };
+            match connection {
+                Ok(connection) => return Ok(connection),
+                Err(e) => error = RogcatError::Io(format!("{}: {}", addr, e)),
+            }
+        }
+        Err(error.into())
+    }
+
+    /// Connect or wait before the next attempt
+    fn reconnect(&mut self) {
+        match self.connect() {
+            Ok(connection) => {
+                eprintln!("Connected to {}", self.url);
+                self.connection = Some(connection);
+                self.backoff = BACKOFF_MIN;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to connect to {}: {}. Retrying in {:?}",
+                    self.url, e, self.backoff
+                );
+                thread::sleep(self.backoff);
+                self.backoff = min(self.backoff * 2, BACKOFF_MAX);
+            }
+        }
+    }
+
+    /// Send the pending records. Blocks until the collector accepts them.
+    fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            let result = match self.connection {
+                Some(Connection::Tcp(ref mut stream)) => stream.write_all(&self.pending),
+                Some(Connection::Udp(ref socket)) => socket.send(&self.pending).map(drop),
+                None => {
+                    self.reconnect();
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) => self.pending.clear(),
+                Err(e) => {
+                    eprintln!("Connection to {} failed: {}", self.url, e);
+                    self.connection = None;
+                }
+            }
+        }
+    }
+
+    fn serialize(&mut self, record: Record) -> Result<(), Error> {
+        match self.human {
+            Some(ref mut human) => {
+                let buffer = human.render(record, Buffer::no_color())?;
+                self.pending.extend_from_slice(buffer.as_slice());
+            }
+            None => {
+                self.pending
+                    .extend_from_slice(self.format.fmt_record(&record)?.as_bytes());
+                self.pending.push(b'\n');
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Forwarder {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        self.serialize(record)?;
+        if self.url.scheme() == "udp" {
+            // One datagram per record
+            if self.pending.last() == Some(&b'\n') {
+                self.pending.pop();
+            }
+            self.flush();
+        } else if self.pending.len() >= FLUSH_SIZE {
+            self.flush();
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.flush();
+        Ok(Async::Ready(()))
+    }
+}
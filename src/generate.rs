@@ -0,0 +1,480 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Shell completions and man pages generated from the command line definition
+
+use crate::{cli::cli, error::RogcatError};
+use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, ArgSettings, Shell};
+use failure::Error;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+/// Option or flag of a command
+struct Opt {
+    short: Option<char>,
+    long: Option<String>,
+    help: String,
+    value: Option<String>,
+    possible: Vec<String>,
+    multiple: bool,
+}
+
+/// Positional argument of a command
+struct Positional {
+    name: String,
+    help: String,
+    required: bool,
+    multiple: bool,
+}
+
+/// Command or subcommand with its arguments
+struct Command {
+    name: String,
+    about: String,
+    long_about: String,
+    opts: Vec<Opt>,
+    positionals: Vec<Positional>,
+    subcommands: Vec<Command>,
+}
+
+impl Command {
+    fn from_app(app: &App) -> Command {
+        let p = &app.p;
+        let mut opts: Vec<Opt> = p
+            .flags
+            .iter()
+            .filter(|f| !f.b.is_set(ArgSettings::Hidden))
+            .map(|f| Opt {
+                short: f.s.short,
+                long: f.s.long.map(ToOwned::to_owned),
+                help: f.b.help.unwrap_or_default().to_owned(),
+                value: None,
+                possible: Vec::new(),
+                multiple: f.b.is_set(ArgSettings::Multiple),
+            })
+            .collect();
+        opts.extend(
+            p.opts
+                .iter()
+                .filter(|o| !o.b.is_set(ArgSettings::Hidden))
+                .map(|o| Opt {
+                    short: o.s.short,
+                    long: o.s.long.map(ToOwned::to_owned),
+                    help: o.b.help.unwrap_or_default().to_owned(),
+                    value: Some(
+                        o.v.val_names
+                            .as_ref()
+                            .and_then(|n| n.values().next().copied())
+                            .unwrap_or(o.b.name)
+                            .to_owned(),
+                    ),
+                    possible: o
+                        .v
+                        .possible_vals
+                        .as_ref()
+                        .map(|v| v.iter().map(|s| (*s).to_owned()).collect())
+                        .unwrap_or_default(),
+                    multiple: o.b.is_set(ArgSettings::Multiple),
+                }),
+        );
+        opts.sort_by(|a, b| (a.long.as_ref(), a.short).cmp(&(b.long.as_ref(), b.short)));
+        opts.push(Opt {
+            short: Some('h'),
+            long: Some("help".to_owned()),
+            help: "Prints help information".to_owned(),
+            value: None,
+            possible: Vec::new(),
+            multiple: false,
+        });
+
+        let positionals = p
+            .positionals
+            .values()
+            .filter(|a| !a.b.is_set(ArgSettings::Hidden))
+            .map(|a| Positional {
+                name: a.b.name.to_owned(),
+                help: a.b.help.unwrap_or_default().to_owned(),
+                required: a.b.is_set(ArgSettings::Required),
+                multiple: a.b.is_set(ArgSettings::Multiple),
+            })
+            .collect();
+
+        let mut subcommands: Vec<Command> = p
+            .subcommands
+            .iter()
+            .filter(|s| !s.p.is_set(AppSettings::Hidden))
+            .map(Command::from_app)
+            .collect();
+        subcommands.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Command {
+            name: p.meta.name.clone(),
+            about: p.meta.about.unwrap_or_default().to_owned(),
+            long_about: p
+                .meta
+                .long_about
+                .or(p.meta.about)
+                .unwrap_or_default()
+                .to_owned(),
+            opts,
+            positionals,
+            subcommands,
+        }
+    }
+}
+
+/// Generate completions or man pages for each target. Files are written to `--dir` if given.
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let targets: Vec<&str> = args
+        .values_of("target")
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    let dir = args.value_of("dir").map(Path::new);
+    if dir.is_none() && targets.len() > 1 {
+        return Err(RogcatError::Config("Multiple targets require --dir".into()).into());
+    }
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir).map_err(|e| {
+            RogcatError::Config(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+    }
+
+    let command = Command::from_app(&cli());
+    for target in targets {
+        match dir {
+            Some(dir) if target == "man" => {
+                man_pages(&command, crate_name!(), dir)?;
+            }
+            Some(dir) => {
+                let file = dir.join(file_name(target));
+                let mut out = File::create(&file).map_err(|e| {
+                    RogcatError::Config(format!("Failed to create {}: {}", file.display(), e))
+                })?;
+                generate(target, &command, &mut out)?;
+                eprintln!("Wrote {}", file.display());
+            }
+            None => generate(target, &command, &mut io::stdout())?,
+        }
+    }
+    Ok(())
+}
+
+/// Conventional file name for `target`
+fn file_name(target: &str) -> String {
+    let name = crate_name!();
+    match target {
+        "bash" => format!("{name}.bash"),
+        "elvish" => format!("{name}.elv"),
+        "fig" => format!("{name}.ts"),
+        "fish" => format!("{name}.fish"),
+        "nushell" => format!("{name}.nu"),
+        "powershell" => format!("_{name}.ps1"),
+        "zsh" => format!("_{name}"),
+        _ => format!("{name}.1"),
+    }
+}
+
+fn generate<W: Write>(target: &str, command: &Command, out: &mut W) -> Result<(), Error> {
+    match target {
+        "nushell" => nushell(command, out),
+        "fig" => fig(command, out),
+        "man" => man(command, crate_name!(), out),
+        shell => {
+            let shell = shell.parse::<Shell>().map_err(RogcatError::Config)?;
+            let mut app = cli();
+            expand_groups(&mut app);
+            app.gen_completions_to(crate_name!(), shell, out);
+            Ok(())
+        }
+    }
+}
+
+/// Replace groups in conflicts with their arguments. The zsh generator of clap expects arguments.
+fn expand_groups(app: &mut App<'static, 'static>) {
+    let groups: HashMap<&str, Vec<&str>> = app
+        .p
+        .groups
+        .iter()
+        .map(|g| (g.name, g.args.clone()))
+        .collect();
+    let expand = |blacklist: &mut Option<Vec<&str>>| {
+        if let Some(names) = blacklist {
+            *names = names
+                .iter()
+                .flat_map(|n| groups.get(n).cloned().unwrap_or_else(|| vec![*n]))
+                .collect();
+        }
+    };
+    for flag in &mut app.p.flags {
+        expand(&mut flag.b.blacklist);
+    }
+    for opt in &mut app.p.opts {
+        expand(&mut opt.b.blacklist);
+    }
+    for positional in app.p.positionals.values_mut() {
+        expand(&mut positional.b.blacklist);
+    }
+    for subcommand in &mut app.p.subcommands {
+        expand_groups(subcommand);
+    }
+}
+
+/// Nushell `extern` definitions with value completers for possible values
+fn nushell<W: Write>(command: &Command, out: &mut W) -> Result<(), Error> {
+    fn externs<W: Write>(command: &Command, path: &str, out: &mut W) -> Result<(), Error> {
+        for opt in &command.opts {
+            if let (Some(long), false) = (&opt.long, opt.possible.is_empty()) {
+                let values = opt
+                    .possible
+                    .iter()
+                    .map(|v| format!("\"{v}\""))
+                    .collect::<Vec<_>>();
+                writeln!(out, "  def \"nu-complete {} {}\" [] {{", path, long)?;
+                writeln!(out, "    [ {} ]", values.join(" "))?;
+                writeln!(out, "  }}\n")?;
+            }
+        }
+
+        if !command.about.is_empty() {
+            writeln!(out, "  # {}", command.about)?;
+        }
+        writeln!(out, "  export extern \"{path}\" [")?;
+        for opt in &command.opts {
+            let mut flag = match (&opt.long, opt.short) {
+                (Some(long), Some(short)) => format!("--{long}(-{short})"),
+                (Some(long), None) => format!("--{long}"),
+                (None, Some(short)) => format!("-{short}"),
+                (None, None) => continue,
+            };
+            if opt.value.is_some() {
+                flag.push_str(": string");
+                if let (Some(long), false) = (&opt.long, opt.possible.is_empty()) {
+                    flag.push_str(&format!("@\"nu-complete {path} {long}\""));
+                }
+            }
+            writeln!(out, "    {:<40} # {}", flag, opt.help)?;
+        }
+        for positional in &command.positionals {
+            let name = positional.name.to_lowercase().replace('-', "_");
+            let param = if positional.multiple {
+                format!("...{name}: string")
+            } else if positional.required {
+                format!("{name}: string")
+            } else {
+                format!("{name}?: string")
+            };
+            writeln!(out, "    {:<40} # {}", param, positional.help)?;
+        }
+        writeln!(out, "  ]\n")?;
+
+        for subcommand in &command.subcommands {
+            externs(subcommand, &format!("{} {}", path, subcommand.name), out)?;
+        }
+        Ok(())
+    }
+
+    writeln!(out, "module completions {{\n")?;
+    externs(command, &command.name, out)?;
+    writeln!(out, "}}\n\nexport use completions *")?;
+    Ok(())
+}
+
+/// Fig completion spec
+fn fig<W: Write>(command: &Command, out: &mut W) -> Result<(), Error> {
+    fn string(s: &str) -> String {
+        serde_json::to_string(s).unwrap_or_default()
+    }
+
+    fn spec<W: Write>(command: &Command, indent: usize, out: &mut W) -> Result<(), Error> {
+        let pad = " ".repeat(indent);
+        writeln!(out, "{}name: {},", pad, string(&command.name))?;
+        writeln!(out, "{}description: {},", pad, string(&command.about))?;
+        if !command.subcommands.is_empty() {
+            writeln!(out, "{pad}subcommands: [")?;
+            for subcommand in &command.subcommands {
+                writeln!(out, "{pad}  {{")?;
+                spec(subcommand, indent + 4, out)?;
+                writeln!(out, "{pad}  }},")?;
+            }
+            writeln!(out, "{pad}],")?;
+        }
+        writeln!(out, "{pad}options: [")?;
+        for opt in &command.opts {
+            let names = opt
+                .short
+                .map(|s| format!("-{s}"))
+                .into_iter()
+                .chain(opt.long.as_ref().map(|l| format!("--{l}")))
+                .map(|n| string(&n))
+                .collect::<Vec<_>>();
+            writeln!(out, "{pad}  {{")?;
+            writeln!(out, "{}    name: [{}],", pad, names.join(", "))?;
+            writeln!(out, "{}    description: {},", pad, string(&opt.help))?;
+            if opt.multiple {
+                writeln!(out, "{pad}    isRepeatable: true,")?;
+            }
+            if let Some(ref value) = opt.value {
+                write!(out, "{}    args: {{ name: {}", pad, string(value))?;
+                if !opt.possible.is_empty() {
+                    let values = opt.possible.iter().map(|v| string(v)).collect::<Vec<_>>();
+                    write!(out, ", suggestions: [{}]", values.join(", "))?;
+                }
+                writeln!(out, " }},")?;
+            }
+            writeln!(out, "{pad}  }},")?;
+        }
+        writeln!(out, "{pad}],")?;
+        if !command.positionals.is_empty() {
+            writeln!(out, "{pad}args: [")?;
+            for positional in &command.positionals {
+                write!(
+                    out,
+                    "{}  {{ name: {}, description: {}",
+                    pad,
+                    string(&positional.name),
+                    string(&positional.help)
+                )?;
+                if !positional.required {
+                    write!(out, ", isOptional: true")?;
+                }
+                if positional.multiple {
+                    write!(out, ", isVariadic: true")?;
+                }
+                writeln!(out, " }},")?;
+            }
+            writeln!(out, "{pad}],")?;
+        }
+        Ok(())
+    }
+
+    writeln!(out, "const completion: Fig.Spec = {{")?;
+    spec(command, 2, out)?;
+    writeln!(out, "}};\n\nexport default completion;")?;
+    Ok(())
+}
+
+/// Escape `s` for roff
+fn roff(s: &str) -> String {
+    let escaped = s.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{escaped}")
+    } else {
+        escaped
+    }
+}
+
+/// Man page of `command`. `name` is the full name e.g `rogcat-grep` for subcommands.
+fn man<W: Write>(command: &Command, name: &str, out: &mut W) -> Result<(), Error> {
+    writeln!(
+        out,
+        ".TH {} 1 \"{} {}\"",
+        name.to_uppercase(),
+        crate_name!(),
+        crate_version!()
+    )?;
+    writeln!(out, ".SH NAME\n{} \\- {}", roff(name), roff(&command.about))?;
+
+    let mut synopsis = format!("\\fB{}\\fR [OPTIONS]", roff(&name.replace('-', " ")));
+    for positional in &command.positionals {
+        let mut p = format!("\\fI{}\\fR", roff(&positional.name));
+        if positional.multiple {
+            p.push_str("...");
+        }
+        if !positional.required {
+            p = format!("[{p}]");
+        }
+        synopsis.push(' ');
+        synopsis.push_str(&p);
+    }
+    if !command.subcommands.is_empty() {
+        synopsis.push_str(" [SUBCOMMAND]");
+    }
+    writeln!(out, ".SH SYNOPSIS\n{synopsis}")?;
+    writeln!(out, ".SH DESCRIPTION\n{}", roff(&command.long_about))?;
+
+    writeln!(out, ".SH OPTIONS")?;
+    for opt in &command.opts {
+        let mut names = opt
+            .short
+            .map(|s| format!("\\fB\\-{s}\\fR"))
+            .into_iter()
+            .chain(
+                opt.long
+                    .as_ref()
+                    .map(|l| format!("\\fB\\-\\-{}\\fR", roff(l))),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Some(ref value) = opt.value {
+            names.push_str(&format!("=\\fI{}\\fR", roff(value)));
+        }
+        writeln!(out, ".TP\n{}\n{}", names, roff(&opt.help))?;
+        if !opt.possible.is_empty() {
+            writeln!(
+                out,
+                ".br\n[possible values: {}]",
+                roff(&opt.possible.join(", "))
+            )?;
+        }
+    }
+
+    if !command.positionals.is_empty() {
+        writeln!(out, ".SH ARGUMENTS")?;
+        for positional in &command.positionals {
+            writeln!(
+                out,
+                ".TP\n\\fI{}\\fR\n{}",
+                roff(&positional.name),
+                roff(&positional.help)
+            )?;
+        }
+    }
+
+    if !command.subcommands.is_empty() {
+        writeln!(out, ".SH SUBCOMMANDS")?;
+        for subcommand in &command.subcommands {
+            writeln!(
+                out,
+                ".TP\n{}\\-{}(1)\n{}",
+                roff(name),
+                roff(&subcommand.name),
+                roff(&subcommand.about)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the man pages of `command` and its subcommands to `dir`
+fn man_pages(command: &Command, name: &str, dir: &Path) -> Result<(), Error> {
+    let file = dir.join(format!("{name}.1"));
+    let mut out = File::create(&file)
+        .map_err(|e| RogcatError::Config(format!("Failed to create {}: {}", file.display(), e)))?;
+    man(command, name, &mut out)?;
+    eprintln!("Wrote {}", file.display());
+    for subcommand in &command.subcommands {
+        man_pages(subcommand, &format!("{}-{}", name, subcommand.name), dir)?;
+    }
+    Ok(())
+}
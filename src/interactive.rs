@@ -0,0 +1,359 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Interactive mode of the human terminal output: pause the live output,
+//! scroll back, edit filters and quit with the keyboard.
+
+use crate::{error::RogcatError, filter, internal, profiles, recorder};
+use failure::Error;
+use ratatui::crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType},
+};
+use rogcat::record::Record;
+use std::{
+    collections::VecDeque,
+    io::{stdout, Write},
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Number of records kept for scrolling back
+const HISTORY: usize = 10_000;
+
+/// Set while the terminal is in raw mode
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct State {
+    paused: bool,
+    /// The input is exhausted
+    ended: bool,
+    /// Number of records scrolled back from the latest one
+    offset: usize,
+    /// Records and their rendering
+    history: VecDeque<(Record, Vec<u8>)>,
+    /// Records and their rendering received while paused
+    pending: Vec<(Record, Vec<u8>)>,
+    /// Input of the filter prompt
+    prompt: Option<String>,
+    /// Error of the last filter input
+    error: Option<String>,
+}
+
+/// Handle to the interactive output shared with the keyboard thread
+#[derive(Clone)]
+pub struct Interactive {
+    state: Arc<Mutex<State>>,
+}
+
+/// True while the interactive mode owns the terminal
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Restore the terminal if the interactive mode is active
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        clear_line().ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+impl Interactive {
+    pub fn new() -> Result<Interactive, Error> {
+        if !atty::is(atty::Stream::Stdout) {
+            return Err(RogcatError::Config(
+                "Interactive mode requires stdout to be a terminal".to_owned(),
+            )
+            .into());
+        }
+        terminal::enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let keys = state.clone();
+        thread::spawn(move || loop {
+            match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    let mut state = keys.lock().expect("Failed to get interactive lock");
+                    if let Err(e) = state.key(key) {
+                        restore();
+                        eprintln!("{e}");
+                        exit(1);
+                    }
+                }
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        });
+
+        Ok(Interactive { state })
+    }
+
+    /// Print `record` rendered as `bytes` or buffer it while paused
+    pub fn print(&self, record: Record, bytes: Vec<u8>) -> Result<(), Error> {
+        let bytes = crlf(&bytes);
+        let mut state = self.state.lock().expect("Failed to get interactive lock");
+        if state.paused {
+            state.pending.push((record, bytes));
+            state.status()
+        } else {
+            write(&bytes)?;
+            state.push(record, bytes);
+            Ok(())
+        }
+    }
+
+    /// Mark the end of the input. The keyboard thread keeps the output open for
+    /// scrolling back until the user quits.
+    pub fn finish(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().expect("Failed to get interactive lock");
+        state.ended = true;
+        state.status()
+    }
+}
+
+impl State {
+    fn key(&mut self, key: KeyEvent) -> Result<(), Error> {
+        let page = rows()? - 1;
+        if self.prompt.is_some() {
+            return self.prompt_key(key, page);
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                restore();
+                exit(0);
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                restore();
+                exit(0);
+            }
+            // ctrl-\ dumps the flight recorder like SIGQUIT does outside of raw mode
+            KeyCode::Char('\\') | KeyCode::Char('4')
+                if key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                recorder::trigger()
+            }
+            KeyCode::Char(' ') if self.paused => return self.resume(),
+            KeyCode::Char(' ') => self.paused = true,
+            KeyCode::Char('/') => {
+                self.paused = true;
+                self.prompt = Some(String::new());
+            }
+            KeyCode::Char('p') => profiles::cycle(),
+            KeyCode::Up => self.scroll(1, true),
+            KeyCode::Down => self.scroll(1, false),
+            KeyCode::PageUp => self.scroll(page, true),
+            KeyCode::PageDown => self.scroll(page, false),
+            KeyCode::Home => self.scroll(self.history.len(), true),
+            KeyCode::End => self.scroll(self.history.len(), false),
+            _ => return Ok(()),
+        }
+        if self.offset > 0 {
+            self.draw(page)?;
+        }
+        self.status()
+    }
+
+    /// Edit the filter prompt. The entered filter is added or removed and applied to
+    /// the scroll back.
+    fn prompt_key(&mut self, key: KeyEvent, page: usize) -> Result<(), Error> {
+        self.error = None;
+        let input = self.prompt.as_mut().expect("Missing prompt");
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                restore();
+                exit(0);
+            }
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                let input = self.prompt.take().unwrap_or_default();
+                if !input.is_empty() {
+                    if let Err(e) = filter::toggle_live(&input) {
+                        // Regex errors span several lines
+                        let error = e.to_string();
+                        self.error = Some(error.split_whitespace().collect::<Vec<_>>().join(" "));
+                        return self.status();
+                    }
+                }
+                self.offset = 0;
+                self.draw(page)?;
+                return self.resume();
+            }
+            _ => (),
+        }
+        self.status()
+    }
+
+    /// Records of the history that pass the filters edited at runtime
+    fn visible(&self) -> Vec<&[u8]> {
+        self.history
+            .iter()
+            .filter(|(record, _)| internal::is_internal(record) || filter::live_matches(record))
+            .map(|(_, bytes)| bytes.as_slice())
+            .collect()
+    }
+
+    fn scroll(&mut self, n: usize, back: bool) {
+        self.paused = true;
+        self.offset = if back {
+            (self.offset + n).min(self.visible().len().saturating_sub(1))
+        } else {
+            self.offset.saturating_sub(n)
+        };
+    }
+
+    /// Leave the pause and print the buffered records
+    fn resume(&mut self) -> Result<(), Error> {
+        clear_line()?;
+        if self.offset > 0 {
+            self.offset = 0;
+            self.draw(rows()?)?;
+        }
+        self.paused = false;
+        // Filters may have been added while paused
+        for (record, bytes) in std::mem::take(&mut self.pending) {
+            if internal::is_internal(&record) || filter::live_matches(&record) {
+                write(&bytes)?;
+            }
+            self.push(record, bytes);
+        }
+        if self.ended {
+            self.status()?;
+        }
+        Ok(())
+    }
+
+    /// Draw the history page ending at offset with `rows` lines
+    fn draw(&self, rows: usize) -> Result<(), Error> {
+        let visible = self.visible();
+        let end = visible.len().saturating_sub(self.offset);
+        let mut lines = 0;
+        let mut start = end;
+        while start > 0 {
+            let n = visible[start - 1].iter().filter(|b| **b == b'\n').count();
+            if lines + n > rows {
+                break;
+            }
+            lines += n;
+            start -= 1;
+        }
+
+        let mut out = stdout();
+        queue!(out, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        out.flush()?;
+        for bytes in &visible[start..end] {
+            write(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Print the pause status into the last line
+    fn status(&self) -> Result<(), Error> {
+        if let Some(ref input) = self.prompt {
+            let filters = filter::live_filters();
+            let status = format!(
+                " FILTER [tag:|message:][!]REGEX{}{}. enter: add or remove, esc: cancel ",
+                if filters.is_empty() { "" } else { ", active: " },
+                filters.join(" "),
+            );
+            return status_line(&status, &format!("/{input}"));
+        }
+        if let Some(ref error) = self.error {
+            return status_line(&format!(" {error} "), "");
+        }
+        if self.ended && !self.paused {
+            let status =
+                " END: \u{2191}\u{2193} PgUp PgDn: scroll, /: filter, p: profile, q: quit ";
+            return status_line(status, "");
+        }
+        let status = format!(
+            " PAUSED: {} records buffered, {} back. space: resume, \u{2191}\u{2193} PgUp PgDn: scroll, /: filter, p: profile, q: quit ",
+            self.pending.len(),
+            self.offset
+        );
+        status_line(&status, "")
+    }
+
+    fn push(&mut self, record: Record, bytes: Vec<u8>) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((record, bytes));
+    }
+}
+
+/// Number of terminal rows
+fn rows() -> Result<usize, Error> {
+    let (_, rows) = terminal::size()?;
+    Ok(if rows < 2 { 24 } else { rows as usize })
+}
+
+/// Clear the current line. Crossterm falls back to the console API on legacy Windows consoles.
+fn clear_line() -> Result<(), Error> {
+    let mut out = stdout();
+    queue!(out, cursor::MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+    out.flush().map_err(Into::into)
+}
+
+/// Replace the current line with `status` in reverse video followed by `input`
+fn status_line(status: &str, input: &str) -> Result<(), Error> {
+    let mut out = stdout();
+    queue!(
+        out,
+        cursor::MoveToColumn(0),
+        Clear(ClearType::CurrentLine),
+        SetAttribute(Attribute::Reverse),
+        Print(status),
+        SetAttribute(Attribute::Reset),
+        Print(input)
+    )?;
+    out.flush().map_err(Into::into)
+}
+
+fn write(bytes: &[u8]) -> Result<(), Error> {
+    let mut out = stdout();
+    out.write_all(bytes)?;
+    out.flush().map_err(Into::into)
+}
+
+/// The terminal does not return the carriage on newlines in raw mode
+fn crlf(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len() + 8);
+    for b in bytes {
+        if *b == b'\n' {
+            result.push(b'\r');
+        }
+        result.push(*b);
+    }
+    result
+}
@@ -0,0 +1,121 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Internal pipeline events (respawns, rotations...) that are injected into
+//! the record stream tagged with `TAG`.
+
+use failure::Error;
+use futures::{
+    sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    Async, Poll, Stream,
+};
+use lazy_static::lazy_static;
+use rogcat::record::{Level, Record, Timestamp};
+use std::sync::Mutex;
+use time::strftime;
+
+/// Tag of internal records
+pub const TAG: &str = "ROGCAT";
+
+lazy_static! {
+    static ref EVENTS: Mutex<Option<UnboundedSender<Record>>> = Mutex::new(None);
+}
+
+/// Emit an internal event. This is a noop unless `merge` is used.
+pub fn emit<T: Into<String>>(message: T) {
+    if let Some(ref tx) = *EVENTS.lock().expect("Failed to get event lock") {
+        tx.unbounded_send(record(Level::Info, message)).ok();
+    }
+}
+
+/// Internal record with `level` e.g for warnings that are part of the
+/// record stream
+pub fn record<T: Into<String>>(level: Level, message: T) -> Record {
+    let message = message.into();
+    let timestamp = Timestamp::now();
+    let raw = format!(
+        "{}.{:03}     0     0 {} {}: {}",
+        strftime("%m-%d %H:%M:%S", &timestamp).unwrap_or_default(),
+        timestamp.tm_nsec / 1_000_000,
+        level,
+        TAG,
+        message
+    );
+    Record {
+        timestamp: Some(timestamp),
+        message,
+        level,
+        tags: vec![TAG.to_owned()],
+        process: "0".to_owned(),
+        thread: "0".to_owned(),
+        raw,
+        ..Default::default()
+    }
+}
+
+/// Returns true if `record` is an internal event
+pub fn is_internal(record: &Record) -> bool {
+    record.tags.len() == 1 && record.tags[0] == TAG
+}
+
+/// Stream that merges internal events into a record stream
+pub struct Merge<S> {
+    stream: S,
+    events: UnboundedReceiver<Record>,
+    done: bool,
+}
+
+/// Inject internal events into `stream`. Pending events are drained
+/// when `stream` terminates.
+pub fn merge<S: Stream<Item = Record, Error = Error>>(stream: S) -> Merge<S> {
+    let (tx, events) = unbounded();
+    *EVENTS.lock().expect("Failed to get event lock") = Some(tx);
+    Merge {
+        stream,
+        events,
+        done: false,
+    }
+}
+
+impl<S: Stream<Item = Record, Error = Error>> Stream for Merge<S> {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            if let Ok(Async::Ready(Some(record))) = self.events.poll() {
+                return Ok(Async::Ready(Some(record)));
+            }
+
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            match self.stream.poll()? {
+                Async::Ready(None) => {
+                    // Drop the sender in order to terminate events after draining
+                    EVENTS.lock().expect("Failed to get event lock").take();
+                    self.done = true;
+                }
+                poll => return Ok(poll),
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! ISO-TP (ISO 15765-2) reassembly and UDS (ISO 14229) decoding of CAN frames
+
+use crate::{error::RogcatError, internal, utils::parse_number};
+use clap::ArgMatches;
+use failure::Error;
+use rogcat::record::Level;
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+
+/// Reassembles ISO-TP messages between testers and ECUs
+#[derive(Debug, Default)]
+pub struct IsoTp {
+    /// Request ids of the testers and response ids of the ECUs
+    pairs: Vec<(u32, u32)>,
+    /// Incomplete multi frame messages per CAN id
+    transfers: HashMap<u32, Transfer>,
+}
+
+#[derive(Debug)]
+struct Transfer {
+    len: usize,
+    data: Vec<u8>,
+    /// Expected sequence number of the next consecutive frame
+    sequence: u8,
+}
+
+impl IsoTp {
+    /// Parse `--isotp` arguments like `0x7E0:0x7E8`
+    pub fn from_args(args: &ArgMatches) -> Result<Option<IsoTp>, Error> {
+        let pairs = args
+            .values_of("isotp")
+            .unwrap_or_default()
+            .map(|pair| {
+                let invalid = || RogcatError::Config(format!("Invalid ISO-TP id pair: {}", pair));
+                let (request, response) = pair.split_once(':').ok_or_else(invalid)?;
+                let id = |id| {
+                    parse_number(id)
+                        .and_then(|id| u32::try_from(id).ok())
+                        .ok_or_else(invalid)
+                };
+                Ok((id(request)?, id(response)?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(if pairs.is_empty() {
+            None
+        } else {
+            Some(IsoTp {
+                pairs,
+                ..Default::default()
+            })
+        })
+    }
+
+    /// True if frames with `id` carry ISO-TP messages
+    pub fn handles(&self, id: u32) -> bool {
+        self.pairs.iter().any(|(req, res)| *req == id || *res == id)
+    }
+
+    /// Feed a frame with `id` and return the message it completes if any
+    pub fn push(&mut self, id: u32, frame: &[u8]) -> Option<Vec<u8>> {
+        let pci = *frame.first()?;
+        match pci >> 4 {
+            // Single frame. A length of 0 escapes to the CAN FD length in the second byte.
+            0 => {
+                self.transfers.remove(&id);
+                let (len, data) = match pci & 0x0F {
+                    0 => (*frame.get(1)? as usize, frame.get(2..)?),
+                    len => (len as usize, &frame[1..]),
+                };
+                data.get(..len).map(<[u8]>::to_vec)
+            }
+            // First frame. A length of 0 escapes to a 32 bit length.
+            1 => {
+                let len = usize::from(pci & 0x0F) << 8 | usize::from(*frame.get(1)?);
+                let (len, data) = if len == 0 {
+                    let len = u32::from_be_bytes(frame.get(2..6)?.try_into().ok()?);
+                    (len as usize, frame.get(6..)?)
+                } else {
+                    (len, &frame[2..])
+                };
+                if self.transfers.contains_key(&id) {
+                    internal::emit(format!("ISO-TP 0x{id:x}: incomplete message dropped"));
+                }
+                let transfer = Transfer {
+                    len,
+                    data: data.to_vec(),
+                    sequence: 1,
+                };
+                self.transfers.insert(id, transfer);
+                None
+            }
+            // Consecutive frame
+            2 => {
+                let transfer = self.transfers.get_mut(&id)?;
+                if pci & 0x0F != transfer.sequence {
+                    internal::emit(format!(
+                        "ISO-TP 0x{:x}: expected sequence number {} but got {}",
+                        id,
+                        transfer.sequence,
+                        pci & 0x0F
+                    ));
+                    self.transfers.remove(&id);
+                    return None;
+                }
+                transfer.sequence = (transfer.sequence + 1) & 0x0F;
+                transfer.data.extend_from_slice(&frame[1..]);
+                if transfer.data.len() >= transfer.len {
+                    let mut transfer = self.transfers.remove(&id)?;
+                    transfer.data.truncate(transfer.len);
+                    Some(transfer.data)
+                } else {
+                    None
+                }
+            }
+            // Flow control frames don't carry data
+            _ => None,
+        }
+    }
+
+    /// True if `id` is the response id of an ECU
+    pub fn is_response(&self, id: u32) -> bool {
+        self.pairs.iter().any(|(_, res)| *res == id)
+    }
+}
+
+/// Level and description of the UDS message `payload`
+pub fn describe(payload: &[u8], response: bool) -> (Level, String) {
+    let hex = |data: &[u8]| {
+        data.iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let sid = match payload.first() {
+        Some(sid) => *sid,
+        None => return (Level::Info, "Empty message".into()),
+    };
+
+    if sid == 0x7F && payload.len() >= 3 {
+        let service = payload[1];
+        let code = payload[2];
+        let message = format!(
+            "{} (0x{:02x}) negative response: {} (0x{:02x})",
+            service_name(service).unwrap_or("Unknown service"),
+            service,
+            response_code_name(code).unwrap_or("unknown"),
+            code
+        );
+        // A pending response is a regular part of long running requests
+        let level = if code == 0x78 {
+            Level::Info
+        } else {
+            Level::Warn
+        };
+        return (level, message);
+    }
+
+    let (service, kind) = if response && sid >= 0x40 {
+        (sid - 0x40, "positive response")
+    } else {
+        (sid, "request")
+    };
+    let message = format!(
+        "{} (0x{:02x}) {}: {}",
+        service_name(service).unwrap_or("Unknown service"),
+        sid,
+        kind,
+        hex(&payload[1..])
+    );
+    (Level::Info, message)
+}
+
+fn service_name(sid: u8) -> Option<&'static str> {
+    Some(match sid {
+        0x10 => "DiagnosticSessionControl",
+        0x11 => "ECUReset",
+        0x14 => "ClearDiagnosticInformation",
+        0x19 => "ReadDTCInformation",
+        0x22 => "ReadDataByIdentifier",
+        0x23 => "ReadMemoryByAddress",
+        0x24 => "ReadScalingDataByIdentifier",
+        0x27 => "SecurityAccess",
+        0x28 => "CommunicationControl",
+        0x29 => "Authentication",
+        0x2A => "ReadDataByPeriodicIdentifier",
+        0x2C => "DynamicallyDefineDataIdentifier",
+        0x2E => "WriteDataByIdentifier",
+        0x2F => "InputOutputControlByIdentifier",
+        0x31 => "RoutineControl",
+        0x34 => "RequestDownload",
+        0x35 => "RequestUpload",
+        0x36 => "TransferData",
+        0x37 => "RequestTransferExit",
+        0x38 => "RequestFileTransfer",
+        0x3D => "WriteMemoryByAddress",
+        0x3E => "TesterPresent",
+        0x83 => "AccessTimingParameter",
+        0x84 => "SecuredDataTransmission",
+        0x85 => "ControlDTCSetting",
+        0x86 => "ResponseOnEvent",
+        0x87 => "LinkControl",
+        _ => return None,
+    })
+}
+
+fn response_code_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x10 => "generalReject",
+        0x11 => "serviceNotSupported",
+        0x12 => "subFunctionNotSupported",
+        0x13 => "incorrectMessageLengthOrInvalidFormat",
+        0x14 => "responseTooLong",
+        0x21 => "busyRepeatRequest",
+        0x22 => "conditionsNotCorrect",
+        0x24 => "requestSequenceError",
+        0x25 => "noResponseFromSubnetComponent",
+        0x26 => "failurePreventsExecutionOfRequestedAction",
+        0x31 => "requestOutOfRange",
+        0x33 => "securityAccessDenied",
+        0x35 => "invalidKey",
+        0x36 => "exceedNumberOfAttempts",
+        0x37 => "requiredTimeDelayNotExpired",
+        0x70 => "uploadDownloadNotAccepted",
+        0x71 => "transferDataSuspended",
+        0x72 => "generalProgrammingFailure",
+        0x73 => "wrongBlockSequenceCounter",
+        0x78 => "requestCorrectlyReceivedResponsePending",
+        0x7E => "subFunctionNotSupportedInActiveSession",
+        0x7F => "serviceNotSupportedInActiveSession",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn isotp() -> IsoTp {
+        IsoTp {
+            pairs: vec![(0x7E0, 0x7E8)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn single_frame() {
+        let mut isotp = isotp();
+        assert_eq!(
+            isotp.push(0x7E0, &[0x02, 0x10, 0x03, 0xAA, 0xAA]),
+            Some(vec![0x10, 0x03])
+        );
+        // CAN FD escape of the length
+        assert_eq!(isotp.push(0x7E0, &[0x00, 0x01, 0x3E]), Some(vec![0x3E]));
+        // Length exceeds the frame
+        assert_eq!(isotp.push(0x7E0, &[0x05, 0x10, 0x03]), None);
+        assert_eq!(isotp.push(0x7E0, &[]), None);
+    }
+
+    #[test]
+    fn multi_frame() {
+        let mut isotp = isotp();
+        let first = [0x10, 0x0A, 0x62, 0xF1, 0x90, 0x01, 0x02, 0x03];
+        assert_eq!(isotp.push(0x7E8, &first), None);
+        // Flow control of the tester
+        assert_eq!(isotp.push(0x7E0, &[0x30, 0x00, 0x00]), None);
+        assert_eq!(
+            isotp.push(0x7E8, &[0x21, 0x04, 0x05, 0x06, 0x07, 0xAA, 0xAA]),
+            Some(vec![
+                0x62, 0xF1, 0x90, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07
+            ])
+        );
+        assert!(isotp.transfers.is_empty());
+    }
+
+    #[test]
+    fn multi_frame_escaped_length() {
+        let mut isotp = isotp();
+        let first = [0x10, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01, 0x02];
+        assert_eq!(isotp.push(0x7E8, &first), None);
+        assert_eq!(
+            isotp.push(0x7E8, &[0x21, 0x03, 0xAA]),
+            Some(vec![0x01, 0x02, 0x03])
+        );
+    }
+
+    #[test]
+    fn sequence_wraps() {
+        let mut isotp = isotp();
+        // 6 bytes of the first frame and 16 consecutive frames with 7 bytes each
+        let len = 6 + 16 * 7;
+        let mut first = vec![0x10 | (len >> 8) as u8, len as u8];
+        first.extend_from_slice(&[0; 6]);
+        assert_eq!(isotp.push(0x7E8, &first), None);
+        for n in 1..16u8 {
+            assert_eq!(isotp.push(0x7E8, &[0x20 | n, n, n, n, n, n, n, n]), None);
+        }
+        let message = isotp.push(0x7E8, &[0x20, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(message.len(), len);
+        assert_eq!(&message[6..13], &[1; 7]);
+    }
+
+    #[test]
+    fn sequence_error() {
+        let mut isotp = isotp();
+        let first = [0x10, 0x0A, 0x62, 0xF1, 0x90, 0x01, 0x02, 0x03];
+        assert_eq!(isotp.push(0x7E8, &first), None);
+        assert_eq!(isotp.push(0x7E8, &[0x22, 0x04, 0x05, 0x06, 0x07]), None);
+        assert!(isotp.transfers.is_empty());
+        // Consecutive frames without a first frame are ignored
+        assert_eq!(isotp.push(0x7E8, &[0x21, 0x04, 0x05, 0x06, 0x07]), None);
+    }
+
+    #[test]
+    fn single_frame_aborts_transfer() {
+        let mut isotp = isotp();
+        let first = [0x10, 0x0A, 0x62, 0xF1, 0x90, 0x01, 0x02, 0x03];
+        assert_eq!(isotp.push(0x7E8, &first), None);
+        assert_eq!(isotp.push(0x7E8, &[0x01, 0x7E]), Some(vec![0x7E]));
+        assert_eq!(isotp.push(0x7E8, &[0x21, 0x04, 0x05, 0x06, 0x07]), None);
+    }
+
+    #[test]
+    fn ids() {
+        let isotp = isotp();
+        assert!(isotp.handles(0x7E0));
+        assert!(isotp.handles(0x7E8));
+        assert!(!isotp.handles(0x123));
+        assert!(isotp.is_response(0x7E8));
+        assert!(!isotp.is_response(0x7E0));
+    }
+
+    #[test]
+    fn describe_uds() {
+        assert_eq!(
+            describe(&[0x22, 0xF1, 0x90], false),
+            (
+                Level::Info,
+                "ReadDataByIdentifier (0x22) request: f1 90".to_owned()
+            )
+        );
+        assert_eq!(
+            describe(&[0x62, 0xF1, 0x90, 0x41], true),
+            (
+                Level::Info,
+                "ReadDataByIdentifier (0x62) positive response: f1 90 41".to_owned()
+            )
+        );
+        assert_eq!(
+            describe(&[0x7F, 0x27, 0x35], true),
+            (
+                Level::Warn,
+                "SecurityAccess (0x27) negative response: invalidKey (0x35)".to_owned()
+            )
+        );
+        assert_eq!(describe(&[0x7F, 0x31, 0x78], true).0, Level::Info);
+        assert_eq!(describe(&[], true).1, "Empty message");
+    }
+}
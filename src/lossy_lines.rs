@@ -162,10 +162,7 @@ impl Decoder for LossyLinesCodec {
                     // newline, return an error and start discarding on the
                     // next call.
                     self.is_discarding = true;
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "line length limit exceeded",
-                    ))
+                    Err(io::Error::other("line length limit exceeded"))
                 } else {
                     // We didn't find a line or reach the length limit, so the next
                     // call will resume searching at the current offset.
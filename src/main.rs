@@ -18,90 +18,265 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use clap::value_t;
 use failure::Error;
 use futures::{sync::oneshot, Future, Sink, Stream};
-use rogcat::{parser, record::Record};
-use std::{env, process::exit, str::FromStr};
+use rogcat::record::Record;
+use std::{env, panic::AssertUnwindSafe, process::exit, str::FromStr};
 use tokio::runtime::Runtime;
 use tokio_signal::ctrl_c;
 use url::Url;
 
+mod bugreport;
 mod cli;
+mod console;
+mod crashes;
+mod diagnostics;
+mod error;
 mod filewriter;
 mod filter;
+mod forwarder;
+mod generate;
+mod interactive;
+mod internal;
+#[cfg(target_os = "linux")]
+mod isotp;
 mod lossy_lines;
+mod notify;
+mod pipeline;
 mod profiles;
+mod progress;
+mod props;
 mod reader;
+mod recorder;
+mod resolver;
+mod stats;
 mod subcommands;
+mod symbolizer;
 mod terminal;
+mod tui;
 mod utils;
+mod wireless;
 
 const DEFAULT_BUFFER: [&str; 4] = ["main", "events", "crash", "kernel"];
 
 #[derive(Debug, Clone)]
 pub enum StreamData {
-    Record(Record),
+    Record(Box<Record>),
     Line(String),
+    /// Source e.g the file or peer the following lines are read from
+    Source(String),
 }
 
 type LogStream = Box<dyn Stream<Item = StreamData, Error = Error> + Send>;
 type LogSink = Box<dyn Sink<SinkItem = Record, SinkError = Error> + Send>;
+type LogRecords = Box<dyn Stream<Item = Record, Error = Error> + Send>;
 
 fn run() -> Result<(), Error> {
-    let args = cli::cli().get_matches();
-    utils::config_init();
+    let args = cli::cli().get_matches_safe().unwrap_or_else(|e| {
+        // Help and version are printed to stdout and exit with 0
+        if !e.use_stderr() {
+            e.exit()
+        }
+        eprintln!("{}", e.message);
+        exit(error::EXIT_CONFIG)
+    });
+    utils::config_init(&args)?;
     subcommands::run(&args);
 
+    // Year and utc offset of the device used to complete logcat timestamps
+    let mut device_date = None;
+    // Boot time of the device used to convert kernel uptime stamps
+    let mut boot_time = None;
+    // Event log tags are pulled from the device if logcat is captured
+    let mut device = false;
+    // The format of stdin and ffx is detected from the first lines
+    let mut detect = false;
+
     let source = {
-        if args.is_present("input") {
+        if args.is_present("watch") || args.is_present("follow") {
+            reader::watch(&args)?
+        } else if args.is_present("inputs") {
             reader::files(&args)?
         } else if args.is_present("fuchsia") || env::args().next() == Some("ffxcat".into()) {
+            detect = true;
             reader::fuchsia(&args)?
         } else {
             match args.value_of("COMMAND") {
                 Some(c) => {
                     if c == "-" {
-                        reader::stdin()
+                        detect = true;
+                        reader::stdin(reader::Framing::from_args(&args))
                     } else if let Ok(url) = Url::parse(c) {
                         match url.scheme() {
                             #[cfg(target_os = "linux")]
-                            "can" => reader::can(url.host_str().expect("Invalid can device"))?,
-                            "tcp" => reader::tcp(&url)?,
-                            "serial" => reader::serial(&args),
+                            "can" => reader::can(&args, &url)?,
+                            "tcp" => reader::tcp(&args, &url)?,
+                            "listen" | "listen+udp" | "udp" => reader::listen(&args, &url)?,
+                            "serial" => reader::serial(&args)?,
                             _ => reader::process(&args)?,
                         }
                     } else {
                         reader::process(&args)?
                     }
                 }
-                None => reader::logcat(&args)?,
+                None if utils::stdin_piped() && args.subcommand_name().is_none() => {
+                    detect = true;
+                    reader::stdin(reader::Framing::from_args(&args))
+                }
+                None => {
+                    utils::select_device(&args)?;
+                    device_date = utils::device_date(&args);
+                    boot_time = utils::device_boot_time(&args);
+                    device = true;
+                    reader::logcat(&args)?
+                }
             }
         }
     };
 
+    if let Some(tags) = utils::event_tags(&args, device)? {
+        tags.install();
+    }
+
     let profile = profiles::from_args(&args)?;
-    let sink = if args.is_present("output") {
-        filewriter::try_from(&args)?
+    profiles::watch(&args)?;
+    // Output to a file or collector passed with `-o` or set in the profile
+    let output = args.is_present("output") || profile.output.is_some();
+    for arg in &[
+        "overwrite",
+        "records-per-file",
+        "filename-format",
+        "format-file",
+    ] {
+        if !output && args.is_present(arg) {
+            return Err(error::RogcatError::Config(format!(
+                "--{} requires --output or an output in the profile",
+                arg
+            ))
+            .into());
+        }
+    }
+    // The output of the flight recorder is written on triggers only
+    let sink = if output && !args.is_present("flight-recorder") {
+        let file = filewriter::try_from(&args, &profile)?;
+        // Display the records with `--format` on the terminal as well
+        if args.is_present("format-file") {
+            pipeline::fanout(vec![terminal::try_from(&args, &profile)?, file])
+        } else {
+            file
+        }
     } else {
         terminal::try_from(&args, &profile)?
     };
+    let sink = recorder::wrap(&args, &profile, sink)?;
+    let sink = pipeline::per_tag(&args, sink);
 
     // Stop process after n records if argument head is passed
     let mut head = args
         .value_of("head")
         .map(|v| usize::from_str(v).expect("Invalid head arguement"));
 
+    // Slice the records by their index
+    let (skip, end) = match args.value_of("range") {
+        Some(range) => utils::parse_range(range)?,
+        None if args.is_present("skip") => (
+            value_t!(args, "skip", u64).unwrap_or_else(|e| e.exit()),
+            None,
+        ),
+        None => (0, None),
+    };
+
+    // Keep the last n records of input files. Logcat dumps its tail itself.
+    let tail = if args.is_present("inputs") && args.is_present("tail") {
+        Some(value_t!(args, "tail", usize).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+
+    // Stop process after a duration from the first record
+    let duration = args
+        .value_of("for")
+        .map(utils::parse_duration)
+        .transpose()?;
+
+    let overrides = pipeline::LevelOverrides::from_profile(&profile)?;
+    let rewrite = pipeline::Rewrite::from_args_profile(&args, &profile)?;
+    let resolver = resolver::Resolver::from_args(&args)?;
+    let symbolizer = symbolizer::Symbolizer::from_args(&args)?;
+    let annotate = pipeline::Annotate::from_args(&args)?;
+    let hash = pipeline::Hash::from_args(&args)?;
+    let on_match = pipeline::OnMatch::from_args_profile(&args, &profile)?;
+    let mut notifier = notify::Notifier::from_args_profile(&args, &profile)?;
     let filter = filter::from_args_profile(&args, &profile)?;
-    let mut parser = parser::Parser::default();
+    let filter_stats = filter.stats();
+    let filter_stats_done = filter.stats();
+    let mut parser = pipeline::LineParser::from_args(&args, &profile, device_date, boot_time)?;
+    if detect {
+        parser.detect_format(&args);
+    }
+    // Parse and filter files on multiple threads
+    let jobs = args
+        .value_of("jobs")
+        .and_then(|jobs| usize::from_str(jobs).ok())
+        .filter(|jobs| *jobs > 1);
+    let stats = stats::Collector::from_args(&args)?;
+    let stats_records = stats.clone();
+    let stats_done = stats.clone();
 
     let mut runtime = Runtime::new()?;
 
-    let f = source
-        .map(move |a| match a {
-            StreamData::Line(line) => parser.parse(line),
-            StreamData::Record(record) => record,
+    // Interleave the records of the input files by time instead of concatenating them
+    let source = if args.is_present("merge-by-time") {
+        pipeline::merge_by_time(reader::each_file(&args)?, &parser)
+    } else {
+        source
+    };
+
+    let mut prepare = pipeline::Prepare::new(
+        args.clone(),
+        overrides,
+        rewrite,
+        symbolizer,
+        resolver,
+        filter,
+    );
+    let records: LogRecords = match jobs {
+        Some(jobs) => pipeline::parallel(source, parser, prepare, jobs),
+        None => Box::new(
+            parser
+                .records(source)
+                .filter_map(move |record| prepare.apply(record)),
+        ),
+    };
+
+    let records = records.skip(skip);
+    let records: LogRecords = match end {
+        Some(end) => Box::new(records.take(end - skip)),
+        None => Box::new(records),
+    };
+    let records: LogRecords = match tail {
+        Some(count) => Box::new(pipeline::tail(records, count)),
+        None => records,
+    };
+
+    let records = records
+        .map(move |record| match annotate {
+            Some(ref annotate) => annotate.apply(record),
+            None => record,
+        })
+        .map(move |record| match hash {
+            Some(ref hash) => hash.apply(record),
+            None => record,
+        })
+        .inspect(move |record| {
+            if let Some(ref on_match) = on_match {
+                on_match.apply(record);
+            }
+            if let Some(ref mut notifier) = notifier {
+                notifier.apply(record);
+            }
         })
-        .filter(move |r| filter.filter(r))
         .take_while(move |_| {
             Ok(match head {
                 Some(0) => false,
@@ -112,9 +287,59 @@ fn run() -> Result<(), Error> {
                 None => true,
             })
         })
-        .forward(sink)
-        .map(|_| exit(0))
-        .map_err(|e| eprintln!("{e}"));
+        .inspect(move |record| {
+            if let Some(ref stats) = stats_records {
+                stats.add(record);
+            }
+        });
+    let records: LogRecords = match duration {
+        Some(duration) => Box::new(pipeline::until(records, duration)),
+        None => Box::new(records),
+    };
+    let records: LogRecords = if args.is_present("no-internal") {
+        Box::new(records)
+    } else {
+        Box::new(internal::merge(records))
+    };
+
+    // Keep ingesting live sources if the terminal is slower
+    let records = if output || args.is_present("inputs") {
+        records
+    } else {
+        pipeline::decouple(records, &runtime.executor())
+    };
+
+    let f = records.forward(sink).then(move |result| -> Result<(), ()> {
+        match result {
+            // A closed stdout is a regular end of processing e.g `rogcat | head`
+            Err(ref e) if e.downcast_ref::<terminal::BrokenPipe>().is_none() => {
+                interactive::restore();
+                eprintln!("{e}");
+                exit(error::exit_code(e))
+            }
+            result => {
+                // Drop the sink to let file writers flush e.g html
+                drop(result);
+                filter_stats_done.finish();
+                if let Some(stats) = stats_done {
+                    if let Err(e) = stats.finish() {
+                        interactive::restore();
+                        eprintln!("{e}");
+                        exit(error::exit_code(&e))
+                    }
+                }
+                // The interactive mode keeps the output open until the user quits
+                if interactive::active() {
+                    return Ok(());
+                }
+                exit(0)
+            }
+        }
+    });
+    // Drop and flush the sinks if the processing panics
+    let f = AssertUnwindSafe(f)
+        .catch_unwind()
+        .then(|result| result.unwrap_or_else(|_| exit(101)));
     let mut f = Some(oneshot::spawn(f, &runtime.executor()));
 
     // Cancel stream processing on ctrl-c
@@ -123,14 +348,22 @@ fn run() -> Result<(), Error> {
         Ok(())
     }))?;
 
+    filter_stats.finish();
+    if let Some(stats) = stats {
+        stats.finish()?;
+    }
+
     Ok(())
 }
 
 fn main() {
-    match run() {
+    diagnostics::install();
+    let result = run();
+    interactive::restore();
+    match result {
         Err(e) => {
             eprintln!("{e}");
-            exit(1)
+            exit(error::exit_code(&e))
         }
         Ok(_) => exit(0),
     }
@@ -0,0 +1,173 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Desktop notifications for records matching the `notify` regexes of the profile
+
+use crate::{error::RogcatError, internal, profiles::Profile};
+use clap::ArgMatches;
+use failure::Error;
+use regex::RegexSet;
+use rogcat::record::Record;
+use std::{
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Minimum interval between two notifications. Matches in between are counted.
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Maximum number of message characters shown in a notification
+const NOTIFY_MESSAGE_LEN: usize = 200;
+
+/// Toast with the title and body read from the environment
+#[cfg(windows)]
+const TOAST_SCRIPT: &str = "\
+[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null; \
+$t = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+$x = $t.GetElementsByTagName('text'); \
+$x.Item(0).AppendChild($t.CreateTextNode($env:ROGCAT_TITLE)) > $null; \
+$x.Item(1).AppendChild($t.CreateTextNode($env:ROGCAT_BODY)) > $null; \
+[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('rogcat').Show([Windows.UI.Notifications.ToastNotification]::new($t))";
+
+pub struct Notifier {
+    patterns: RegexSet,
+    last: Option<Instant>,
+    /// Matches suppressed since the last notification
+    suppressed: usize,
+    /// Cleared if the notification command is not available
+    available: bool,
+}
+
+impl Notifier {
+    /// Notifications for the `--notify` regexes and the `notify` regexes of the profile
+    pub fn from_args_profile(
+        args: &ArgMatches,
+        profile: &Profile,
+    ) -> Result<Option<Notifier>, Error> {
+        let patterns = args
+            .values_of("notify")
+            .unwrap_or_default()
+            .chain(profile.notify.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        let patterns = RegexSet::new(patterns)
+            .map_err(|e| RogcatError::Config(format!("Invalid notify regex: {}", e)))?;
+        Ok(Some(Notifier {
+            patterns,
+            last: None,
+            suppressed: 0,
+            available: true,
+        }))
+    }
+
+    pub fn apply(&mut self, record: &Record) {
+        if internal::is_internal(record)
+            || !self.available
+            || !self.patterns.is_match(&record.message)
+        {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            if now.duration_since(last) < NOTIFY_INTERVAL {
+                self.suppressed += 1;
+                return;
+            }
+        }
+        self.last = Some(now);
+
+        let title = format!("{:?} {}", record.level, record.tags.join(" "));
+        let mut body = record
+            .message
+            .chars()
+            .take(NOTIFY_MESSAGE_LEN)
+            .collect::<String>();
+        if self.suppressed > 0 {
+            body.push_str(&format!(" (and {} more)", self.suppressed));
+            self.suppressed = 0;
+        }
+        self.notify(&title, &body);
+    }
+
+    /// Show a notification with the tools of the platform and wait for them on a thread
+    fn notify(&mut self, title: &str, body: &str) {
+        #[cfg(target_os = "macos")]
+        let mut command = {
+            let mut command = Command::new("osascript");
+            command
+                .args(["-e", "on run argv"])
+                .args([
+                    "-e",
+                    "display notification (item 2 of argv) with title (item 1 of argv)",
+                ])
+                .args(["-e", "end run", title, body]);
+            command
+        };
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("powershell");
+            command
+                .args(["-NoProfile", "-NonInteractive", "-Command", TOAST_SCRIPT])
+                .env("ROGCAT_TITLE", title)
+                .env("ROGCAT_BODY", body);
+            command
+        };
+        #[cfg(not(any(target_os = "macos", windows)))]
+        let mut command = {
+            let mut command = Command::new("notify-send");
+            // Messages like `--help` or `-t 0` must not be taken as options
+            command.args(["--app-name", "rogcat", "--", title, body]);
+            command
+        };
+
+        let spawned = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(e) => {
+                self.available = false;
+                internal::emit(format!(
+                    "Disabled desktop notifications. Failed to run {}: {}",
+                    command.get_program().to_string_lossy(),
+                    e
+                ));
+            }
+        }
+    }
+}
+
+/// Report the matches suppressed since the last notification at the end of the stream
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        if self.available && self.suppressed > 0 {
+            let body = format!("{} more matching records", self.suppressed);
+            self.suppressed = 0;
+            self.notify("rogcat", &body);
+        }
+    }
+}
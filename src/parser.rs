@@ -18,89 +18,141 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::record::{Level, Record, Timestamp};
+use crate::record::{self, Level, Record, Timestamp};
 use csv::ReaderBuilder;
 use failure::Fail;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::TryInto,
+    fmt::{self, Display, Formatter},
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+};
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take, take_till1, take_until, take_until1, take_while_m_n},
-    character::{
-        complete::{char, hex_digit1, i32, space0, space1},
-        is_digit,
-    },
-    combinator::{map, opt, peek, rest},
-    error::Error,
-    multi::{many0, many1},
+    bytes::complete::{tag, take_till1, take_until, take_until1},
+    character::complete::{char, hex_digit1, space0, space1},
+    combinator::{map, opt, rest},
+    error::{Error, ErrorKind},
     IResult,
 };
 
 use serde::{Deserialize, Serialize};
-use serde_json::from_str;
+use serde_json::{from_str, Value};
 use std::io::{Cursor, Read};
 
-use time::Tm;
+use time::{Timespec, Tm};
 
-#[derive(Fail, Debug)]
-#[fail(display = "{}", _0)]
+#[derive(Debug)]
 pub struct ParserError(String);
 
-trait FormatParser: Send + Sync {
+impl Display for ParserError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Fail for ParserError {}
+
+lazy_static! {
+    /// Start of a record in the threadtime format
+    static ref RECORD_START: Regex = Regex::new(
+        r"((\d{4}-)?\d{2}-\d{2} +\d{1,2}:\d{2}:\d{2}\.\d{3,6}( ?([+-]\d{4}|UTC|GMT))?|\d{10,11}\.\d{3,6}) +[[:xdigit:]]+ +[[:xdigit:]]+ [VDIWEFA] "
+    )
+    .unwrap();
+}
+
+pub trait FormatParser: Send + Sync {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError>;
 }
 
+/// Ascii digits at the start of `line`. Timestamps are parsed for every line and the
+/// nom combinators took the most of the parsing time.
+fn digits(line: &str, min: usize, max: usize) -> IResult<&str, i32> {
+    let len = line
+        .bytes()
+        .take(max)
+        .take_while(u8::is_ascii_digit)
+        .count();
+    if len < min {
+        return Err(nom::Err::Error(Error::new(line, ErrorKind::Digit)));
+    }
+    let value = line.as_bytes()[..len]
+        .iter()
+        .fold(0, |value, digit| value * 10 + i32::from(digit - b'0'));
+    Ok((&line[len..], value))
+}
+
 fn parse_year(line: &str) -> IResult<&str, i32> {
-    //let (line, year) = map(take(4usize), |s: &str| s.parse::<i32>())(line)?;
-    let (line, year) = peek_and_parse_i32(line, 4)?;
-    let (line, _) = take(4usize)(line)?;
+    let (line, year) = digits(line, 4, 4)?;
     let (line, _) = char('-')(line)?;
     Ok((line, year))
 }
 
-fn peek_and_parse_i32(line: &str, n: usize) -> IResult<&str, i32> {
-    let (line, value) = peek(take::<usize, &str, Error<_>>(n))(line)?;
-    let value = i32(value)?;
-    Ok((line, value.1))
+/// Seconds since the epoch of `logcat -v epoch` e.g 1700000000.123 or 1700000000.123456
+fn epoch(line: &str) -> IResult<&str, Tm> {
+    let len = line.bytes().take_while(u8::is_ascii_digit).count();
+    // Shorter numbers are the uptime of `-v monotonic`
+    if !(10..=11).contains(&len) {
+        return Err(nom::Err::Error(Error::new(line, ErrorKind::Digit)));
+    }
+    let seconds = line[..len].parse::<i64>().unwrap_or_default();
+    let (line, _) = char('.')(&line[len..])?;
+    let (line, millis) = digits(line, 3, 3)?;
+    let (line, micros) = opt(|line| digits(line, 3, 3))(line)?;
+    let nsec = millis * 1_000_000 + micros.unwrap_or(0) * 1000;
+    Ok((line, time::at_utc(Timespec::new(seconds, nsec))))
 }
 
-fn take_and_parse_i32(line: &str, n: usize) -> IResult<&str, i32> {
-    let (line, value) = take::<usize, &str, Error<_>>(n)(line)?;
-    let value = i32(value)?;
-    Ok((line, value.1))
+/// Utc offset in seconds e.g +0130 or the zone names UTC and GMT of `logcat -v zone`
+fn zone(line: &str) -> IResult<&str, i32> {
+    if let Some(line) = line
+        .strip_prefix("UTC")
+        .or_else(|| line.strip_prefix("GMT"))
+    {
+        return Ok((line, 0));
+    }
+    match line.as_bytes().first() {
+        Some(sign @ (b'+' | b'-')) => {
+            let sign = if *sign == b'-' { -1 } else { 1 };
+            let (line, hours) = digits(&line[1..], 2, 2)?;
+            let (line, minutes) = digits(line, 2, 2)?;
+            Ok((line, sign * (hours * 60 * 60 + minutes * 60)))
+        }
+        _ => Err(nom::Err::Error(Error::new(line, ErrorKind::Char))),
+    }
 }
 
 // 2017-03-25 19:11:19.052
 // or
 // 2017-03-25 19:11:19.052321
+// or
+// 03-25 19:11:19.052 +0000
+// or
+// 1490469079.052
 fn timestamp(line: &str) -> IResult<&str, Tm> {
+    if let Ok(epoch) = epoch(line) {
+        return Ok(epoch);
+    }
     let (line, year) = opt(parse_year)(line)?;
-    let (line, month) = take_and_parse_i32(line, 2)?;
+    let (line, month) = digits(line, 2, 2)?;
     let (line, _) = char('-')(line)?;
-    let (line, day) = take_and_parse_i32(line, 2)?;
+    let (line, day) = digits(line, 2, 2)?;
     let (line, _) = space0(line)?;
-    let (line, hour) = take_until1(":")(line)?;
-    let hour = i32(hour)?.1;
+    let (line, hour) = digits(line, 1, 2)?;
     let (line, _) = char(':')(line)?;
-    let (line, minute) = take_until1(":")(line)?;
-    let minute = take_and_parse_i32(minute, 2)?.1;
+    let (line, minute) = digits(line, 2, 2)?;
     let (line, _) = char(':')(line)?;
-    let (line, second) = map(take_until1("."), |s: &str| s.parse::<i32>().unwrap_or(0))(line)?;
+    let (line, second) = digits(line, 1, 2)?;
     let (line, _) = char('.')(line)?;
-    let (line, millis) = map(take_while_m_n(3, 3, |c| is_digit(c as u8)), |s| {
-        take_and_parse_i32(s, 3).unwrap().1
-    })(line)?;
-    let (line, micros) = opt(map(take_while_m_n(3, 3, |c| is_digit(c as u8)), |s| {
-        take_and_parse_i32(s, 3).unwrap().1
-    }))(line)?;
-    let (line, sign) = opt(alt((map(char('-'), |_| -1), map(char('+'), |_| 1))))(line)?;
-    let utcoff = match sign {
-        Some(sign) => {
-            let (line, utc_off_hrs) = map(take(2usize), |s: &str| s.parse::<i32>())(line)?;
-            let (_line, utc_off_mins) = map(take(2usize), |s: &str| s.parse::<i32>())(line)?;
-            sign * (utc_off_hrs.unwrap_or(0) * 60 * 60 + utc_off_mins.unwrap_or(0) * 60)
-        }
-        None => 0,
-    };
+    let (line, millis) = digits(line, 3, 3)?;
+    let (line, micros) = opt(|line| digits(line, 3, 3))(line)?;
+    // The zone is attached or separated by a space
+    let (line, utcoff) = zone(line.strip_prefix(' ').unwrap_or(line)).unwrap_or((line, 0));
 
     Ok((
         line,
@@ -109,8 +161,9 @@ fn timestamp(line: &str) -> IResult<&str, Tm> {
             tm_min: minute,
             tm_hour: hour,
             tm_mday: day,
-            tm_mon: month,
-            tm_year: year.unwrap_or(0),
+            tm_mon: month - 1,
+            // Zero marks an unknown year that is completed with the device date
+            tm_year: year.map_or(0, |year| year - 1900),
             tm_wday: 0,
             tm_yday: 0,
             tm_isdst: 0,
@@ -132,33 +185,58 @@ fn level(line: &str) -> IResult<&str, Level> {
     ))(line)
 }
 
-fn printable(line: &str) -> IResult<&str, Record> {
+/// Fields of a record borrowed from the parsed line. Nothing is allocated until a line
+/// is parsed successfully and converted into a `Record`.
+#[derive(Default)]
+struct Fields<'a> {
+    timestamp: Option<Tm>,
+    level: Level,
+    tag: &'a str,
+    process: &'a str,
+    thread: &'a str,
+    message: &'a str,
+}
+
+impl From<Fields<'_>> for Record {
+    fn from(fields: Fields<'_>) -> Record {
+        Record {
+            timestamp: fields.timestamp.map(Timestamp::new),
+            message: fields.message.trim().to_owned(),
+            level: fields.level,
+            tags: vec![fields.tag.trim().to_owned()],
+            process: fields.process.trim().to_owned(),
+            thread: fields.thread.trim().to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+fn printable(line: &str) -> IResult<&str, Fields<'_>> {
     let (line, timestamp) = timestamp(line)?;
-    let (line, _) = many0(space1)(line)?;
+    let (line, _) = space0(line)?;
     let (line, process) = hex_digit1(line)?;
-    let (line, _) = many0(space1)(line)?;
+    let (line, _) = space0(line)?;
     let (line, thread) = hex_digit1(line)?;
-    let (line, _) = many0(space1)(line)?;
+    let (line, _) = space0(line)?;
     let (line, level) = level(line)?;
     let (line, _) = space0(line)?;
     let (line, logtag) = take_until(": ")(line)?;
     let (line, _) = tag(": ")(line)?;
     let (line, message) = opt(rest)(line)?;
 
-    let rec = Record {
-        timestamp: Some(Timestamp::new(timestamp)),
-        message: message.unwrap_or("").trim().to_owned(),
+    let fields = Fields {
+        timestamp: Some(timestamp),
         level,
-        tags: vec![logtag.trim().to_owned()],
-        process: process.trim().to_owned(),
-        thread: thread.trim().to_owned(),
-        ..Default::default()
+        tag: logtag,
+        process,
+        thread,
+        message: message.unwrap_or(""),
     };
 
-    Ok((line, rec))
+    Ok((line, fields))
 }
 
-fn parse_mindroid_short(line: &str) -> IResult<&str, Record> {
+fn parse_mindroid_short(line: &str) -> IResult<&str, Fields<'_>> {
     let (line, level) = level(line)?;
     let (line, _) = char('/')(line)?;
     let (line, logtag) = take_till1(|c| c == '(' || c == ':')(line)?;
@@ -168,40 +246,39 @@ fn parse_mindroid_short(line: &str) -> IResult<&str, Record> {
     let (line, _) = opt(tag(")"))(line)?;
     let (line, _) = opt(tag(": "))(line)?;
     let (line, message) = opt(rest)(line)?;
-    let rec = Record {
-        process: process.unwrap_or("").trim().to_owned(),
-        timestamp: None,
-        message: message.unwrap_or("").trim().to_owned(),
+    let fields = Fields {
         level,
-        tags: vec![logtag.trim().to_owned()],
+        tag: logtag,
+        process: process.unwrap_or(""),
+        message: message.unwrap_or(""),
         ..Default::default()
     };
-    Ok((line, rec))
+    Ok((line, fields))
 }
 
-fn parse_mindroid_long(line: &str) -> IResult<&str, Record> {
+fn parse_mindroid_long(line: &str) -> IResult<&str, Fields<'_>> {
     let (line, timestamp) = timestamp(line)?;
-    let (line, _) = many1(space1)(line)?;
+    let (line, _) = space1(line)?;
     let (line, _) = opt(tag("0x"))(line)?;
     let (line, process) = hex_digit1(line)?;
-    let (line, _) = many1(space1)(line)?;
+    let (line, _) = space1(line)?;
     let (line, level) = level(line)?;
     let (line, _) = space0(line)?;
     let (line, logtag) = take_until(": ")(line)?;
     let (line, _) = tag(": ")(line)?;
     let (line, message) = opt(rest)(line)?;
-    let rec = Record {
-        process: process.trim().to_owned(),
-        timestamp: Some(Timestamp::new(timestamp)),
-        message: message.unwrap_or("").trim().to_owned(),
+    let fields = Fields {
+        timestamp: Some(timestamp),
         level,
-        tags: vec![logtag.trim().to_owned()],
+        tag: logtag,
+        process,
+        message: message.unwrap_or(""),
         ..Default::default()
     };
-    Ok((line, rec))
+    Ok((line, fields))
 }
 
-fn parse_mindroid(line: &str) -> IResult<&str, Record> {
+fn parse_mindroid(line: &str) -> IResult<&str, Fields<'_>> {
     let mindroid = alt((parse_mindroid_short, parse_mindroid_long))(line)?;
     Ok(mindroid)
 }
@@ -224,13 +301,34 @@ pub fn property(line: &str) -> IResult<&str, (String, String)> {
     Ok((line, (prop.to_string(), val.to_string())))
 }
 
+/// Kernel uptime prefix of dmesg lines like `[114416.534450]` or `<6>[    5.123456]`. Returns
+/// the syslog priority if present, the uptime in seconds and the remaining text.
+pub fn kernel_uptime(s: &str) -> Option<(Option<u8>, f64, &str)> {
+    let (priority, s) = match s.strip_prefix('<') {
+        Some(s) => {
+            let (priority, s) = s.split_once('>')?;
+            (Some(priority.parse::<u8>().ok()?), s)
+        }
+        None => (None, s),
+    };
+    let (uptime, rest) = s.strip_prefix('[')?.split_once(']')?;
+    let uptime = uptime.trim_start();
+    let (secs, fraction) = uptime.split_once('.')?;
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(secs) || !digits(fraction) {
+        return None;
+    }
+    Some((priority, uptime.parse().ok()?, rest.trim_start()))
+}
+
 pub struct DefaultParser;
 
 impl FormatParser for DefaultParser {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        // The nom error contains the remaining input and is not formatted
         printable(line)
-            .map(|(_, record)| record)
-            .map_err(|e| ParserError(format!("{e}")))
+            .map(|(_, fields)| fields.into())
+            .map_err(|_| ParserError("Failed to parse threadtime".to_string()))
     }
 }
 
@@ -239,8 +337,8 @@ pub struct MindroidParser;
 impl FormatParser for MindroidParser {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
         parse_mindroid(line)
-            .map(|(_, record)| record)
-            .map_err(|e| ParserError(format!("{e}")))
+            .map(|(_, fields)| fields.into())
+            .map_err(|_| ParserError("Failed to parse mindroid".to_string()))
     }
 }
 
@@ -259,6 +357,11 @@ impl FormatParser for CsvParser {
             thread: String,
             raw: String,
         }
+        // Building a csv reader is expensive compared to counting the separators of the
+        // seven columns
+        if line.bytes().filter(|b| *b == b',').count() < 6 {
+            return Err(ParserError("Failed to parse csv".to_string()));
+        }
         let reader = Cursor::new(line).chain(Cursor::new([b'\n']));
         let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(reader);
         if let Some(result) = rdr.deserialize::<CsvRecord>().next() {
@@ -272,7 +375,7 @@ impl FormatParser for CsvParser {
                 thread,
                 raw,
             } = record;
-            let record = Record {
+            let mut record = Record {
                 timestamp,
                 message,
                 level,
@@ -280,7 +383,12 @@ impl FormatParser for CsvParser {
                 process,
                 thread,
                 raw,
+                ..Default::default()
             };
+            // Rows without a raw column get a threadtime line instead of the csv text
+            if record.raw.is_empty() {
+                record.raw = record.threadtime();
+            }
             Ok(record)
         } else {
             Err(ParserError("Failed to parse csv".to_string()))
@@ -296,6 +404,106 @@ impl FormatParser for JsonParser {
     }
 }
 
+/// Maximum size in bytes of a json object spanning multiple lines
+const JSON_MAX_SIZE: usize = 64 * 1024;
+/// Maximum nesting depth of a json object spanning multiple lines
+const JSON_MAX_DEPTH: usize = 32;
+/// Maximum number of lines of a json object spanning multiple lines
+const JSON_MAX_LINES: usize = 1024;
+
+/// Result of feeding a line into a `JsonAssembler`
+#[derive(Debug, PartialEq)]
+pub enum Assembled {
+    /// Lines that are not part of a multi line json object
+    Lines(Vec<String>),
+    /// Lines of a complete multi line json object
+    Object(Vec<String>),
+}
+
+/// Assembles pretty printed json objects that span multiple lines by
+/// counting braces outside of strings.
+#[derive(Clone, Default)]
+pub struct JsonAssembler {
+    lines: Vec<String>,
+    size: usize,
+    depth: usize,
+    string: bool,
+    escaped: bool,
+}
+
+impl JsonAssembler {
+    /// Feed `line`. Lines are passed through unless they start or continue
+    /// a json object. Objects exceeding the size, depth or line limits are passed
+    /// through line by line.
+    pub fn push(&mut self, line: String) -> Assembled {
+        if self.lines.is_empty() && !Self::is_start(&line) {
+            return Assembled::Lines(vec![line]);
+        }
+
+        self.size += line.len();
+        let valid = self.scan(&line);
+        self.lines.push(line);
+
+        if !valid || self.size > JSON_MAX_SIZE || self.lines.len() > JSON_MAX_LINES {
+            Assembled::Lines(self.flush())
+        } else if self.depth == 0 {
+            let lines = self.flush();
+            if lines.len() == 1 {
+                Assembled::Lines(lines)
+            } else {
+                Assembled::Object(lines)
+            }
+        } else {
+            Assembled::Lines(Vec::new())
+        }
+    }
+
+    /// Return the buffered lines of an incomplete object
+    pub fn flush(&mut self) -> Vec<String> {
+        let lines = std::mem::take(&mut self.lines);
+        *self = JsonAssembler::default();
+        lines
+    }
+
+    /// An object starts with a brace followed by a key or nothing
+    fn is_start(line: &str) -> bool {
+        let line = line.trim();
+        line.starts_with('{') && (line.len() == 1 || line[1..].trim_start().starts_with('"'))
+    }
+
+    /// Update the nesting depth. Returns false if the line cannot be part of
+    /// a valid object.
+    fn scan(&mut self, line: &str) -> bool {
+        for c in line.chars() {
+            if self.string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.string = false,
+                    _ => (),
+                }
+                continue;
+            }
+            match c {
+                '"' if self.depth > 0 => self.string = true,
+                '{' | '[' => {
+                    self.depth += 1;
+                    if self.depth > JSON_MAX_DEPTH {
+                        return false;
+                    }
+                }
+                '}' | ']' => match self.depth.checked_sub(1) {
+                    Some(depth) => self.depth = depth,
+                    None => return false,
+                },
+                c if self.depth == 0 && !c.is_whitespace() => return false,
+                _ => (),
+            }
+        }
+        true
+    }
+}
+
 // [seconds][pid][tid][tags] LEVEL: message
 // [01086.023158][boot-drivers:dev][driver,platform_bus] INFO: [platform-bus.cc(292)] Boot Item ZBI_TYPE_SERIAL_NUMBER not found
 fn parse_fuchsia(line: &str) -> IResult<&str, Record> {
@@ -372,178 +580,1739 @@ impl FormatParser for FuchsiaParser {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
         parse_fuchsia(line)
             .map(|(_, record)| record)
-            .map_err(|e| ParserError(format!("{e}")))
+            .map_err(|_| ParserError("Failed to parse fuchsia".to_string()))
     }
 }
 
-pub struct Parser(Vec<Box<dyn FormatParser>>);
+/// Log data of `ffx --machine json log`. The data is either wrapped like
+/// `{"data":{"TargetLog":{..}}}` or the plain diagnostics data of e.g `ffx log --machine json dump`.
+fn parse_fuchsia_json(line: &str) -> Option<Record> {
+    let value = from_str::<Value>(line).ok()?;
+    let (data, symbolized) = match value.get("data") {
+        Some(data) => match (data.get("TargetLog"), data.get("SymbolizedTargetLog")) {
+            (Some(log), _) => (log, None),
+            (None, Some(Value::Array(log))) => (log.first()?, log.get(1).and_then(Value::as_str)),
+            _ => return None,
+        },
+        None => (&value, None),
+    };
+    let metadata = data.get("metadata")?;
+    let root = data.pointer("/payload/root")?;
 
-impl Default for Parser {
-    fn default() -> Self {
-        Parser(vec![
-            Box::new(DefaultParser),
-            Box::new(MindroidParser),
-            Box::new(CsvParser),
-            Box::new(JsonParser),
-            Box::new(FuchsiaParser),
-        ])
+    let string = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    let mut fields = metadata
+        .as_object()?
+        .iter()
+        .filter(|(key, _)| ["pid", "tid", "file", "line", "component_url"].contains(&key.as_str()))
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| (key.clone(), string(value)))
+        .collect::<BTreeMap<_, _>>();
+    if let Some(keys) = root.get("keys").and_then(Value::as_object) {
+        fields.extend(keys.iter().map(|(key, value)| (key.clone(), string(value))));
     }
+
+    let level = match metadata.get("severity")?.as_str()?.to_uppercase().as_str() {
+        "TRACE" => Level::Trace,
+        "DEBUG" => Level::Debug,
+        "INFO" => Level::Info,
+        "WARN" => Level::Warn,
+        "ERROR" => Level::Error,
+        "FATAL" => Level::Fatal,
+        _ => Level::None,
+    };
+    let mut tags = metadata
+        .get("tags")
+        .and_then(Value::as_array)
+        .map(|tags| {
+            tags.iter()
+                .filter_map(Value::as_str)
+                .map(ToOwned::to_owned)
+                .collect()
+        })
+        .unwrap_or_else(Vec::new);
+    tags.sort();
+    let message = symbolized
+        .or_else(|| root.pointer("/message/value").and_then(Value::as_str))
+        .unwrap_or_default();
+
+    Some(Record {
+        // Nanoseconds since boot
+        timestamp: metadata
+            .get("timestamp")
+            .and_then(Value::as_f64)
+            .map(|ns| Timestamp::from_secs(ns / 1e9)),
+        message: message.trim().to_owned(),
+        level,
+        tags,
+        process: data
+            .get("moniker")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned(),
+        thread: fields.get("tid").cloned().unwrap_or_default(),
+        raw: line.to_owned(),
+        fields,
+        ..Default::default()
+    })
 }
 
-impl Parser {
-    pub fn parse(&mut self, raw: String) -> Record {
-        for (index, parser) in self.0.iter().map(Box::as_ref).enumerate() {
-            if let Ok(mut record) = parser.try_parse_str(raw.as_str()) {
-                if index > 0 {
-                    self.0.swap(index, index - 1);
-                }
-                record.raw = raw;
-                return record;
-            }
-        }
+#[derive(Default)]
+pub struct FuchsiaJsonParser;
 
-        // Seems that we cannot parse this record
-        // Treat the raw input as message
-        Record {
-            message: raw.clone(),
-            raw,
-            ..Default::default()
-        }
+impl FormatParser for FuchsiaJsonParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        parse_fuchsia_json(line).ok_or_else(|| ParserError("Failed to parse ffx json".to_string()))
     }
 }
 
-#[test]
-fn parse_level() {
-    assert_eq!(level("V").unwrap().1, Level::Verbose);
-    assert_eq!(level("D").unwrap().1, Level::Debug);
-    assert_eq!(level("I").unwrap().1, Level::Info);
-    assert_eq!(level("W").unwrap().1, Level::Warn);
-    assert_eq!(level("E").unwrap().1, Level::Error);
-    assert_eq!(level("F").unwrap().1, Level::Fatal);
-    assert_eq!(level("A").unwrap().1, Level::Assert);
+// $GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47
+// $GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A
+fn parse_nmea(line: &str) -> Option<Record> {
+    let line = line.trim_end();
+    let sentence = line.strip_prefix('$').or_else(|| line.strip_prefix('!'))?;
+    let (sentence, checksum) = match sentence.rsplit_once('*') {
+        Some((sentence, checksum)) => (sentence, Some(u8::from_str_radix(checksum, 16).ok()?)),
+        None => (sentence, None),
+    };
+    let (address, fields) = sentence.split_once(',')?;
+    if address.len() < 3 || !address.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    // Proprietary sentences start with 'P' and have no talker
+    let (talker, kind) = match address.strip_prefix('P') {
+        Some(kind) => ("P", kind),
+        None => address.split_at(2),
+    };
+
+    // A sentence with an invalid checksum is shown but flagged
+    let level = match checksum {
+        Some(checksum) if sentence.bytes().fold(0, |a, b| a ^ b) != checksum => Level::Error,
+        _ => Level::Info,
+    };
+
+    let fields = fields.split(',').collect::<Vec<_>>();
+    let (time, date) = match kind {
+        "GGA" | "GNS" | "GBS" | "GST" => (fields.first(), None),
+        "RMC" => (fields.first(), fields.get(8).map(|d| d.to_string())),
+        "GLL" => (fields.get(4), None),
+        "ZDA" => (
+            fields.first(),
+            match (fields.get(1), fields.get(2), fields.get(3)) {
+                (Some(d), Some(m), Some(y)) if y.len() == 4 => {
+                    Some(format!("{}{}{}", d, m, &y[2..]))
+                }
+                _ => None,
+            },
+        ),
+        _ => (None, None),
+    };
+    let timestamp = time.and_then(|time| nmea_timestamp(time, date.as_deref()));
+
+    Some(Record {
+        timestamp: timestamp.map(Timestamp::new),
+        message: fields.join(","),
+        level,
+        tags: vec![kind.to_owned(), talker.to_owned()],
+        ..Default::default()
+    })
 }
-#[test]
-fn parser_year() {
-    let date_wrong = "00";
-    let just_date = "2000";
-    let date_with_month = "2000-03";
-    let val = opt(parse_year)(date_wrong);
-    let val2 = opt(parse_year)(date_with_month);
-    let val3 = opt(parse_year)(just_date);
-    assert_eq!(Ok(("00", None)), val);
-    assert_eq!(Ok(("2000", None)), val3);
-    assert_eq!(Ok(("03", Some(2000))), val2);
+
+// Time hhmmss.ss and an optional date ddmmyy
+fn nmea_timestamp(time: &str, date: Option<&str>) -> Option<Tm> {
+    let number = |s: &str, range| s.get(range).and_then(|n: &str| n.parse::<i32>().ok());
+    let (hour, minute, second) = (
+        number(time, 0..2)?,
+        number(time, 2..4)?,
+        number(time, 4..6)?,
+    );
+    let nsec = time
+        .get(6..)
+        .and_then(|f| f.parse::<f64>().ok())
+        .map_or(0, |f| (f * 1e9) as i32);
+    let (day, month, year) = match date {
+        Some(date) => (
+            number(date, 0..2)?,
+            number(date, 2..4)? - 1,
+            number(date, 4..6)? + 100,
+        ),
+        None => (1, 0, 0),
+    };
+    Some(Tm {
+        tm_sec: second,
+        tm_min: minute,
+        tm_hour: hour,
+        tm_mday: day,
+        tm_mon: month,
+        tm_year: year,
+        tm_wday: 0,
+        tm_yday: 0,
+        tm_isdst: 0,
+        tm_utcoff: 0,
+        tm_nsec: nsec,
+    })
 }
 
-#[test]
-fn parse_timestamp() {
-    let ts = timestamp("03-25 19:11:19.054211").unwrap();
-    assert_eq!(00, ts.1.tm_year);
-    assert_eq!(3, ts.1.tm_mon);
-    assert_eq!(25, ts.1.tm_mday);
-    assert_eq!(19, ts.1.tm_hour);
-    assert_eq!(11, ts.1.tm_min);
-    assert_eq!(19, ts.1.tm_sec);
-    timestamp("03-25 7:11:19.052").unwrap();
-    timestamp("2017-03-25 19:11:19.052").unwrap();
-    timestamp("2017-03-25 19:11:19.052123").unwrap();
+pub struct NmeaParser;
+
+impl FormatParser for NmeaParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        parse_nmea(line).ok_or_else(|| ParserError("Failed to parse nmea".to_string()))
+    }
 }
-#[test]
-fn parse_unparseable() {
-    let p = DefaultParser {};
-    assert!(p.try_parse_str("").is_err());
+
+/// Levels of the syslog severities emergency, alert, critical, error, warning, notice, informational
+/// and debug
+const SYSLOG_LEVELS: [Level; 8] = [
+    Level::Fatal,
+    Level::Fatal,
+    Level::Fatal,
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Info,
+    Level::Debug,
+];
+
+/// Level of a syslog severity. Notice is a custom level between info and warn.
+fn syslog_level(severity: usize) -> (Level, Option<u32>) {
+    match severity {
+        5 => record::parse_level("notice"),
+        _ => (SYSLOG_LEVELS[severity].clone(), None),
+    }
 }
 
-#[test]
-fn parse_printable() {
-    let t = "03-01 02:19:45.207     1     2 I EXT4-fs (mmcblk3p8): mounted filesystem with \
-             ordered data mode. Opts: (null)";
-    let p = DefaultParser {};
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(r.level, Level::Info);
-    assert_eq!(r.tags, vec!("EXT4-fs (mmcblk3p8)"));
-    assert_eq!(r.process, "1");
-    assert_eq!(r.thread, "2");
-    assert_eq!(
-        r.message,
-        "mounted filesystem with ordered data mode. Opts: (null)"
-    );
+/// Names of the syslog facilities
+const SYSLOG_FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "audit", "alert", "clock", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+/// Header fields of a syslog message
+struct SyslogHeader<'a> {
+    timestamp: Option<Tm>,
+    host: Option<&'a str>,
+    app: Option<&'a str>,
+    pid: Option<&'a str>,
+    message: &'a str,
+}
 
-    let t = "03-01 02:19:42.868     0     0 D /soc/aips-bus@02100000/usdhc@0219c000: \
-             voltage-ranges unspecified";
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(r.level, Level::Debug);
-    assert_eq!(r.tags, vec!("/soc/aips-bus@02100000/usdhc@0219c000"));
-    assert_eq!(r.process, "0");
-    assert_eq!(r.thread, "0");
-    assert_eq!(r.message, "voltage-ranges unspecified");
+// <34>1 2003-10-11T22:14:15.003Z mymachine.example.com su 123 ID47 - 'su root' failed
+// <34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed
+// Oct 11 22:14:15 mymachine su[123]: 'su root' failed
+fn parse_syslog(line: &str) -> Option<Record> {
+    let (pri, rest) = match line.strip_prefix('<') {
+        Some(rest) => {
+            let (pri, rest) = rest.split_once('>')?;
+            let pri = pri.parse::<usize>().ok().filter(|pri| *pri < 8 * 24)?;
+            (Some(pri), rest)
+        }
+        None => (None, line),
+    };
+    let header = match rest.strip_prefix("1 ") {
+        Some(rest) => syslog_rfc5424(rest)?,
+        None => syslog_rfc3164(rest)?,
+    };
 
-    let t = "11-06 13:58:53.582 31359 31420 I GStreamer+amc: 0:00:00.326067533 0xb8ef2a00";
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(
-        r.timestamp,
-        Some(Timestamp {
-            tm: Tm {
-                tm_year: 0,
-                tm_mon: 11,
-                tm_mday: 6,
-                tm_hour: 13,
-                tm_min: 58,
-                tm_sec: 53,
-                tm_nsec: 582000000,
-                tm_wday: 0,
-                tm_yday: 0,
-                tm_isdst: 0,
-                tm_utcoff: 0,
+    let nil = |field: Option<&str>| field.filter(|f| *f != "-").map(str::to_owned);
+    let tags = nil(header.app)
+        .into_iter()
+        .chain(pri.map(|pri| SYSLOG_FACILITIES[pri >> 3].to_owned()))
+        .chain(nil(header.host))
+        .collect();
+    let (level, severity) = pri.map_or((Level::Info, None), |pri| syslog_level(pri & 7));
+
+    Some(Record {
+        timestamp: header.timestamp.map(Timestamp::new),
+        message: header.message.trim_start_matches('\u{feff}').to_owned(),
+        level,
+        severity,
+        tags,
+        process: nil(header.pid).unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+// 2003-10-11T22:14:15.003Z mymachine.example.com su 123 ID47 [exampleSDID@32473 iut="3"] message
+fn syslog_rfc5424(line: &str) -> Option<SyslogHeader<'_>> {
+    let mut fields = line.splitn(6, ' ');
+    let timestamp = fields.next()?;
+    let host = fields.next()?;
+    let app = fields.next()?;
+    let pid = fields.next()?;
+    let _msgid = fields.next()?;
+    let rest = fields.next().unwrap_or_default();
+
+    // Skip the structured data elements. A ']' in a value is escaped.
+    let message = if let Some(rest) = rest.strip_prefix('-') {
+        rest
+    } else {
+        let mut escaped = false;
+        let mut open = false;
+        let mut end = None;
+        for (n, c) in rest.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '[' if !open => open = true,
+                ']' if open && !escaped => open = false,
+                ' ' if !open => {
+                    end = Some(n);
+                    break;
+                }
+                _ => escaped = false,
             }
-        })
-    );
-    assert_eq!(r.level, Level::Info);
-    assert_eq!(r.tags, vec!("GStreamer+amc"));
-    assert_eq!(r.process, "31359");
-    assert_eq!(r.thread, "31420");
-    assert_eq!(r.message, "0:00:00.326067533 0xb8ef2a00");
+        }
+        end.map_or("", |n| &rest[n..])
+    };
 
-    let t = "11-06 13:58:53.582 31359 31420 A GStreamer+amc: 0:00:00.326067533 0xb8ef2a00";
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(r.level, Level::Assert);
-    assert_eq!(r.tags, vec!("GStreamer+amc"));
-    assert_eq!(r.process, "31359");
-    assert_eq!(r.thread, "31420");
-    assert_eq!(r.message, "0:00:00.326067533 0xb8ef2a00");
+    Some(SyslogHeader {
+        timestamp: syslog_timestamp(timestamp),
+        host: Some(host),
+        app: Some(app),
+        pid: Some(pid),
+        message: message.trim_start(),
+    })
+}
 
-    let t = "03-26 13:17:38.345     0     0 I [114416.534450,0] mdss_dsi_off-: ";
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(r.level, Level::Info);
-    assert_eq!(r.tags, vec!("[114416.534450,0] mdss_dsi_off-"));
-    assert_eq!(r.message, "");
+// 2003-10-11T22:14:15.003+02:00 or 2003-10-11T22:14:15Z
+fn syslog_timestamp(timestamp: &str) -> Option<Tm> {
+    let (date, time) = timestamp.split_once('T')?;
+    let mut date = date.split('-').map(|d| d.parse::<i32>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    let (time, utcoff) = match time.find(['Z', '+', '-']) {
+        Some(n) => {
+            let (time, offset) = time.split_at(n);
+            let utcoff = match offset.split_once(':') {
+                Some((hours, minutes)) => {
+                    let sign = if hours.starts_with('-') { -1 } else { 1 };
+                    let hours = hours[1..].parse::<i32>().ok()?;
+                    sign * (hours * 60 * 60 + minutes.parse::<i32>().ok()? * 60)
+                }
+                None => 0,
+            };
+            (time, utcoff)
+        }
+        None => (time, 0),
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.split(':').map(|t| t.parse::<i32>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    let nsec = if fraction.is_empty() {
+        0
+    } else {
+        let digits = fraction.len().min(9);
+        fraction[..digits].parse::<i32>().ok()? * 10i32.pow(9 - digits as u32)
+    };
+
+    Some(Tm {
+        tm_sec: second,
+        tm_min: minute,
+        tm_hour: hour,
+        tm_mday: day,
+        tm_mon: month - 1,
+        tm_year: year - 1900,
+        tm_wday: 0,
+        tm_yday: 0,
+        tm_isdst: 0,
+        tm_utcoff: utcoff,
+        tm_nsec: nsec,
+    })
 }
 
-#[test]
-fn test_parse_mindroid() {
-    let t = "I/Runtime: Mindroid runtime system node id: 1";
-    let p = MindroidParser {};
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(r.level, Level::Info);
-    assert_eq!(r.tags, vec!("Runtime"));
-    assert_eq!(r.process, "");
-    assert_eq!(r.thread, "");
-    assert_eq!(r.message, "Mindroid runtime system node id: 1");
+// Oct 11 22:14:15 mymachine su[123]: 'su root' failed
+fn syslog_rfc3164(line: &str) -> Option<SyslogHeader<'_>> {
+    // The day is padded with a space e.g "Oct  1"
+    let timestamp = line.get(..15)?.replace("  ", " 0");
+    let timestamp = time::strptime(&timestamp, "%b %d %H:%M:%S").ok()?;
+    let rest = line.get(15..)?.strip_prefix(' ')?;
+    let (host, rest) = rest.split_once(' ')?;
+    let (tag, message) = rest.split_once(": ")?;
+    if tag.is_empty() || tag.contains(' ') {
+        return None;
+    }
+    let (app, pid) = match tag.strip_suffix(']').and_then(|t| t.split_once('[')) {
+        Some((app, pid)) => (app, Some(pid)),
+        None => (tag, None),
+    };
 
-    let t = "D/ServiceManager(000000000000000C): foo bar";
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(r.level, Level::Debug);
-    assert_eq!(r.tags, vec!("ServiceManager"));
-    assert_eq!(r.process, "000000000000000C");
-    assert_eq!(r.thread, "");
-    assert_eq!(r.message, "foo bar");
+    Some(SyslogHeader {
+        timestamp: Some(timestamp),
+        host: Some(host),
+        app: Some(app),
+        pid,
+        message,
+    })
+}
 
-    let t = "D/ServiceManager(0x123): Service MediaPlayer has been created in process main";
-    let r = p.try_parse_str(t).unwrap();
-    assert_eq!(r.level, Level::Debug);
-    assert_eq!(r.tags, vec!("ServiceManager"));
-    assert_eq!(r.process, "123");
-    assert_eq!(r.thread, "");
+pub struct SyslogParser;
+
+impl FormatParser for SyslogParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        parse_syslog(line).ok_or_else(|| ParserError("Failed to parse syslog".to_string()))
+    }
+}
+
+/// Name and parameter names of an Android event log tag
+#[derive(Clone, Debug, Default, PartialEq)]
+struct EventTag {
+    name: String,
+    params: Vec<String>,
+}
+
+/// Android event log tags as listed in `/system/etc/event-log-tags`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventTags {
+    tags: HashMap<u32, EventTag>,
+    /// Tag numbers by name to describe events of the text format
+    names: HashMap<String, u32>,
+}
+
+lazy_static! {
+    static ref EVENT_TAGS: RwLock<EventTags> = RwLock::new(EventTags::default());
+}
+
+impl EventTags {
+    /// Parse lines like `30014 am_proc_start (User|1|5),(PID|1|5),(Process Name|3)`
+    pub fn parse(content: &str) -> EventTags {
+        let mut tags = EventTags::default();
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, char::is_whitespace);
+            let (number, name) = match (fields.next().map(str::parse), fields.next()) {
+                (Some(Ok(number)), Some(name)) if !name.is_empty() => (number, name),
+                _ => continue,
+            };
+            let params = fields
+                .next()
+                .unwrap_or_default()
+                .split("),")
+                .filter_map(|param| {
+                    let param = param.trim().trim_start_matches('(').trim_end_matches(')');
+                    param.split('|').next().filter(|p| !p.is_empty())
+                })
+                .map(ToOwned::to_owned)
+                .collect();
+            tags.names.insert(name.to_owned(), number);
+            tags.tags.insert(
+                number,
+                EventTag {
+                    name: name.to_owned(),
+                    params,
+                },
+            );
+        }
+        tags
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Use `tags` to describe events of the binary and text format
+    pub fn install(self) {
+        *EVENT_TAGS.write().expect("Failed to get event tags lock") = self;
+    }
+
+    /// Tag name and a message with the parameter names like `logcat -v descriptive`
+    fn describe(&self, number: u32, values: &[String], list: bool) -> (String, String) {
+        let tag = match self.tags.get(&number) {
+            Some(tag) => tag,
+            None if list => return (number.to_string(), format!("[{}]", values.join(","))),
+            None => return (number.to_string(), values.join(",")),
+        };
+        let values = values
+            .iter()
+            .enumerate()
+            .map(|(n, value)| match tag.params.get(n) {
+                Some(param) => format!("{param}={value}"),
+                None => value.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let message = if list { format!("[{values}]") } else { values };
+        (tag.name.clone(), message)
+    }
+}
+
+/// Add the parameter names of the installed event tags to an event of the text
+/// format e.g `am_proc_start: [0,1234,10050,com.example]`. The message is kept
+/// if the number of values does not match.
+pub fn describe_event(record: &mut Record) {
+    let tags = EVENT_TAGS.read().expect("Failed to get event tags lock");
+    if tags.is_empty() {
+        return;
+    }
+    let tag = match record
+        .tags
+        .first()
+        .and_then(|name| tags.names.get(name))
+        .and_then(|number| tags.tags.get(number))
+    {
+        Some(tag) if !tag.params.is_empty() => tag,
+        _ => return,
+    };
+    let (values, list) = match record
+        .message
+        .strip_prefix('[')
+        .and_then(|m| m.strip_suffix(']'))
+    {
+        Some(values) => (values.split(',').collect::<Vec<_>>(), true),
+        None => (vec![record.message.as_str()], false),
+    };
+    if values.len() != tag.params.len() {
+        return;
+    }
+    let values = tag
+        .params
+        .iter()
+        .zip(values)
+        .map(|(param, value)| format!("{param}={value}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    record.message = if list { format!("[{values}]") } else { values };
+}
+
+/// Names of the Android log buffers indexed by log id
+const LOG_BUFFERS: &[&str] = &[
+    "main", "radio", "events", "system", "crash", "stats", "security", "kernel",
+];
+
+/// Parse an entry of the logcat binary format (`logcat -B`). Returns the record
+/// and the number of bytes consumed or `None` if `buf` holds an incomplete entry.
+pub fn logger_entry(buf: &[u8]) -> Result<Option<(Record, usize)>, ParserError> {
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+    let u16_at = |o: usize| u16::from_le_bytes([buf[o], buf[o + 1]]) as usize;
+    let u32_at = |o: usize| u32::from_le_bytes([buf[o], buf[o + 1], buf[o + 2], buf[o + 3]]);
+
+    // The header size field is padding in version 1 of the header
+    let len = u16_at(0);
+    let header_size = match u16_at(2) {
+        0 => 20,
+        n if n < 20 => {
+            return Err(ParserError(format!("Invalid logger entry header size {n}")));
+        }
+        n => n,
+    };
+    if buf.len() < header_size + len {
+        return Ok(None);
+    }
+
+    let pid = u32_at(4) as i32;
+    let tid = u32_at(8);
+    let timestamp = time::at(time::Timespec::new(
+        i64::from(u32_at(12)),
+        u32_at(16) as i32,
+    ));
+    let buffer = if header_size >= 24 {
+        LOG_BUFFERS.get(u32_at(20) as usize).copied()
+    } else {
+        None
+    };
+    let uid = if header_size >= 28 {
+        Some(u32_at(24))
+    } else {
+        None
+    };
+
+    let payload = &buf[header_size..header_size + len];
+    let (level, tag, message) = match buffer {
+        Some("events") | Some("stats") | Some("security") => event_payload(payload)?,
+        _ => text_payload(payload)?,
+    };
+
+    let raw = format!(
+        "{}.{:03} {:5} {:5} {} {}: {}",
+        time::strftime("%m-%d %H:%M:%S", &timestamp).unwrap_or_default(),
+        timestamp.tm_nsec / 1_000_000,
+        pid,
+        tid,
+        level,
+        tag,
+        message
+    );
+    let record = Record {
+        timestamp: Some(Timestamp::new(timestamp)),
+        message,
+        level,
+        tags: vec![tag],
+        process: pid.to_string(),
+        thread: tid.to_string(),
+        raw,
+        uid,
+        buffer: buffer.map(ToOwned::to_owned),
+        ..Default::default()
+    };
+    Ok(Some((record, header_size + len)))
+}
+
+/// Priority, tag and message of a text log entry
+fn text_payload(payload: &[u8]) -> Result<(Level, String, String), ParserError> {
+    let (priority, payload) = payload
+        .split_first()
+        .ok_or_else(|| ParserError("Empty logger entry".to_string()))?;
+    let level = match priority {
+        2 => Level::Verbose,
+        3 => Level::Debug,
+        4 => Level::Info,
+        5 => Level::Warn,
+        6 => Level::Error,
+        7 => Level::Fatal,
+        _ => Level::None,
+    };
+    let mut fields = payload.splitn(2, |b| *b == 0);
+    let tag = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+    let message = fields.next().unwrap_or_default();
+    let message = String::from_utf8_lossy(message)
+        .trim_end_matches(['\0', '\n'])
+        .to_owned();
+    Ok((level, tag, message))
+}
+
+/// Tag name and decoded values of a binary event
+fn event_payload(payload: &[u8]) -> Result<(Level, String, String), ParserError> {
+    fn value(buf: &[u8], depth: usize) -> Option<(String, &[u8])> {
+        let (kind, buf) = buf.split_first()?;
+        match kind {
+            0 if buf.len() >= 4 => Some((
+                i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]).to_string(),
+                &buf[4..],
+            )),
+            1 if buf.len() >= 8 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&buf[..8]);
+                Some((i64::from_le_bytes(bytes).to_string(), &buf[8..]))
+            }
+            2 if buf.len() >= 4 => {
+                let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+                let s = buf.get(4..4 + len)?;
+                Some((String::from_utf8_lossy(s).into_owned(), &buf[4 + len..]))
+            }
+            3 if !buf.is_empty() && depth < 8 => {
+                let (count, mut buf) = buf.split_first()?;
+                let mut items = Vec::with_capacity(*count as usize);
+                for _ in 0..*count {
+                    let (item, rest) = value(buf, depth + 1)?;
+                    items.push(item);
+                    buf = rest;
+                }
+                Some((format!("[{}]", items.join(",")), buf))
+            }
+            4 if buf.len() >= 4 => Some((
+                f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]).to_string(),
+                &buf[4..],
+            )),
+            _ => None,
+        }
+    }
+
+    if payload.len() < 4 {
+        return Err(ParserError("Invalid event".to_string()));
+    }
+    let tag = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+    let invalid = || ParserError(format!("Invalid event payload of tag {tag}"));
+
+    // The values of a list are described individually
+    let (values, list) = match payload[4..].split_first() {
+        Some((3, [count, buf @ ..])) => {
+            let mut buf = buf;
+            let mut values = Vec::with_capacity(*count as usize);
+            for _ in 0..*count {
+                let (value, rest) = value(buf, 1).ok_or_else(invalid)?;
+                values.push(value);
+                buf = rest;
+            }
+            (values, true)
+        }
+        _ => (vec![value(&payload[4..], 0).ok_or_else(invalid)?.0], false),
+    };
+    let (tag, message) = EVENT_TAGS
+        .read()
+        .expect("Failed to get event tags lock")
+        .describe(tag, &values, list);
+    Ok((Level::Info, tag, message))
+}
+
+/// Buffer name of a logcat divider line like `--------- beginning of main`. The flag is true
+/// for the `switch to` dividers printed with `logcat -D` whenever the buffer changes.
+pub fn logcat_divider(line: &str) -> Option<(&str, bool)> {
+    let divider = line.trim_end().strip_prefix("--------- ")?;
+    if let Some(buffer) = divider.strip_prefix("beginning of ") {
+        Some((buffer, false))
+    } else {
+        divider
+            .strip_prefix("switch to ")
+            .map(|buffer| (buffer, true))
+    }
+}
+
+/// True if `message` contains the start of another logcat record
+pub fn has_record_start(message: &str) -> bool {
+    RECORD_START.is_match(message)
+}
+
+/// Split a line at record starts that are embedded after the beginning of the
+/// line e.g caused by interleaved writes. Returns `None` if there are none.
+pub fn split_garbled(line: &str) -> Option<Vec<&str>> {
+    let starts = RECORD_START
+        .find_iter(line)
+        .map(|m| m.start())
+        .filter(|start| *start > 0)
+        .collect::<Vec<_>>();
+    if starts.is_empty() {
+        return None;
+    }
+
+    let mut parts = Vec::with_capacity(starts.len() + 1);
+    let mut offset = 0;
+    for start in starts {
+        parts.push(&line[offset..start]);
+        offset = start;
+    }
+    parts.push(&line[offset..]);
+    Some(parts)
+}
+
+/// Record of a CAN frame in the format of the SocketCAN reader
+fn can_record(
+    timestamp: Timestamp,
+    channel: u32,
+    id: u32,
+    extended: bool,
+    tx: bool,
+    data: &[u8],
+) -> Record {
+    let hex = |separator| {
+        data.iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+    let process = format!("can{channel}");
+    let raw = format!(
+        "({}) {} {}#{}",
+        time::strftime("%s.%f", &timestamp).unwrap_or_default(),
+        process,
+        if extended {
+            format!("{id:08X}")
+        } else {
+            format!("{id:X}")
+        },
+        hex("")
+    );
+    Record {
+        timestamp: Some(timestamp),
+        message: format!("{} {} ", if extended { "E" } else { " " }, hex(" ")),
+        tags: vec![format!("0x{id:x}")],
+        process,
+        thread: if tx { "Tx" } else { "Rx" }.to_owned(),
+        can_id: Some(id),
+        raw,
+        ..Default::default()
+    }
+}
+
+/// Record of a CAN error frame
+fn can_error_record(timestamp: Timestamp, channel: u32) -> Record {
+    let process = format!("can{channel}");
+    Record {
+        raw: format!(
+            "({}) {} ErrorFrame",
+            time::strftime("%s.%f", &timestamp).unwrap_or_default(),
+            process
+        ),
+        timestamp: Some(timestamp),
+        message: "Error frame".to_owned(),
+        level: Level::Error,
+        process,
+        ..Default::default()
+    }
+}
+
+// Vector ASC with hexadecimal ids and relative timestamps
+//    0.015991 1  7E0             Tx   d 8 02 10 03 00 00 00 00 00  Length = 227000 BitCount = 117
+//    1.000000 2  18FEF100x       Rx   d 3 ff 00 12
+//    2.501000 1  ErrorFrame
+//    3.000000 CANFD   1 Rx        7E8                                   1 0 8  8 02 50 03 00 00 00 00 00
+fn parse_asc(line: &str) -> Option<Record> {
+    let mut tokens = line.split_whitespace();
+    let time = tokens.next().filter(|t| t.contains('.'))?;
+    let timestamp = Timestamp::from_secs(time.parse::<f64>().ok()?);
+
+    let fd = tokens.clone().next() == Some("CANFD");
+    if fd {
+        tokens.next();
+    }
+    let channel = tokens.next()?.parse::<u32>().ok()?;
+    let id = tokens.next()?;
+    if id == "ErrorFrame" {
+        return Some(can_error_record(timestamp, channel));
+    }
+
+    let (id, dir) = if fd {
+        // The direction precedes the id of CAN FD frames
+        (tokens.next()?, id)
+    } else {
+        (id, tokens.next()?)
+    };
+    let (id, extended) = match id.strip_suffix('x') {
+        Some(id) => (id, true),
+        None => (id, false),
+    };
+    let id = u32::from_str_radix(id, 16).ok()?;
+    let tx = match dir {
+        "Tx" => true,
+        "Rx" => false,
+        _ => return None,
+    };
+
+    let len = if fd {
+        // An optional symbolic name is followed by the bit rate switch, error state indicator,
+        // dlc and data length
+        let mut tokens = tokens.by_ref().skip_while(|t| t.parse::<u8>().is_err());
+        let _brs = tokens.next()?;
+        let _esi = tokens.next()?;
+        let _dlc = tokens.next()?;
+        tokens.next()?.parse::<usize>().ok()?
+    } else {
+        match tokens.next()? {
+            "d" => usize::from_str_radix(tokens.next()?, 16).ok()?.min(8),
+            "r" => 0,
+            _ => return None,
+        }
+    };
+    let data = tokens
+        .take(len)
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    if data.len() != len {
+        return None;
+    }
+
+    Some(can_record(timestamp, channel, id, extended, tx, &data))
+}
+
+pub struct AscParser;
+
+impl FormatParser for AscParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        parse_asc(line).ok_or_else(|| ParserError("Failed to parse asc".to_string()))
+    }
+}
+
+/// Magic of Vector BLF files
+pub const BLF_MAGIC: &[u8] = b"LOGG";
+const BLF_OBJECT_MAGIC: &[u8] = b"LOBJ";
+const BLF_OBJECT_HEADER_SIZE: usize = 16;
+/// Upper bound of the file header, objects and uncompressed log containers
+const BLF_MAX_SIZE: usize = 64 * 1024 * 1024;
+const BLF_LOG_CONTAINER: u32 = 10;
+const BLF_CAN_MESSAGE: u32 = 1;
+const BLF_CAN_ERROR: u32 = 2;
+const BLF_CAN_ERROR_EXT: u32 = 73;
+const BLF_CAN_MESSAGE2: u32 = 86;
+const BLF_CAN_FD_MESSAGE: u32 = 100;
+const BLF_CAN_FD_MESSAGE_64: u32 = 101;
+/// Flag of object timestamps in nanoseconds instead of 10 microseconds
+const BLF_TIME_ONE_NANS: u32 = 2;
+/// Flag of extended CAN ids
+const BLF_EXTENDED_ID: u32 = 0x8000_0000;
+
+/// Reads the CAN frames of Vector BLF (binary logging format) files as records
+pub struct BlfReader<R> {
+    reader: R,
+    /// Start of the measurement
+    start: time::Timespec,
+    /// Uncompressed objects of log containers
+    buffer: Vec<u8>,
+    offset: usize,
+}
+
+impl<R: Read> BlfReader<R> {
+    /// Read the file header of `reader`
+    pub fn new(mut reader: R) -> Result<BlfReader<R>, ParserError> {
+        let error = |e| ParserError(format!("Failed to read blf header: {e}"));
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header).map_err(error)?;
+        if &header[..4] != BLF_MAGIC {
+            return Err(ParserError("Not a blf file".to_string()));
+        }
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if size > BLF_MAX_SIZE {
+            return Err(ParserError(format!("Invalid blf header size {size}")));
+        }
+        let mut rest = vec![0u8; size.saturating_sub(header.len())];
+        reader.read_exact(&mut rest).map_err(error)?;
+
+        // The start time is a SYSTEMTIME at offset 48 of the header
+        let u16_at = |n: usize| {
+            rest.get(n - 8..n - 6)
+                .map_or(0, |b| i32::from(u16::from_le_bytes([b[0], b[1]])))
+        };
+        let start = time::Tm {
+            tm_year: u16_at(48) - 1900,
+            tm_mon: u16_at(50) - 1,
+            tm_mday: u16_at(54),
+            tm_hour: u16_at(56),
+            tm_min: u16_at(58),
+            tm_sec: u16_at(60),
+            tm_nsec: u16_at(62) * 1_000_000,
+            ..time::empty_tm()
+        }
+        .to_timespec();
+
+        Ok(BlfReader {
+            reader,
+            start,
+            buffer: Vec::new(),
+            offset: 0,
+        })
+    }
+
+    /// Read the next object of the file. Log containers are uncompressed into the buffer.
+    /// Returns false at the end of the file.
+    fn fill(&mut self) -> Result<bool, ParserError> {
+        let error = |e| ParserError(format!("Failed to read blf object: {e}"));
+        let mut header = [0u8; BLF_OBJECT_HEADER_SIZE];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(error(e)),
+        }
+        if &header[..4] != BLF_OBJECT_MAGIC {
+            return Err(ParserError("Invalid blf object".to_string()));
+        }
+        let size = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let kind = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+        if size > BLF_MAX_SIZE {
+            return Err(ParserError(format!("Invalid blf object size {size}")));
+        }
+        // Objects are padded to 4 bytes
+        let mut object = vec![0u8; size.saturating_sub(BLF_OBJECT_HEADER_SIZE) + size % 4];
+        self.reader.read_exact(&mut object).map_err(error)?;
+
+        // Drop parsed objects
+        self.buffer.drain(..self.offset);
+        self.offset = 0;
+
+        if kind != BLF_LOG_CONTAINER {
+            self.buffer.extend_from_slice(&header);
+            self.buffer.extend_from_slice(&object);
+            return Ok(true);
+        }
+
+        // Compression method followed by 14 bytes of reserved fields and the uncompressed size
+        let invalid = || ParserError("Invalid blf container".to_string());
+        let data = size
+            .checked_sub(BLF_OBJECT_HEADER_SIZE)
+            .and_then(|end| object.get(16..end))
+            .ok_or_else(invalid)?;
+        match u16::from_le_bytes([object[0], object[1]]) {
+            0 => self.buffer.extend_from_slice(data),
+            2 => {
+                // Stop at the limit instead of inflating arbitrary amounts of data
+                let limit = BLF_MAX_SIZE.saturating_sub(self.buffer.len()) as u64;
+                let len = flate2::read::ZlibDecoder::new(data)
+                    .take(limit + 1)
+                    .read_to_end(&mut self.buffer)
+                    .map_err(error)?;
+                if len as u64 > limit {
+                    return Err(invalid());
+                }
+            }
+            method => {
+                return Err(ParserError(format!(
+                    "Unsupported blf compression method {method}"
+                )))
+            }
+        }
+        Ok(true)
+    }
+
+    /// Parse the next complete object of the buffer. Returns the record of CAN
+    /// frames and errors or None for other objects.
+    fn object(&mut self) -> Option<Result<Option<Record>, ParserError>> {
+        let buffer = &self.buffer[self.offset..];
+        if buffer.len() < BLF_OBJECT_HEADER_SIZE {
+            return None;
+        }
+        if &buffer[..4] != BLF_OBJECT_MAGIC {
+            self.offset = self.buffer.len();
+            return Some(Err(ParserError("Invalid blf object".to_string())));
+        }
+        let size = u32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]) as usize;
+        let kind = u32::from_le_bytes([buffer[12], buffer[13], buffer[14], buffer[15]]);
+        if buffer.len() < size {
+            return None;
+        }
+        let record = blf_record(&buffer[..size], kind, self.start);
+        let padding = if kind == BLF_CAN_FD_MESSAGE_64 {
+            0
+        } else {
+            size % 4
+        };
+        self.offset += (size.max(BLF_OBJECT_HEADER_SIZE) + padding).min(buffer.len());
+        Some(Ok(record))
+    }
+}
+
+/// Record of the BLF object `object` of type `kind` if it's a CAN frame or error
+fn blf_record(object: &[u8], kind: u32, start: time::Timespec) -> Option<Record> {
+    let u16_at = |n: usize| {
+        object
+            .get(n..n + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |n: usize| {
+        object
+            .get(n..n + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    // Object header version 1 and 2 start with the flags and contain the timestamp at 24
+    let header_size = usize::from(u16_at(4)?);
+    let flags = u32_at(16)?;
+    let timestamp = u64::from(u32_at(24)?) | u64::from(u32_at(28)?) << 32;
+    let nanos = if flags & BLF_TIME_ONE_NANS != 0 {
+        timestamp
+    } else {
+        timestamp * 10_000
+    };
+    let timestamp = Timestamp::new(time::at_utc(
+        start + time::Duration::nanoseconds(nanos as i64),
+    ));
+
+    // Offsets in the object body
+    let body = header_size;
+    let id = |n| u32_at(body + n).map(|id| (id & !BLF_EXTENDED_ID, id & BLF_EXTENDED_ID != 0));
+    let data = |n, len: u8, max| object.get(body + n..body + n + usize::from(len).min(max));
+    let record = match kind {
+        BLF_CAN_MESSAGE | BLF_CAN_MESSAGE2 => {
+            let (id, extended) = id(4)?;
+            let tx = *object.get(body + 2)? & 1 != 0;
+            let data = data(8, *object.get(body + 3)?, 8)?;
+            can_record(timestamp, u32::from(u16_at(body)?), id, extended, tx, data)
+        }
+        BLF_CAN_FD_MESSAGE => {
+            let (id, extended) = id(4)?;
+            let tx = *object.get(body + 2)? & 1 != 0;
+            let data = data(20, *object.get(body + 14)?, 64)?;
+            can_record(timestamp, u32::from(u16_at(body)?), id, extended, tx, data)
+        }
+        BLF_CAN_FD_MESSAGE_64 => {
+            let (id, extended) = id(4)?;
+            let tx = *object.get(body + 34)? & 1 != 0;
+            let data = data(40, *object.get(body + 2)?, 64)?;
+            let channel = u32::from(*object.get(body)?);
+            can_record(timestamp, channel, id, extended, tx, data)
+        }
+        BLF_CAN_ERROR | BLF_CAN_ERROR_EXT => can_error_record(timestamp, u32::from(u16_at(body)?)),
+        _ => return None,
+    };
+    Some(record)
+}
+
+impl<R: Read> Iterator for BlfReader<R> {
+    type Item = Result<Record, ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.object() {
+                Some(Ok(Some(record))) => return Some(Ok(record)),
+                Some(Ok(None)) => continue,
+                Some(Err(e)) => return Some(Err(e)),
+                None => match self.fill() {
+                    Ok(true) => continue,
+                    Ok(false) => return None,
+                    Err(e) => return Some(Err(e)),
+                },
+            }
+        }
+    }
+}
+
+/// Magics of pcap files with microsecond and nanosecond timestamps in little and big endian
+pub const PCAP_MAGICS: [&[u8]; 4] = [
+    b"\xd4\xc3\xb2\xa1",
+    b"\xa1\xb2\xc3\xd4",
+    b"\x4d\x3c\xb2\xa1",
+    b"\xa1\xb2\x3c\x4d",
+];
+/// Magic of pcapng files which is the type of the section header block
+pub const PCAPNG_MAGIC: &[u8] = b"\x0a\x0d\x0d\x0a";
+/// Byte order magic of pcapng section headers written in big endian
+const PCAPNG_BIG_ENDIAN: &[u8] = b"\x1a\x2b\x3c\x4d";
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_OBSOLETE_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+/// Upper bound of pcap records and pcapng blocks
+const PCAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+/// Payload of a tcp or udp flow that is not terminated by a newline
+#[derive(Default)]
+struct Flow {
+    /// Expected sequence number of tcp flows
+    next: Option<u32>,
+    partial: Vec<u8>,
+}
+
+/// Reads pcap and pcapng captures and provides the lines of the tcp and udp payloads
+/// together with the address of the sender. Tcp segments are joined per flow and
+/// retransmissions are dropped. Reordered segments are not sorted.
+pub struct PcapReader<R> {
+    reader: R,
+    /// Pcapng instead of pcap format
+    ng: bool,
+    /// Byte order of the file or the current pcapng section
+    big: bool,
+    /// Link types of the interfaces of the current pcapng section or the pcap file
+    interfaces: Vec<u16>,
+    flows: BTreeMap<(SocketAddr, SocketAddr), Flow>,
+    lines: VecDeque<(SocketAddr, String)>,
+    done: bool,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read the file header of `reader`
+    pub fn new(mut reader: R) -> Result<PcapReader<R>, ParserError> {
+        let error = |e| ParserError(format!("Failed to read pcap header: {e}"));
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(error)?;
+        let mut pcap = PcapReader {
+            reader,
+            ng: false,
+            big: false,
+            interfaces: Vec::new(),
+            flows: BTreeMap::new(),
+            lines: VecDeque::new(),
+            done: false,
+        };
+        if magic == PCAPNG_MAGIC {
+            pcap.ng = true;
+            let mut len = [0u8; 4];
+            pcap.reader.read_exact(&mut len).map_err(error)?;
+            pcap.section(&len)?;
+        } else if let Some(n) = PCAP_MAGICS.iter().position(|m| *m == magic) {
+            pcap.big = n % 2 == 1;
+            let mut header = [0u8; 20];
+            pcap.reader.read_exact(&mut header).map_err(error)?;
+            pcap.interfaces.push(pcap.u32(&header[16..]) as u16);
+        } else {
+            return Err(ParserError("Not a pcap file".to_string()));
+        }
+        Ok(pcap)
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    /// Read `len` bytes or None at the end of the file
+    fn read(&mut self, len: usize) -> Result<Option<Vec<u8>>, ParserError> {
+        if len > PCAP_MAX_SIZE {
+            return Err(ParserError(format!("Invalid pcap record size {len}")));
+        }
+        let mut buffer = vec![0u8; len];
+        match self.reader.read_exact(&mut buffer) {
+            Ok(()) => Ok(Some(buffer)),
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(ParserError(format!("Failed to read pcap: {e}"))),
+        }
+    }
+
+    /// Read the rest of a pcapng section header block after its type and `len`
+    fn section(&mut self, len: &[u8]) -> Result<(), ParserError> {
+        let bom = self
+            .read(4)?
+            .ok_or_else(|| ParserError("Truncated pcapng section header".to_string()))?;
+        self.big = bom == PCAPNG_BIG_ENDIAN;
+        let len = self.u32(len) as usize;
+        self.read(len.saturating_sub(12))?
+            .ok_or_else(|| ParserError("Truncated pcapng section header".to_string()))?;
+        self.interfaces.clear();
+        Ok(())
+    }
+
+    /// Read the next packet with its link type. Returns None at the end of the file.
+    fn packet(&mut self) -> Result<Option<(u16, Vec<u8>)>, ParserError> {
+        if !self.ng {
+            let header = match self.read(16)? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            let len = self.u32(&header[8..]) as usize;
+            return Ok(self.read(len)?.map(|data| (self.interfaces[0], data)));
+        }
+
+        loop {
+            let header = match self.read(8)? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            if header[..4] == *PCAPNG_MAGIC {
+                self.section(&header[4..])?;
+                continue;
+            }
+            let kind = self.u32(&header);
+            let len = self.u32(&header[4..]) as usize;
+            let body = match self.read(len.saturating_sub(8))? {
+                Some(body) => body,
+                None => return Ok(None),
+            };
+            // The body is followed by the repeated block length
+            let body = &body[..body.len().saturating_sub(4)];
+            let (interface, data) = match kind {
+                PCAPNG_INTERFACE if body.len() >= 2 => {
+                    self.interfaces.push(self.u16(body));
+                    continue;
+                }
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                    let len = self.u32(&body[12..]) as usize;
+                    (self.u32(body) as usize, body[20..].get(..len))
+                }
+                PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                    let len = (self.u32(body) as usize).min(body.len() - 4);
+                    (0, body[4..].get(..len))
+                }
+                PCAPNG_OBSOLETE_PACKET if body.len() >= 20 => {
+                    let len = self.u32(&body[12..]) as usize;
+                    (usize::from(self.u16(body)), body[20..].get(..len))
+                }
+                _ => continue,
+            };
+            match (self.interfaces.get(interface), data) {
+                (Some(link), Some(data)) => return Ok(Some((*link, data.to_vec()))),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Queue the lines of the tcp or udp payload of `packet`
+    fn payload(&mut self, link: u16, packet: &[u8]) {
+        let (source, destination, tcp, payload) = match pcap_segment(link, packet) {
+            Some(segment) => segment,
+            None => return,
+        };
+        let (seq, flags) = match tcp {
+            Some(tcp) => tcp,
+            None => {
+                // Datagrams are complete messages
+                self.split(source, payload, true);
+                return;
+            }
+        };
+
+        let key = (source, destination);
+        let flow = self.flows.entry(key).or_default();
+        let seq = if flags & TCP_SYN != 0 {
+            seq.wrapping_add(1)
+        } else {
+            seq
+        };
+        let end = seq.wrapping_add(payload.len() as u32);
+        let mut payload = payload;
+        if let Some(next) = flow.next {
+            // Drop retransmitted data
+            let seen = next.wrapping_sub(seq) as i32;
+            if seen > 0 {
+                payload = payload.get(seen as usize..).unwrap_or_default();
+            }
+        }
+        if flow
+            .next
+            .is_none_or(|next| end.wrapping_sub(next) as i32 > 0)
+        {
+            flow.next = Some(end);
+        }
+        let mut data = mem::take(&mut flow.partial);
+        data.extend_from_slice(payload);
+        if flags & (TCP_FIN | TCP_RST) != 0 {
+            self.flows.remove(&key);
+            self.split(source, &data, true);
+        } else {
+            let partial = self.split(source, &data, false);
+            if let Some(flow) = self.flows.get_mut(&key) {
+                flow.partial = partial;
+            }
+        }
+    }
+
+    /// Queue the lines of `data` and return the unterminated rest unless `flush` is set
+    fn split(&mut self, source: SocketAddr, data: &[u8], flush: bool) -> Vec<u8> {
+        let mut lines = data.split(|b| *b == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            if lines.peek().is_none() && (!flush || line.is_empty()) {
+                return line.to_vec();
+            }
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            self.lines
+                .push_back((source, String::from_utf8_lossy(line).into_owned()));
+        }
+        Vec::new()
+    }
+}
+
+/// Type of the tcp or udp `data` of the network layer
+type Segment<'a> = (SocketAddr, SocketAddr, Option<(u32, u8)>, &'a [u8]);
+
+/// Sender, receiver, tcp sequence number and flags and the payload of the tcp or udp
+/// segment of the `packet` of link type `link`
+fn pcap_segment(link: u16, packet: &[u8]) -> Option<Segment<'_>> {
+    let u16_at = |b: &[u8], n: usize| b.get(n..n + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let ip = match link {
+        // BSD loopback
+        0 => packet.get(4..)?,
+        // Ethernet with optional vlan tags
+        1 => {
+            let mut offset = 12;
+            while matches!(u16_at(packet, offset)?, 0x8100 | 0x88a8) {
+                offset += 4;
+            }
+            packet.get(offset + 2..)?
+        }
+        // Raw ip
+        12 | 14 | 101 | 228 | 229 => packet,
+        // Linux cooked capture v1 and v2
+        113 => packet.get(16..)?,
+        276 => packet.get(20..)?,
+        _ => return None,
+    };
+
+    let (source, destination, protocol, data): (IpAddr, IpAddr, u8, &[u8]) = match ip.first()? >> 4
+    {
+        4 => {
+            let header = usize::from(ip[0] & 0xf) * 4;
+            // Only the first fragment contains the transport header
+            if u16_at(ip, 6)? & 0x1fff != 0 {
+                return None;
+            }
+            let len = usize::from(u16_at(ip, 2)?).min(ip.len());
+            let address = |n: usize| -> Option<IpAddr> {
+                let b: [u8; 4] = ip.get(n..n + 4)?.try_into().ok()?;
+                Some(IpAddr::from(b))
+            };
+            (
+                address(12)?,
+                address(16)?,
+                *ip.get(9)?,
+                ip.get(header..len.max(header))?,
+            )
+        }
+        6 => {
+            let address = |n: usize| -> Option<IpAddr> {
+                let b: [u8; 16] = ip.get(n..n + 16)?.try_into().ok()?;
+                Some(IpAddr::from(b))
+            };
+            let len = (40 + usize::from(u16_at(ip, 4)?)).min(ip.len());
+            let mut next = *ip.get(6)?;
+            let mut offset = 40;
+            // Skip hop by hop, routing and destination options
+            while matches!(next, 0 | 43 | 60) {
+                next = *ip.get(offset)?;
+                offset += (usize::from(*ip.get(offset + 1)?) + 1) * 8;
+            }
+            (
+                address(8)?,
+                address(24)?,
+                next,
+                ip.get(offset..len.max(offset))?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source = SocketAddr::new(source, u16_at(data, 0)?);
+    let destination = SocketAddr::new(destination, u16_at(data, 2)?);
+    match protocol {
+        6 => {
+            let seq = u32::from_be_bytes(data.get(4..8)?.try_into().ok()?);
+            let offset = usize::from(*data.get(12)? >> 4) * 4;
+            let flags = *data.get(13)?;
+            Some((source, destination, Some((seq, flags)), data.get(offset..)?))
+        }
+        17 => {
+            let len = usize::from(u16_at(data, 4)?).clamp(8, data.len().max(8));
+            Some((source, destination, None, data.get(8..len)?))
+        }
+        _ => None,
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<(SocketAddr, String), ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Some(Ok(line));
+            }
+            if self.done {
+                return None;
+            }
+            match self.packet() {
+                Ok(Some((link, packet))) => self.payload(link, &packet),
+                Ok(None) => {
+                    // Flush the unterminated lines of all flows
+                    self.done = true;
+                    for ((source, _), flow) in mem::take(&mut self.flows) {
+                        self.split(source, &flow.partial, true);
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Parser of custom line formats defined by a regex with the named capture
+/// groups `timestamp`, `level`, `tag`, `process`, `thread` and `message`
+pub struct RegexParser {
+    regex: Regex,
+}
+
+impl RegexParser {
+    pub fn new(pattern: &str) -> Result<RegexParser, ParserError> {
+        let regex =
+            Regex::new(pattern).map_err(|e| ParserError(format!("Invalid parser regex: {e}")))?;
+        if !regex.capture_names().any(|name| name == Some("message")) {
+            return Err(ParserError(format!(
+                "Parser regex {pattern} has no capture group named message"
+            )));
+        }
+        Ok(RegexParser { regex })
+    }
+
+    /// Timestamps like the default format, RFC 3339 or seconds since the epoch
+    fn timestamp(s: &str) -> Option<Timestamp> {
+        timestamp(s)
+            .ok()
+            .map(|(_, tm)| tm)
+            .or_else(|| syslog_timestamp(&s.replacen(' ', "T", 1)))
+            .map(Timestamp::new)
+            .or_else(|| s.parse::<f64>().ok().map(Timestamp::from_secs))
+    }
+
+    /// Level and custom severity of names, letters or syslog severities
+    fn level(level: &str) -> (Level, Option<u32>) {
+        match level.to_lowercase().as_str() {
+            "warning" => (Level::Warn, None),
+            "err" => (Level::Error, None),
+            "crit" => record::parse_level("critical"),
+            l if l.len() > 1 => record::parse_level(l),
+            _ => match level.parse::<usize>() {
+                Ok(severity) if severity < SYSLOG_LEVELS.len() => syslog_level(severity),
+                _ => (Level::from(level.to_uppercase().as_str()), None),
+            },
+        }
+    }
+}
+
+impl FormatParser for RegexParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        let captures = self
+            .regex
+            .captures(line)
+            .ok_or_else(|| ParserError("Failed to parse with custom regex".to_string()))?;
+        let field = |name| captures.name(name).map(|m| m.as_str());
+        let (level, severity) = field("level").map(RegexParser::level).unwrap_or_default();
+
+        Ok(Record {
+            timestamp: field("timestamp").and_then(RegexParser::timestamp),
+            message: field("message").unwrap_or_default().to_owned(),
+            level,
+            severity,
+            tags: field("tag")
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| vec![tag.to_owned()])
+                .unwrap_or_default(),
+            process: field("process").unwrap_or_default().to_owned(),
+            thread: field("thread").unwrap_or_default().to_owned(),
+            raw: line.to_owned(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Names of the available format parsers in default order
+pub const PARSERS: &[&str] = &[
+    "default",
+    "mindroid",
+    "csv",
+    "json",
+    "fuchsia",
+    "fuchsia-json",
+    "syslog",
+    "asc",
+    "nmea",
+];
+
+/// Format parsers tried in order. Clones share the format parsers.
+#[derive(Clone)]
+pub struct Parser {
+    /// Format parsers and their names
+    parsers: Vec<(String, Arc<dyn FormatParser>)>,
+    /// Move successful parsers to the front
+    adaptive: bool,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new(PARSERS, true)
+    }
+}
+
+impl Parser {
+    /// Create a parser with the format parsers `names` tried in the given order.
+    /// If `adaptive` is set, successful parsers are moved towards the front.
+    /// Unknown names are ignored.
+    pub fn new(names: &[&str], adaptive: bool) -> Parser {
+        let parsers = names
+            .iter()
+            .filter_map(|name| {
+                let parser: Arc<dyn FormatParser> = match *name {
+                    "default" => Arc::new(DefaultParser),
+                    "mindroid" => Arc::new(MindroidParser),
+                    "csv" => Arc::new(CsvParser),
+                    "json" => Arc::new(JsonParser),
+                    "fuchsia" => Arc::new(FuchsiaParser),
+                    "fuchsia-json" => Arc::new(FuchsiaJsonParser),
+                    "syslog" => Arc::new(SyslogParser),
+                    "asc" => Arc::new(AscParser),
+                    "nmea" => Arc::new(NmeaParser),
+                    _ => return None,
+                };
+                Some((name.to_string(), parser))
+            })
+            .collect();
+        Parser { parsers, adaptive }
+    }
+
+    /// Try `parser` named `name` before all other format parsers
+    pub fn prepend(&mut self, name: &str, parser: Box<dyn FormatParser>) {
+        self.parsers.insert(0, (name.to_owned(), parser.into()));
+    }
+
+    /// Order the format parsers by the number of `lines` they parse. Returns the name
+    /// of the best parser and the number of lines it parsed if any.
+    pub fn detect(&mut self, lines: &[String]) -> Option<(&str, usize)> {
+        let mut scored = self
+            .parsers
+            .drain(..)
+            .map(|(name, parser)| {
+                let count = lines
+                    .iter()
+                    .filter(|line| parser.try_parse_str(line).is_ok())
+                    .count();
+                (count, name, parser)
+            })
+            .collect::<Vec<_>>();
+        // The sort is stable: the given order is kept for parsers with equal counts
+        scored.sort_by_key(|(count, _, _)| std::cmp::Reverse(*count));
+        let best = scored.first().map(|(count, _, _)| *count).unwrap_or(0);
+        self.parsers = scored
+            .into_iter()
+            .map(|(_, name, parser)| (name, parser))
+            .collect();
+        self.parsers
+            .first()
+            .filter(|_| best > 0)
+            .map(|(name, _)| (name.as_str(), best))
+    }
+
+    /// Try all format parsers on `line` without falling back to a raw record.
+    /// The `raw` field is returned as provided by the format (e.g csv and json).
+    pub fn try_parse(&mut self, line: &str) -> Option<Record> {
+        for (index, (_, parser)) in self.parsers.iter().enumerate() {
+            if let Ok(record) = parser.try_parse_str(line) {
+                if self.adaptive && index > 0 {
+                    self.parsers.swap(index, index - 1);
+                }
+                return Some(record);
+            }
+        }
+        None
+    }
+
+    pub fn parse(&mut self, raw: String) -> Record {
+        match self.try_parse(&raw) {
+            Some(mut record) => {
+                record.raw = raw;
+                record
+            }
+            None => Parser::fallback(raw),
+        }
+    }
+
+    /// Record for a line that cannot be parsed: treat the raw input as message
+    pub fn fallback(raw: String) -> Record {
+        Record {
+            message: raw.clone(),
+            raw,
+            ..Default::default()
+        }
+    }
+}
+
+#[test]
+fn parse_level() {
+    assert_eq!(level("V").unwrap().1, Level::Verbose);
+    assert_eq!(level("D").unwrap().1, Level::Debug);
+    assert_eq!(level("I").unwrap().1, Level::Info);
+    assert_eq!(level("W").unwrap().1, Level::Warn);
+    assert_eq!(level("E").unwrap().1, Level::Error);
+    assert_eq!(level("F").unwrap().1, Level::Fatal);
+    assert_eq!(level("A").unwrap().1, Level::Assert);
+}
+#[test]
+fn parser_year() {
+    let date_wrong = "00";
+    let just_date = "2000";
+    let date_with_month = "2000-03";
+    let val = opt(parse_year)(date_wrong);
+    let val2 = opt(parse_year)(date_with_month);
+    let val3 = opt(parse_year)(just_date);
+    assert_eq!(Ok(("00", None)), val);
+    assert_eq!(Ok(("2000", None)), val3);
+    assert_eq!(Ok(("03", Some(2000))), val2);
+}
+
+#[test]
+fn parse_timestamp() {
+    let ts = timestamp("03-25 19:11:19.054211").unwrap();
+    assert_eq!(00, ts.1.tm_year);
+    assert_eq!(2, ts.1.tm_mon);
+    assert_eq!(25, ts.1.tm_mday);
+    assert_eq!(19, ts.1.tm_hour);
+    assert_eq!(11, ts.1.tm_min);
+    assert_eq!(19, ts.1.tm_sec);
+    timestamp("03-25 7:11:19.052").unwrap();
+    timestamp("2017-03-25 19:11:19.052").unwrap();
+    timestamp("2017-03-25 19:11:19.052123").unwrap();
+}
+#[test]
+fn parse_unparseable() {
+    let p = DefaultParser {};
+    assert!(p.try_parse_str("").is_err());
+}
+
+#[test]
+fn parse_printable() {
+    let t = "03-01 02:19:45.207     1     2 I EXT4-fs (mmcblk3p8): mounted filesystem with \
+             ordered data mode. Opts: (null)";
+    let p = DefaultParser {};
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tags, vec!("EXT4-fs (mmcblk3p8)"));
+    assert_eq!(r.process, "1");
+    assert_eq!(r.thread, "2");
+    assert_eq!(
+        r.message,
+        "mounted filesystem with ordered data mode. Opts: (null)"
+    );
+
+    let t = "03-01 02:19:42.868     0     0 D /soc/aips-bus@02100000/usdhc@0219c000: \
+             voltage-ranges unspecified";
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Debug);
+    assert_eq!(r.tags, vec!("/soc/aips-bus@02100000/usdhc@0219c000"));
+    assert_eq!(r.process, "0");
+    assert_eq!(r.thread, "0");
+    assert_eq!(r.message, "voltage-ranges unspecified");
+
+    let t = "11-06 13:58:53.582 31359 31420 I GStreamer+amc: 0:00:00.326067533 0xb8ef2a00";
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(
+        r.timestamp,
+        Some(Timestamp {
+            tm: Tm {
+                tm_year: 0,
+                tm_mon: 10,
+                tm_mday: 6,
+                tm_hour: 13,
+                tm_min: 58,
+                tm_sec: 53,
+                tm_nsec: 582000000,
+                tm_wday: 0,
+                tm_yday: 0,
+                tm_isdst: 0,
+                tm_utcoff: 0,
+            }
+        })
+    );
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tags, vec!("GStreamer+amc"));
+    assert_eq!(r.process, "31359");
+    assert_eq!(r.thread, "31420");
+    assert_eq!(r.message, "0:00:00.326067533 0xb8ef2a00");
+
+    let t = "11-06 13:58:53.582 31359 31420 A GStreamer+amc: 0:00:00.326067533 0xb8ef2a00";
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Assert);
+    assert_eq!(r.tags, vec!("GStreamer+amc"));
+    assert_eq!(r.process, "31359");
+    assert_eq!(r.thread, "31420");
+    assert_eq!(r.message, "0:00:00.326067533 0xb8ef2a00");
+
+    let t = "03-26 13:17:38.345     0     0 I [114416.534450,0] mdss_dsi_off-: ";
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tags, vec!("[114416.534450,0] mdss_dsi_off-"));
+    assert_eq!(r.message, "");
+}
+
+#[test]
+fn test_parse_mindroid() {
+    let t = "I/Runtime: Mindroid runtime system node id: 1";
+    let p = MindroidParser {};
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tags, vec!("Runtime"));
+    assert_eq!(r.process, "");
+    assert_eq!(r.thread, "");
+    assert_eq!(r.message, "Mindroid runtime system node id: 1");
+
+    let t = "D/ServiceManager(000000000000000C): foo bar";
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Debug);
+    assert_eq!(r.tags, vec!("ServiceManager"));
+    assert_eq!(r.process, "000000000000000C");
+    assert_eq!(r.thread, "");
+    assert_eq!(r.message, "foo bar");
+
+    let t = "D/ServiceManager(0x123): Service MediaPlayer has been created in process main";
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Debug);
+    assert_eq!(r.tags, vec!("ServiceManager"));
+    assert_eq!(r.process, "123");
+    assert_eq!(r.thread, "");
     assert_eq!(
         r.message,
         "Service MediaPlayer has been created in process main"
@@ -590,6 +2359,25 @@ fn test_parse_csv() {
     );
 }
 
+#[test]
+fn parse_garbled() {
+    assert!(split_garbled("03-01 02:19:45.207     1     2 I Foo: bar").is_none());
+    assert!(split_garbled("foo 03-01 02:19:45.207 happened").is_none());
+    let parts = split_garbled(
+        "03-01 02:19:45.207     1     2 I Foo: b03-01 02:19:45.208     1     3 W Bar: baz",
+    )
+    .unwrap();
+    assert_eq!(
+        parts,
+        vec![
+            "03-01 02:19:45.207     1     2 I Foo: b",
+            "03-01 02:19:45.208     1     3 W Bar: baz"
+        ]
+    );
+    let parts = split_garbled("ar03-01 02:19:45.208  1  3 W Bar: baz").unwrap();
+    assert_eq!(parts, vec!["ar", "03-01 02:19:45.208  1  3 W Bar: baz"]);
+}
+
 #[test]
 fn parse_property() {
     let t = "[ro.build.tags]: [release-keys]";
@@ -599,6 +2387,31 @@ fn parse_property() {
     );
 }
 
+#[test]
+fn parse_bugreport_section() {
+    let t = "SYSTEM LOG (logcat -v threadtime -d *:v)";
+    assert_eq!(
+        bugreport_section(t).unwrap().1,
+        (
+            "SYSTEM LOG ".to_owned(),
+            "logcat -v threadtime -d *:v".to_owned()
+        )
+    );
+}
+
+#[test]
+fn parse_kernel_uptime() {
+    assert_eq!(
+        kernel_uptime("[114416.534450] init: starting"),
+        Some((None, 114416.53445, "init: starting"))
+    );
+    assert_eq!(
+        kernel_uptime("<6>[    5.123456] binder: open"),
+        Some((Some(6), 5.123456, "binder: open"))
+    );
+    assert_eq!(kernel_uptime("[boot-drivers:dev] INFO: x"), None);
+}
+
 #[test]
 fn test_parse_section() {
     let mut p = Parser::default();
@@ -648,3 +2461,379 @@ fn test_parse_fuchsia() {
         "ignoring Reply to Information-Request: missing Server Id option"
     );
 }
+
+#[test]
+fn assemble_json() {
+    let mut json = JsonAssembler::default();
+    let lines = |l: &[&str]| l.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        json.push("{\"a\": 1}".into()),
+        Assembled::Lines(lines(&["{\"a\": 1}"]))
+    );
+    assert_eq!(json.push("{".into()), Assembled::Lines(vec![]));
+    assert_eq!(
+        json.push("  \"a\": \"}{\\\"\",".into()),
+        Assembled::Lines(vec![])
+    );
+    assert_eq!(
+        json.push("}".into()),
+        Assembled::Object(lines(&["{", "  \"a\": \"}{\\\"\",", "}"]))
+    );
+    assert_eq!(json.push("{x".into()), Assembled::Lines(lines(&["{x"])));
+    assert_eq!(json.push("{".into()), Assembled::Lines(vec![]));
+    assert_eq!(json.flush(), lines(&["{"]));
+
+    // An unterminated object is given up after JSON_MAX_LINES
+    assert_eq!(json.push("{\"a\": [".into()), Assembled::Lines(vec![]));
+    for _ in 1..JSON_MAX_LINES {
+        assert_eq!(json.push("1,".into()), Assembled::Lines(vec![]));
+    }
+    match json.push("1,".into()) {
+        Assembled::Lines(lines) => assert_eq!(lines.len(), JSON_MAX_LINES + 1),
+        object => panic!("Unexpected {:?}", object),
+    }
+    assert_eq!(json.push("x".into()), Assembled::Lines(lines(&["x"])));
+}
+
+#[test]
+fn parse_logger_entry() {
+    let payload = b"\x04Tag\x00message\n\x00";
+    let mut entry = vec![payload.len() as u8, 0, 28, 0];
+    for field in &[1234u32, 5678, 1_700_000_000, 1_000_000, 3, 1000] {
+        entry.extend_from_slice(&field.to_le_bytes());
+    }
+    entry.extend_from_slice(payload);
+
+    assert_eq!(logger_entry(&entry[..entry.len() - 1]).unwrap(), None);
+    let (record, len) = logger_entry(&entry).unwrap().unwrap();
+    assert_eq!(len, entry.len());
+    assert_eq!(record.level, Level::Info);
+    assert_eq!(record.tags, vec!["Tag".to_string()]);
+    assert_eq!(record.message, "message");
+    assert_eq!(record.process, "1234");
+    assert_eq!(record.thread, "5678");
+    assert_eq!(record.uid, Some(1000));
+    assert_eq!(record.buffer, Some("system".to_string()));
+    assert_eq!(record.timestamp.unwrap().tm_nsec, 1_000_000);
+}
+
+#[test]
+fn parse_syslog_rfc() {
+    let r = SyslogParser
+        .try_parse_str(
+            "<34>1 2003-10-11T22:14:15.003Z mymachine su 123 ID47 [a b=\"x\\]y\"] 'su root' failed",
+        )
+        .unwrap();
+    assert_eq!(r.level, Level::Fatal);
+    assert_eq!(r.tags, vec!["su", "auth", "mymachine"]);
+    assert_eq!(r.process, "123");
+    assert_eq!(r.message, "'su root' failed");
+    assert_eq!(r.timestamp.unwrap().tm_nsec, 3_000_000);
+
+    let r = SyslogParser
+        .try_parse_str("<13>Oct  1 22:14:15 router dhcpd[42]: lease renewed")
+        .unwrap();
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.severity(), 45);
+    assert_eq!(r.tags, vec!["dhcpd", "user", "router"]);
+    assert_eq!(r.process, "42");
+    assert_eq!(r.message, "lease renewed");
+    assert_eq!(r.timestamp.unwrap().tm_mday, 1);
+
+    assert!(SyslogParser.try_parse_str("<200>1 - - - - - -").is_err());
+    assert!(SyslogParser.try_parse_str("garbage").is_err());
+}
+
+#[test]
+fn test_parse_asc() {
+    let r = AscParser
+        .try_parse_str("   1.000000 2  18FEF100x       Rx   d 3 ff 00 12")
+        .unwrap();
+    assert_eq!(r.can_id, Some(0x18fef100));
+    assert_eq!(r.process, "can2");
+    assert_eq!(r.thread, "Rx");
+    assert_eq!(r.message, "E ff 00 12 ");
+
+    let r = AscParser
+        .try_parse_str("   2.501000 1  ErrorFrame")
+        .unwrap();
+    assert_eq!(r.level, Level::Error);
+
+    assert!(AscParser
+        .try_parse_str("base hex  timestamps absolute")
+        .is_err());
+    assert!(AscParser
+        .try_parse_str("   1.000000 2  7E0 Rx d 3 ff")
+        .is_err());
+}
+
+#[test]
+fn test_parse_nmea() {
+    let r = NmeaParser
+        .try_parse_str("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47")
+        .unwrap();
+    assert_eq!(r.tags, vec!["GGA", "GP"]);
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.timestamp.unwrap().tm_min, 35);
+
+    let r = NmeaParser
+        .try_parse_str("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48")
+        .unwrap();
+    assert_eq!(r.level, Level::Error);
+
+    let r = NmeaParser
+        .try_parse_str("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A")
+        .unwrap();
+    assert_eq!(r.timestamp.unwrap().tm_mon, 2);
+
+    assert!(NmeaParser.try_parse_str("$ foo").is_err());
+}
+
+#[test]
+fn parse_regex() {
+    let p = RegexParser::new(
+        r"^\[(?P<timestamp>[^\]]+)\] (?P<level>\w+) (?P<tag>\S+)\[(?P<process>\d+)\]: (?P<message>.*)$",
+    )
+    .unwrap();
+    let r = p
+        .try_parse_str("[2024-05-01T10:00:00.123Z] WARNING net[42]: link down")
+        .unwrap();
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.tags, vec!("net"));
+    assert_eq!(r.process, "42");
+    assert_eq!(r.message, "link down");
+    assert_eq!(r.timestamp.unwrap().tm_nsec, 123_000_000);
+    assert!(p.try_parse_str("link down").is_err());
+    assert!(RegexParser::new("(?P<tag>.*)").is_err());
+}
+
+#[test]
+fn format_invalid_timestamp() {
+    let valid = DefaultParser
+        .try_parse_str("12-31 23:59:59.999  1  2 I A: b")
+        .unwrap()
+        .timestamp
+        .unwrap();
+    assert_eq!(valid.tm_mon, 11);
+    assert_eq!(
+        valid.format("%b %m-%d %H:%M:%S").unwrap(),
+        "Dec 12-31 23:59:59"
+    );
+
+    let insane = [
+        Tm {
+            tm_mon: 12,
+            ..valid.tm
+        },
+        Tm {
+            tm_mon: -1,
+            ..valid.tm
+        },
+        Tm {
+            tm_wday: 9,
+            ..valid.tm
+        },
+        Tm {
+            tm_hour: 99_999,
+            ..valid.tm
+        },
+        Tm {
+            tm_nsec: -1,
+            ..valid.tm
+        },
+        Tm {
+            tm_year: i32::MAX,
+            ..valid.tm
+        },
+        Tm {
+            tm_utcoff: i32::MIN,
+            ..valid.tm
+        },
+    ];
+    for tm in insane.iter() {
+        let timestamp = Timestamp::new(*tm);
+        assert!(!timestamp.is_valid());
+        assert_eq!(timestamp.format("%a %b %m-%d %H:%M:%S.%f %s"), None);
+        let record = Record {
+            timestamp: Some(timestamp),
+            ..Default::default()
+        };
+        assert!(serde_json::to_string(&record)
+            .unwrap()
+            .starts_with("{\"timestamp\":null"));
+    }
+}
+
+#[test]
+fn describe_event_tags() {
+    let tags = EventTags::parse(
+        "# comment\n30014 am_proc_start (User|1|5),(PID|1|5),(Process Name|3)\n42 answer\n",
+    );
+    let values = ["0", "4321", "com.example"].map(ToOwned::to_owned);
+    assert_eq!(
+        tags.describe(30014, &values, true),
+        (
+            "am_proc_start".to_owned(),
+            "[User=0,PID=4321,Process Name=com.example]".to_owned()
+        )
+    );
+    assert_eq!(
+        tags.describe(42, &values[..1], false),
+        ("answer".to_owned(), "0".to_owned())
+    );
+    assert_eq!(
+        tags.describe(7, &values[..2], true),
+        ("7".to_owned(), "[0,4321]".to_owned())
+    );
+}
+
+#[test]
+fn test_parse_fuchsia_json() {
+    let p = FuchsiaJsonParser {};
+    let line = r#"{"data":{"TargetLog":{"data_source":"Logs","metadata":{"timestamp":1086023158000,"severity":"WARN","tags":["netstack","dhcp"],"pid":1234,"tid":5678,"file":null},"moniker":"core/network/netstack","payload":{"root":{"message":{"value":"lease expired "},"keys":{"iface":"eth0","attempt":3}}},"version":1}},"version":1}"#;
+    let r = p.try_parse_str(line).unwrap();
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.process, "core/network/netstack");
+    assert_eq!(r.thread, "5678");
+    assert_eq!(r.tags, vec!("dhcp", "netstack"));
+    assert_eq!(r.message, "lease expired");
+    assert_eq!(r.timestamp.unwrap().tm_sec, 6);
+    let fields = r
+        .fields
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec!("attempt=3", "iface=eth0", "pid=1234", "tid=5678")
+    );
+    assert!(p
+        .try_parse_str(r#"{"data":{"FfxEvent":"TargetDisconnected"}}"#)
+        .is_err());
+}
+
+#[test]
+fn detect_format() {
+    let fuchsia = "[01086.023158][boot-drivers:dev][driver,platform_bus] INFO: Boot";
+    let lines = vec![fuchsia.to_owned(), fuchsia.to_owned(), "garbage".to_owned()];
+    let mut parser = Parser::default();
+    assert_eq!(parser.detect(&lines), Some(("fuchsia", 2)));
+    assert_eq!(parser.parsers[0].0, "fuchsia");
+    assert_eq!(parser.detect(&["garbage".to_owned()]), None);
+}
+
+#[test]
+fn parse_timestamp_utc_offset() {
+    let (rest, ts) = timestamp("03-25 19:11:19.052+0130  123").unwrap();
+    assert_eq!(rest, "  123");
+    assert_eq!(ts.tm_utcoff, 5400);
+    assert_eq!(ts.tm_nsec, 52_000_000);
+    assert!(timestamp("03-25 19:11:19.05").is_err());
+}
+
+#[test]
+fn parse_timestamp_epoch_and_zone() {
+    let (rest, ts) = timestamp("1490469079.052321  123").unwrap();
+    assert_eq!(rest, "  123");
+    assert_eq!((ts.tm_year, ts.tm_mon, ts.tm_mday), (117, 2, 25));
+    assert_eq!((ts.tm_hour, ts.tm_min, ts.tm_sec), (19, 11, 19));
+    assert_eq!(ts.tm_nsec, 52_321_000);
+    assert_eq!(ts.tm_utcoff, 0);
+    // Uptime of -v monotonic
+    assert!(timestamp("  1079.052").is_err());
+
+    let (rest, ts) = timestamp("03-25 19:11:19.052 -0500  123").unwrap();
+    assert_eq!((rest, ts.tm_utcoff), ("  123", -18000));
+    let (rest, _) = timestamp("03-25 19:11:19.052 UTC  123").unwrap();
+    assert_eq!(rest, "  123");
+
+    let p = DefaultParser {};
+    let record = p
+        .try_parse_str("1490469079.052  1234  1234 I Tag: message")
+        .unwrap();
+    assert_eq!(record.message, "message");
+    assert_eq!(record.timestamp.unwrap().tm_year, 117);
+    let record = p
+        .try_parse_str("03-25 19:11:19.052 +0000  1234  1234 I Tag: message")
+        .unwrap();
+    assert_eq!(record.tags, vec!["Tag".to_owned()]);
+}
+
+#[test]
+fn test_pcap_reader() {
+    // Ethernet, ipv4 and tcp or udp headers of a packet with `payload`
+    let frame = |tcp: Option<(u32, u8)>, payload: &[u8]| {
+        let mut frame = vec![0u8; 12];
+        frame.extend([0x08, 0x00]);
+        let transport = if tcp.is_some() { 20 } else { 8 };
+        let len = (20 + transport + payload.len()) as u16;
+        frame.extend([0x45, 0, (len >> 8) as u8, len as u8, 0, 0, 0, 0, 64]);
+        frame.extend([if tcp.is_some() { 6 } else { 17 }, 0, 0]);
+        frame.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend([0x13, 0x88, 0x00, 0x50]);
+        match tcp {
+            Some((seq, flags)) => {
+                frame.extend(seq.to_be_bytes());
+                frame.extend([0, 0, 0, 0, 0x50, flags, 0, 0, 0, 0, 0, 0]);
+            }
+            None => frame.extend([0, (8 + payload.len()) as u8, 0, 0]),
+        }
+        frame.extend(payload);
+        frame
+    };
+    let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+    pcap.extend([0u8; 8]);
+    pcap.extend([0xff, 0xff, 0, 0, 1, 0, 0, 0]);
+    for frame in [
+        frame(Some((99, TCP_SYN)), b""),
+        frame(Some((100, 0x18)), b"first\r\nsec"),
+        frame(Some((100, 0x18)), b"first\r\nsec"),
+        frame(None, b"datagram"),
+        frame(Some((110, 0x18)), b"ond\nthird"),
+    ] {
+        pcap.extend([0u8; 8]);
+        pcap.extend((frame.len() as u32).to_le_bytes());
+        pcap.extend((frame.len() as u32).to_le_bytes());
+        pcap.extend(frame);
+    }
+
+    let lines = PcapReader::new(Cursor::new(pcap))
+        .unwrap()
+        .map(|line| line.unwrap())
+        .collect::<Vec<_>>();
+    assert!(lines
+        .iter()
+        .all(|(peer, _)| peer.to_string() == "10.0.0.1:5000"));
+    let lines = lines.into_iter().map(|(_, line)| line).collect::<Vec<_>>();
+    assert_eq!(lines, vec!("first", "datagram", "second", "third"));
+    assert!(PcapReader::new(Cursor::new(b"LOGG")).is_err());
+}
+
+#[test]
+fn test_logcat_divider() {
+    assert_eq!(
+        logcat_divider("--------- beginning of main"),
+        Some(("main", false))
+    );
+    assert_eq!(
+        logcat_divider("--------- switch to crash\r"),
+        Some(("crash", true))
+    );
+    assert_eq!(logcat_divider("--------- something else"), None);
+    assert_eq!(
+        logcat_divider("01-02 10:00:00.000 I Foo: --------- switch to main"),
+        None
+    );
+}
+
+#[test]
+fn parse_csv_without_raw() {
+    let record = CsvParser
+        .try_parse_str("01-02 10:00:00.123000000,hello,Info,Foo,100,101,")
+        .unwrap();
+    assert_eq!(record.raw, "01-02 10:00:00.123   100   101 I Foo: hello");
+    let record = CsvParser
+        .try_parse_str("01-02 10:00:00.123000000,hello,Info,Foo,100,101,line")
+        .unwrap();
+    assert_eq!(record.raw, "line");
+}
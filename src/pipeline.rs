@@ -0,0 +1,1693 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Processing stages between the sources and the sinks
+
+use crate::{
+    diagnostics, error::RogcatError, filter::Filter, internal, profiles, profiles::Profile,
+    resolver::Resolver, symbolizer::Symbolizer, LogRecords, LogSink, LogStream, StreamData,
+};
+use clap::ArgMatches;
+use failure::{err_msg, Error};
+use futures::{
+    future::{err, ok, Either},
+    stream::{iter_ok, Fuse},
+    sync::{mpsc::channel, oneshot},
+    try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream,
+};
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder, RegexSet};
+use rogcat::{
+    parser::{
+        describe_event, has_record_start, kernel_uptime, logcat_divider, split_garbled, Assembled,
+        FormatParser, JsonAssembler, JsonParser, Parser, RegexParser, PARSERS,
+    },
+    record::{self, Level, Record, Timestamp},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::{Ordering as CmpOrdering, Reverse},
+    collections::{hash_map::RandomState, BTreeMap, BinaryHeap, HashMap, VecDeque},
+    fs::read_to_string,
+    hash::{BuildHasher, Hasher},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use time::Timespec;
+use tokio::{runtime::TaskExecutor, timer::Delay};
+
+/// Number of lines sampled to detect the input format
+const DETECT_LINES: usize = 20;
+/// Time after the first line until the format is detected from fewer lines
+const DETECT_DEADLINE: Duration = Duration::from_millis(200);
+
+/// Number of consecutive unparseable lines reported as internal event
+const PARSE_FALLBACK_BURST: usize = 10;
+
+/// Number of lines parsed at once by a thread of `parallel`
+const CHUNK_LINES: usize = 4096;
+
+/// Number of chunks per thread of `parallel` that are queued or parsed
+const CHUNKS_PER_JOB: usize = 2;
+
+/// Number of records ingested but not yet consumed by the output
+static BACKLOG: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximum number of records buffered by `decouple`. Further records are dropped.
+const DECOUPLE_CAPACITY: usize = 100_000;
+
+/// Number of records dropped by `decouple` that are not yet reported
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Build a parser with the order and formats selected in `args` that tries the
+/// custom format of `profile` first
+pub fn parser(args: &ArgMatches, profile: &Profile) -> Result<Parser, Error> {
+    let format = args
+        .value_of("input-format")
+        .or_else(|| args.is_present("fuchsia-json").then_some("fuchsia-json"));
+    if let Some(format) = format {
+        return Ok(Parser::new(&[format], false));
+    }
+    let disabled = args
+        .values_of("disable-parser")
+        .unwrap_or_default()
+        .collect::<Vec<_>>();
+    let names = PARSERS
+        .iter()
+        .filter(|name| !disabled.contains(name))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut parser = Parser::new(&names, args.value_of("parser-order") != Some("fixed"));
+    if let Some(ref pattern) = profile.parser {
+        parser.prepend("profile", Box::new(RegexParser::new(pattern)?));
+    }
+    Ok(parser)
+}
+
+/// Replaces the level of records per tag
+#[derive(Clone)]
+pub struct LevelOverrides {
+    levels: HashMap<String, (Level, Option<u32>)>,
+}
+
+impl LevelOverrides {
+    /// Level overrides of the profile if any
+    pub fn from_profile(profile: &Profile) -> Result<Option<LevelOverrides>, Error> {
+        let levels = profile
+            .level_overrides
+            .iter()
+            .map(|(tag, level)| match record::parse_level(level) {
+                (Level::None, None) => Err(RogcatError::Config(format!(
+                    "Unknown level {} of tag {}",
+                    level, tag
+                ))),
+                level => Ok((tag.clone(), level)),
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(if levels.is_empty() {
+            None
+        } else {
+            Some(LevelOverrides { levels })
+        })
+    }
+
+    pub fn apply(&self, mut record: Record) -> Record {
+        if let Some((level, severity)) = record.tags.iter().find_map(|tag| self.levels.get(tag)) {
+            record.level = level.clone();
+            record.severity = *severity;
+        }
+        record
+    }
+}
+
+/// Rewrites message and raw field of records e.g to redact tokens
+pub struct Rewrite {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Rule {
+    /// Parse a sed like substitution `s/regex/replacement/flags`. Any character following
+    /// the `s` is used as delimiter. Supported flags are `g` (replace all) and `i` (case
+    /// insensitive). Groups are referenced with `\1`. A `$` and characters escaped with a
+    /// backslash are literal in the replacement.
+    fn parse(rule: &str) -> Result<Rule, Error> {
+        let invalid = || RogcatError::Config(format!("Invalid rewrite rule: {}", rule));
+        let mut chars = rule.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid().into());
+        }
+        let delimiter = chars.next().ok_or_else(invalid)?;
+
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let replacement = parts.len() == 2;
+            let part = parts.last_mut().unwrap();
+            match c {
+                '\\' if !escaped => escaped = true,
+                // Group references in the replacement
+                c if escaped && replacement && c.is_ascii_digit() => {
+                    escaped = false;
+                    part.push_str(&format!("${{{c}}}"));
+                }
+                // The regex crate expands `$` in replacements
+                '$' if replacement && (escaped || delimiter != '$') => {
+                    escaped = false;
+                    part.push_str("$$");
+                }
+                c if escaped && (replacement || c == delimiter) => {
+                    escaped = false;
+                    part.push(c);
+                }
+                c if escaped => {
+                    escaped = false;
+                    part.push('\\');
+                    part.push(c);
+                }
+                c if c == delimiter => parts.push(String::new()),
+                c => part.push(c),
+            }
+        }
+
+        if parts.len() != 3 {
+            return Err(invalid().into());
+        }
+        let flags = parts.pop().unwrap();
+        let replacement = parts.pop().unwrap();
+        let pattern = parts.pop().unwrap();
+        if flags.chars().any(|f| f != 'g' && f != 'i') {
+            return Err(invalid().into());
+        }
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(flags.contains('i'))
+            .build()
+            .map_err(|e| RogcatError::Config(format!("Invalid rewrite rule {}: {}", rule, e)))?;
+        Ok(Rule {
+            regex,
+            replacement,
+            global: flags.contains('g'),
+        })
+    }
+
+    fn apply(&self, s: &mut String) {
+        let result = if self.global {
+            self.regex.replace_all(s, self.replacement.as_str())
+        } else {
+            self.regex.replace(s, self.replacement.as_str())
+        };
+        if let std::borrow::Cow::Owned(result) = result {
+            *s = result;
+        }
+    }
+}
+
+impl Rewrite {
+    /// Rewrite rules from the command line and the profile
+    pub fn from_args_profile(
+        args: &ArgMatches,
+        profile: &Profile,
+    ) -> Result<Option<Rewrite>, Error> {
+        let rules = args
+            .values_of("rewrite")
+            .unwrap_or_default()
+            .chain(profile.rewrite.iter().map(String::as_str))
+            .map(Rule::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(if rules.is_empty() {
+            None
+        } else {
+            Some(Rewrite { rules })
+        })
+    }
+
+    pub fn apply(&self, mut record: Record) -> Record {
+        for rule in &self.rules {
+            rule.apply(&mut record.message);
+            rule.apply(&mut record.raw);
+        }
+        record
+    }
+}
+
+/// Field selected with `--hash-fields`
+enum HashField {
+    Process,
+    Thread,
+    Tag,
+    Message,
+    /// Capture groups or the matches of a regex within the message
+    MessageRegex(Regex),
+}
+
+impl HashField {
+    fn parse(field: &str) -> Result<HashField, Error> {
+        Ok(match field {
+            "process" => HashField::Process,
+            "thread" => HashField::Thread,
+            "tag" => HashField::Tag,
+            "message" => HashField::Message,
+            _ => match field
+                .strip_prefix("message:/")
+                .and_then(|f| f.strip_suffix('/'))
+            {
+                Some(pattern) => HashField::MessageRegex(Regex::new(pattern).map_err(|e| {
+                    RogcatError::Config(format!("Invalid hash field {}: {}", field, e))
+                })?),
+                None => {
+                    return Err(
+                        RogcatError::Config(format!("Invalid hash field: {}", field)).into(),
+                    )
+                }
+            },
+        })
+    }
+
+    /// Split a comma separated list of fields. Commas within `/regex/` are kept.
+    fn parse_list(fields: &str) -> Result<Vec<HashField>, Error> {
+        let mut result = vec![];
+        let mut field = String::new();
+        let mut regex = false;
+        let mut escaped = false;
+        for c in fields.chars() {
+            match c {
+                ',' if !regex => result.push(HashField::parse(&field)?),
+                '/' if !escaped => regex = field.starts_with("message:") && !regex,
+                _ => (),
+            }
+            escaped = c == '\\' && !escaped;
+            if c == ',' && !regex {
+                field.clear();
+            } else {
+                field.push(c);
+            }
+        }
+        result.push(HashField::parse(&field)?);
+        Ok(result)
+    }
+}
+
+/// Replaces selected fields or parts of messages with salted hashes e.g to share logs
+/// without personal data. Equal values are replaced with equal hashes.
+pub struct Hash {
+    fields: Vec<HashField>,
+    salt: String,
+}
+
+impl Hash {
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Hash>, Error> {
+        let fields = match args.value_of("hash-fields") {
+            Some(fields) => HashField::parse_list(fields)?,
+            None => return Ok(None),
+        };
+        // Without a salt the hashes of different runs cannot be correlated
+        let salt = match args.value_of("hash-salt") {
+            Some(salt) => salt.to_owned(),
+            None => {
+                let random = RandomState::new().build_hasher().finish();
+                format!("{random:016x}")
+            }
+        };
+        Ok(Some(Hash { fields, salt }))
+    }
+
+    /// Salted hash of `value`
+    fn hash(&self, value: &str) -> String {
+        let mut sha = Sha256::new();
+        sha.update(self.salt.as_bytes());
+        sha.update([0]);
+        sha.update(value.as_bytes());
+        sha.finalize()[..6]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Replace the capture groups or the whole matches of `regex` in `message`
+    fn hash_matches(&self, regex: &Regex, message: &str) -> String {
+        let mut result = String::with_capacity(message.len());
+        let mut last = 0;
+        for captures in regex.captures_iter(message) {
+            let groups = captures.iter().skip(1).flatten().collect::<Vec<_>>();
+            let groups = if groups.is_empty() {
+                captures.get(0).into_iter().collect()
+            } else {
+                groups
+            };
+            for group in groups {
+                if group.start() < last {
+                    continue;
+                }
+                result.push_str(&message[last..group.start()]);
+                result.push_str(&self.hash(group.as_str()));
+                last = group.end();
+            }
+        }
+        result.push_str(&message[last..]);
+        result
+    }
+
+    /// Replace tokens of `s` that equal a key of `values`
+    fn replace_tokens(s: &str, values: &HashMap<String, String>) -> String {
+        let separator = |c: char| !(c.is_alphanumeric() || "_.-$".contains(c));
+        let mut result = String::with_capacity(s.len());
+        let mut rest = s;
+        while !rest.is_empty() {
+            let end = rest.find(separator).unwrap_or(rest.len());
+            let (token, tail) = rest.split_at(end);
+            result.push_str(values.get(token).map(String::as_str).unwrap_or(token));
+            let end = tail.find(|c| !separator(c)).unwrap_or(tail.len());
+            result.push_str(&tail[..end]);
+            rest = &tail[end..];
+        }
+        result
+    }
+
+    pub fn apply(&self, mut record: Record) -> Record {
+        if internal::is_internal(&record) {
+            return record;
+        }
+
+        // Hashed fields are replaced in the raw line as well
+        let mut values = HashMap::new();
+        let mut hash_field = |value: &mut String| {
+            if !value.is_empty() {
+                let hash = self.hash(value);
+                values.insert(std::mem::replace(value, hash.clone()), hash);
+            }
+        };
+        let message = record.message.clone();
+        for field in &self.fields {
+            match field {
+                HashField::Process => {
+                    hash_field(&mut record.process);
+                    record.process_name.as_mut().map(&mut hash_field);
+                }
+                HashField::Thread => hash_field(&mut record.thread),
+                HashField::Tag => record.tags.iter_mut().for_each(&mut hash_field),
+                HashField::Message => {
+                    record.message = self.hash(&record.message);
+                }
+                HashField::MessageRegex(regex) => {
+                    record.message = self.hash_matches(regex, &record.message);
+                }
+            }
+        }
+
+        record.raw = match record.raw.strip_suffix(message.as_str()) {
+            Some(header) if !message.is_empty() => {
+                Self::replace_tokens(header, &values) + &record.message
+            }
+            _ => {
+                let mut raw = Self::replace_tokens(&record.raw, &values);
+                if message != record.message && !message.is_empty() {
+                    raw = raw.replace(&message, &record.message);
+                }
+                raw
+            }
+        };
+        record
+    }
+}
+
+lazy_static! {
+    /// Transmitted telephony messages: reference-ril AT channel, AT command echos, RIL requests
+    /// and QMI requests
+    static ref TELEPHONY_TX: RegexSet = RegexSet::new([
+        r"^AT> ",
+        r"^AT([+&%$^*#]\w|[ADEHIOQSVZ]\d*$|$)",
+        r"^\[\d{4}\]> ",
+        r"\bQMI_\w+_REQ\b",
+    ])
+    .unwrap();
+    /// Received telephony messages: AT channel, RIL responses and unsolicited responses and QMI
+    /// responses and indications
+    static ref TELEPHONY_RX: RegexSet = RegexSet::new([
+        r"^AT< ",
+        r"^\[(\d{4}|UNSL)\]< ",
+        r"\bQMI_\w+_(RESP|IND)\b",
+    ])
+    .unwrap();
+    /// `key=value` pairs in messages. Values are either quoted or end at whitespace or a
+    /// delimiter
+    static ref KEY_VALUE: Regex =
+        Regex::new(r#"(?:^|[\s,;(\[{])([A-Za-z_][\w.-]*)=("(?:[^"\\]|\\.)*"|[^\s,;)\]}]*)"#)
+            .unwrap();
+}
+
+/// Extract `key=value` pairs of the message into the fields of `record`. Fields set by the
+/// parser take precedence
+pub fn key_values(mut record: Record) -> Record {
+    if !record.message.contains('=') {
+        return record;
+    }
+    for captures in KEY_VALUE.captures_iter(&record.message) {
+        let value = &captures[2];
+        let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            value[1..value.len() - 1].replace("\\\"", "\"")
+        } else {
+            value.to_owned()
+        };
+        record.fields.entry(captures[1].to_owned()).or_insert(value);
+    }
+    record
+}
+
+/// Set the direction of AT commands, RIL and QMI messages
+pub fn telephony(mut record: Record) -> Record {
+    if TELEPHONY_TX.is_match(&record.message) {
+        record.direction = Some("TX".into());
+    } else if TELEPHONY_RX.is_match(&record.message) {
+        record.direction = Some("RX".into());
+    }
+    record
+}
+
+/// Appends annotations like links to error code descriptions to matching records
+pub struct Annotate {
+    annotations: Vec<(Regex, String)>,
+}
+
+impl Annotate {
+    /// Load the regex to annotation mapping from the toml file passed with `--annotate`.
+    /// Groups of the regex are referenced in the annotation with `$1`.
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Annotate>, Error> {
+        let file = match args.value_of("annotate") {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let map = read_to_string(file)
+            .map_err(|e| RogcatError::Config(format!("Failed to open {}: {}", file, e)))?;
+        let map: BTreeMap<String, String> = toml::from_str(&map)
+            .map_err(|e| RogcatError::Config(format!("Failed to parse {}: {}", file, e)))?;
+        let annotations = map
+            .into_iter()
+            .map(|(regex, annotation)| {
+                Regex::new(&regex)
+                    .map(|regex| (regex, annotation))
+                    .map_err(|e| {
+                        RogcatError::Config(format!("Invalid annotation regex {}: {}", regex, e))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(Annotate { annotations }))
+    }
+
+    pub fn apply(&self, mut record: Record) -> Record {
+        for (regex, annotation) in &self.annotations {
+            if let Some(captures) = regex.captures(&record.message) {
+                let mut expanded = String::new();
+                captures.expand(annotation, &mut expanded);
+                record.annotations.push(expanded);
+            }
+        }
+        record
+    }
+}
+
+/// Commands run when a record matches a regex e.g `--on-match 'FATAL:adb exec-out screencap > crash.png'`
+pub struct OnMatch {
+    actions: Vec<Action>,
+}
+
+struct Action {
+    regex: Regex,
+    command: String,
+    /// Set while the command runs. Matches are skipped meanwhile.
+    running: Arc<AtomicBool>,
+}
+
+impl Action {
+    /// Parse `regex:command`. A colon within the regex is escaped with a backslash.
+    fn parse(action: &str) -> Result<Action, Error> {
+        let mut escaped = false;
+        let split = action
+            .char_indices()
+            .find(|(_, c)| {
+                let split = *c == ':' && !escaped;
+                escaped = *c == '\\' && !escaped;
+                split
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(|| {
+                RogcatError::Config(format!("Invalid action {}. Use <regex>:<command>", action))
+            })?;
+        let (regex, command) = (&action[..split], action[split + 1..].trim());
+        if command.is_empty() {
+            return Err(
+                RogcatError::Config(format!("Missing command in action {}", action)).into(),
+            );
+        }
+        Ok(Action {
+            regex: Regex::new(&regex.replace("\\:", ":")).map_err(|e| {
+                RogcatError::Config(format!("Invalid action regex {}: {}", regex, e))
+            })?,
+            command: command.to_owned(),
+            running: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Spawn the command with the record in the environment and wait for it on a thread
+    fn run(&self, record: &Record) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        #[cfg(not(windows))]
+        let mut command = Command::new("sh");
+        #[cfg(not(windows))]
+        command.arg("-c");
+        #[cfg(windows)]
+        let mut command = Command::new("cmd");
+        #[cfg(windows)]
+        command.arg("/C");
+        command
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            // Keep the output of rogcat intact e.g when piped
+            .stdout(Stdio::null())
+            .env(
+                "ROGCAT_TIMESTAMP",
+                record
+                    .timestamp
+                    .as_ref()
+                    .and_then(|t| t.format("%m-%d %H:%M:%S.%f"))
+                    .unwrap_or_default(),
+            )
+            .env("ROGCAT_LEVEL", record.level.to_string())
+            .env("ROGCAT_TAG", record.tags.join(" "))
+            .env("ROGCAT_PID", &record.process)
+            .env("ROGCAT_TID", &record.thread)
+            .env("ROGCAT_MESSAGE", &record.message)
+            .env("ROGCAT_RAW", &record.raw);
+
+        let running = self.running.clone();
+        match command.spawn() {
+            Ok(mut child) => {
+                internal::emit(format!("Running {}", self.command));
+                thread::spawn(move || {
+                    match child.wait() {
+                        Ok(status) if !status.success() => {
+                            internal::emit(format!("Action failed with {status}"))
+                        }
+                        Err(e) => internal::emit(format!("Action failed: {e}")),
+                        _ => (),
+                    }
+                    running.store(false, Ordering::SeqCst);
+                });
+            }
+            Err(e) => {
+                internal::emit(format!("Failed to run {}: {}", self.command, e));
+                running.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl OnMatch {
+    /// Actions from the command line and the profile
+    pub fn from_args_profile(
+        args: &ArgMatches,
+        profile: &Profile,
+    ) -> Result<Option<OnMatch>, Error> {
+        let actions = args
+            .values_of("on-match")
+            .unwrap_or_default()
+            .chain(profile.on_match.iter().map(String::as_str))
+            .map(Action::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(if actions.is_empty() {
+            None
+        } else {
+            Some(OnMatch { actions })
+        })
+    }
+
+    pub fn apply(&self, record: &Record) {
+        if internal::is_internal(record) {
+            return;
+        }
+        self.actions
+            .iter()
+            .filter(|action| action.regex.is_match(&record.message))
+            .for_each(|action| action.run(record));
+    }
+}
+
+/// Turns lines into records
+#[derive(Clone, Default)]
+pub struct LineParser {
+    parser: Parser,
+    /// Year and utc offset used to complete timestamps
+    device_date: Option<(i32, i32)>,
+    /// Boot time in seconds since the epoch used to convert kernel uptime stamps
+    boot_time: Option<f64>,
+    /// Number of consecutive unparseable lines
+    unparsed: usize,
+    /// Number of records recovered from garbled lines
+    recovered: usize,
+    /// Multi line json objects unless the json parser is disabled
+    json: Option<JsonAssembler>,
+    /// Source the current lines are read from
+    source: Option<String>,
+    /// Logcat buffer of the current lines announced by the last divider
+    buffer: Option<String>,
+    /// First lines used to detect the input format
+    sample: Option<Vec<String>>,
+    /// Print the detected input format
+    verbose: bool,
+}
+
+impl LineParser {
+    pub fn from_args(
+        args: &ArgMatches,
+        profile: &Profile,
+        device_date: Option<(i32, i32)>,
+        boot_time: Option<f64>,
+    ) -> Result<LineParser, Error> {
+        // Multi line objects are assembled from files only. An unterminated object would hold
+        // back the lines of live sources.
+        let files = args.is_present("input") || args.is_present("input-dir");
+        let json = !args
+            .values_of("disable-parser")
+            .unwrap_or_default()
+            .any(|p| p == "json")
+            && match args.value_of("input-format") {
+                Some(format) => format == "json",
+                None => files,
+            };
+        Ok(LineParser {
+            parser: parser(args, profile)?,
+            device_date,
+            // The boot time passed on the command line overrules the one of the device
+            boot_time: args
+                .value_of("boot-time")
+                .and_then(|t| t.parse().ok())
+                .or(boot_time),
+            json: if json {
+                Some(JsonAssembler::default())
+            } else {
+                None
+            },
+            ..Default::default()
+        })
+    }
+
+    /// Detect the input format from the first lines unless `--input-format` is passed
+    pub fn detect_format(&mut self, args: &ArgMatches) {
+        if !args.is_present("input-format") && !args.is_present("fuchsia-json") {
+            self.sample = Some(Vec::with_capacity(DETECT_LINES));
+            self.verbose = args.is_present("verbose");
+        }
+    }
+
+    /// Order the parsers by the sampled lines and parse them
+    fn detect(&mut self) -> Vec<Record> {
+        let sample = match self.sample.take() {
+            Some(sample) => sample,
+            None => return vec![],
+        };
+        let detected = self.parser.detect(&sample);
+        if self.verbose {
+            match detected {
+                Some((name, count)) => eprintln!(
+                    "Detected input format {} ({} of {} lines)",
+                    name,
+                    count,
+                    sample.len()
+                ),
+                None => eprintln!("Failed to detect the input format"),
+            }
+        }
+        sample
+            .into_iter()
+            .flat_map(|line| self.parse(StreamData::Line(line)))
+            .collect()
+    }
+
+    /// Parse the lines of `source` into records
+    pub fn records(mut self, source: LogStream) -> LogRecords {
+        let sampling = self.sample.is_some();
+        Box::new(
+            Sampling::new(source, sampling)
+                .map(Some)
+                .chain(iter_ok(vec![None]))
+                .map(move |item| {
+                    iter_ok(match item {
+                        Some(Sample::Data(data)) => self.parse(data),
+                        // Slow sources must not hold back the sampled lines
+                        Some(Sample::Deadline) => self.detect(),
+                        None => {
+                            let records = self.flush();
+                            self.report_unparsed();
+                            records
+                        }
+                    })
+                })
+                .flatten(),
+        )
+    }
+
+    /// Parse stream data into one or more records
+    pub fn parse(&mut self, data: StreamData) -> Vec<Record> {
+        if let Some(sample) = self.sample.as_mut() {
+            if let StreamData::Line(line) = data {
+                sample.push(line);
+                return if sample.len() < DETECT_LINES {
+                    vec![]
+                } else {
+                    self.detect()
+                };
+            }
+            let mut records = self.detect();
+            records.extend(self.parse(data));
+            return records;
+        }
+
+        match data {
+            StreamData::Line(line) => {
+                if let Some((buffer, switch)) = logcat_divider(&line) {
+                    self.buffer = Some(buffer.to_owned());
+                    // Switches are printed whenever the buffer changes and carry no information
+                    if switch {
+                        return vec![];
+                    }
+                }
+                let assembled = match self.json.as_mut() {
+                    Some(json) => json.push(line),
+                    None => Assembled::Lines(vec![line]),
+                };
+                let records = match assembled {
+                    Assembled::Lines(lines) => lines
+                        .into_iter()
+                        .flat_map(|line| self.parse_garbled(line))
+                        .collect(),
+                    Assembled::Object(lines) => self.parse_object(lines),
+                };
+                self.with_source(records)
+            }
+            StreamData::Record(record) => self.with_source(vec![*record]),
+            StreamData::Source(source) => {
+                // Lines of an incomplete json object belong to the previous source
+                let records = self.flush();
+                self.source = Some(source);
+                self.buffer = None;
+                records
+            }
+        }
+    }
+
+    /// Parse lines of an incomplete json object at the end of the input
+    fn flush(&mut self) -> Vec<Record> {
+        let mut records = self.detect();
+        let lines = self
+            .json
+            .as_mut()
+            .map(JsonAssembler::flush)
+            .unwrap_or_default();
+        records.extend(lines.into_iter().flat_map(|line| self.parse_garbled(line)));
+        self.with_source(records)
+    }
+
+    /// Set the source and buffer of `records` to the ones the current lines are read from
+    fn with_source(&self, mut records: Vec<Record>) -> Vec<Record> {
+        for record in &mut records {
+            if let Some(ref source) = self.source {
+                record.source = Some(source.clone());
+            }
+            if record.buffer.is_none() {
+                record.buffer.clone_from(&self.buffer);
+            }
+        }
+        records
+    }
+
+    /// Parse a multi line json object or its lines if it's not a record
+    fn parse_object(&mut self, lines: Vec<String>) -> Vec<Record> {
+        let object = lines.join("\n");
+        match JsonParser.try_parse_str(&object) {
+            Ok(mut record) => {
+                record.raw = object;
+                vec![record]
+            }
+            Err(_) => lines
+                .into_iter()
+                .flat_map(|line| self.parse_garbled(line))
+                .collect(),
+        }
+    }
+
+    /// Split lines that no parser accepts or whose message contains another record start.
+    /// Csv and json records carry complete lines in their raw field and are never split.
+    fn parse_garbled(&mut self, line: String) -> Vec<Record> {
+        let parsed = self.parser.try_parse(&line);
+        if parsed
+            .as_ref()
+            .is_none_or(|record| has_record_start(&record.message))
+        {
+            if let Some(parts) = split_garbled(&line) {
+                self.recovered += parts.len() - 1;
+                internal::emit(format!(
+                    "Split garbled line into {} records ({} recovered in total)",
+                    parts.len(),
+                    self.recovered
+                ));
+                return parts
+                    .into_iter()
+                    .map(|part| self.parse_line(part.to_owned()))
+                    .collect();
+            }
+        }
+        vec![self.complete(parsed, line)]
+    }
+
+    fn parse_line(&mut self, line: String) -> Record {
+        let parsed = self.parser.try_parse(&line);
+        self.complete(parsed, line)
+    }
+
+    /// Complete a parsed record or fall back to the plain line
+    fn complete(&mut self, parsed: Option<Record>, line: String) -> Record {
+        match parsed {
+            Some(mut record) => {
+                describe_event(&mut record);
+                if let (Some((year, utcoff)), Some(timestamp)) =
+                    (self.device_date, record.timestamp.as_mut())
+                {
+                    if timestamp.tm.tm_year == 0 {
+                        timestamp.tm.tm_year = year;
+                    }
+                    if timestamp.tm.tm_utcoff == 0 {
+                        timestamp.tm.tm_utcoff = utcoff;
+                    }
+                }
+                self.report_unparsed();
+                // Keep the raw field of formats that carry one (csv, json)
+                if record.raw.is_empty() {
+                    record.raw = line;
+                }
+                self.kernel_time(&mut record);
+                record
+            }
+            None => {
+                self.unparsed += 1;
+                let mut record = Parser::fallback(line);
+                self.kernel_time(&mut record);
+                record
+            }
+        }
+    }
+
+    /// Report a burst of consecutive unparseable lines that ended or reached the end of the input
+    fn report_unparsed(&mut self) {
+        if self.unparsed >= PARSE_FALLBACK_BURST {
+            internal::emit(format!(
+                "Failed to parse {} consecutive lines",
+                self.unparsed
+            ));
+        }
+        self.unparsed = 0;
+    }
+
+    /// Replace the timestamp of records with a kernel uptime prefix in the message or
+    /// tag with the wall clock time. The prefix is removed.
+    fn kernel_time(&self, record: &mut Record) {
+        let boot_time = match self.boot_time {
+            Some(boot_time) => boot_time,
+            None => return,
+        };
+        let (priority, uptime) =
+            if let Some((priority, uptime, message)) = kernel_uptime(&record.message) {
+                record.message = message.to_owned();
+                (priority, uptime)
+            } else if let Some((priority, uptime, tag)) =
+                record.tags.first().and_then(|tag| kernel_uptime(tag))
+            {
+                record.tags[0] = tag.to_owned();
+                (priority, uptime)
+            } else {
+                return;
+            };
+
+        let time = boot_time + uptime;
+        let time = Timespec::new(time.trunc() as i64, (time.fract() * 1e9) as i32);
+        record.timestamp = Some(Timestamp::new(time::at(time)));
+        if record.level == Level::None {
+            record.level = match priority.map(|p| p & 7) {
+                Some(0..=2) => Level::Fatal,
+                Some(3) => Level::Error,
+                Some(4) => Level::Warn,
+                Some(7) => Level::Debug,
+                _ => Level::Info,
+            };
+        }
+    }
+}
+
+/// Next record of an input of `MergeByTime`
+struct Head {
+    /// Time of the record or the last record with a time of the same input
+    time: Timespec,
+    input: usize,
+    record: Record,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Head) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Head) -> CmpOrdering {
+        (self.time, self.input).cmp(&(other.time, other.input))
+    }
+}
+
+/// Records of several inputs interleaved by their timestamps
+pub struct MergeByTime {
+    inputs: Vec<Option<LogRecords>>,
+    /// Time of the last record with a valid timestamp per input
+    times: Vec<Timespec>,
+    /// Inputs without a record in `heads`
+    pending: Vec<usize>,
+    heads: BinaryHeap<Reverse<Head>>,
+}
+
+/// Parse each of `inputs` with a clone of `parser` and merge the records by time. Records
+/// without a timestamp follow the preceding record of their input.
+pub fn merge_by_time(inputs: Vec<LogStream>, parser: &LineParser) -> LogStream {
+    let inputs = inputs
+        .into_iter()
+        .map(|input| Some(parser.clone().records(input)))
+        .collect::<Vec<_>>();
+    Box::new(MergeByTime {
+        times: vec![Timespec::new(i64::MIN, 0); inputs.len()],
+        pending: (0..inputs.len()).rev().collect(),
+        inputs,
+        heads: BinaryHeap::new(),
+    })
+}
+
+impl Stream for MergeByTime {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        // Every input that is not done needs a head before the oldest can be emitted
+        while let Some(&input) = self.pending.last() {
+            let poll = match self.inputs[input] {
+                Some(ref mut records) => records.poll()?,
+                None => Async::Ready(None),
+            };
+            match poll {
+                Async::Ready(Some(record)) => {
+                    if let Some(timestamp) = record.timestamp.as_ref().filter(|t| t.is_valid()) {
+                        self.times[input] = timestamp.to_timespec();
+                    }
+                    self.heads.push(Reverse(Head {
+                        time: self.times[input],
+                        input,
+                        record,
+                    }));
+                }
+                Async::Ready(None) => self.inputs[input] = None,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+            self.pending.pop();
+        }
+
+        Ok(Async::Ready(self.heads.pop().map(|Reverse(head)| {
+            self.pending.push(head.input);
+            StreamData::Record(Box::new(head.record))
+        })))
+    }
+}
+
+/// Stages from the parsed record up to the filter. Clones are run in the threads of
+/// `parallel`.
+#[derive(Clone)]
+pub struct Prepare {
+    args: ArgMatches<'static>,
+    overrides: Option<LevelOverrides>,
+    rewrite: Option<Arc<Rewrite>>,
+    symbolizer: Option<Arc<Symbolizer>>,
+    resolver: Option<Resolver>,
+    filter: Filter,
+    /// Extract `key=value` pairs from messages
+    fields: bool,
+    /// Generation of the applied profile
+    generation: usize,
+}
+
+impl Prepare {
+    pub fn new(
+        args: ArgMatches<'static>,
+        overrides: Option<LevelOverrides>,
+        rewrite: Option<Rewrite>,
+        symbolizer: Option<Symbolizer>,
+        resolver: Option<Resolver>,
+        filter: Filter,
+    ) -> Prepare {
+        let fields = args.is_present("extract-fields") || args.is_present("field");
+        Prepare {
+            args,
+            overrides,
+            rewrite: rewrite.map(Arc::new),
+            symbolizer: symbolizer.map(Arc::new),
+            resolver,
+            filter,
+            fields,
+            generation: 0,
+        }
+    }
+
+    /// Process `record` and return it unless it is filtered
+    pub fn apply(&mut self, record: Record) -> Option<Record> {
+        diagnostics::line(&record.raw);
+        let record = match self.rewrite {
+            Some(ref rewrite) => rewrite.apply(record),
+            None => record,
+        };
+        let record = telephony(record);
+        let record = if self.fields {
+            key_values(record)
+        } else {
+            record
+        };
+        let record = match self.symbolizer {
+            Some(ref symbolizer) => symbolizer.apply(record),
+            None => record,
+        };
+        let record = match self.resolver {
+            Some(ref resolver) => resolver.apply(record),
+            None => record,
+        };
+        if let Some(profile) = profiles::reloaded(&mut self.generation) {
+            if let Err(e) = self.filter.reload(&self.args, &profile) {
+                internal::emit(format!("Failed to apply profile: {e}"));
+            }
+            match LevelOverrides::from_profile(&profile) {
+                Ok(overrides) => self.overrides = overrides,
+                Err(e) => internal::emit(format!("Failed to apply profile: {e}")),
+            }
+        }
+        let record = match self.overrides {
+            Some(ref overrides) => overrides.apply(record),
+            None => record,
+        };
+        Some(record).filter(|record| self.filter.filter(record))
+    }
+}
+
+/// Item of `Sampling`
+enum Sample {
+    Data(StreamData),
+    /// `DETECT_DEADLINE` passed since the first line
+    Deadline,
+}
+
+/// Stream that signals the end of the format detection deadline once
+struct Sampling {
+    stream: LogStream,
+    /// Task that reads the source while sampling
+    ingest: Option<Box<dyn Future<Item = (), Error = ()> + Send>>,
+    deadline: Option<Delay>,
+    /// The deadline is started with the next line
+    armed: bool,
+}
+
+impl Sampling {
+    fn new(source: LogStream, sampling: bool) -> Sampling {
+        if !sampling {
+            return Sampling {
+                stream: source,
+                ingest: None,
+                deadline: None,
+                armed: false,
+            };
+        }
+        // Reading stdin blocks the polling task and the deadline with it
+        let (tx, rx) = channel(DETECT_LINES);
+        let ingest = source
+            .then(Ok::<_, ()>)
+            .forward(tx.sink_map_err(|_| ()))
+            .map(|_| ());
+        Sampling {
+            stream: Box::new(rx.then(|item| {
+                item.map_err(|_| err_msg("Ingestion stopped unexpectedly"))
+                    .and_then(|result| result)
+            })),
+            ingest: Some(Box::new(ingest)),
+            deadline: None,
+            armed: true,
+        }
+    }
+}
+
+impl Stream for Sampling {
+    type Item = Sample;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Sample>, Error> {
+        if let Some(ingest) = self.ingest.take() {
+            tokio::spawn(ingest);
+        }
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.poll()?.is_ready() {
+                self.deadline = None;
+                return Ok(Async::Ready(Some(Sample::Deadline)));
+            }
+        }
+
+        let data = try_ready!(self.stream.poll());
+        if self.armed {
+            if let Some(StreamData::Line(_)) = data {
+                let mut deadline = Delay::new(Instant::now() + DETECT_DEADLINE);
+                // Register the timer
+                deadline.poll()?;
+                self.deadline = Some(deadline);
+                self.armed = false;
+            }
+        }
+        Ok(Async::Ready(data.map(Sample::Data)))
+    }
+}
+
+/// Stream that terminates a given duration after its first item
+pub struct Until<S> {
+    stream: S,
+    duration: Duration,
+    deadline: Option<Delay>,
+}
+
+/// Terminate `stream` `duration` after the first item
+pub fn until<S: Stream<Error = Error>>(stream: S, duration: Duration) -> Until<S> {
+    Until {
+        stream,
+        duration,
+        deadline: None,
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for Until<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.poll()?.is_ready() {
+                return Ok(Async::Ready(None));
+            }
+        }
+
+        let poll = self.stream.poll()?;
+        if self.deadline.is_none() {
+            if let Async::Ready(Some(_)) = poll {
+                let mut deadline = Delay::new(Instant::now() + self.duration);
+                // Register the timer
+                deadline.poll()?;
+                self.deadline = Some(deadline);
+            }
+        }
+        Ok(poll)
+    }
+}
+
+/// Stream of the last records of a finite stream
+pub struct Tail<S: Stream> {
+    stream: S,
+    count: usize,
+    buffer: VecDeque<S::Item>,
+    done: bool,
+}
+
+/// Buffer `stream` until its end and provide its last `count` items
+pub fn tail<S: Stream<Error = Error>>(stream: S, count: usize) -> Tail<S> {
+    Tail {
+        stream,
+        count,
+        buffer: VecDeque::new(),
+        done: false,
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for Tail<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        while !self.done {
+            match try_ready!(self.stream.poll()) {
+                Some(item) => {
+                    if self.buffer.len() == self.count {
+                        self.buffer.pop_front();
+                    }
+                    if self.count > 0 {
+                        self.buffer.push_back(item);
+                    }
+                }
+                None => self.done = true,
+            }
+        }
+        Ok(Async::Ready(self.buffer.pop_front()))
+    }
+}
+
+/// Sink that passes only the first or last records of each tag
+pub struct PerTag {
+    sink: LogSink,
+    limit: usize,
+    last: bool,
+    counts: HashMap<String, usize>,
+    /// Most recent records of each tag with their sequence number
+    buffers: HashMap<String, VecDeque<(u64, Record)>>,
+    sequence: u64,
+    /// Buffered records that are flushed into `sink` on close
+    pending: Option<VecDeque<Record>>,
+}
+
+/// Send each record to all of `sinks` e.g the terminal and an output file
+pub fn fanout(sinks: Vec<LogSink>) -> LogSink {
+    sinks
+        .into_iter()
+        .reduce(|a, b| Box::new(a.fanout(b)))
+        .expect("No sink")
+}
+
+/// Wrap `sink` if `--first-per-tag` or `--last-per-tag` is passed
+pub fn per_tag(args: &ArgMatches, sink: LogSink) -> LogSink {
+    let (limit, last) = match (
+        args.value_of("first-per-tag"),
+        args.value_of("last-per-tag"),
+    ) {
+        (Some(n), _) => (n, false),
+        (_, Some(n)) => (n, true),
+        _ => return sink,
+    };
+    Box::new(PerTag {
+        sink,
+        limit: limit.parse().expect("Invalid per tag limit"),
+        last,
+        counts: HashMap::new(),
+        buffers: HashMap::new(),
+        sequence: 0,
+        pending: None,
+    })
+}
+
+impl Sink for PerTag {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        if internal::is_internal(&record) {
+            return self.sink.start_send(record);
+        }
+        let tag = record.tags.first().cloned().unwrap_or_default();
+        if self.last {
+            let buffer = self.buffers.entry(tag).or_default();
+            buffer.push_back((self.sequence, record));
+            if buffer.len() > self.limit {
+                buffer.pop_front();
+            }
+            self.sequence += 1;
+            Ok(AsyncSink::Ready)
+        } else {
+            let count = self.counts.entry(tag).or_default();
+            if *count < self.limit {
+                *count += 1;
+                self.sink.start_send(record)
+            } else {
+                Ok(AsyncSink::Ready)
+            }
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        if self.pending.is_none() {
+            // Restore the order of arrival across tags
+            let mut records = self
+                .buffers
+                .drain()
+                .flat_map(|(_, b)| b)
+                .collect::<Vec<_>>();
+            records.sort_by_key(|(sequence, _)| *sequence);
+            self.pending = Some(records.into_iter().map(|(_, r)| r).collect());
+        }
+        if let Some(ref mut pending) = self.pending {
+            while let Some(record) = pending.pop_front() {
+                if let AsyncSink::NotReady(record) = self.sink.start_send(record)? {
+                    pending.push_front(record);
+                    self.sink.poll_complete()?;
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+        self.sink.close()
+    }
+}
+
+/// Number of records buffered between ingestion and output
+pub fn backlog() -> usize {
+    BACKLOG.load(Ordering::Relaxed)
+}
+
+/// Ingest `stream` on `executor` independent of the speed of the consumer
+/// of the returned stream. The number of buffered records is available via
+/// `backlog`. Records that exceed `DECOUPLE_CAPACITY` are dropped and reported
+/// once the consumer caught up.
+pub fn decouple(stream: LogRecords, executor: &TaskExecutor) -> LogRecords {
+    let (mut tx, rx) = channel(DECOUPLE_CAPACITY);
+    let ingest = stream.then(Ok::<_, ()>).for_each(move |result| {
+        BACKLOG.fetch_add(1, Ordering::Relaxed);
+        match result {
+            // Errors end the processing and are never dropped
+            Err(e) => Either::A(tx.clone().send(Err(e)).map(|_| ()).map_err(|_| ())),
+            Ok(record) => Either::B(match tx.try_send(Ok(record)) {
+                Ok(()) => ok(()),
+                Err(ref e) if e.is_full() => {
+                    BACKLOG.fetch_sub(1, Ordering::Relaxed);
+                    DROPPED.fetch_add(1, Ordering::Relaxed);
+                    ok(())
+                }
+                Err(_) => err(()),
+            }),
+        }
+    });
+    executor.spawn(ingest);
+
+    Box::new(rx.then(|item| {
+        let backlog = BACKLOG.fetch_sub(1, Ordering::Relaxed) - 1;
+        if backlog < DECOUPLE_CAPACITY / 2 {
+            let dropped = DROPPED.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                internal::emit(format!(
+                    "Dropped {dropped} records while the output was behind"
+                ));
+            }
+        }
+        item.map_err(|_| err_msg("Ingestion stopped unexpectedly"))
+            .and_then(|result| result)
+    }))
+}
+
+/// Lines parsed by a thread of `parallel`
+struct Chunk {
+    /// Source of the first line
+    source: Option<String>,
+    /// Logcat buffer of the first line
+    buffer: Option<String>,
+    data: Vec<StreamData>,
+    records: oneshot::Sender<Vec<Record>>,
+}
+
+/// Parse and prepare the lines of `source` in chunks on `jobs` threads. The records are
+/// emitted in the order of the input.
+pub fn parallel(
+    source: LogStream,
+    parser: LineParser,
+    prepare: Prepare,
+    jobs: usize,
+) -> LogRecords {
+    let (tx, rx) = mpsc::channel::<Chunk>();
+    let rx = Arc::new(Mutex::new(rx));
+    for _ in 0..jobs {
+        let rx = rx.clone();
+        let mut parser = parser.clone();
+        let mut prepare = prepare.clone();
+        thread::spawn(move || loop {
+            let chunk = match rx.lock().expect("Failed to get chunk lock").recv() {
+                Ok(chunk) => chunk,
+                Err(_) => break,
+            };
+            parser.source = chunk.source;
+            parser.buffer = chunk.buffer;
+            let mut records = Vec::with_capacity(chunk.data.len());
+            for data in chunk.data {
+                records.extend(parser.parse(data));
+            }
+            records.extend(parser.flush());
+            // Chunks of one thread are not adjacent
+            parser.report_unparsed();
+            let records = records
+                .into_iter()
+                .filter_map(|record| prepare.apply(record))
+                .collect();
+            chunk.records.send(records).ok();
+        });
+    }
+
+    Box::new(Parallel {
+        source: source.fuse(),
+        json: parser.json.map(|_| JsonAssembler::default()),
+        source_name: None,
+        chunk: Vec::with_capacity(CHUNK_LINES),
+        chunk_source: None,
+        buffer_name: None,
+        chunk_buffer: None,
+        chunks: tx,
+        pending: VecDeque::new(),
+        max_pending: jobs * CHUNKS_PER_JOB,
+        records: Vec::new().into_iter(),
+    })
+}
+
+struct Parallel {
+    source: Fuse<LogStream>,
+    /// Multi line json objects are kept in one chunk
+    json: Option<JsonAssembler>,
+    /// Source of the last line
+    source_name: Option<String>,
+    chunk: Vec<StreamData>,
+    chunk_source: Option<String>,
+    /// Logcat buffer announced by the last divider
+    buffer_name: Option<String>,
+    chunk_buffer: Option<String>,
+    chunks: mpsc::Sender<Chunk>,
+    /// Records of the dispatched chunks in input order
+    pending: VecDeque<oneshot::Receiver<Vec<Record>>>,
+    max_pending: usize,
+    records: std::vec::IntoIter<Record>,
+}
+
+impl Parallel {
+    fn push(&mut self, data: StreamData) {
+        if let StreamData::Line(ref line) = data {
+            if let Some((buffer, _)) = logcat_divider(line) {
+                self.buffer_name = Some(buffer.to_owned());
+            }
+        }
+        match data {
+            StreamData::Line(line) => match self.json {
+                Some(ref mut json) => match json.push(line) {
+                    Assembled::Lines(lines) | Assembled::Object(lines) => {
+                        self.chunk.extend(lines.into_iter().map(StreamData::Line))
+                    }
+                },
+                None => self.chunk.push(StreamData::Line(line)),
+            },
+            StreamData::Source(source) => {
+                self.flush_json();
+                self.source_name = Some(source.clone());
+                self.buffer_name = None;
+                self.chunk.push(StreamData::Source(source));
+            }
+            data => self.chunk.push(data),
+        }
+    }
+
+    /// Add the lines of an incomplete json object to the current chunk
+    fn flush_json(&mut self) {
+        if let Some(ref mut json) = self.json {
+            let lines = json.flush();
+            self.chunk.extend(lines.into_iter().map(StreamData::Line));
+        }
+    }
+
+    /// Send the current chunk to the threads
+    fn dispatch(&mut self) {
+        if self.chunk.is_empty() {
+            return;
+        }
+        let (tx, rx) = oneshot::channel();
+        let chunk = Chunk {
+            source: self.chunk_source.clone(),
+            buffer: self.chunk_buffer.clone(),
+            data: std::mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_LINES)),
+            records: tx,
+        };
+        self.chunk_source = self.source_name.clone();
+        self.chunk_buffer = self.buffer_name.clone();
+        // The threads only stop if the chunk sender is dropped
+        self.chunks.send(chunk).ok();
+        self.pending.push_back(rx);
+    }
+}
+
+impl Stream for Parallel {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Ok(Async::Ready(Some(record)));
+            }
+
+            if let Some(records) = self.pending.front_mut() {
+                match records.poll() {
+                    Ok(Async::Ready(records)) => {
+                        self.pending.pop_front();
+                        self.records = records.into_iter();
+                        continue;
+                    }
+                    Ok(Async::NotReady)
+                        if self.pending.len() >= self.max_pending || self.source.is_done() =>
+                    {
+                        return Ok(Async::NotReady)
+                    }
+                    Ok(Async::NotReady) => (),
+                    Err(_) => return Err(err_msg("Parser thread failed")),
+                }
+            } else if self.source.is_done() {
+                return Ok(Async::Ready(None));
+            }
+
+            match self.source.poll()? {
+                Async::Ready(Some(data)) => {
+                    self.push(data);
+                    if self.chunk.len() >= CHUNK_LINES {
+                        self.dispatch();
+                    }
+                }
+                Async::Ready(None) => {
+                    self.flush_json();
+                    self.dispatch();
+                }
+                Async::NotReady if self.chunk.is_empty() => return Ok(Async::NotReady),
+                // Don't hold back lines while the source is waiting
+                Async::NotReady => self.dispatch(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rogcat::record::Format;
+
+    const LINE: &str = "01-02 10:00:00.000  100  101 I Foo: hello";
+
+    /// Parse `line` and expect exactly one record
+    fn parse(parser: &mut LineParser, line: String) -> Record {
+        let mut records = parser.parse(StreamData::Line(line));
+        assert_eq!(records.len(), 1);
+        records.remove(0)
+    }
+
+    #[test]
+    fn csv_json_round_trip() {
+        let mut parser = LineParser::default();
+        let record = parse(&mut parser, LINE.to_owned());
+        for format in &[Format::Csv(None), Format::Json] {
+            let line = format.fmt_record(&record).unwrap();
+            assert_eq!(parse(&mut parser, line), record);
+        }
+        assert_eq!(parser.recovered, 0);
+    }
+
+    fn rewrite(rule: &str, s: &str) -> String {
+        let mut s = s.to_owned();
+        Rule::parse(rule).unwrap().apply(&mut s);
+        s
+    }
+
+    #[test]
+    fn rewrite_rules() {
+        assert_eq!(rewrite("s/a/b/", "aaa"), "baa");
+        assert_eq!(rewrite("s/a/b/g", "aaa"), "bbb");
+        assert_eq!(rewrite("s/A/b/gi", "aAa"), "bbb");
+        assert_eq!(rewrite("s|/|-|g", "a/b/c"), "a-b-c");
+        assert_eq!(rewrite(r"s/\//-/g", "a/b"), "a-b");
+        assert_eq!(rewrite(r"s/\d+/N/g", "a1b22"), "aNbN");
+        // Groups are referenced with a backslash, `$` is literal
+        assert_eq!(rewrite(r"s/(\w+)=(\w+)/\2=\1/", "key=value"), "value=key");
+        assert_eq!(rewrite("s/price/$1/", "price"), "$1");
+        assert_eq!(rewrite("s/x/${y}$$/", "x"), "${y}$$");
+        assert_eq!(rewrite(r"s/x/\$\\/", "x"), "$\\");
+        assert_eq!(rewrite(r"s$x$\$$", "x"), "$");
+
+        for invalid in &["", "s", "x/a/b/", "s/a/b", "s/a/b/c/", "s/a/b/x", "s/(/b/"] {
+            assert!(Rule::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn garbled_lines() {
+        let mut parser = LineParser::default();
+        let records = parser.parse(StreamData::Line(format!("{LINE}{LINE}")));
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.message == "hello"));
+        assert_eq!(parser.recovered, 1);
+    }
+
+    #[test]
+    fn detect_deadline() {
+        let parser = LineParser {
+            sample: Some(Vec::new()),
+            ..Default::default()
+        };
+        // A source that stays open after its first line
+        let source: LogStream = Box::new(
+            iter_ok(vec![StreamData::Line(LINE.to_owned())]).chain(futures::stream::empty()),
+        );
+        let records = parser.records(source).take(1).collect();
+        let records = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(tokio::timer::Timeout::new(records, Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "hello");
+    }
+}
@@ -18,18 +18,51 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::utils;
+use crate::{error::RogcatError, internal, utils};
 use clap::{value_t, ArgMatches};
-use failure::{format_err, Error};
+use failure::Error;
+use lazy_static::lazy_static;
+use rogcat::record;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap, convert::Into, env::var, fs::File, io::Read, ops::AddAssign,
-    path::PathBuf,
+    collections::HashMap,
+    convert::Into,
+    env::var,
+    fs::File,
+    io::Read,
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
 };
 use toml::from_str;
 
 const DEFAULT_PROFILE_NAME: &str = "default";
 
+/// Interval between checks of the profiles file for modifications
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Incremented whenever a profile is reloaded or selected at runtime
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref RELOADED: Mutex<Reloaded> = Mutex::new(Reloaded::default());
+}
+
+/// Profile reloaded or selected at runtime
+#[derive(Default)]
+struct Reloaded {
+    /// Watched profiles file
+    file: Option<PathBuf>,
+    /// Name of the selected profile
+    name: Option<String>,
+    profile: Option<Profile>,
+}
+
 /// Profile definition with filters and misc
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Profile {
@@ -37,27 +70,110 @@ pub struct Profile {
     pub extends: Vec<String>,
     pub filter: Vec<String>,
     pub filter_case_insensitive: Vec<String>,
-    pub highlight: Vec<String>,
+    /// Filter list files. Relative paths are resolved against the profiles file.
+    pub filter_files: Vec<String>,
+    pub highlight: Vec<Highlight>,
+    /// Custom levels and their severity e.g `NOTICE = 45`
+    pub custom_levels: HashMap<String, u32>,
+    /// Minimum level per tag. The tag "*" applies to all other tags.
+    pub levels: HashMap<String, String>,
+    /// Levels that replace the level of records per tag e.g of components logging everything as error
+    pub level_overrides: HashMap<String, String>,
     pub message: Vec<String>,
     pub message_case_insensitive: Vec<String>,
+    /// Messages that raise a desktop notification
+    pub notify: Vec<String>,
+    /// Commands run on matching records like `<regex>:<command>`
+    pub on_match: Vec<String>,
+    /// Default output file if `-o` isn't passed. `~` and `%date%` are expanded.
+    pub output: Option<String>,
+    /// Default records per file of the profile output
+    pub records_per_file: Option<String>,
+    /// Default format of the profile output
+    pub format: Option<String>,
+    /// Regex with named capture groups for a custom line format
+    pub parser: Option<String>,
+    /// Process ids and ranges like `100-200`. The prefix '!' excludes.
+    pub pid: Vec<String>,
+    /// Sed like substitutions applied to the message and raw line
+    pub rewrite: Vec<String>,
     pub tag: Vec<String>,
     pub tag_case_insensitive: Vec<String>,
+    /// Groups of tags shown as the group name and selected with `-t group:NAME`
+    pub tag_groups: HashMap<String, Vec<String>>,
+    /// Thread ids and ranges like `100-200`. The prefix '!' excludes.
+    pub tid: Vec<String>,
+}
+
+/// Highlight pattern with an optional style of the matching text
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Highlight {
+    pub pattern: String,
+    /// Foreground color e.g `magenta`, an ANSI 256 color number or `r,g,b`
+    pub fg: Option<String>,
+    /// Background color
+    pub bg: Option<String>,
+    #[serde(default)]
+    pub bold: bool,
+    #[serde(default)]
+    pub underline: bool,
+}
+
+impl Highlight {
+    /// Plain pattern without a style e.g from the command line
+    pub fn pattern(pattern: &str) -> Highlight {
+        Highlight {
+            pattern: pattern.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_styled(&self) -> bool {
+        self.fg.is_some() || self.bg.is_some() || self.bold || self.underline
+    }
+}
+
+/// Highlights are either plain patterns or tables with a style
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum HighlightFile {
+    Pattern(String),
+    Styled(Highlight),
+}
+
+impl From<HighlightFile> for Highlight {
+    fn from(f: HighlightFile) -> Highlight {
+        match f {
+            HighlightFile::Pattern(pattern) => Highlight::pattern(&pattern),
+            HighlightFile::Styled(highlight) => highlight,
+        }
+    }
 }
 
 /// Create a new Profiles instance from a give configuration file
 /// and default if file is not present or readable
 pub fn from_args(args: &ArgMatches) -> Result<Profile, Error> {
     let file = file(Some(args))?;
+    if args.is_present("verbose") {
+        eprintln!("Profiles file: {}", file.display());
+    }
+    load(&file, args.value_of("profile")).map(|(profile, _)| profile)
+}
+
+/// Read the profile `name` or the default profile from `file`. Returns the profile and the
+/// names of all profiles.
+fn load(file: &Path, name: Option<&str>) -> Result<(Profile, Vec<String>), Error> {
     if !file.exists() {
-        Ok(Profile::default())
+        Ok((Profile::default(), vec![]))
     } else {
         let mut config = String::new();
-        File::open(file.clone())
-            .map_err(|e| format_err!("Failed to open {}: {}", file.display(), e))?
+        File::open(file)
+            .map_err(|e| RogcatError::Config(format!("Failed to open {}: {}", file.display(), e)))?
             .read_to_string(&mut config)?;
 
-        let mut config_file: ConfigurationFile = from_str(&config)
-            .map_err(|e| format_err!("Failed to parse {}: {}", file.display(), e))?;
+        let mut config_file: ConfigurationFile = from_str(&config).map_err(|e| {
+            RogcatError::Config(format!("Failed to parse {}: {}", file.display(), e))
+        })?;
 
         let profiles: HashMap<String, Profile> = config_file
             .profile
@@ -66,10 +182,10 @@ pub fn from_args(args: &ArgMatches) -> Result<Profile, Error> {
             .collect();
 
         let mut profile = Profile::default();
-        if let Some(n) = args.value_of("profile") {
+        if let Some(n) = name {
             profile = profiles
                 .get(n)
-                .ok_or_else(|| format_err!("Unknown profile {}", n))?
+                .ok_or_else(|| RogcatError::Config(format!("Unknown profile {}", n)))?
                 .clone();
             expand(n, &mut profile, &profiles)?;
         } else if let Some(default_profile) = profiles.get(DEFAULT_PROFILE_NAME) {
@@ -77,8 +193,104 @@ pub fn from_args(args: &ArgMatches) -> Result<Profile, Error> {
             expand(DEFAULT_PROFILE_NAME, &mut profile, &profiles)?;
         }
 
-        Ok(profile)
+        let dir = file.parent().unwrap_or_else(|| Path::new("."));
+        for filter_file in &mut profile.filter_files {
+            *filter_file = dir.join(&filter_file).display().to_string();
+        }
+
+        for (name, severity) in &profile.custom_levels {
+            record::register_level(name, *severity);
+        }
+
+        let mut names = profiles.into_keys().collect::<Vec<_>>();
+        names.sort();
+        Ok((profile, names))
+    }
+}
+
+/// Watch the profiles file and reload the selected profile if it's modified
+pub fn watch(args: &ArgMatches) -> Result<(), Error> {
+    let file = file(Some(args))?;
+    {
+        let mut reloaded = RELOADED.lock().expect("Failed to get profile lock");
+        reloaded.file = Some(file.clone());
+        reloaded.name = args.value_of("profile").map(ToOwned::to_owned);
+    }
+
+    let modified = |file: &Path| file.metadata().and_then(|m| m.modified()).ok();
+    let mut last = modified(&file);
+    thread::spawn(move || loop {
+        thread::sleep(WATCH_INTERVAL);
+        let current = modified(&file);
+        if current != last {
+            last = current;
+            let name = RELOADED
+                .lock()
+                .expect("Failed to get profile lock")
+                .name
+                .clone();
+            reload(name, "Reloaded");
+        }
+    });
+    Ok(())
+}
+
+/// Select the next profile of the profiles file
+pub fn cycle() {
+    let (file, name) = {
+        let reloaded = RELOADED.lock().expect("Failed to get profile lock");
+        match reloaded.file {
+            Some(ref file) => (file.clone(), reloaded.name.clone()),
+            None => return,
+        }
+    };
+    let names = match load(&file, None) {
+        Ok((_, names)) => names,
+        Err(e) => return internal::emit(e.to_string()),
+    };
+    let next = match name {
+        Some(name) => names.iter().find(|n| **n > name).or_else(|| names.first()),
+        None => names.first(),
+    };
+    if let Some(next) = next {
+        reload(Some(next.clone()), "Selected");
+    }
+}
+
+/// Load the profile `name` and publish it to `reloaded`
+fn reload(name: Option<String>, action: &str) {
+    let mut reloaded = RELOADED.lock().expect("Failed to get profile lock");
+    let file = match reloaded.file {
+        Some(ref file) => file.clone(),
+        None => return,
+    };
+    match load(&file, name.as_deref()) {
+        Ok((profile, _)) => {
+            internal::emit(format!(
+                "{} profile {}",
+                action,
+                name.as_deref().unwrap_or(DEFAULT_PROFILE_NAME)
+            ));
+            reloaded.name = name;
+            reloaded.profile = Some(profile);
+            GENERATION.fetch_add(1, Ordering::SeqCst);
+        }
+        Err(e) => internal::emit(format!("Failed to reload profile: {e}")),
+    }
+}
+
+/// Profile that is reloaded or selected after the `generation` seen by the caller
+pub fn reloaded(generation: &mut usize) -> Option<Profile> {
+    let current = GENERATION.load(Ordering::SeqCst);
+    if current == *generation {
+        return None;
     }
+    *generation = current;
+    RELOADED
+        .lock()
+        .expect("Failed to get profile lock")
+        .profile
+        .clone()
 }
 
 /// Expand a profile with file content
@@ -88,18 +300,19 @@ fn expand(n: &str, p: &mut Profile, a: &HashMap<String, Profile>) -> Result<(),
         let extends = p.extends.clone();
         p.extends.clear();
         for e in &extends {
-            let f = a
-                .get(e)
-                .ok_or_else(|| format_err!("Unknown extend profile name {} used in {}", e, n))?;
+            let f = a.get(e).ok_or_else(|| {
+                RogcatError::Config(format!("Unknown extend profile name {} used in {}", e, n))
+            })?;
             *p += f.clone();
         }
 
         recursion_limit -= 1;
         if recursion_limit == 0 {
-            return Err(format_err!(
+            return Err(RogcatError::Config(format!(
                 "Reached recursion limit while resolving profile {} extends",
                 n
-            ));
+            ))
+            .into());
         }
     }
     Ok(())
@@ -113,10 +326,11 @@ fn file(args: Option<&ArgMatches>) -> Result<PathBuf, Error> {
             if f.exists() {
                 return Ok(f);
             } else {
-                return Err(format_err!(
+                return Err(RogcatError::Config(format!(
                     "Cannot find {}. Use --profiles_path to specify the path manually!",
                     f.display()
-                ));
+                ))
+                .into());
             }
         }
     }
@@ -124,10 +338,11 @@ fn file(args: Option<&ArgMatches>) -> Result<PathBuf, Error> {
         if f.exists() {
             Ok(f)
         } else {
-            Err(format_err!(
+            Err(RogcatError::Config(format!(
                 "Cannot find {} set in ROGCAT_PROFILES!",
                 f.display()
             ))
+            .into())
         }
     } else {
         Ok(utils::config_dir().join("profiles.toml"))
@@ -147,11 +362,25 @@ struct ProfileFile {
     extends: Option<Vec<String>>,
     filter: Option<Vec<String>>,
     filter_case_insensitive: Option<Vec<String>>,
-    highlight: Option<Vec<String>>,
+    filter_files: Option<Vec<String>>,
+    highlight: Option<Vec<HighlightFile>>,
+    custom_levels: Option<HashMap<String, u32>>,
+    levels: Option<HashMap<String, String>>,
+    level_overrides: Option<HashMap<String, String>>,
     message: Option<Vec<String>>,
     message_case_insensitive: Option<Vec<String>>,
+    notify: Option<Vec<String>>,
+    on_match: Option<Vec<String>>,
+    output: Option<String>,
+    records_per_file: Option<toml::Value>,
+    format: Option<String>,
+    parser: Option<String>,
+    pid: Option<Vec<String>>,
+    rewrite: Option<Vec<String>>,
     tag: Option<Vec<String>>,
     tag_case_insensitive: Option<Vec<String>>,
+    tag_groups: Option<HashMap<String, Vec<String>>>,
+    tid: Option<Vec<String>>,
 }
 
 impl From<ProfileFile> for Profile {
@@ -161,11 +390,34 @@ impl From<ProfileFile> for Profile {
             extends: f.extends.unwrap_or_default(),
             filter: f.filter.unwrap_or_default(),
             filter_case_insensitive: f.filter_case_insensitive.unwrap_or_default(),
-            highlight: f.highlight.unwrap_or_default(),
+            filter_files: f.filter_files.unwrap_or_default(),
+            highlight: f
+                .highlight
+                .unwrap_or_default()
+                .into_iter()
+                .map(Highlight::from)
+                .collect(),
+            custom_levels: f.custom_levels.unwrap_or_default(),
+            levels: f.levels.unwrap_or_default(),
+            level_overrides: f.level_overrides.unwrap_or_default(),
             message: f.message.unwrap_or_default(),
             message_case_insensitive: f.message_case_insensitive.unwrap_or_default(),
+            notify: f.notify.unwrap_or_default(),
+            on_match: f.on_match.unwrap_or_default(),
+            output: f.output,
+            // Allow numbers and suffixed counts like "100k"
+            records_per_file: f.records_per_file.map(|n| match n {
+                toml::Value::String(n) => n,
+                n => n.to_string(),
+            }),
+            format: f.format,
+            parser: f.parser,
+            pid: f.pid.unwrap_or_default(),
+            rewrite: f.rewrite.unwrap_or_default(),
             tag: f.tag.unwrap_or_default(),
             tag_case_insensitive: f.tag_case_insensitive.unwrap_or_default(),
+            tag_groups: f.tag_groups.unwrap_or_default(),
+            tid: f.tid.unwrap_or_default(),
         }
     }
 }
@@ -181,8 +433,47 @@ impl AddAssign for Profile {
         }
 
         vec_extend!(self.extends, other.extends);
+        vec_extend!(self.filter_files, other.filter_files);
         vec_extend!(self.highlight, other.highlight);
         vec_extend!(self.message, other.message);
+        vec_extend!(self.notify, other.notify);
+        vec_extend!(self.on_match, other.on_match);
         vec_extend!(self.tag, other.tag);
+        vec_extend!(self.pid, other.pid);
+        vec_extend!(self.tid, other.tid);
+
+        if self.parser.is_none() {
+            self.parser = other.parser;
+        }
+        if self.output.is_none() {
+            self.output = other.output;
+        }
+        if self.records_per_file.is_none() {
+            self.records_per_file = other.records_per_file;
+        }
+        if self.format.is_none() {
+            self.format = other.format;
+        }
+
+        // Rewrite rules are applied in order
+        for rule in other.rewrite {
+            if !self.rewrite.contains(&rule) {
+                self.rewrite.push(rule);
+            }
+        }
+
+        // Levels of the extending profile take precedence
+        for (tag, level) in other.levels {
+            self.levels.entry(tag).or_insert(level);
+        }
+        for (tag, level) in other.level_overrides {
+            self.level_overrides.entry(tag).or_insert(level);
+        }
+        for (name, tags) in other.tag_groups {
+            self.tag_groups.entry(name).or_insert(tags);
+        }
+        for (name, severity) in other.custom_levels {
+            self.custom_levels.entry(name).or_insert(severity);
+        }
     }
 }
@@ -0,0 +1,134 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Progress reporting. Renders an indicatif progress bar on a terminal and
+//! falls back to throttled plain text lines if stderr is not a TTY (e.g CI).
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+/// Minimum interval between two plain text progress lines
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+pub enum Progress {
+    Bar(ProgressBar),
+    Plain(RefCell<Plain>),
+    /// Report nothing e.g while writing in the background
+    Hidden,
+}
+
+pub struct Plain {
+    len: Option<u64>,
+    position: u64,
+    message: String,
+    start: Instant,
+    last: Instant,
+}
+
+impl Plain {
+    fn report(&mut self, force: bool) {
+        let now = Instant::now();
+        if force || now.duration_since(self.last) >= PLAIN_INTERVAL {
+            self.last = now;
+            let elapsed = now.duration_since(self.start).as_secs();
+            match self.len {
+                Some(len) => {
+                    eprintln!("{}: {}/{} ({}s)", self.message, self.position, len, elapsed)
+                }
+                None => eprintln!("{}: {} ({}s)", self.message, self.position, elapsed),
+            }
+        }
+    }
+}
+
+impl Progress {
+    /// Create a new progress indicator with optional length
+    pub fn new(len: Option<u64>, template: &str, chars: &str) -> Progress {
+        if atty::is(atty::Stream::Stderr) {
+            let pb = ProgressBar::new(len.unwrap_or(u64::MAX));
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(template)
+                    .progress_chars(chars),
+            );
+            Progress::Bar(pb)
+        } else {
+            let now = Instant::now();
+            Progress::Plain(RefCell::new(Plain {
+                len,
+                position: 0,
+                message: String::new(),
+                start: now,
+                last: now,
+            }))
+        }
+    }
+
+    pub fn set_message(&self, message: &str) {
+        match self {
+            Progress::Bar(pb) => pb.set_message(message),
+            Progress::Plain(plain) => {
+                let mut plain = plain.borrow_mut();
+                plain.message = message.to_owned();
+                plain.report(true);
+            }
+            Progress::Hidden => (),
+        }
+    }
+
+    pub fn set_position(&self, position: u64) {
+        match self {
+            Progress::Bar(pb) => pb.set_position(position),
+            Progress::Plain(plain) => {
+                let mut plain = plain.borrow_mut();
+                plain.position = position;
+                plain.report(false);
+            }
+            Progress::Hidden => (),
+        }
+    }
+
+    pub fn inc(&self, delta: u64) {
+        match self {
+            Progress::Bar(pb) => pb.inc(delta),
+            Progress::Plain(plain) => {
+                let mut plain = plain.borrow_mut();
+                plain.position += delta;
+                plain.report(false);
+            }
+            Progress::Hidden => (),
+        }
+    }
+
+    /// Finish and replace the progress with `message`
+    pub fn finish_with_message(&self, message: &str) {
+        match self {
+            Progress::Bar(pb) => {
+                pb.set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
+                pb.finish_with_message(message);
+            }
+            Progress::Plain(_) => eprintln!("{message}"),
+            Progress::Hidden => (),
+        }
+    }
+}
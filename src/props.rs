@@ -0,0 +1,148 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Snapshots and diffs of the system properties of a device
+
+use crate::{
+    error::RogcatError,
+    utils::{self, device_shell, select_device, selected_device},
+};
+use clap::ArgMatches;
+use failure::Error;
+use rogcat::parser::property;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Write,
+    path::PathBuf,
+};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+type Properties = BTreeMap<String, String>;
+
+/// Print the properties of the device or their changes since the snapshot `--diff`
+/// and optionally save them as `--save`
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let properties = getprop(args)?;
+    let color = match args.value_of("color") {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        _ if atty::is(atty::Stream::Stdout) => ColorChoice::Auto,
+        _ => ColorChoice::Never,
+    };
+    let mut out = StandardStream::stdout(color);
+
+    if let Some(name) = args.value_of("diff") {
+        let file = snapshot(name)?;
+        let snapshot: Properties = File::open(&file)
+            .map_err(|e| {
+                RogcatError::Config(format!("Failed to open snapshot {}: {}", file.display(), e))
+            })
+            .and_then(|f| {
+                serde_json::from_reader(f).map_err(|e| {
+                    RogcatError::Config(format!("Failed to parse {}: {}", file.display(), e))
+                })
+            })?;
+        diff(&snapshot, &properties, &mut out)?;
+    } else if !args.is_present("save") {
+        for (name, value) in &properties {
+            writeln!(out, "[{}]: [{}]", name, value)?;
+        }
+    }
+
+    if let Some(name) = args.value_of("save") {
+        let file = snapshot(name)?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).map_err(|e| {
+                RogcatError::Io(format!("Failed to create {}: {}", dir.display(), e))
+            })?;
+        }
+        let f = File::create(&file)
+            .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", file.display(), e)))?;
+        serde_json::to_writer_pretty(f, &properties)?;
+        eprintln!(
+            "Saved {} properties to {}",
+            properties.len(),
+            file.display()
+        );
+    }
+    Ok(())
+}
+
+/// Properties of the device read with `getprop`
+fn getprop(args: &ArgMatches) -> Result<Properties, Error> {
+    select_device(args)?;
+    let output = device_shell(selected_device(args), false, ["getprop"])?
+        .output()
+        .map_err(|e| RogcatError::Device(format!("Failed to run adb: {}", e)))?;
+    if !output.status.success() {
+        return Err(RogcatError::Device(format!(
+            "Failed to run getprop: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| property(line.trim_end()).ok())
+        .map(|(_, property)| property)
+        .collect())
+}
+
+/// File of the snapshot `name` in the configuration directory
+fn snapshot(name: &str) -> Result<PathBuf, Error> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return Err(RogcatError::Config(format!("Invalid snapshot name {}", name)).into());
+    }
+    Ok(utils::config_dir()
+        .join("props")
+        .join(format!("{}.json", name)))
+}
+
+/// Print added, removed and changed properties
+fn diff<W: WriteColor>(before: &Properties, after: &Properties, out: &mut W) -> Result<(), Error> {
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    let mut names = before.keys().chain(after.keys()).collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    for name in names {
+        match (before.get(name), after.get(name)) {
+            (None, Some(value)) => {
+                added += 1;
+                out.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+                writeln!(out, "+ [{}]: [{}]", name, value)?;
+            }
+            (Some(value), None) => {
+                removed += 1;
+                out.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+                writeln!(out, "- [{}]: [{}]", name, value)?;
+            }
+            (Some(old), Some(new)) if old != new => {
+                changed += 1;
+                out.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))?;
+                writeln!(out, "~ [{}]: [{}] -> [{}]", name, old, new)?;
+            }
+            _ => (),
+        }
+    }
+    out.reset()?;
+    eprintln!("{} added, {} removed, {} changed", added, removed, changed);
+    Ok(())
+}
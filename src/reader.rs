@@ -18,29 +18,60 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+#[cfg(target_os = "linux")]
+use crate::isotp::{self, IsoTp};
 use crate::{
+    error::RogcatError,
+    internal,
     lossy_lines::{lossy_lines, LossyLinesCodec},
-    utils::{adb, config_get},
+    pipeline,
+    utils::{
+        self, adb_cmdline, config_dir, config_get, parse_duration, parse_number, select_device,
+        selected_device,
+    },
     LogStream, StreamData, DEFAULT_BUFFER,
 };
+use bytes::BytesMut;
 use clap::{value_t, ArgMatches};
 use failure::{err_msg, format_err, Error};
-use futures::{stream::iter_ok, Async, Future, Stream};
-use rogcat::record::Level;
-#[cfg(target_os = "linux")]
-use rogcat::record::{Record, Timestamp};
+use futures::{
+    future::{err, lazy, ok, select_ok, Either},
+    sink::Wait,
+    stream::iter_ok,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        oneshot,
+    },
+    try_ready, Async, Future, Poll, Sink, Stream,
+};
+use lazy_static::lazy_static;
+use memmap2::Mmap;
+use regex::Regex;
+use rogcat::{
+    parser::{logger_entry, BlfReader, PcapReader, BLF_MAGIC, PCAPNG_MAGIC, PCAP_MAGICS},
+    record::{parse_level, Level, Record, Timestamp},
+};
+use rusqlite::{types::ValueRef, Connection, OpenFlags, Row};
 use std::{
     borrow::ToOwned,
+    cmp::max,
+    collections::VecDeque,
     convert::Into,
-    io::BufReader,
-    net::ToSocketAddrs,
-    path::PathBuf,
+    fs::{self, read_dir},
+    io::{BufRead, BufReader, Seek},
+    iter::once,
+    mem,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 use tokio::{
-    codec::{Decoder, FramedRead},
-    fs::File,
-    net::TcpStream,
+    codec::{Decoder, FramedRead, LengthDelimitedCodec},
+    io::AsyncRead,
+    net::{TcpListener, TcpStream, UdpSocket},
+    timer::{Delay, Interval, Timeout},
 };
 use tokio_process::{Child, CommandExt};
 use url::Url;
@@ -52,103 +83,1493 @@ struct Process {
     respawn: bool,
     child: Option<Child>,
     stream: Option<LogStream>,
+    /// Resume state of logcat streams
+    resume: Option<Resume>,
+    /// Buffer statistics queried at startup or before a respawn
+    stats: Option<StatsQuery>,
+    /// Framing of stdout
+    framing: Framing,
+    /// adb command including the device selection of logcat streams
+    adb: Option<Vec<String>>,
+    /// Wait for the device or a restart delay before the next spawn
+    waiting: Option<Box<dyn Future<Item = (), Error = Error> + Send>>,
+    /// The device dropped off adb
+    disconnected: bool,
+    /// Time of the last spawn
+    spawned: Option<Instant>,
+}
+
+/// Minimum interval between two spawns
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref LOGCAT_TIMESTAMP: Regex = Regex::new(r"^\d\d-\d\d \d\d:\d\d:\d\d\.\d{3}").unwrap();
+}
+
+/// Record counts of the selected buffers from `logcat -S`
+#[derive(Clone, Copy, Debug)]
+struct BufferStats {
+    /// Records logged since boot
+    total: u64,
+    /// Records currently in the ring buffer
+    retained: u64,
+}
+
+impl BufferStats {
+    /// Parse the first table of `logcat -S`. Columns are the buffer names
+    /// and cells are formatted `size/num`.
+    fn parse(output: &str, buffers: &[String]) -> Option<BufferStats> {
+        let mut lines = output
+            .lines()
+            .map(str::split_whitespace)
+            .skip_while(|l| l.clone().next() != Some("size/num"));
+        let columns = lines.next()?.skip(1).collect::<Vec<_>>();
+        let mut row = |name: &str| -> Option<u64> {
+            let cells = lines.find(|l| l.clone().next() == Some(name))?;
+            let cells = cells
+                .skip(1)
+                .map(|c| c.split('/').nth(1).and_then(|n| n.parse::<u64>().ok()))
+                .collect::<Option<Vec<_>>>()?;
+            if cells.len() != columns.len() {
+                return None;
+            }
+            let selected = |column: &&str| {
+                if buffers.iter().any(|b| b == "all") {
+                    *column == "Total"
+                } else {
+                    buffers.iter().any(|b| b == column)
+                }
+            };
+            let mut selected = columns
+                .iter()
+                .zip(cells)
+                .filter(|(c, _)| selected(c))
+                .peekable();
+            selected.peek()?;
+            Some(selected.map(|(_, n)| n).sum())
+        };
+        let total = row("Total")?;
+        let retained = row("Now")?;
+        Some(BufferStats { total, retained })
+    }
+}
+
+/// Pending `logcat -S` run. Failures yield no statistics.
+type StatsQuery = Box<dyn Future<Item = Option<BufferStats>, Error = Error> + Send>;
+
+/// Tracks the position of a logcat stream in order to resume after a
+/// reconnect without duplicates and to quantify records lost in between.
+struct Resume {
+    stats_cmd: Vec<String>,
+    buffers: Vec<String>,
+    /// Estimated number of records logged on the device up to the last received one
+    position: Option<u64>,
+    /// Timestamp of the last received record
+    timestamp: Option<String>,
+    /// Lines received with `timestamp`
+    tail: Vec<String>,
+    /// Lines already received that are expected again after resuming
+    skip: Vec<String>,
+    /// Lines received since the last spawn
+    received: u64,
+    /// Records are filtered on the device. The buffer statistics cannot be related to the
+    /// received records.
+    filtered: bool,
+    /// File the last timestamp is persisted to for `--resume`
+    persist: Option<PathBuf>,
+    /// Time of the last write to `persist`
+    persisted: Option<Instant>,
+}
+
+/// Minimum interval between two writes of the persisted timestamp
+const PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Resume {
+    fn new(cmd: &[String], buffers: Vec<String>) -> Resume {
+        let mut stats_cmd = cmd.to_vec();
+        stats_cmd.push("-S".into());
+        Resume {
+            stats_cmd,
+            buffers,
+            position: None,
+            timestamp: None,
+            tail: Vec::new(),
+            skip: Vec::new(),
+            received: 0,
+            filtered: false,
+            persist: None,
+            persisted: None,
+        }
+    }
+
+    /// Continue at the timestamp persisted by a previous run. The first line of the file
+    /// is the timestamp followed by the lines received with it.
+    fn load(&mut self) {
+        let content = match self.persist.as_ref().map(fs::read_to_string) {
+            Some(Ok(content)) => content,
+            _ => return,
+        };
+        let mut lines = content.lines();
+        if let Some(timestamp) = lines.next().filter(|t| LOGCAT_TIMESTAMP.is_match(t)) {
+            self.timestamp = Some(timestamp.to_owned());
+            self.tail = lines.map(ToOwned::to_owned).collect();
+        }
+    }
+
+    /// Write the timestamp of the last record and the lines received with it
+    fn store(&mut self) {
+        let (persist, timestamp) = match (&self.persist, &self.timestamp) {
+            (Some(persist), Some(timestamp)) => (persist, timestamp),
+            _ => return,
+        };
+        let mut content = timestamp.clone();
+        for line in &self.tail {
+            content.push('\n');
+            content.push_str(line);
+        }
+        if let Some(dir) = persist.parent() {
+            fs::create_dir_all(dir).ok();
+        }
+        fs::write(persist, content).ok();
+        self.persisted = Some(Instant::now());
+    }
+
+    /// Query the buffer statistics without blocking the executor. None if the records are
+    /// filtered on the device.
+    fn query(&self) -> Option<StatsQuery> {
+        if self.filtered {
+            return None;
+        }
+        let buffers = self.buffers.clone();
+        let query = Command::new(&self.stats_cmd[0])
+            .args(&self.stats_cmd[1..])
+            .stderr(Stdio::null())
+            .output_async()
+            .then(move |output| {
+                Ok(output.ok().and_then(|output| {
+                    BufferStats::parse(&String::from_utf8_lossy(&output.stdout), &buffers)
+                }))
+            });
+        Some(Box::new(query))
+    }
+
+    /// Relate the statistics queried at startup to the lines received since
+    fn baseline(&mut self, stats: Option<BufferStats>) {
+        if let (Some(stats), None) = (stats, self.position) {
+            self.position = Some(stats.total.saturating_sub(stats.retained) + self.received);
+        }
+    }
+
+    /// Additional logcat arguments for the next spawn. `stats` are queried before respawns.
+    fn args(&mut self, stats: Option<BufferStats>) -> Vec<String> {
+        self.received = 0;
+        if let (Some(stats), Some(position)) = (stats, self.position) {
+            let oldest = stats.total.saturating_sub(stats.retained);
+            if stats.total < position {
+                internal::emit("Device rebooted");
+                self.timestamp = None;
+                self.tail.clear();
+            } else if self.timestamp.is_some() && oldest > position {
+                internal::emit(format!(
+                    "~{} records lost while disconnected",
+                    oldest - position
+                ));
+            }
+        }
+
+        self.position = match (stats, self.timestamp.is_some()) {
+            (Some(stats), true) => Some(max(
+                self.position.unwrap_or(0),
+                stats.total.saturating_sub(stats.retained),
+            )),
+            (Some(stats), false) => Some(stats.total.saturating_sub(stats.retained)),
+            (None, _) => self.position,
+        };
+
+        match self.timestamp {
+            Some(ref timestamp) => {
+                self.skip = mem::take(&mut self.tail);
+                vec!["-T".into(), timestamp.clone()]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Track a received line. Returns false if the line is a duplicate.
+    fn line(&mut self, line: &str) -> bool {
+        let timestamp = match LOGCAT_TIMESTAMP.find(line) {
+            Some(m) => m.as_str(),
+            None => return true,
+        };
+
+        if self.timestamp.as_deref() != Some(timestamp) {
+            self.timestamp = Some(timestamp.to_owned());
+            self.tail.clear();
+            self.skip.clear();
+        } else if let Some(index) = self.skip.iter().position(|l| l == line) {
+            self.skip.swap_remove(index);
+            return false;
+        }
+
+        self.tail.push(line.to_owned());
+        self.received += 1;
+        if let Some(ref mut position) = self.position {
+            *position += 1;
+        }
+        if self
+            .persisted
+            .is_none_or(|p| p.elapsed() >= PERSIST_INTERVAL)
+        {
+            self.store();
+        }
+        true
+    }
+}
+
+/// Regex of the `--glob` file name pattern
+fn glob(args: &ArgMatches) -> Result<Regex, Error> {
+    let glob = args.value_of("glob").unwrap_or("*");
+    Regex::new(&format!(
+        "^{}$",
+        regex::escape(glob).replace("\\*", ".*").replace("\\?", ".")
+    ))
+    .map_err(Into::into)
+}
+
+/// Files of `dir` with a name matching `glob`
+fn dir_files(dir: &Path, glob: &Regex) -> Result<Vec<PathBuf>, Error> {
+    Ok(read_dir(dir)
+        .map_err(|e| RogcatError::Io(format!("Failed to read {}: {}", dir.display(), e)))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .map(|name| glob.is_match(&name.to_string_lossy()))
+                .unwrap_or(false)
+        })
+        .collect())
+}
+
+/// Files of `dir` and its subdirectories in alphanumerical order
+fn walk(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = read_dir(&dir)
+            .and_then(|entries| {
+                entries
+                    .map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| RogcatError::Io(format!("Failed to read {}: {}", dir.display(), e)))?;
+        entries.sort();
+        let (subdirs, entries): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.is_dir());
+        files.extend(entries.into_iter().filter(|e| e.is_file()));
+        dirs.extend(subdirs.into_iter().rev());
+    }
+    Ok(files)
+}
+
+/// Regex of a path pattern. `*` and `?` match within one directory and `**` matches
+/// any number of directories.
+fn path_glob(pattern: &str) -> Result<Regex, Error> {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(Into::into)
+}
+
+/// Files matching a pattern like `logs/**/*.log` in alphanumerical order
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut base = PathBuf::new();
+    let mut rest = vec![];
+    for component in Path::new(pattern).components() {
+        let name = component.as_os_str().to_string_lossy();
+        if rest.is_empty() && !name.contains(['*', '?']) {
+            base.push(component);
+        } else {
+            rest.push(name.into_owned());
+        }
+    }
+    let glob = path_glob(&rest.join("/"))?;
+    let dir = if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base.as_path()
+    };
+    if !dir.is_dir() {
+        return Err(RogcatError::Io(format!("No files match {}", pattern)).into());
+    }
+    let files = walk(dir)?
+        .into_iter()
+        .filter(|file| {
+            let relative = file.strip_prefix(dir).unwrap_or(file);
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            glob.is_match(&relative)
+        })
+        .map(|file| {
+            // Keep paths relative to the working directory like the shell does
+            if base.as_os_str().is_empty() {
+                file.strip_prefix(".").map(Path::to_owned).unwrap_or(file)
+            } else {
+                file
+            }
+        })
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        Err(RogcatError::Io(format!("No files match {}", pattern)).into())
+    } else {
+        Ok(files)
+    }
+}
+
+/// Files of `dir` matching `glob` in alphanumerical order. Subdirectories are read if
+/// `recursive` is set.
+fn input_dir_files(dir: &Path, glob: &Regex, recursive: bool) -> Result<Vec<PathBuf>, Error> {
+    if !recursive {
+        let mut files = dir_files(dir, glob)?;
+        files.sort();
+        return Ok(files);
+    }
+    Ok(walk(dir)?
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .map(|name| glob.is_match(&name.to_string_lossy()))
+                .unwrap_or(false)
+        })
+        .collect())
+}
+
+/// Input files passed with `--input` and the files of `--input-dir` matching `--glob`.
+/// Directories and patterns passed with `--input` are expanded.
+fn input_files(args: &ArgMatches) -> Result<Vec<PathBuf>, Error> {
+    let glob = glob(args)?;
+    let recursive = args.is_present("recursive");
+    let mut files = vec![];
+    for input in args.values_of("input").unwrap_or_default() {
+        let path = Path::new(input);
+        if path.is_dir() {
+            files.extend(input_dir_files(path, &glob, recursive)?);
+        } else if !path.exists() && input.contains(['*', '?']) {
+            files.extend(expand_glob(input)?);
+        } else {
+            files.push(path.to_owned());
+        }
+    }
+
+    for dir in args.values_of("input-dir").unwrap_or_default() {
+        files.extend(input_dir_files(Path::new(dir), &glob, recursive)?);
+    }
+
+    if files.is_empty() {
+        Err(RogcatError::Io("No input files".to_owned()).into())
+    } else {
+        Ok(files)
+    }
 }
 
-/// Open a file and provide a stream of lines
+/// Open files and provide a stream of lines. Files that cannot be read are
+/// skipped with a warning record.
 pub fn files(args: &ArgMatches) -> Result<LogStream, Error> {
-    let files = args
-        .values_of("input")
-        .ok_or_else(|| err_msg("Missing input argument"))?
-        .map(PathBuf::from)
-        .collect::<Vec<PathBuf>>();
-
-    let f = iter_ok::<_, Error>(files)
-        .map(|f| {
-            File::open(f.clone())
-                .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
-                .flatten_stream()
-                .map(StreamData::Line)
-                .map_err(move |e| format_err!("Failed to open {}: {}", f.display(), e))
+    let files = input_files(args)?;
+
+    if args.is_present("mmap") {
+        // The source of the records is the file if more than one is read
+        let sources = files.len() > 1;
+        return Ok(Box::new(
+            iter_ok::<_, Error>(files)
+                .map(move |file| {
+                    if sources {
+                        with_source(file.display().to_string(), mmap(file))
+                    } else {
+                        mmap(file)
+                    }
+                })
+                .flatten(),
+        ));
+    }
+
+    Ok(Box::new(Files {
+        total: files.len(),
+        paths: files.into(),
+        reading: VecDeque::new(),
+        data: Vec::new().into_iter(),
+        current: None,
+    }))
+}
+
+/// One stream per input file e.g to merge the files by time. The source of the records is the file.
+pub fn each_file(args: &ArgMatches) -> Result<Vec<LogStream>, Error> {
+    let files = input_files(args)?;
+    let total = files.len();
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            Box::new(Files {
+                total,
+                paths: vec![file].into(),
+                reading: VecDeque::new(),
+                data: Vec::new().into_iter(),
+                current: None,
+            }) as LogStream
         })
-        .flatten();
+        .collect())
+}
+
+/// Number of files read ahead concurrently
+const READ_AHEAD_FILES: usize = 4;
+/// Number of lines passed from the reading thread at once
+const READ_BATCH: usize = 1000;
+/// Number of batches buffered per file that is read ahead
+const READ_AHEAD_BATCHES: usize = 10;
+
+enum FileEvent {
+    /// Lines of text files or records of binary files
+    Data(Vec<StreamData>),
+    Error(String),
+    /// Number of lines or records read
+    Done(usize),
+}
+
+/// Header of SQLite database files
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// Read `path` line by line or as BLF, pcap or SQLite file on a separate thread
+fn read_file(path: PathBuf) -> Receiver<FileEvent> {
+    let (tx, rx) = channel(READ_AHEAD_BATCHES);
+    thread::spawn(move || {
+        let mut tx = tx.wait();
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                let message = format!("Failed to open {}: {}", path.display(), e);
+                tx.send(FileEvent::Error(message)).ok();
+                return;
+            }
+        };
+        let mut reader = BufReader::new(file);
+        let mut batch = Vec::with_capacity(READ_BATCH);
+
+        if reader.fill_buf().is_ok_and(|b| b.starts_with(BLF_MAGIC)) {
+            read_blf(&path, reader, &mut tx);
+            return;
+        }
+        if reader.fill_buf().is_ok_and(|b| {
+            b.starts_with(PCAPNG_MAGIC) || PCAP_MAGICS.iter().any(|m| b.starts_with(m))
+        }) {
+            read_pcap(&path, reader, &mut tx);
+            return;
+        }
+        if reader.fill_buf().is_ok_and(|b| b.starts_with(SQLITE_MAGIC)) {
+            read_sqlite(&path, &mut tx);
+            return;
+        }
+
+        let mut buffer = Vec::new();
+        let mut lines = 0;
+        loop {
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => break,
+                Ok(_) => {
+                    // Strip all \r\n occurences because on Windows "adb logcat" ends lines with "\r\r\n"
+                    while buffer.ends_with(b"\r") || buffer.ends_with(b"\n") {
+                        buffer.pop();
+                    }
+                    batch.push(StreamData::Line(
+                        String::from_utf8_lossy(&buffer).into_owned(),
+                    ));
+                    lines += 1;
+                    if batch.len() == READ_BATCH {
+                        let lines = mem::replace(&mut batch, Vec::with_capacity(READ_BATCH));
+                        if tx.send(FileEvent::Data(lines)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let message = format!("Failed to read {}: {}", path.display(), e);
+                    tx.send(FileEvent::Data(batch)).ok();
+                    tx.send(FileEvent::Error(message)).ok();
+                    return;
+                }
+            }
+        }
+        tx.send(FileEvent::Data(batch)).ok();
+        tx.send(FileEvent::Done(lines)).ok();
+    });
+    rx
+}
+
+/// Send the records of the BLF file `path` in batches
+fn read_blf<R: BufRead>(path: &Path, reader: R, tx: &mut Wait<Sender<FileEvent>>) {
+    let records = match BlfReader::new(reader) {
+        Ok(records) => records,
+        Err(e) => {
+            let message = format!("Failed to read {}: {}", path.display(), e);
+            tx.send(FileEvent::Error(message)).ok();
+            return;
+        }
+    };
+    let mut batch = Vec::with_capacity(READ_BATCH);
+    let mut count = 0;
+    for record in records {
+        match record {
+            Ok(record) => {
+                batch.push(StreamData::Record(Box::new(record)));
+                count += 1;
+                if batch.len() == READ_BATCH {
+                    let records = mem::replace(&mut batch, Vec::with_capacity(READ_BATCH));
+                    if tx.send(FileEvent::Data(records)).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                let message = format!("Failed to read {}: {}", path.display(), e);
+                tx.send(FileEvent::Data(batch)).ok();
+                tx.send(FileEvent::Error(message)).ok();
+                return;
+            }
+        }
+    }
+    tx.send(FileEvent::Data(batch)).ok();
+    tx.send(FileEvent::Done(count)).ok();
+}
+
+/// Send the payload lines of the pcap file `path` in batches. The sender of the lines
+/// is the source.
+fn read_pcap<R: BufRead>(path: &Path, reader: R, tx: &mut Wait<Sender<FileEvent>>) {
+    let lines = match PcapReader::new(reader) {
+        Ok(lines) => lines,
+        Err(e) => {
+            let message = format!("Failed to read {}: {}", path.display(), e);
+            tx.send(FileEvent::Error(message)).ok();
+            return;
+        }
+    };
+    let mut batch = Vec::with_capacity(READ_BATCH);
+    let mut current = None;
+    let mut count = 0;
+    for line in lines {
+        match line {
+            Ok((peer, line)) => {
+                if current != Some(peer) {
+                    current = Some(peer);
+                    batch.push(StreamData::Source(peer.to_string()));
+                }
+                batch.push(StreamData::Line(line));
+                count += 1;
+                if batch.len() >= READ_BATCH {
+                    let lines = mem::replace(&mut batch, Vec::with_capacity(READ_BATCH));
+                    if tx.send(FileEvent::Data(lines)).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                let message = format!("Failed to read {}: {}", path.display(), e);
+                tx.send(FileEvent::Data(batch)).ok();
+                tx.send(FileEvent::Error(message)).ok();
+                return;
+            }
+        }
+    }
+    tx.send(FileEvent::Data(batch)).ok();
+    tx.send(FileEvent::Done(count)).ok();
+}
+
+/// Send the rows of the `records` table of the SQLite database `path` in batches
+fn read_sqlite(path: &Path, tx: &mut Wait<Sender<FileEvent>>) {
+    let mut batch = Vec::with_capacity(READ_BATCH);
+    match sqlite_rows(path, &mut batch, tx) {
+        Ok(Some(count)) => {
+            tx.send(FileEvent::Data(batch)).ok();
+            tx.send(FileEvent::Done(count)).ok();
+        }
+        // The receiver is gone
+        Ok(None) => (),
+        Err(e) => {
+            let message = format!("Failed to read {}: {}", path.display(), e);
+            tx.send(FileEvent::Data(batch)).ok();
+            tx.send(FileEvent::Error(message)).ok();
+        }
+    }
+}
+
+/// Collect the rows of `path` in `batch` and send full batches. Returns the number of rows
+/// or None if the receiver is gone.
+fn sqlite_rows(
+    path: &Path,
+    batch: &mut Vec<StreamData>,
+    tx: &mut Wait<Sender<FileEvent>>,
+) -> Result<Option<usize>, rusqlite::Error> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare("SELECT * FROM records")?;
+    let columns = statement
+        .column_names()
+        .iter()
+        .map(|c| c.to_lowercase())
+        .collect::<Vec<_>>();
+    let mut rows = statement.query([])?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        batch.push(StreamData::Record(Box::new(sqlite_record(&columns, row)?)));
+        count += 1;
+        if batch.len() == READ_BATCH {
+            let records = mem::replace(batch, Vec::with_capacity(READ_BATCH));
+            if tx.send(FileEvent::Data(records)).is_err() {
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(count))
+}
+
+/// Record of a row with columns named like the ones of `--csv-columns` and values formatted
+/// like the csv output. Other columns are added to the fields.
+fn sqlite_record(columns: &[String], row: &Row) -> Result<Record, rusqlite::Error> {
+    let mut record = Record::default();
+    for (n, column) in columns.iter().enumerate() {
+        // Values of any type are taken as text e.g integer process ids
+        let value = match row.get_ref(n)? {
+            ValueRef::Null => continue,
+            ValueRef::Integer(i) => i.to_string(),
+            ValueRef::Real(f) => f.to_string(),
+            ValueRef::Text(t) | ValueRef::Blob(t) => String::from_utf8_lossy(t).into_owned(),
+        };
+        match column.as_str() {
+            "timestamp" => {
+                record.timestamp = time::strptime(&value, "%m-%d %H:%M:%S.%f")
+                    .ok()
+                    .map(Timestamp::new)
+            }
+            "message" => record.message = value,
+            "level" => {
+                let (level, severity) = parse_level(&value);
+                record.level = level;
+                record.severity = severity;
+            }
+            "tag" => record.tags = value.split(',').map(|t| t.trim().to_owned()).collect(),
+            "process" => record.process = value,
+            "thread" => record.thread = value,
+            "raw" => record.raw = value,
+            "process_name" => record.process_name = Some(value),
+            "source" => record.source = Some(value),
+            "buffer" => record.buffer = Some(value),
+            "fields" => {
+                let fields = Record {
+                    message: value,
+                    ..Default::default()
+                };
+                record.fields.extend(pipeline::key_values(fields).fields);
+            }
+            _ => {
+                record.fields.insert(column.clone(), value);
+            }
+        }
+    }
+    if record.raw.is_empty() {
+        record.raw = record.threadtime();
+    }
+    Ok(record)
+}
+
+/// Lines of multiple files in order. The following files are read ahead
+/// concurrently.
+struct Files {
+    paths: VecDeque<PathBuf>,
+    reading: VecDeque<(PathBuf, Instant, Receiver<FileEvent>)>,
+    /// Lines or records of the current batch
+    data: std::vec::IntoIter<StreamData>,
+    total: usize,
+    /// File of the current batch. The source of the records if more than one file is read.
+    current: Option<PathBuf>,
+}
+
+impl Stream for Files {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        loop {
+            if let Some(data) = self.data.next() {
+                return Ok(Async::Ready(Some(data)));
+            }
+
+            while self.reading.len() < READ_AHEAD_FILES {
+                match self.paths.pop_front() {
+                    Some(path) => {
+                        self.reading
+                            .push_back((path.clone(), Instant::now(), read_file(path)))
+                    }
+                    None => break,
+                }
+            }
+
+            let (path, start, events) = match self.reading.front_mut() {
+                Some(reading) => reading,
+                None => return Ok(Async::Ready(None)),
+            };
+            match events.poll() {
+                Ok(Async::Ready(Some(FileEvent::Data(data)))) => {
+                    if self.total > 1 && self.current.as_ref() != Some(path) {
+                        self.current = Some(path.clone());
+                        let source = StreamData::Source(path.display().to_string());
+                        self.data = once(source).chain(data).collect::<Vec<_>>().into_iter();
+                    } else {
+                        self.data = data.into_iter();
+                    }
+                }
+                Ok(Async::Ready(Some(FileEvent::Error(message)))) => {
+                    let record = internal::record(Level::Warn, message);
+                    return Ok(Async::Ready(Some(StreamData::Record(Box::new(record)))));
+                }
+                Ok(Async::Ready(Some(FileEvent::Done(lines)))) => {
+                    if self.total > 1 {
+                        internal::emit(format!(
+                            "Read {} lines from {} in {:.1}s",
+                            lines,
+                            path.display(),
+                            start.elapsed().as_secs_f32()
+                        ));
+                    }
+                }
+                Ok(Async::Ready(None)) | Err(_) => {
+                    self.reading.pop_front();
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Interval between scans of watched directories
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A file of a watched directory or `--follow` input that is followed
+struct WatchedFile {
+    path: PathBuf,
+    name: String,
+    reader: BufReader<std::fs::File>,
+    /// Incomplete last line
+    partial: Vec<u8>,
+}
+
+impl WatchedFile {
+    /// Returns true if the file at `path` was replaced e.g by log rotation or
+    /// truncated. A missing file is followed until a new one appears.
+    fn rotated(&mut self) -> bool {
+        let metadata = match self.path.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if let Ok(open) = self.reader.get_ref().metadata() {
+                if open.ino() != metadata.ino() || open.dev() != metadata.dev() {
+                    return true;
+                }
+            }
+        }
+        self.reader
+            .stream_position()
+            .map(|position| metadata.len() < position)
+            .unwrap_or(false)
+    }
+}
+
+/// Lines of files appearing in watched directories. Files are read in the
+/// order of their modification time and followed while they grow. Files
+/// passed with `--follow` are reopened when they are rotated.
+struct Watch {
+    dirs: Vec<PathBuf>,
+    /// Files passed with `--input` and `--follow`
+    inputs: Vec<PathBuf>,
+    glob: Regex,
+    files: Vec<WatchedFile>,
+    interval: Interval,
+    pending: VecDeque<StreamData>,
+    /// Name of the file of the last emitted lines
+    current: Option<String>,
+}
+
+/// Watch the directories passed with `--input-dir` for files matching `--glob`
+/// and follow the files passed with `--input` if `--follow` is set
+pub fn watch(args: &ArgMatches) -> Result<LogStream, Error> {
+    let mut watch = Watch {
+        dirs: args
+            .values_of("input-dir")
+            .unwrap_or_default()
+            .map(PathBuf::from)
+            .collect(),
+        inputs: args
+            .values_of("input")
+            .unwrap_or_default()
+            .map(PathBuf::from)
+            .collect(),
+        glob: glob(args)?,
+        files: Vec::new(),
+        interval: Interval::new_interval(WATCH_INTERVAL),
+        pending: VecDeque::new(),
+        current: None,
+    };
+    watch.scan()?;
+    Ok(Box::new(watch))
+}
+
+impl Watch {
+    /// Open files that appeared or were rotated since the last scan
+    fn scan(&mut self) -> Result<(), Error> {
+        let pending = &mut self.pending;
+        self.files.retain_mut(|file| {
+            if !file.rotated() {
+                return true;
+            }
+            if !file.partial.is_empty() {
+                pending.push_back(StreamData::Line(
+                    String::from_utf8_lossy(&file.partial).into_owned(),
+                ));
+            }
+            internal::emit(format!("{} was rotated or truncated", file.path.display()));
+            false
+        });
+
+        let mut new = Vec::new();
+        for dir in &self.dirs {
+            for path in dir_files(dir, &self.glob)? {
+                if !self.files.iter().any(|f| f.path == path) {
+                    let modified = path.metadata().and_then(|m| m.modified()).ok();
+                    new.push((modified, path));
+                }
+            }
+        }
+        new.sort();
+
+        // Inputs are opened in the order they are passed
+        let inputs = self
+            .inputs
+            .iter()
+            .filter(|path| !self.files.iter().any(|f| &f.path == *path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in inputs
+            .into_iter()
+            .chain(new.into_iter().map(|(_, path)| path))
+        {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            match std::fs::File::open(&path) {
+                Ok(file) => {
+                    internal::emit(format!("Reading {}", path.display()));
+                    self.files.push(WatchedFile {
+                        path,
+                        name,
+                        reader: BufReader::new(file),
+                        partial: Vec::new(),
+                    });
+                }
+                Err(e) => {
+                    // Retried on the next scan
+                    internal::emit(format!("Failed to open {}: {}", path.display(), e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read complete lines of the first file with new content. Returns false
+    /// if no file has new lines.
+    fn read(&mut self) -> Result<bool, Error> {
+        for file in &mut self.files {
+            let mut lines = Vec::new();
+            while lines.len() < READ_BATCH {
+                match file.reader.read_until(b'\n', &mut file.partial)? {
+                    0 => break,
+                    _ if !file.partial.ends_with(b"\n") => break,
+                    _ => {
+                        while file.partial.ends_with(b"\r") || file.partial.ends_with(b"\n") {
+                            file.partial.pop();
+                        }
+                        lines.push(String::from_utf8_lossy(&file.partial).into_owned());
+                        file.partial.clear();
+                    }
+                }
+            }
+
+            if !lines.is_empty() {
+                if self.current.as_ref() != Some(&file.name) {
+                    self.current = Some(file.name.clone());
+                    self.pending
+                        .push_back(StreamData::Source(file.name.clone()));
+                }
+                self.pending.extend(lines.into_iter().map(StreamData::Line));
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Stream for Watch {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        loop {
+            if let Some(data) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(data)));
+            }
+
+            if !self.read()? {
+                match self.interval.poll()? {
+                    Async::Ready(Some(_)) => self.scan()?,
+                    Async::Ready(None) => return Ok(Async::Ready(None)),
+                    Async::NotReady => return Ok(Async::NotReady),
+                }
+            }
+        }
+    }
+}
+
+/// Lines of a memory mapped file
+struct MmapLines {
+    mmap: Mmap,
+    offset: usize,
+}
+
+impl Iterator for MmapLines {
+    type Item = StreamData;
+
+    fn next(&mut self) -> Option<StreamData> {
+        let rest = &self.mmap[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let (line, len) = match rest.iter().position(|b| *b == b'\n') {
+            Some(n) => (&rest[..n], n + 1),
+            None => (rest, rest.len()),
+        };
+        self.offset += len;
+        // Strip all \r occurences because on Windows "adb logcat" ends lines with "\r\r\n"
+        let end = line.iter().rposition(|b| *b != b'\r').map_or(0, |n| n + 1);
+        Some(StreamData::Line(
+            String::from_utf8_lossy(&line[..end]).into_owned(),
+        ))
+    }
+}
+
+/// Memory map file `f` and provide a stream of lines
+fn mmap(f: PathBuf) -> LogStream {
+    // Safety: the mapping is read only. Truncating the file while it is
+    // mapped is not supported.
+    match std::fs::File::open(&f).and_then(|file| unsafe { Mmap::map(&file) }) {
+        Ok(mmap) => Box::new(iter_ok(MmapLines { mmap, offset: 0 })),
+        Err(e) => {
+            let message = format!("Failed to open {}: {}", f.display(), e);
+            let record = internal::record(Level::Warn, message);
+            Box::new(iter_ok(vec![StreamData::Record(Box::new(record))]))
+        }
+    }
+}
+
+/// Framing of byte streams read from stdin or tcp
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    /// Every frame is prefixed with a big endian u32 length
+    Len32,
+    /// Frames are terminated by a newline
+    Newline,
+    /// Entries of the logcat binary format (`logcat -B`)
+    Binary,
+}
+
+impl Framing {
+    pub fn from_args(args: &ArgMatches) -> Framing {
+        match args.value_of("framing") {
+            Some("len32") => Framing::Len32,
+            Some("binary") => Framing::Binary,
+            _ => Framing::Newline,
+        }
+    }
+}
+
+/// Decoder of the logcat binary format
+struct LoggerEntryCodec;
+
+impl Decoder for LoggerEntryCodec {
+    type Item = Record;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Record>, Error> {
+        match logger_entry(buf).map_err(|e| RogcatError::Parse(format!("{}", e)))? {
+            Some((record, len)) => {
+                buf.advance(len);
+                Ok(Some(record))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Record>, Error> {
+        let record = self.decode(buf)?;
+        if record.is_none() && !buf.is_empty() {
+            internal::emit(format!(
+                "Discarding {} bytes of a truncated entry",
+                buf.len()
+            ));
+            buf.clear();
+        }
+        Ok(record)
+    }
+}
+
+/// Split `io` into frames according to `framing` and provide a stream of lines
+fn framed<T: AsyncRead + Send + 'static>(io: T, framing: Framing) -> LogStream {
+    match framing {
+        Framing::Len32 => Box::new(
+            FramedRead::new(io, LengthDelimitedCodec::new())
+                .map_err(Into::into)
+                .map(|frame| StreamData::Line(String::from_utf8_lossy(&frame).into_owned())),
+        ),
+        Framing::Newline => Box::new(
+            FramedRead::new(io, LossyLinesCodec::new())
+                .map_err(Into::into)
+                .map(StreamData::Line),
+        ),
+        Framing::Binary => Box::new(
+            FramedRead::new(io, LoggerEntryCodec)
+                .map(|record| StreamData::Record(Box::new(record))),
+        ),
+    }
+}
 
-    Ok(Box::new(f))
+/// Prepend the source of the records of `stream` e.g a device serial or can interface
+fn with_source(source: String, stream: LogStream) -> LogStream {
+    Box::new(iter_ok(vec![StreamData::Source(source)]).chain(stream))
 }
 
 /// Open stdin and provide a stream of lines
-pub fn stdin() -> LogStream {
-    let s = FramedRead::new(tokio::io::stdin(), LossyLinesCodec::new())
-        .map_err(Into::into)
-        .map(StreamData::Line);
-    Box::new(s)
+pub fn stdin(framing: Framing) -> LogStream {
+    framed(tokio::io::stdin(), framing)
 }
 
-/// Open a serial port and provide a stream of lines
-pub fn serial(_args: &ArgMatches) -> LogStream {
-    unimplemented!()
+/// Open a serial port like `serial:///dev/ttyUSB0@115200,8N1` and provide a stream of lines.
+/// The baud rate defaults to 115200 and the format to 8N1.
+pub fn serial(args: &ArgMatches) -> Result<LogStream, Error> {
+    use tokio_serial::{DataBits, Parity, SerialPortSettings, StopBits};
+
+    let url = args.value_of("COMMAND").unwrap_or_default();
+    let invalid = || {
+        RogcatError::Config(format!(
+            "Invalid serial port: {}. Use e.g serial:///dev/ttyUSB0@115200,8N1",
+            url
+        ))
+    };
+    let port = url.strip_prefix("serial://").ok_or_else(invalid)?;
+    let (device, settings) = port.split_once('@').unwrap_or((port, ""));
+    let (baud_rate, format) = settings.split_once(',').unwrap_or((settings, "8N1"));
+
+    let mut format = format.chars();
+    let settings = SerialPortSettings {
+        baud_rate: if baud_rate.is_empty() {
+            115_200
+        } else {
+            baud_rate.parse().map_err(|_| invalid())?
+        },
+        data_bits: match format.next() {
+            Some('5') => DataBits::Five,
+            Some('6') => DataBits::Six,
+            Some('7') => DataBits::Seven,
+            Some('8') => DataBits::Eight,
+            _ => return Err(invalid().into()),
+        },
+        parity: match format.next() {
+            Some('N') => Parity::None,
+            Some('E') => Parity::Even,
+            Some('O') => Parity::Odd,
+            _ => return Err(invalid().into()),
+        },
+        stop_bits: match format.next() {
+            Some('1') => StopBits::One,
+            Some('2') => StopBits::Two,
+            _ => return Err(invalid().into()),
+        },
+        ..Default::default()
+    };
+
+    let port = tokio_serial::Serial::from_path(device, &settings)
+        .map_err(|e| RogcatError::Device(format!("Failed to open {}: {}", device, e)))?;
+    Ok(with_source(
+        device.to_owned(),
+        framed(port, Framing::from_args(args)),
+    ))
 }
 
+/// Open the SocketCAN device of `url` e.g `can://can0`. The query `filter` lists ids and id
+/// ranges that are filtered by the kernel e.g `can://can0?filter=0x100-0x1FF,0x7DF`. The optional
+/// `mask` applies to the single ids. Frames of the `--isotp` ids are reassembled and decoded.
 #[cfg(target_os = "linux")]
-pub fn can(dev: &str) -> Result<LogStream, Error> {
+pub fn can(args: &ArgMatches, url: &Url) -> Result<LogStream, Error> {
+    let dev = url
+        .host_str()
+        .ok_or_else(|| RogcatError::Config("Invalid can device".to_owned()))?;
     let process = dev.to_string();
     let now = time::now();
-    let stream = tokio_socketcan::CANSocket::open(dev)?
+    let socket = tokio_socketcan::CANSocket::open(dev)?;
+    let filters = can_filters(url)?;
+    if !filters.is_empty() {
+        socket.set_filter(&filters)?;
+    }
+    let mut isotp = IsoTp::from_args(args)?;
+    let stream = socket
         .map_err(std::convert::Into::into)
-        .map(move |s| {
-            let data = s
-                .data()
-                .iter()
-                .map(|b| format!("{:02x}", b))
-                .collect::<Vec<String>>();
+        .filter_map(move |s| {
+            let hex = |data: &[u8], separator| {
+                data.iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<String>>()
+                    .join(separator)
+            };
+            let id = if s.is_extended() {
+                format!("{:08X}", s.id())
+            } else {
+                format!("{:X}", s.id())
+            };
+
+            if let Some(ref mut isotp) = isotp {
+                if isotp.handles(s.id()) {
+                    let payload = isotp.push(s.id(), s.data())?;
+                    let (level, message) = isotp::describe(&payload, isotp.is_response(s.id()));
+                    let timestamp = time::now();
+                    return Some(StreamData::Record(Box::new(Record {
+                        timestamp: Some(Timestamp::new(timestamp)),
+                        level,
+                        message,
+                        tags: vec![format!("0x{:x}", s.id())],
+                        can_id: Some(s.id()),
+                        raw: format!(
+                            "({}) {} {}#{}",
+                            timestamp.strftime("%s.%f").unwrap(),
+                            process,
+                            id,
+                            hex(&payload, "")
+                        ),
+                        process: process.clone(),
+                        ..Default::default()
+                    })));
+                }
+            }
+
             let extended = if s.is_extended() { "E" } else { " " };
-            StreamData::Record(Record {
+            Some(StreamData::Record(Box::new(Record {
                 timestamp: Some(Timestamp::new(now)),
-                message: format!("{} {} ", extended, data.join(" ")),
+                message: format!("{} {} ", extended, hex(s.data(), " ")),
                 tags: vec![format!("0x{:x}", s.id())],
+                can_id: Some(s.id()),
                 raw: format!(
                     "({}) {} {}#{}",
                     now.strftime("%s.%f").unwrap(),
                     process,
-                    if s.is_extended() {
-                        format!("{:08X}", s.id())
-                    } else {
-                        format!("{:X}", s.id())
-                    },
-                    data.join("")
+                    id,
+                    hex(s.data(), "")
                 ),
                 process: process.clone(),
                 ..Default::default()
-            })
+            })))
         });
-    Ok(Box::new(stream))
+    Ok(with_source(dev.to_owned(), Box::new(stream)))
+}
+
+/// Kernel filters from the query of a can url
+#[cfg(target_os = "linux")]
+fn can_filters(url: &Url) -> Result<Vec<socketcan::CANFilter>, Error> {
+    let parse = |id: &str| {
+        parse_number(id)
+            .filter(|id| *id <= u64::from(socketcan::EFF_MASK))
+            .map(|id| id as u32)
+            .ok_or_else(|| RogcatError::Config(format!("Invalid can id: {}", id)))
+    };
+
+    let mut ranges = Vec::new();
+    let mut mask = socketcan::EFF_MASK;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "filter" => {
+                for range in value.split(',') {
+                    let range = match range.split_once('-') {
+                        Some((start, end)) => (parse(start)?, parse(end)?),
+                        None => (parse(range)?, parse(range)?),
+                    };
+                    if range.0 > range.1 {
+                        return Err(RogcatError::Config(format!(
+                            "Invalid can id range: {}",
+                            value
+                        ))
+                        .into());
+                    }
+                    ranges.push(range);
+                }
+            }
+            "mask" => mask = parse(&value)?,
+            _ => return Err(RogcatError::Config(format!("Invalid can option: {}", key)).into()),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .flat_map(|(start, end)| {
+            if start == end {
+                vec![(start, mask)]
+            } else {
+                can_range_filters(start, end)
+            }
+        })
+        .map(|(id, mask)| {
+            socketcan::CANFilter::new(id, mask)
+                .map_err(|e| RogcatError::Config(format!("Invalid can filter: {}", e)).into())
+        })
+        .collect()
+}
+
+/// Split the id range `start..=end` into id and mask pairs that match exactly the range
+#[cfg(target_os = "linux")]
+fn can_range_filters(start: u32, end: u32) -> Vec<(u32, u32)> {
+    let mut filters = Vec::new();
+    let (mut start, end) = (u64::from(start), u64::from(end));
+    while start <= end {
+        // Largest aligned block at start that fits into the range
+        let mut size = 1u64 << start.trailing_zeros().min(29);
+        while start + size - 1 > end {
+            size >>= 1;
+        }
+        filters.push((start as u32, socketcan::EFF_MASK & !(size as u32 - 1)));
+        start += size;
+    }
+    filters
 }
 
 /// Connect to tcp socket and profile a stream of lines
-pub fn tcp(addr: &Url) -> Result<LogStream, Error> {
-    let addr = addr
+/// Default timeout for resolving and connecting tcp sources
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before trying the next address while previous connection attempts are pending
+const CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connect to the tcp source `url`. The address is resolved in a thread and all resolved
+/// addresses are tried with a staggered start. The first established connection wins.
+pub fn tcp(args: &ArgMatches, url: &Url) -> Result<LogStream, Error> {
+    let framing = Framing::from_args(args);
+    let timeout = args
+        .value_of("connect-timeout")
+        .map(parse_duration)
+        .transpose()?
+        .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+
+    let (tx, rx) = oneshot::channel();
+    let url = url.clone();
+    thread::spawn(move || tx.send(url.to_socket_addrs().map(Iterator::collect)));
+
+    let connect = rx
+        .map_err(|_| err_msg("Failed to resolve address"))
+        .and_then(|addrs| addrs.map_err(|e| format_err!("Failed to resolve address: {}", e)))
+        .and_then(|addrs: Vec<SocketAddr>| {
+            if addrs.is_empty() {
+                return Either::A(err(err_msg("Failed to resolve address")));
+            }
+            let attempts = interleave(addrs).into_iter().enumerate().map(|(n, addr)| {
+                Delay::new(Instant::now() + CONNECT_ATTEMPT_DELAY * n as u32)
+                    .map_err(Error::from)
+                    .and_then(move |_| {
+                        TcpStream::connect(&addr).map_err(move |e| format_err!("{}: {}", addr, e))
+                    })
+            });
+            Either::B(select_ok(attempts).map(|(stream, _)| stream))
+        });
+
+    let s = Timeout::new(connect, timeout)
+        .map_err(move |e| match e.into_inner() {
+            Some(e) => RogcatError::Device(format!("Failed to connect: {}", e)).into(),
+            None => RogcatError::Device(format!("Failed to connect: Timeout after {:?}", timeout))
+                .into(),
+        })
+        .map(move |s: TcpStream| match s.peer_addr() {
+            Ok(peer) => with_source(peer.to_string(), framed(s, framing)),
+            Err(_) => framed(s, framing),
+        })
+        .flatten_stream();
+
+    Ok(Box::new(s))
+}
+
+/// Number of lines buffered per client of a listening socket
+const CLIENT_BUFFER: usize = 1000;
+
+/// Maximum size of a received datagram
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// Receive lines on the address of `url`. The scheme `listen` accepts any number of tcp clients
+/// and `listen+udp` or `udp` receive datagrams e.g from syslog. Each record is tagged with the
+/// address of its peer.
+pub fn listen(args: &ArgMatches, url: &Url) -> Result<LogStream, Error> {
+    let addr = url
         .to_socket_addrs()?
         .next()
-        .ok_or_else(|| err_msg("Failed to parse addr"))?;
-    let s = TcpStream::connect(&addr)
-        .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
-        .flatten_stream()
-        .map_err(|e| format_err!("Failed to connect: {}", e))
-        .map(StreamData::Line);
+        .ok_or_else(|| RogcatError::Config("Failed to parse addr".to_owned()))?;
 
-    Ok(Box::new(s))
+    if url.scheme() != "listen" {
+        let datagrams = Datagrams {
+            socket: UdpSocket::bind(&addr)?,
+            buffer: vec![0; MAX_DATAGRAM_SIZE],
+        };
+        // A datagram may contain several lines
+        let lines = datagrams
+            .map(|(peer, datagram)| {
+                let lines = datagram
+                    .lines()
+                    .map(|line| (peer, StreamData::Line(line.to_owned())))
+                    .collect::<Vec<_>>();
+                iter_ok(lines)
+            })
+            .flatten();
+        return Ok(tag_peers(lines));
+    }
+
+    let framing = Framing::from_args(args);
+    let listener = TcpListener::bind(&addr)?;
+    let (tx, rx) = channel(CLIENT_BUFFER);
+    let accept = listener
+        .incoming()
+        .map_err(|e| eprintln!("Failed to accept client: {e}"))
+        .for_each(move |socket| {
+            let peer = socket.peer_addr().map_err(drop)?;
+            internal::emit(format!("Client {peer} connected"));
+            let client = framed(socket, framing)
+                .map(move |data| (peer, data))
+                .map_err(move |e| internal::emit(format!("Client {peer} failed: {e}")))
+                .forward(tx.clone().sink_map_err(drop))
+                .then(move |_| {
+                    internal::emit(format!("Client {peer} disconnected"));
+                    Ok(())
+                });
+            tokio::spawn(client);
+            Ok(())
+        });
+
+    // The clients are served on the runtime that polls the returned stream
+    let clients = lazy(move || {
+        tokio::spawn(accept);
+        Ok(rx.map_err(|_| err_msg("Failed to receive from clients")))
+    })
+    .flatten_stream();
+
+    Ok(tag_peers(clients))
+}
+
+/// Precede the data of each peer with its address if the peer changes
+fn tag_peers<S>(stream: S) -> LogStream
+where
+    S: Stream<Item = (SocketAddr, StreamData), Error = Error> + Send + 'static,
+{
+    let mut current = None;
+    Box::new(
+        stream
+            .map(move |(peer, data)| {
+                if current == Some(peer) {
+                    iter_ok(vec![data])
+                } else {
+                    current = Some(peer);
+                    iter_ok(vec![StreamData::Source(peer.to_string()), data])
+                }
+            })
+            .flatten(),
+    )
+}
+
+/// Datagrams received on a udp socket
+struct Datagrams {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl Stream for Datagrams {
+    type Item = (SocketAddr, String);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Error> {
+        let (len, peer) = try_ready!(self.socket.poll_recv_from(&mut self.buffer));
+        let datagram = String::from_utf8_lossy(&self.buffer[..len]).into_owned();
+        Ok(Async::Ready(Some((peer, datagram))))
+    }
+}
+
+/// Alternate between IPv6 and IPv4 addresses to quickly fall back if one family is broken
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    let mut addrs = Vec::new();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break addrs,
+            (a, b) => addrs.extend(a.into_iter().chain(b)),
+        }
+    }
 }
 
-/// Start logcat
+/// Start logcat with adb or directly if rogcat runs on the device
 pub fn logcat(args: &ArgMatches) -> Result<LogStream, Error> {
-    let mut cmd = vec![adb()?.display().to_string()];
+    let on_device = utils::on_device();
+    let mut cmd = if on_device {
+        Vec::new()
+    } else {
+        adb_cmdline()?
+    };
 
-    if args.is_present("dev") {
-        let device = value_t!(args, "dev", String).unwrap_or_else(|e| e.exit());
+    select_device(args)?;
+    let device = selected_device(args);
+    if let Some(ref device) = device {
         cmd.push("-s".into());
-        cmd.push(device);
+        cmd.push(device.clone());
     }
+    let adb = cmd.clone();
 
-    cmd.push("logcat".into());
+    // The binary format is not mangled by the terminal handling of adb shell
+    let framing = Framing::from_args(args);
+    if on_device {
+        cmd.push(utils::logcat());
+    } else if framing == Framing::Binary {
+        cmd.push("exec-out".into());
+        cmd.push("logcat".into());
+    } else {
+        cmd.push("logcat".into());
+    }
+    if framing == Framing::Binary {
+        cmd.push("-B".into());
+    }
     let mut respawn = args.is_present("restart") | config_get::<bool>("restart").unwrap_or(true);
 
     if args.is_present("tail") {
@@ -168,22 +1589,109 @@ pub fn logcat(args: &ArgMatches) -> Result<LogStream, Error> {
         respawn = false;
     }
 
-    for buffer in args
+    let buffers = args
         .values_of("buffer")
         .map(|m| m.map(ToOwned::to_owned).collect::<Vec<String>>())
         .or_else(|| config_get("buffer"))
-        .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
-    {
+        .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect());
+    for buffer in &buffers {
         cmd.push("-b".into());
-        cmd.push(buffer);
+        cmd.push(buffer.clone());
+    }
+    // Let logcat announce the buffer of the following lines. The binary format carries it anyway.
+    let multiple = buffers.len() > 1 || buffers.iter().any(|b| b == "all" || b.contains(','));
+    if multiple
+        && framing != Framing::Binary
+        && device_sdk(device.clone()).is_some_and(|sdk| sdk >= 24)
+    {
+        cmd.push("-D".into());
     }
 
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    // Filter on the device
+    let mut filtered = false;
+    if let Some(pid) = args.value_of("logcat-pid") {
+        cmd.push(format!("--pid={pid}"));
+        filtered = true;
+    }
+    if let Some(uids) = args.values_of("logcat-uid") {
+        cmd.push(format!("--uid={}", uids.collect::<Vec<_>>().join(",")));
+        filtered = true;
+    }
+
+    let resume = args.is_present("resume");
+    if resume && framing == Framing::Binary {
+        return Err(
+            RogcatError::Config("--resume requires the text format of logcat".to_owned()).into(),
+        );
+    }
+
+    let mut process = Process::with_cmd(cmd, respawn);
+    process.framing = framing;
+    if (respawn || resume) && framing != Framing::Binary {
+        let mut state = Resume::new(&process.cmd, buffers);
+        state.filtered = filtered;
+        state.persist = device_serial(&adb).map(|serial| config_dir().join("resume").join(serial));
+        if resume {
+            state.load();
+        }
+        process.resume = Some(state);
+    }
+    // Wait for the device and detect disconnects unless running on the device
+    process.adb = Some(adb).filter(|_| !on_device);
+    // The device is the source of the records if selected
+    match device {
+        Some(device) => Ok(with_source(device, Box::new(process))),
+        None => Ok(Box::new(process)),
+    }
+}
+
+/// API level of `device`. Logcat supports dividers on buffer switches (`-D`) since 24.
+fn device_sdk(device: Option<String>) -> Option<u32> {
+    let output = utils::device_shell(device, false, ["getprop", "ro.build.version.sdk"])
+        .ok()?
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
 }
 
-/// Start ffx log
+/// Serial of the device selected by `adb` or of the device rogcat runs on suitable as file name
+fn device_serial(adb: &[String]) -> Option<String> {
+    let mut cmd = match adb.split_first() {
+        Some((adb, args)) => {
+            let mut cmd = Command::new(adb);
+            cmd.args(args).arg("get-serialno");
+            cmd
+        }
+        None => {
+            let mut cmd = Command::new("getprop");
+            cmd.arg("ro.serialno");
+            cmd
+        }
+    };
+    let output = cmd.stderr(Stdio::null()).output().ok()?;
+    let serial = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if !output.status.success() || serial.is_empty() || serial == "unknown" {
+        return None;
+    }
+    Some(
+        serial
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+                _ => '_',
+            })
+            .collect(),
+    )
+}
+
+/// Start ffx log. The structured output of `--machine json` is used with `--ffx-json`.
 pub fn fuchsia(args: &ArgMatches) -> Result<LogStream, Error> {
-    let mut cmd = vec!["ffx", "log", "--no-color"];
+    let mut cmd = if args.is_present("fuchsia-json") {
+        vec!["ffx", "--machine", "json", "log"]
+    } else {
+        vec!["ffx", "log", "--no-color"]
+    };
 
     if args.is_present("dump") {
         cmd.push("--dump");
@@ -226,23 +1734,98 @@ impl Process {
             respawn,
             child: None,
             stream: None,
+            resume: None,
+            stats: None,
+            framing: Framing::Newline,
+            adb: None,
+            waiting: None,
+            disconnected: false,
+            spawned: None,
+        }
+    }
+
+    /// Check if the device is online with `adb get-state`
+    fn online(adb: &[String]) -> bool {
+        Command::new(&adb[0])
+            .args(&adb[1..])
+            .arg("get-state")
+            .stderr(Stdio::null())
+            .output()
+            .map(|output| output.status.success() && output.stdout.starts_with(b"device"))
+            .unwrap_or(false)
+    }
+
+    /// Schedule the next spawn after the process terminated. Logcat streams wait
+    /// for the device if it dropped off adb. The spawn is delayed if the process
+    /// terminated right after the last one.
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.stream = None;
+        self.child = None;
+        // A pending startup query is superseded by the one before the respawn
+        self.stats = None;
+
+        if let Some(adb) = self.adb.as_ref().filter(|adb| !Process::online(adb)) {
+            if !self.disconnected {
+                internal::emit("Device disconnected");
+                self.disconnected = true;
+            }
+            let wait = Command::new(&adb[0])
+                .args(&adb[1..])
+                .arg("wait-for-device")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status_async()?
+                .map_err(Error::from)
+                .and_then(|status| {
+                    if status.success() {
+                        Either::A(ok(()))
+                    } else {
+                        // Do not hammer a failing adb
+                        Either::B(Delay::new(Instant::now() + RESTART_DELAY).map_err(Error::from))
+                    }
+                });
+            self.waiting = Some(Box::new(wait));
+            return Ok(());
         }
+
+        internal::emit(format!("Restarting \"{}\"", self.cmd.join(" ")));
+        if let Some(spawned) = self.spawned.filter(|s| s.elapsed() < RESTART_DELAY) {
+            let delay = Delay::new(spawned + RESTART_DELAY).map_err(Error::from);
+            self.waiting = Some(Box::new(delay));
+        }
+        Ok(())
     }
 
-    fn spawn(&mut self) -> Result<Async<Option<StreamData>>, Error> {
+    fn spawn(&mut self, stats: Option<BufferStats>) -> Result<Async<Option<StreamData>>, Error> {
+        let resume = self
+            .resume
+            .as_mut()
+            .map(|resume| resume.args(stats))
+            .unwrap_or_default();
         let mut child = Command::new(self.cmd[0].clone())
             .args(&self.cmd[1..])
+            .args(&resume)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn_async()?;
 
-        let stdout = BufReader::new(child.stdout().take().unwrap());
+        let stdout = child.stdout().take().unwrap();
         let stderr = BufReader::new(child.stderr().take().unwrap());
         self.child = Some(child);
+        // The startup statistics are queried while the first records arrive
+        if self.spawned.is_none() {
+            self.stats = self.resume.as_ref().and_then(Resume::query);
+        }
+        self.spawned = Some(Instant::now());
 
-        let stdout = lossy_lines(stdout)
-            .map_err(Into::into)
-            .map(StreamData::Line);
+        let stdout = match self.framing {
+            Framing::Newline => Box::new(
+                lossy_lines(BufReader::new(stdout))
+                    .map_err(Into::into)
+                    .map(StreamData::Line),
+            ),
+            framing => framed(stdout, framing),
+        };
         let stderr = lossy_lines(stderr)
             .map_err(Into::into)
             .map(StreamData::Line);
@@ -254,18 +1837,187 @@ impl Process {
     }
 }
 
+impl Drop for Process {
+    fn drop(&mut self) {
+        if let Some(ref mut resume) = self.resume {
+            resume.store();
+        }
+    }
+}
+
 impl Stream for Process {
     type Item = StreamData;
     type Error = Error;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
-        if let Some(ref mut inner) = self.stream {
-            match inner.poll() {
-                Ok(Async::Ready(None)) if self.respawn => self.spawn(),
-                poll => poll,
+        loop {
+            if let Some(ref mut waiting) = self.waiting {
+                try_ready!(waiting.poll());
+                self.waiting = None;
+                if self.disconnected {
+                    internal::emit("Device reconnected");
+                    self.disconnected = false;
+                }
             }
-        } else {
-            self.spawn()
+
+            let poll = if let Some(ref mut inner) = self.stream {
+                if let (Some(query), Some(resume)) = (self.stats.as_mut(), self.resume.as_mut()) {
+                    if let Async::Ready(stats) = query.poll()? {
+                        resume.baseline(stats);
+                        self.stats = None;
+                    }
+                }
+                match inner.poll()? {
+                    Async::Ready(None) if self.respawn => {
+                        self.reconnect()?;
+                        continue;
+                    }
+                    poll => poll,
+                }
+            } else {
+                // Respawns wait for the buffer statistics to estimate the lost records
+                let stats = match (self.resume.as_ref(), self.spawned) {
+                    (Some(resume), Some(_)) => {
+                        if self.stats.is_none() {
+                            self.stats = resume.query();
+                        }
+                        match self.stats.as_mut() {
+                            Some(query) => {
+                                let stats = try_ready!(query.poll());
+                                self.stats = None;
+                                stats
+                            }
+                            None => None,
+                        }
+                    }
+                    _ => None,
+                };
+                self.spawn(stats)?
+            };
+
+            if let (Some(resume), Async::Ready(Some(StreamData::Line(line)))) =
+                (self.resume.as_mut(), &poll)
+            {
+                if !resume.line(line) {
+                    continue;
+                }
+            }
+            return Ok(poll);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS: &str = "\
+size/num main               system             crash              events             Total
+Total    33947331/176553    4128574/22187      0/0                8012412/44711      46088317/243451
+Now      259808/1352        252460/1234        0/0                252760/1588        765028/4174
+Logspan  2:15:16.632        6:22:09.414                           6:23:33.451        6:23:33.451
+
+Chattiest UIDs in main log buffer:                           Size Pruned
+UID   PACKAGE                                                BYTES NUM
+1000  system                                                 71380
+";
+
+    fn buffers(buffers: &[&str]) -> Vec<String> {
+        buffers.iter().map(|b| b.to_string()).collect()
+    }
+
+    #[test]
+    fn buffer_stats() {
+        let stats = BufferStats::parse(STATS, &buffers(&["main"])).unwrap();
+        assert_eq!((stats.total, stats.retained), (176553, 1352));
+
+        let stats = BufferStats::parse(STATS, &buffers(&["main", "system", "crash"])).unwrap();
+        assert_eq!((stats.total, stats.retained), (198740, 2586));
+
+        let stats = BufferStats::parse(STATS, &buffers(&["all"])).unwrap();
+        assert_eq!((stats.total, stats.retained), (243451, 4174));
+    }
+
+    #[test]
+    fn buffer_stats_invalid() {
+        // Buffers not in the table
+        assert!(BufferStats::parse(STATS, &buffers(&["radio"])).is_none());
+        assert!(BufferStats::parse("", &buffers(&["main"])).is_none());
+        assert!(BufferStats::parse("logcat: invalid option -- S", &buffers(&["main"])).is_none());
+        // Rows with missing or malformed cells
+        let truncated = "size/num main system\nTotal 1/2\nNow 1/1 1/1\n";
+        assert!(BufferStats::parse(truncated, &buffers(&["main"])).is_none());
+        let malformed = "size/num main\nTotal 1/x\nNow 1/1\n";
+        assert!(BufferStats::parse(malformed, &buffers(&["main"])).is_none());
+    }
+
+    #[test]
+    fn resume() {
+        let mut resume = Resume::new(&buffers(&["logcat"]), buffers(&["main"]));
+        let stats = |total, retained| Some(BufferStats { total, retained });
+
+        // The first spawn starts at the oldest retained record
+        assert!(resume.args(stats(1000, 100)).is_empty());
+        assert_eq!(resume.position, Some(900));
+        assert!(resume.line("01-01 10:00:00.000  1  1 I Tag: a"));
+        assert!(resume.line("01-01 10:00:01.000  1  1 I Tag: b"));
+        assert!(resume.line("01-01 10:00:01.000  1  1 I Tag: c"));
+        assert_eq!(resume.position, Some(903));
+
+        // Resume at the last timestamp and skip the lines received with it
+        assert_eq!(
+            resume.args(stats(1100, 100)),
+            vec!["-T".to_owned(), "01-01 10:00:01.000".to_owned()]
+        );
+        assert_eq!(resume.position, Some(1000));
+        assert!(!resume.line("01-01 10:00:01.000  1  1 I Tag: c"));
+        assert!(!resume.line("01-01 10:00:01.000  1  1 I Tag: b"));
+        assert!(resume.line("01-01 10:00:01.000  1  1 I Tag: d"));
+        assert!(resume.line("01-01 10:00:02.000  1  1 I Tag: e"));
+
+        // Fewer records than received before indicate a reboot
+        assert!(resume.args(stats(10, 10)).is_empty());
+        assert_eq!(resume.position, Some(0));
+    }
+
+    #[test]
+    fn sqlite() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE records (timestamp, level, tag, process, message, fields, extra);
+                 INSERT INTO records VALUES
+                    ('01-02 10:00:00.500', 'Warn', 'A, B', 100, 'hello', 'k=\"a b\"', 1);
+                 INSERT INTO records (message, level) VALUES ('custom', 'notice');",
+            )
+            .unwrap();
+        let mut statement = connection.prepare("SELECT * FROM records").unwrap();
+        let columns = statement
+            .column_names()
+            .iter()
+            .map(|c| c.to_lowercase())
+            .collect::<Vec<_>>();
+        let records = statement
+            .query_map([], |row| sqlite_record(&columns, row))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let record = &records[0];
+        let timestamp = record.timestamp.as_ref().unwrap();
+        assert_eq!((timestamp.tm_mon, timestamp.tm_mday), (0, 2));
+        assert_eq!(timestamp.tm_nsec, 500_000_000);
+        assert_eq!(record.level, Level::Warn);
+        assert_eq!(record.tags, vec!["A", "B"]);
+        assert_eq!(record.process, "100");
+        assert_eq!(record.message, "hello");
+        assert_eq!(record.fields["k"], "a b");
+        assert_eq!(record.fields["extra"], "1");
+        // Rows without a raw column get a threadtime line
+        assert!(record.raw.ends_with("W A,B: hello"), "{}", record.raw);
+
+        assert_eq!(records[1].level, Level::Info);
+        assert_eq!(records[1].severity, Some(45));
+        assert!(records[1].timestamp.is_none());
+    }
+}
@@ -20,15 +20,18 @@
 
 use csv::WriterBuilder;
 use failure::{format_err, Error};
+use lazy_static::lazy_static;
 use serde::{
     de::{Deserializer, Visitor},
     ser::Serializer,
     Deserialize, Serialize,
 };
 use std::{
+    collections::{BTreeMap, HashMap},
     fmt::{Display, Formatter},
     ops::Deref,
     str::FromStr,
+    sync::RwLock,
 };
 use time::{strftime, strptime, Tm};
 
@@ -36,29 +39,168 @@ type StdResult<T, E> = std::result::Result<T, E>;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Format {
-    Csv,
+    /// Csv with optional column selection. All fields without header if `None`
+    Csv(Option<Vec<CsvColumn>>),
     Html,
     Human,
     Json,
+    /// Raw lines on the terminal without any escaping. Lines matching a highlight are colored.
+    Passthrough,
     Raw,
 }
 
+/// Selectable column of the csv format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsvColumn {
+    Timestamp,
+    Message,
+    Level,
+    Tag,
+    Process,
+    Thread,
+    Raw,
+    ProcessName,
+    Source,
+    Buffer,
+    Fields,
+}
+
+impl CsvColumn {
+    /// Parse a comma separated list of column names
+    pub fn parse_list(s: &str) -> Result<Vec<CsvColumn>, Error> {
+        s.split(',')
+            .map(str::trim)
+            .map(|c| CsvColumn::from_str(c).map_err(|e| format_err!("{}: {}", e, c)))
+            .collect()
+    }
+
+    fn value(self, record: &Record) -> Result<String, Error> {
+        Ok(match self {
+            CsvColumn::Timestamp => match record.timestamp {
+                Some(ref ts) => ts.format("%m-%d %H:%M:%S.%f").unwrap_or_default(),
+                None => String::new(),
+            },
+            CsvColumn::Message => record.message.clone(),
+            CsvColumn::Level => format!("{:?}", record.level),
+            CsvColumn::Tag => record.tags.join(","),
+            CsvColumn::Process => record.process.clone(),
+            CsvColumn::Thread => record.thread.clone(),
+            CsvColumn::Raw => record.raw.clone(),
+            CsvColumn::ProcessName => record.process_name.clone().unwrap_or_default(),
+            CsvColumn::Source => record.source.clone().unwrap_or_default(),
+            CsvColumn::Buffer => record.buffer.clone().unwrap_or_default(),
+            CsvColumn::Fields => record
+                .fields
+                .iter()
+                .map(|(key, value)| {
+                    if value.is_empty() || value.contains(char::is_whitespace) {
+                        format!("{}={:?}", key, value)
+                    } else {
+                        format!("{}={}", key, value)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        })
+    }
+}
+
+impl FromStr for CsvColumn {
+    type Err = &'static str;
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "timestamp" => Ok(CsvColumn::Timestamp),
+            "message" => Ok(CsvColumn::Message),
+            "level" => Ok(CsvColumn::Level),
+            "tag" => Ok(CsvColumn::Tag),
+            "process" => Ok(CsvColumn::Process),
+            "thread" => Ok(CsvColumn::Thread),
+            "raw" => Ok(CsvColumn::Raw),
+            "process_name" => Ok(CsvColumn::ProcessName),
+            "source" => Ok(CsvColumn::Source),
+            "buffer" => Ok(CsvColumn::Buffer),
+            "fields" => Ok(CsvColumn::Fields),
+            _ => Err("Invalid csv column"),
+        }
+    }
+}
+
+impl Display for CsvColumn {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                CsvColumn::Timestamp => "timestamp",
+                CsvColumn::Message => "message",
+                CsvColumn::Level => "level",
+                CsvColumn::Tag => "tag",
+                CsvColumn::Process => "process",
+                CsvColumn::Thread => "thread",
+                CsvColumn::Raw => "raw",
+                CsvColumn::ProcessName => "process_name",
+                CsvColumn::Source => "source",
+                CsvColumn::Buffer => "buffer",
+                CsvColumn::Fields => "fields",
+            }
+        )
+    }
+}
+
+/// Write a single csv row without the trailing newline
+fn csv_row<I: IntoIterator<Item = String>>(row: I) -> Result<String, Error> {
+    let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
+    wtr.write_record(row)?;
+    wtr.flush()?;
+    Ok(String::from_utf8(wtr.into_inner().unwrap())?
+        .trim_end_matches('\n')
+        .to_owned())
+}
+
 impl Format {
+    /// Optional header line written before the first record
+    pub fn header(&self) -> Result<Option<String>, Error> {
+        match self {
+            Format::Csv(Some(columns)) => {
+                csv_row(columns.iter().map(ToString::to_string)).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
     pub fn fmt_record(&self, record: &Record) -> Result<String, Error> {
         match self {
-            Format::Csv => {
+            Format::Csv(None) => {
                 let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
-                wtr.serialize(record)?;
+                // Keep the column layout independent of optional fields
+                wtr.serialize(Record {
+                    process_name: None,
+                    annotations: Vec::new(),
+                    uid: None,
+                    buffer: None,
+                    can_id: None,
+                    direction: None,
+                    source: None,
+                    severity: None,
+                    fields: BTreeMap::new(),
+                    ..record.clone()
+                })?;
                 wtr.flush()?;
                 Ok(String::from_utf8(wtr.into_inner().unwrap())?
                     .trim_end_matches('\n')
                     .to_owned())
             }
+            Format::Csv(Some(columns)) => csv_row(
+                columns
+                    .iter()
+                    .map(|c| c.value(record))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
             Format::Html => unimplemented!(),
             Format::Human => unimplemented!(),
             Format::Json => serde_json::to_string(record)
                 .map_err(|e| format_err!("Json serialization error: {}", e)),
-            Format::Raw => Ok(record.raw.clone()),
+            Format::Passthrough | Format::Raw => Ok(record.raw.clone()),
         }
     }
 }
@@ -67,10 +209,11 @@ impl FromStr for Format {
     type Err = &'static str;
     fn from_str(s: &str) -> StdResult<Self, Self::Err> {
         match s {
-            "csv" => Ok(Format::Csv),
+            "csv" => Ok(Format::Csv(None)),
             "html" => Ok(Format::Html),
             "human" => Ok(Format::Human),
             "json" => Ok(Format::Json),
+            "passthrough" => Ok(Format::Passthrough),
             "raw" => Ok(Format::Raw),
             _ => Err("Format parsing error"),
         }
@@ -83,10 +226,11 @@ impl Display for Format {
             f,
             "{}",
             match *self {
-                Format::Csv => "csv",
+                Format::Csv(_) => "csv",
                 Format::Html => "html",
                 Format::Human => "human",
                 Format::Json => "json",
+                Format::Passthrough => "passthrough",
                 Format::Raw => "raw",
             }
         )
@@ -131,7 +275,7 @@ impl Display for Level {
     }
 }
 
-impl<'a> From<&'a str> for Level {
+impl From<&str> for Level {
     fn from(s: &str) -> Self {
         match s {
             "T" | "trace" => Level::Trace,
@@ -151,6 +295,90 @@ impl Level {
     pub fn values() -> &'static [&'static str] {
         LEVEL_VALUES
     }
+
+    /// Position of the level in the ordering used for `--level`. Custom levels
+    /// are ordered in between.
+    pub fn severity(&self) -> u32 {
+        match *self {
+            Level::None => 0,
+            Level::Trace => 10,
+            Level::Verbose => 20,
+            Level::Debug => 30,
+            Level::Info => 40,
+            Level::Warn => 50,
+            Level::Error => 60,
+            Level::Fatal => 70,
+            Level::Assert => 80,
+        }
+    }
+
+    /// The highest level that is not more severe than `severity`
+    pub fn from_severity(severity: u32) -> Level {
+        [
+            Level::Assert,
+            Level::Fatal,
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Verbose,
+            Level::Trace,
+        ]
+        .iter()
+        .find(|level| level.severity() <= severity)
+        .cloned()
+        .unwrap_or_default()
+    }
+}
+
+/// Levels beyond the built in ones and their severity
+const DEFAULT_CUSTOM_LEVELS: &[(&str, u32)] = &[("notice", 45), ("critical", 65)];
+
+lazy_static! {
+    static ref CUSTOM_LEVELS: RwLock<HashMap<String, u32>> = RwLock::new(
+        DEFAULT_CUSTOM_LEVELS
+            .iter()
+            .map(|(name, severity)| (name.to_string(), *severity))
+            .collect()
+    );
+}
+
+/// Add or reorder a custom level e.g `notice`. Names are case insensitive.
+pub fn register_level(name: &str, severity: u32) {
+    CUSTOM_LEVELS
+        .write()
+        .expect("Failed to get custom levels lock")
+        .insert(name.to_lowercase(), severity);
+}
+
+/// Level of `name` and its severity if `name` is a custom level
+pub fn parse_level(name: &str) -> (Level, Option<u32>) {
+    match Level::from(name) {
+        Level::None => (),
+        level => return (level, None),
+    }
+    let name = name.to_lowercase();
+    match Level::from(name.as_str()) {
+        Level::None => (),
+        level => return (level, None),
+    }
+    match CUSTOM_LEVELS
+        .read()
+        .expect("Failed to get custom levels lock")
+        .get(&name)
+    {
+        Some(severity) => (Level::from_severity(*severity), Some(*severity)),
+        None => (Level::None, None),
+    }
+}
+
+/// Severity of a built in or custom level name or a plain number
+pub fn severity(name: &str) -> Option<u32> {
+    match parse_level(name) {
+        (_, Some(severity)) => Some(severity),
+        (Level::None, None) => name.parse().ok(),
+        (level, None) => Some(level.severity()),
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -181,6 +409,32 @@ impl Timestamp {
     pub fn now() -> Timestamp {
         Timestamp { tm: time::now() }
     }
+
+    /// Returns true if all fields are in range. Timestamps of garbled or
+    /// hostile input may not be.
+    pub fn is_valid(&self) -> bool {
+        let tm = &self.tm;
+        (0..=60).contains(&tm.tm_sec)
+            && (0..60).contains(&tm.tm_min)
+            && (0..24).contains(&tm.tm_hour)
+            && (1..=31).contains(&tm.tm_mday)
+            && (0..12).contains(&tm.tm_mon)
+            && (-1900..8100).contains(&tm.tm_year)
+            && (0..7).contains(&tm.tm_wday)
+            && (0..366).contains(&tm.tm_yday)
+            && (0..1_000_000_000).contains(&tm.tm_nsec)
+            && (-86_400..=86_400).contains(&tm.tm_utcoff)
+    }
+
+    /// Format with `format` like `strftime`. Returns `None` for invalid timestamps
+    /// or formats instead of failing or panicking on out of range fields.
+    pub fn format(&self, format: &str) -> Option<String> {
+        if self.is_valid() {
+            strftime(format, &self.tm).ok()
+        } else {
+            None
+        }
+    }
 }
 
 impl Serialize for Timestamp {
@@ -188,9 +442,11 @@ impl Serialize for Timestamp {
     where
         S: Serializer,
     {
-        strftime("%m-%d %H:%M:%S.%f", &self.tm)
-            .map_err(|e| ::serde::ser::Error::custom(e.to_string()))?
-            .serialize(serializer)
+        // Invalid timestamps are written as missing
+        match self.format("%m-%d %H:%M:%S.%f") {
+            Some(timestamp) => timestamp.serialize(serializer),
+            None => serializer.serialize_none(),
+        }
     }
 }
 
@@ -235,4 +491,119 @@ pub struct Record {
     pub process: String,
     pub thread: String,
     pub raw: String,
+    /// Name of the process if resolved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_name: Option<String>,
+    /// Notes and links attached to the record e.g with `--annotate`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<String>,
+    /// User id of the logging process if known e.g from the logcat binary format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    /// Log buffer of the record if known e.g from the logcat binary format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<String>,
+    /// Direction "TX" or "RX" of telephony messages e.g AT commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<String>,
+    /// Id of a CAN frame
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub can_id: Option<u32>,
+    /// Provenance of the record e.g the device serial, input file, tcp peer or can interface
+    #[serde(default, alias = "file", skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Severity of custom levels e.g syslog notice that are shown as the next lower level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<u32>,
+    /// Structured key value fields e.g the pid and keys of Fuchsia logs
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Record {
+    /// Severity of the record used for the `--level` comparison
+    pub fn severity(&self) -> u32 {
+        self.severity.unwrap_or_else(|| self.level.severity())
+    }
+
+    /// Render the record as logcat threadtime line e.g for records read without a raw line
+    pub fn threadtime(&self) -> String {
+        let timestamp = self
+            .timestamp
+            .as_ref()
+            .and_then(|ts| {
+                ts.format("%m-%d %H:%M:%S")
+                    .map(|t| format!("{}.{:03} ", t, ts.tm_nsec / 1_000_000))
+            })
+            .unwrap_or_default();
+        format!(
+            "{}{:>5} {:>5} {} {}: {}",
+            timestamp,
+            self.process,
+            self.thread,
+            self.level,
+            self.tags.join(","),
+            self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_columns() {
+        assert_eq!(
+            CsvColumn::parse_list("timestamp, tag,message").unwrap(),
+            vec![CsvColumn::Timestamp, CsvColumn::Tag, CsvColumn::Message]
+        );
+        assert!(CsvColumn::parse_list("tag,bogus").is_err());
+        assert!(CsvColumn::parse_list("").is_err());
+
+        // Names round trip through Display
+        for name in &[
+            "timestamp",
+            "message",
+            "level",
+            "tag",
+            "process",
+            "thread",
+            "raw",
+            "process_name",
+            "source",
+            "buffer",
+            "fields",
+        ] {
+            assert_eq!(CsvColumn::from_str(name).unwrap().to_string(), *name);
+        }
+    }
+
+    #[test]
+    fn csv_selected_columns() {
+        let mut record = Record {
+            message: "hello, world".to_owned(),
+            level: Level::Warn,
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            process: "100".to_owned(),
+            ..Record::default()
+        };
+        record
+            .fields
+            .insert("key".to_owned(), "two words".to_owned());
+        record.fields.insert("n".to_owned(), "1".to_owned());
+
+        let format = Format::Csv(Some(
+            CsvColumn::parse_list("tag,level,message,process_name,fields").unwrap(),
+        ));
+        assert_eq!(
+            format.header().unwrap().as_deref(),
+            Some("tag,level,message,process_name,fields")
+        );
+        assert_eq!(
+            format.fmt_record(&record).unwrap(),
+            r#""a,b",Warn,"hello, world",,"key=""two words"" n=1""#
+        );
+        assert_eq!(Format::Csv(None).header().unwrap(), None);
+    }
 }
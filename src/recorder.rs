@@ -0,0 +1,219 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Flight recorder that keeps the most recent records in memory and writes them to a file
+//! when a trigger fires e.g `--flight-recorder 5m --trigger 'FATAL EXCEPTION' -o crash.log`
+
+use crate::{
+    error::RogcatError, filewriter, internal, profiles::Profile, utils::parse_duration, LogSink,
+};
+use clap::ArgMatches;
+use failure::Error;
+#[cfg(unix)]
+use futures::{Async, Future, Stream};
+use futures::{AsyncSink, Poll, Sink, StartSend};
+use regex::RegexSet;
+use rogcat::record::{Level, Record};
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Set by a key press in the interactive mode
+static TRIGGERED: AtomicBool = AtomicBool::new(false);
+
+/// Size of the ring buffer
+enum Capacity {
+    Records(usize),
+    Duration(Duration),
+}
+
+pub struct Recorder {
+    sink: LogSink,
+    args: ArgMatches<'static>,
+    profile: Profile,
+    capacity: Capacity,
+    /// Recorded records and their time of arrival
+    records: VecDeque<(Instant, Record)>,
+    triggers: RegexSet,
+    /// Dumps written in the background. The result is reported once they finish.
+    dumps: Vec<JoinHandle<String>>,
+    #[cfg(unix)]
+    signals: Option<Box<dyn Stream<Item = i32, Error = Error> + Send>>,
+}
+
+/// Wrap `sink` with a flight recorder if `--flight-recorder` is passed
+pub fn wrap(
+    args: &ArgMatches<'static>,
+    profile: &Profile,
+    sink: LogSink,
+) -> Result<LogSink, Error> {
+    let capacity = match args.value_of("flight-recorder") {
+        Some(capacity) => capacity,
+        None => return Ok(sink),
+    };
+    let capacity = match capacity.parse::<usize>() {
+        Ok(records) => Capacity::Records(records),
+        Err(_) => Capacity::Duration(parse_duration(capacity).map_err(|_| {
+            RogcatError::Config(format!(
+                "Invalid flight recorder size: {}. Use a number of records or a duration like 5m",
+                capacity
+            ))
+        })?),
+    };
+    let triggers = RegexSet::new(args.values_of("trigger").unwrap_or_default())?;
+
+    Ok(Box::new(Recorder {
+        sink,
+        args: args.clone(),
+        profile: profile.clone(),
+        capacity,
+        records: VecDeque::new(),
+        triggers,
+        dumps: Vec::new(),
+        #[cfg(unix)]
+        signals: None,
+    }))
+}
+
+/// Dump the flight recorder e.g on a key press
+pub fn trigger() {
+    TRIGGERED.store(true, Ordering::Relaxed);
+}
+
+impl Recorder {
+    fn record(&mut self, record: Record) {
+        let now = Instant::now();
+        let reason = self
+            .triggers
+            .is_match(&record.message)
+            .then(|| format!("{}: {}", record.tags.join(" "), record.message));
+        self.records.push_back((now, record));
+        match self.capacity {
+            Capacity::Records(n) => {
+                while self.records.len() > n {
+                    self.records.pop_front();
+                }
+            }
+            Capacity::Duration(duration) => {
+                while let Some((arrival, _)) = self.records.front() {
+                    if now.duration_since(*arrival) <= duration {
+                        break;
+                    }
+                    self.records.pop_front();
+                }
+            }
+        }
+        if let Some(reason) = reason {
+            self.dump(&reason);
+        }
+    }
+
+    /// Write the recorded records to a new file on a separate thread and start over
+    fn dump(&mut self, reason: &str) {
+        if self.records.is_empty() {
+            return;
+        }
+        let records = self.records.drain(..).map(|(_, r)| r).collect::<Vec<_>>();
+        internal::emit(format!(
+            "Flight recorder triggered by {}. Writing {} records",
+            reason,
+            records.len()
+        ));
+        let args = self.args.clone();
+        let profile = self.profile.clone();
+        self.dumps.push(thread::spawn(move || {
+            match filewriter::dump(&args, &profile, &records) {
+                Ok(file) => format!("Flight recorder wrote {}", file.display()),
+                Err(e) => format!("Flight recorder failed: {}", e),
+            }
+        }));
+    }
+
+    /// Dump on SIGUSR1, SIGQUIT (ctrl-\) or a key press
+    fn poll_triggers(&mut self) -> Result<(), Error> {
+        let (finished, running) = self.dumps.drain(..).partition(|d| d.is_finished());
+        self.dumps = running;
+        finished
+            .into_iter()
+            .filter_map(|dump: JoinHandle<String>| dump.join().ok())
+            .for_each(internal::emit);
+        if TRIGGERED.swap(false, Ordering::Relaxed) {
+            self.dump("key press");
+        }
+        #[cfg(unix)]
+        {
+            use tokio_signal::unix::{Signal, SIGQUIT, SIGUSR1};
+            let signals = self.signals.get_or_insert_with(|| {
+                let signal = |signal| Signal::new(signal).flatten_stream();
+                Box::new(signal(SIGUSR1).select(signal(SIGQUIT)).map_err(Into::into))
+            });
+            let mut received = Vec::new();
+            while let Async::Ready(Some(signal)) = signals.poll()? {
+                received.push(signal);
+            }
+            for signal in received {
+                self.dump(if signal == SIGQUIT {
+                    "SIGQUIT"
+                } else {
+                    "SIGUSR1"
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Recorder {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        self.poll_triggers()?;
+        let recorded = (!internal::is_internal(&record)).then(|| record.clone());
+        match self.sink.start_send(record)? {
+            AsyncSink::Ready => {
+                if let Some(record) = recorded {
+                    self.record(record);
+                }
+                Ok(AsyncSink::Ready)
+            }
+            not_ready => Ok(not_ready),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.poll_triggers()?;
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        // Finish the dumps before the process exits. The input is closed, report directly.
+        for dump in self.dumps.drain(..) {
+            if let Ok(message) = dump.join() {
+                self.sink
+                    .start_send(internal::record(Level::Info, message))?;
+            }
+        }
+        self.sink.close()
+    }
+}
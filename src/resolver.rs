@@ -0,0 +1,109 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Resolve the numeric process ids of Android records to process names
+
+use crate::{
+    error::RogcatError,
+    utils::{device_shell, selected_device},
+};
+use clap::ArgMatches;
+use failure::Error;
+use rogcat::record::Record;
+use std::{
+    collections::HashMap,
+    process::{Command, Stdio},
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
+
+/// Interval between process list queries
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Cached pid to name mapping refreshed from `ps` on the device
+#[derive(Clone)]
+pub struct Resolver {
+    names: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl Resolver {
+    /// Start a resolver if `--resolve-names` is passed
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Resolver>, Error> {
+        if !args.is_present("resolve-names") {
+            return Ok(None);
+        }
+
+        let mut cmd = device_shell(selected_device(args), false, ["ps", "-A", "-o", "PID,NAME"])?;
+        cmd.stdin(Stdio::null()).stderr(Stdio::null());
+
+        let resolver = Resolver {
+            names: Arc::new(RwLock::new(HashMap::new())),
+        };
+        resolver.refresh(&mut cmd)?;
+
+        let refresh = resolver.clone();
+        thread::spawn(move || loop {
+            thread::sleep(REFRESH_INTERVAL);
+            // Keep the last mapping if the device is temporarily unavailable
+            refresh.refresh(&mut cmd).ok();
+        });
+
+        Ok(Some(resolver))
+    }
+
+    /// Query the process list. Entries of exited processes are kept for
+    /// records that are received late.
+    fn refresh(&self, cmd: &mut Command) -> Result<(), Error> {
+        let output = cmd.output()?;
+        if !output.status.success() {
+            return Err(RogcatError::Device(format!(
+                "Failed to query process list: {}",
+                output.status
+            ))
+            .into());
+        }
+        let output = String::from_utf8_lossy(&output.stdout);
+        let names = output.lines().skip(1).filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?;
+            let name = fields.next()?;
+            Some((pid.to_owned(), name.to_owned()))
+        });
+        self.names
+            .write()
+            .expect("Failed to get name lock")
+            .extend(names);
+        Ok(())
+    }
+
+    /// Set the process name of `record` if its pid is known
+    pub fn apply(&self, mut record: Record) -> Record {
+        if record.process_name.is_none() {
+            record.process_name = self
+                .names
+                .read()
+                .expect("Failed to get name lock")
+                .get(record.process.trim())
+                .cloned();
+        }
+        record
+    }
+}
@@ -0,0 +1,476 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Record statistics of a run
+
+use crate::{error::RogcatError, filewriter, utils};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use rogcat::record::Record;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Minimum absolute change of a count to be reported
+const MIN_DELTA: u64 = 10;
+/// Minimum relative change of a count in percent to be reported
+const MIN_DELTA_PERCENT: u64 = 50;
+/// Default interval of the rolling statistics written to `--stats-file`
+const DEFAULT_STATS_INTERVAL: &str = "5m";
+
+/// Record counts per level and tag
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Stats {
+    pub records: u64,
+    pub levels: BTreeMap<String, u64>,
+    pub tags: BTreeMap<String, u64>,
+    /// Maximum number of records queued for writing the output file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_queue_max: Option<u64>,
+}
+
+impl Stats {
+    pub fn add(&mut self, record: &Record) {
+        self.records += 1;
+        *self
+            .levels
+            .entry(format!("{:?}", record.level))
+            .or_default() += 1;
+        for tag in &record.tags {
+            *self.tags.entry(tag.clone()).or_default() += 1;
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Stats, Error> {
+        let file = File::open(path)
+            .map_err(|e| RogcatError::Io(format!("Failed to open {}: {}", path.display(), e)))?;
+        serde_json::from_reader(file).map_err(|e| {
+            RogcatError::Parse(format!("Failed to parse {}: {}", path.display(), e)).into()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let file = File::create(path)
+            .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", path.display(), e)))?;
+        serde_json::to_writer_pretty(file, self).map_err(Into::into)
+    }
+
+    /// Describe differences to `baseline` of at least `min_percent`
+    pub fn compare(&self, baseline: &Stats, min_percent: u64) -> Vec<String> {
+        fn delta(name: &str, current: u64, baseline: u64, min_percent: u64) -> Option<String> {
+            let diff = current.abs_diff(baseline);
+            if diff < MIN_DELTA || diff * 100 < baseline * min_percent {
+                return None;
+            }
+            let percent = if baseline == 0 {
+                "new".to_owned()
+            } else {
+                format!(
+                    "{:+}%",
+                    (current as i64 - baseline as i64) * 100 / baseline as i64
+                )
+            };
+            Some(format!("{name}: {baseline} -> {current} ({percent})"))
+        }
+
+        fn deltas(
+            prefix: &str,
+            current: &BTreeMap<String, u64>,
+            baseline: &BTreeMap<String, u64>,
+            min_percent: u64,
+        ) -> Vec<String> {
+            let mut keys = current.keys().chain(baseline.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            keys.into_iter()
+                .filter_map(|k| {
+                    delta(
+                        &format!("{prefix} {k}"),
+                        current.get(k).copied().unwrap_or(0),
+                        baseline.get(k).copied().unwrap_or(0),
+                        min_percent,
+                    )
+                })
+                .collect()
+        }
+
+        delta("records", self.records, baseline.records, min_percent)
+            .into_iter()
+            .chain(deltas("level", &self.levels, &baseline.levels, min_percent))
+            .chain(deltas("tag", &self.tags, &baseline.tags, min_percent))
+            .collect()
+    }
+}
+
+/// Line of the rolling statistics file
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    /// UTC time at the end of the interval
+    time: String,
+    /// Length of the interval in seconds
+    interval: f64,
+    /// Records per second in the interval
+    rate: f64,
+    /// Records since the start of the capture
+    total: u64,
+    /// Counts of the interval
+    #[serde(flatten)]
+    stats: &'a Stats,
+}
+
+/// Counts of the current interval that are appended to the `--stats-file`
+struct Rolling {
+    stats: Stats,
+    start: Instant,
+    total: u64,
+    file: File,
+    path: PathBuf,
+}
+
+impl Rolling {
+    /// Append the counts of the interval as json line and start a new interval
+    fn write(&mut self) -> Result<(), Error> {
+        let interval = self.start.elapsed().as_secs_f64();
+        self.start = Instant::now();
+        let stats = mem::take(&mut self.stats);
+        self.total += stats.records;
+        let snapshot = Snapshot {
+            time: time::strftime("%FT%TZ", &time::now_utc())?,
+            interval,
+            rate: stats.records as f64 / interval.max(f64::EPSILON),
+            total: self.total,
+            stats: &stats,
+        };
+        // Write whole lines to let followers e.g `tail -f` see complete objects
+        let mut line = serde_json::to_vec(&snapshot)?;
+        line.push(b'\n');
+        self.file.write_all(&line).map_err(|e| {
+            RogcatError::Io(format!("Failed to write {}: {}", self.path.display(), e)).into()
+        })
+    }
+}
+
+/// Collects statistics during a run and saves or compares them at exit
+#[derive(Clone)]
+pub struct Collector {
+    stats: Arc<Mutex<Stats>>,
+    file: Option<PathBuf>,
+    baseline: Option<Arc<Stats>>,
+    threshold: Option<u64>,
+    rolling: Option<Arc<Mutex<Rolling>>>,
+}
+
+impl Collector {
+    /// Create a collector if `--stats`, `--stats-baseline` or `--stats-file` is passed
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Collector>, Error> {
+        let file = args.value_of("stats").map(PathBuf::from);
+        let baseline = args
+            .value_of("stats-baseline")
+            .map(|b| Stats::load(Path::new(b)))
+            .transpose()?
+            .map(Arc::new);
+        let threshold = args
+            .value_of("stats-threshold")
+            .map(|t| {
+                t.trim_end_matches('%')
+                    .parse::<u64>()
+                    .map_err(|_| RogcatError::Config(format!("Invalid stats threshold: {t}")))
+            })
+            .transpose()?;
+        let rolling = args
+            .value_of("stats-file")
+            .map(|path| rolling(args, Path::new(path)))
+            .transpose()?;
+        if file.is_none() && baseline.is_none() && rolling.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Collector {
+            stats: Arc::new(Mutex::new(Stats::default())),
+            file,
+            baseline,
+            threshold,
+            rolling,
+        }))
+    }
+
+    pub fn add(&self, record: &Record) {
+        self.stats
+            .lock()
+            .expect("Failed to get stats lock")
+            .add(record);
+        if let Some(ref rolling) = self.rolling {
+            rolling
+                .lock()
+                .expect("Failed to get stats lock")
+                .stats
+                .add(record);
+        }
+    }
+
+    /// Save the statistics and print the differences to the baseline. Fails if there are
+    /// differences and a `--stats-threshold` is set.
+    pub fn finish(&self) -> Result<(), Error> {
+        // The last interval is cut short
+        if let Some(ref rolling) = self.rolling {
+            rolling.lock().expect("Failed to get stats lock").write()?;
+        }
+
+        let mut stats = self.stats.lock().expect("Failed to get stats lock");
+        stats.write_queue_max = Some(filewriter::max_queued() as u64).filter(|max| *max > 0);
+        if let Some(ref file) = self.file {
+            stats.save(file)?;
+        }
+        if let Some(ref baseline) = self.baseline {
+            let deltas = stats.compare(baseline, self.threshold.unwrap_or(MIN_DELTA_PERCENT));
+            if deltas.is_empty() {
+                eprintln!("No significant changes compared to baseline");
+            } else {
+                eprintln!("Significant changes compared to baseline:");
+                for delta in &deltas {
+                    eprintln!("  {delta}");
+                }
+                if let Some(threshold) = self.threshold {
+                    return Err(format_err!(
+                        "{} counts changed by at least {}% compared to baseline",
+                        deltas.len(),
+                        threshold
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Open the `--stats-file` for appending and write the rolling statistics every `--stats-interval`
+fn rolling(args: &ArgMatches, path: &Path) -> Result<Arc<Mutex<Rolling>>, Error> {
+    let interval = utils::parse_duration(
+        args.value_of("stats-interval")
+            .unwrap_or(DEFAULT_STATS_INTERVAL),
+    )?;
+    if interval == Duration::from_secs(0) {
+        return Err(RogcatError::Config("Invalid stats interval: 0".to_owned()).into());
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| RogcatError::Io(format!("Failed to open {}: {}", path.display(), e)))?;
+    let rolling = Arc::new(Mutex::new(Rolling {
+        stats: Stats::default(),
+        start: Instant::now(),
+        total: 0,
+        file,
+        path: path.to_owned(),
+    }));
+
+    let writer = rolling.clone();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Err(e) = writer.lock().expect("Failed to get stats lock").write() {
+            eprintln!("{e}");
+            break;
+        }
+    });
+    Ok(rolling)
+}
+
+/// Width of the rate bars of a report
+const BAR_WIDTH: u64 = 40;
+
+/// Detailed statistics of saved logs printed by the stats subcommand
+pub struct Report {
+    stats: Stats,
+    unparsed: u64,
+    processes: HashMap<String, u64>,
+    /// Records per time bucket keyed by year, month, day and bucket of the day
+    buckets: BTreeMap<(i32, i32, i32, i32), u64>,
+    bucket: i32,
+    messages: HashMap<(String, String), u64>,
+}
+
+impl Report {
+    /// Create a report that counts the rate in buckets of `bucket` seconds
+    pub fn new(bucket: u64) -> Report {
+        Report {
+            stats: Stats::default(),
+            unparsed: 0,
+            processes: HashMap::new(),
+            buckets: BTreeMap::new(),
+            bucket: bucket.clamp(1, 24 * 60 * 60) as i32,
+            messages: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        self.stats.add(record);
+        if !record.process.is_empty() {
+            *self.processes.entry(record.process.clone()).or_default() += 1;
+        }
+        if let Some(ref t) = record.timestamp {
+            let second = t.tm_hour * 60 * 60 + t.tm_min * 60 + t.tm_sec;
+            *self
+                .buckets
+                .entry((t.tm_year, t.tm_mon, t.tm_mday, second / self.bucket))
+                .or_default() += 1;
+        }
+        let tag = record.tags.first().cloned().unwrap_or_default();
+        *self
+            .messages
+            .entry((tag, record.message.clone()))
+            .or_default() += 1;
+    }
+
+    /// Count a line that none of the parsers understood
+    pub fn add_unparsed(&mut self) {
+        self.unparsed += 1;
+    }
+
+    /// Write the report with the `top` most frequent messages
+    pub fn write<W: Write>(&self, out: &mut W, top: usize) -> io::Result<()> {
+        fn by_count<K: Ord>(counts: impl Iterator<Item = (K, u64)>) -> Vec<(K, u64)> {
+            let mut counts = counts.collect::<Vec<_>>();
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            counts
+        }
+
+        writeln!(
+            out,
+            "Records: {} ({} unparsed)",
+            self.stats.records, self.unparsed
+        )?;
+
+        writeln!(out, "\nLevels:")?;
+        for (level, count) in by_count(self.stats.levels.iter().map(|(k, v)| (k, *v))) {
+            writeln!(out, "{count:>10} {level}")?;
+        }
+
+        writeln!(out, "\nTags:")?;
+        for (tag, count) in by_count(self.stats.tags.iter().map(|(k, v)| (k, *v))) {
+            writeln!(out, "{count:>10} {tag}")?;
+        }
+
+        writeln!(out, "\nProcesses:")?;
+        for (process, count) in by_count(self.processes.iter().map(|(k, v)| (k, *v))) {
+            writeln!(out, "{count:>10} {process}")?;
+        }
+
+        writeln!(out, "\nRate (records/s in {}s buckets):", self.bucket)?;
+        let max = self.buckets.values().max().copied().unwrap_or(1);
+        for ((_, _, _, bucket), count) in &self.buckets {
+            let start = bucket * self.bucket;
+            writeln!(
+                out,
+                "  {:02}:{:02}:{:02} {:>10.1} {}",
+                start / 3600,
+                start / 60 % 60,
+                start % 60,
+                *count as f64 / f64::from(self.bucket),
+                "#".repeat((count * BAR_WIDTH / max) as usize)
+            )?;
+        }
+
+        writeln!(out, "\nTop {top} messages:")?;
+        for ((tag, message), count) in by_count(self.messages.iter().map(|(k, v)| (k, *v)))
+            .into_iter()
+            .take(top)
+        {
+            writeln!(out, "{count:>10} {tag}: {message}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(records: u64, levels: &[(&str, u64)], tags: &[(&str, u64)]) -> Stats {
+        let map = |counts: &[(&str, u64)]| {
+            counts
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v))
+                .collect::<BTreeMap<_, _>>()
+        };
+        Stats {
+            records,
+            levels: map(levels),
+            tags: map(tags),
+            write_queue_max: None,
+        }
+    }
+
+    #[test]
+    fn compare_unchanged() {
+        let baseline = stats(100, &[("Info", 100)], &[("a", 60), ("b", 40)]);
+        let current = stats(120, &[("Info", 120)], &[("a", 70), ("b", 50)]);
+        assert!(current.compare(&baseline, MIN_DELTA_PERCENT).is_empty());
+    }
+
+    #[test]
+    fn compare_significant() {
+        let baseline = stats(100, &[("Info", 100)], &[("a", 60), ("b", 40)]);
+        let current = stats(
+            210,
+            &[("Info", 100), ("Error", 110)],
+            &[("a", 60), ("c", 150)],
+        );
+        assert_eq!(
+            current.compare(&baseline, MIN_DELTA_PERCENT),
+            vec![
+                "records: 100 -> 210 (+110%)",
+                "level Error: 0 -> 110 (new)",
+                "tag b: 40 -> 0 (-100%)",
+                "tag c: 0 -> 150 (new)",
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_small_counts() {
+        // Changes below MIN_DELTA records are not significant regardless of the ratio
+        let baseline = stats(2, &[("Info", 2)], &[("a", 2)]);
+        let current = stats(10, &[("Info", 10)], &[("a", 10)]);
+        assert!(current.compare(&baseline, 0).is_empty());
+    }
+
+    #[test]
+    fn compare_threshold() {
+        let baseline = stats(100, &[("Info", 100)], &[("a", 100)]);
+        let current = stats(120, &[("Info", 120)], &[("a", 80)]);
+        assert!(current.compare(&baseline, 25).is_empty());
+        assert_eq!(
+            current.compare(&baseline, 20),
+            vec![
+                "records: 100 -> 120 (+20%)",
+                "level Info: 100 -> 120 (+20%)",
+                "tag a: 100 -> 80 (-20%)",
+            ]
+        );
+    }
+}
@@ -19,27 +19,51 @@
 // SOFTWARE.
 
 use crate::{
-    cli::cli,
-    reader::stdin,
-    utils::{self, adb},
-    StreamData, DEFAULT_BUFFER,
+    bugreport::{self, Extractor, Section},
+    crashes::{Crash, Detector},
+    error::{self, RogcatError},
+    filter::{self, Filter},
+    generate, pipeline,
+    profiles::{self, Profile},
+    progress::Progress,
+    props,
+    reader::{stdin, Framing},
+    stats::Report,
+    symbolizer::Symbolizer,
+    terminal::Human,
+    utils::{self, adb, device_shell, select_device, selected_device},
+    wireless, StreamData, DEFAULT_BUFFER,
 };
-use clap::{crate_name, value_t, ArgMatches};
+use clap::{value_t, ArgMatches};
 use failure::{err_msg, Error};
 use futures::{
     future::ok, stream::Stream, sync::oneshot, Async, AsyncSink, Future, Poll, Sink, StartSend,
 };
-use indicatif::{ProgressBar, ProgressStyle};
-use rogcat::record::Level;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::{
+    parser::{CsvParser, FormatParser, JsonParser, Parser},
+    record::{Format, Level, Record},
+};
 use std::{
     borrow::ToOwned,
-    fs::{DirBuilder, File},
-    io::{BufReader, Write},
+    cmp::min,
+    collections::HashMap,
+    env,
+    fs::{self, DirBuilder, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
     path::{Path, PathBuf},
     process::{exit, Command, Stdio},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Instant,
 };
 use time::{now, strftime};
-use tokio::{io::lines, runtime::Runtime};
+use tokio::runtime::Runtime;
 use tokio_process::CommandExt;
 use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
@@ -47,26 +71,520 @@ pub fn run(args: &ArgMatches) {
     match args.subcommand() {
         ("bugreport", Some(sub_matches)) => bugreport(sub_matches),
         ("clear", Some(sub_matches)) => clear(sub_matches),
-        ("completions", Some(sub_matches)) => completions(sub_matches),
+        // Returns to capture the logs of the connected device
+        ("connect", Some(sub_matches)) => connect(sub_matches),
+        ("convert", Some(sub_matches)) => convert(sub_matches),
+        ("crashes", Some(sub_matches)) => crashes(sub_matches),
         ("devices", _) => devices(),
+        ("generate", Some(sub_matches)) => generate(sub_matches),
+        ("grep", Some(sub_matches)) => grep(sub_matches),
         ("log", Some(sub_matches)) => log(sub_matches),
+        ("props", Some(sub_matches)) => props(sub_matches),
+        ("stats", Some(sub_matches)) => stats(sub_matches),
+        ("tcpip", Some(sub_matches)) => tcpip(sub_matches),
+        ("tui", Some(sub_matches)) => tui(sub_matches),
+        ("view", Some(sub_matches)) => view(sub_matches),
         (_, _) => (),
     }
 }
 
-pub fn completions(args: &ArgMatches) {
-    if let Err(e) = args
-        .value_of("shell")
-        .ok_or_else(|| err_msg("Required shell argument is missing"))
-        .map(str::parse)
-        .map(|s| {
-            cli().gen_completions_to(crate_name!(), s.unwrap(), &mut std::io::stdout());
-        })
-    {
-        eprintln!("Failed to get shell argument: {e}");
-        exit(1);
+/// Connect to a device over the network and select it for the capture unless `--no-stream`
+/// is passed
+pub fn connect(args: &ArgMatches) {
+    match wireless::connect(args) {
+        Ok(_) if args.is_present("no-stream") => exit(0),
+        Ok(serial) => utils::set_device(serial),
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+pub fn tcpip(args: &ArgMatches) {
+    match wireless::tcpip(args) {
+        Ok(()) => exit(0),
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+pub fn generate(args: &ArgMatches) {
+    match generate::run(args) {
+        Ok(()) => exit(0),
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+/// Statistics of a conversion run
+#[derive(Default)]
+struct ConversionStats {
+    files: usize,
+    records: usize,
+    unparsed: usize,
+    verified: usize,
+}
+
+/// Return the name of the first field that differs between `a` and `b`
+fn record_diff(a: &Record, b: &Record) -> Option<&'static str> {
+    if a.timestamp != b.timestamp {
+        Some("timestamp")
+    } else if a.message != b.message {
+        Some("message")
+    } else if a.level != b.level {
+        Some("level")
+    } else if a.tags != b.tags {
+        Some("tags")
+    } else if a.process != b.process {
+        Some("process")
+    } else if a.thread != b.thread {
+        Some("thread")
+    } else if a.raw != b.raw {
+        Some("raw")
     } else {
-        exit(0);
+        None
+    }
+}
+
+/// Parse `line` the way `format` is read back by rogcat
+fn parse_format(parser: &mut Parser, format: &Format, line: &str) -> Result<Record, Error> {
+    match format {
+        Format::Csv(_) => CsvParser.try_parse_str(line).map_err(Into::into),
+        Format::Json => JsonParser.try_parse_str(line).map_err(Into::into),
+        _ => Ok(parser.parse(line.to_owned())),
+    }
+}
+
+fn convert_files(args: &ArgMatches) -> Result<ConversionStats, Error> {
+    let output = PathBuf::from(
+        args.value_of("output")
+            .ok_or_else(|| err_msg("Missing output argument"))?,
+    );
+    if output.exists() && !args.is_present("overwrite") {
+        return Err(RogcatError::Config(format!(
+            "{} exists. Use overwrite flag to force!",
+            output.display()
+        ))
+        .into());
+    }
+
+    let format = args
+        .value_of("format")
+        .or_else(|| output.extension().and_then(std::ffi::OsStr::to_str))
+        .and_then(|f| Format::from_str(f).ok())
+        .unwrap_or(Format::Raw);
+    if format == Format::Html || format == Format::Human {
+        return Err(RogcatError::Config(format!("Cannot convert to {}", format)).into());
+    }
+
+    // Write to a temporary file next to the output that replaces it on success only
+    let file_name = output
+        .file_name()
+        .ok_or_else(|| RogcatError::Config(format!("Invalid output {}", output.display())))?;
+    let tmp = output.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    let file = File::create(&tmp)
+        .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", tmp.display(), e)))?;
+    let stats = convert_into(args, &format, BufWriter::new(file));
+    match stats {
+        Ok(_) => fs::rename(&tmp, &output).map_err(|e| {
+            RogcatError::Io(format!("Failed to create {}: {}", output.display(), e))
+        })?,
+        Err(_) => fs::remove_file(&tmp).unwrap_or_default(),
+    }
+    stats
+}
+
+/// Convert the input files into `format` and write them to `writer`
+fn convert_into<W: Write>(
+    args: &ArgMatches,
+    format: &Format,
+    mut writer: W,
+) -> Result<ConversionStats, Error> {
+    let verify = args.is_present("verify");
+    let mut parser = pipeline::parser(args, &profiles::from_args(args)?)?;
+    let mut verify_parser = Parser::default();
+    let mut stats = ConversionStats::default();
+
+    for input in args.values_of("input").unwrap_or_default() {
+        let file = File::open(input)
+            .map_err(|e| RogcatError::Io(format!("Failed to open {}: {}", input, e)))?;
+        for (n, line) in BufReader::new(file).split(b'\n').enumerate() {
+            let line = line?;
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            // Keep the raw field of formats that carry one (csv, json)
+            let record = match parser.try_parse(line) {
+                Some(mut record) => {
+                    if record.raw.is_empty() {
+                        record.raw = line.to_owned();
+                    }
+                    record
+                }
+                None => {
+                    stats.unparsed += 1;
+                    Record {
+                        message: line.to_owned(),
+                        raw: line.to_owned(),
+                        ..Default::default()
+                    }
+                }
+            };
+
+            let formatted = format.fmt_record(&record)?;
+            if verify {
+                let verified =
+                    parse_format(&mut verify_parser, format, &formatted).map_err(|e| {
+                        RogcatError::Parse(format!(
+                            "{}:{}: Record cannot be read back from {} format: {}",
+                            input,
+                            n + 1,
+                            format,
+                            e
+                        ))
+                    })?;
+                if let Some(field) = record_diff(&record, &verified) {
+                    return Err(RogcatError::Parse(format!(
+                        "{}:{}: Field {} cannot be represented in {} format",
+                        input,
+                        n + 1,
+                        field,
+                        format
+                    ))
+                    .into());
+                }
+                stats.verified += 1;
+            }
+
+            writer.write_all(formatted.as_bytes())?;
+            writer.write_all(b"\n")?;
+            stats.records += 1;
+        }
+        stats.files += 1;
+    }
+
+    writer.flush()?;
+    Ok(stats)
+}
+
+/// Convert input files into a single output file with optional round trip verification
+pub fn convert(args: &ArgMatches) {
+    let start = Instant::now();
+    match convert_files(args) {
+        Ok(stats) => {
+            eprintln!(
+                "Converted {} records ({} unparsed, {} verified) from {} files in {:.2}s",
+                stats.records,
+                stats.unparsed,
+                stats.verified,
+                stats.files,
+                start.elapsed().as_secs_f64()
+            );
+            exit(0);
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+fn stats_report(args: &ArgMatches) -> Result<Report, Error> {
+    let bucket = args
+        .value_of("bucket")
+        .map(utils::parse_duration)
+        .transpose()?
+        .map(|d| d.as_secs())
+        .unwrap_or(60);
+    let mut parser = pipeline::parser(args, &profiles::from_args(args)?)?;
+    let mut report = Report::new(bucket);
+
+    for input in args.values_of("input").unwrap_or_default() {
+        let file = File::open(input)
+            .map_err(|e| RogcatError::Io(format!("Failed to open {}: {}", input, e)))?;
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line?;
+            let line = String::from_utf8_lossy(&line);
+            match parser.try_parse(line.trim_end_matches(['\r', '\n'])) {
+                Some(record) => report.add(&record),
+                None => report.add_unparsed(),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Print counts per level, tag and process, the rate over time and the most frequent messages
+pub fn stats(args: &ArgMatches) {
+    let top = value_t!(args, "top", usize).unwrap_or_else(|e| e.exit());
+    match stats_report(args) {
+        Ok(report) => match report.write(&mut io::stdout().lock(), top) {
+            Err(ref e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                eprintln!("Failed to write report: {e}");
+                exit(error::EXIT_IO);
+            }
+            _ => exit(0),
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+/// Write the raw lines of `crash` to a numbered file in `dir`
+fn write_crash(dir: &Path, n: usize, crash: &Crash) -> Result<PathBuf, Error> {
+    let filename = dir.join(format!("{:03}-{}.log", n, crash.kind.name()));
+    let mut file = File::create(&filename)
+        .map(BufWriter::new)
+        .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", filename.display(), e)))?;
+    for record in &crash.records {
+        writeln!(file, "{}", record.raw)?;
+    }
+    file.flush()?;
+    Ok(filename)
+}
+
+fn crash_files(args: &ArgMatches) -> Result<usize, Error> {
+    let profile = profiles::from_args(args)?;
+    let mut parser = pipeline::parser(args, &profile)?;
+    let mut human = Human::from(args, &profile, Format::Human);
+    let symbolizer = Symbolizer::from_args(args)?;
+    let dir = args.value_of("output-dir").map(PathBuf::from);
+    if let Some(ref dir) = dir {
+        DirBuilder::new()
+            .recursive(true)
+            .create(dir)
+            .map_err(|e| RogcatError::Io(format!("Failed to create {}: {}", dir.display(), e)))?;
+    }
+
+    let mut detector = Detector::default();
+    let mut count = 0;
+    let mut report = |crash: Crash| -> Result<(), Error> {
+        count += 1;
+        if let Some(ref dir) = dir {
+            write_crash(dir, count, &crash)?;
+        }
+        let title = format!("{}: {}", crash.kind, crash.title());
+        human.print_framed(&title, crash.records)
+    };
+
+    for input in args.values_of("input").unwrap_or_default() {
+        let file = File::open(input)
+            .map_err(|e| RogcatError::Io(format!("Failed to open {}: {}", input, e)))?;
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line?;
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some(mut record) = parser.try_parse(line) {
+                record.raw = line.to_owned();
+                if let Some(ref symbolizer) = symbolizer {
+                    record = symbolizer.apply(record);
+                }
+                for crash in detector.add(record) {
+                    report(crash)?;
+                }
+            }
+        }
+    }
+    for crash in detector.finish() {
+        report(crash)?;
+    }
+    Ok(count)
+}
+
+/// Print Java exceptions, native crashes and ANRs of log files and optionally
+/// write each of them to a file
+pub fn crashes(args: &ArgMatches) {
+    match crash_files(args) {
+        Ok(count) => {
+            if let Some(dir) = args.value_of("output-dir") {
+                eprintln!("Wrote {count} crashes to {dir}");
+            } else if count == 0 {
+                eprintln!("No crashes found");
+            }
+            exit(0)
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+/// Print, save or diff the system properties of a device
+pub fn props(args: &ArgMatches) {
+    match props::run(args) {
+        Ok(()) => exit(0),
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+lazy_static! {
+    /// Start of a capture written with `--filename-format date` e.g `2024-03-01-10_30_00-001_trace.log`
+    static ref CAPTURE_DATE: Regex =
+        Regex::new(r"^(\d{4}-\d{2}-\d{2})-(\d{2})_(\d{2})_(\d{2})(-\d{3})?_").unwrap();
+}
+
+/// Start of a dated capture in the form of `utils::parse_capture_date`
+fn capture_date(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let captures = CAPTURE_DATE.captures(name)?;
+    Some(format!(
+        "{} {}:{}:{}",
+        &captures[1], &captures[2], &captures[3], &captures[4]
+    ))
+}
+
+/// Files below `dir` in capture order. Dated captures end where the next one of the same
+/// directory starts and are skipped if they are outside of `since` and `until`.
+fn capture_files(
+    dir: &Path,
+    since: Option<&str>,
+    until: Option<&str>,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .and_then(|entries| {
+                entries
+                    .map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| RogcatError::Io(format!("Failed to read {}: {}", dir.display(), e)))?;
+        entries.sort();
+
+        let dates = entries
+            .iter()
+            .filter_map(|e| capture_date(e))
+            .collect::<Vec<_>>();
+        let mut subdirs = vec![];
+        for entry in entries {
+            if entry.is_dir() {
+                subdirs.push(entry);
+                continue;
+            }
+            if let Some(date) = capture_date(&entry) {
+                let end = dates.iter().find(|d| **d > date);
+                let ended = since.is_some_and(|since| end.is_some_and(|end| end.as_str() <= since));
+                let started = until.is_some_and(|until| date.as_str() > until);
+                if ended || started {
+                    continue;
+                }
+            }
+            files.push(entry);
+        }
+        dirs.extend(subdirs.into_iter().rev());
+    }
+    Ok(files)
+}
+
+/// Records of `file` that pass `filter` with the file name and line number in the file column
+fn grep_file(
+    args: &ArgMatches,
+    profile: &Profile,
+    filter: &Filter,
+    dir: &Path,
+    file: &Path,
+) -> Result<Vec<Record>, Error> {
+    let mut parser = pipeline::parser(args, profile)?;
+    let name = file.strip_prefix(dir).unwrap_or(file).display().to_string();
+    let reader = File::open(file)
+        .map(BufReader::new)
+        .map_err(|e| RogcatError::Io(format!("Failed to open {}: {}", file.display(), e)))?;
+
+    let mut matches = vec![];
+    for (n, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        let mut record = parser.try_parse(line).unwrap_or_else(|| Record {
+            message: line.to_owned(),
+            ..Default::default()
+        });
+        if filter.matches(&record) {
+            record.raw = line.to_owned();
+            record.source = Some(format!("{}:{}", name, n + 1));
+            matches.push(record);
+        }
+    }
+    Ok(matches)
+}
+
+/// Search the captures in parallel and print the matches in capture order
+fn grep_files(args: &ArgMatches) -> Result<usize, Error> {
+    let dir = PathBuf::from(args.value_of("dir").unwrap_or("."));
+    let since = args
+        .value_of("captures-since")
+        .map(|d| utils::parse_capture_date(d, false))
+        .transpose()?;
+    let until = args
+        .value_of("captures-until")
+        .map(|d| utils::parse_capture_date(d, true))
+        .transpose()?;
+    let files = capture_files(&dir, since.as_deref(), until.as_deref())?;
+
+    let profile = profiles::from_args(args)?;
+    let filter = filter::from_args_profile(args, &profile)?;
+    let mut human = Human::from(args, &profile, Format::Human);
+    let jobs = value_t!(args, "jobs", usize)
+        .ok()
+        .or_else(|| thread::available_parallelism().ok().map(Into::into))
+        .unwrap_or(1);
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..min(jobs, files.len()) {
+            let tx = tx.clone();
+            let (next, files, profile, filter, dir) = (&next, &files, &profile, &filter, &dir);
+            scope.spawn(move || {
+                while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let matches = grep_file(args, profile, filter, dir, file);
+                    if tx.send((file, matches)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Files finish out of order. Keep the results until the preceding files are printed.
+        let mut done = HashMap::new();
+        let mut printed = 0;
+        let mut count = 0;
+        for (file, matches) in rx {
+            done.insert(file, matches);
+            while let Some(matches) = files.get(printed).and_then(|f| done.remove(f)) {
+                printed += 1;
+                for record in matches? {
+                    human.print(record)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    })
+}
+
+/// Search rotated captures
+pub fn grep(args: &ArgMatches) {
+    match grep_files(args) {
+        Ok(0) => exit(1),
+        Ok(_) => exit(0),
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
     }
 }
 
@@ -120,24 +638,53 @@ fn report_filename() -> Result<String, Error> {
     Ok(format!("{}-bugreport.txt", strftime(&format, &now())?))
 }
 
+/// Print where the sections of a report were written
+fn extracted(sections: &[Section], dir: &str) {
+    eprintln!(
+        "Extracted {} sections to {}. See {}",
+        sections.len(),
+        dir,
+        Path::new(dir).join(bugreport::INDEX).display()
+    );
+}
+
 /// Performs a dumpstate and write to fs. Note: The Android 7+ dumpstate is not supported.
 pub fn bugreport(args: &ArgMatches) {
+    let extract = args.value_of("extract");
+    if let (Some(input), Some(dir)) = (args.value_of("input"), extract) {
+        match bugreport::extract(Path::new(input), Path::new(dir)) {
+            Ok(sections) => {
+                extracted(&sections, dir);
+                exit(0)
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                exit(error::exit_code(&e))
+            }
+        }
+    }
+
     let filename = value_t!(args.value_of("file"), String)
         .unwrap_or_else(|_| report_filename().expect("Failed to generate filename"));
     let filename_path = PathBuf::from(&filename);
     if !args.is_present("overwrite") && filename_path.exists() {
         eprintln!("File {filename} already exists");
-        exit(1);
+        exit(error::EXIT_CONFIG);
     }
-    let mut adb = adb().expect("Failed to find adb");
+    let mut cmd = adb().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(error::EXIT_DEVICE)
+    });
 
-    if args.is_present("dev") {
-        let device = value_t!(args, "dev", String).unwrap_or_else(|e| e.exit());
-        adb.push::<String>("-s".into());
-        adb.push(device);
+    if let Err(e) = select_device(args) {
+        eprintln!("{e}");
+        exit(error::exit_code(&e));
+    }
+    if let Some(device) = selected_device(args) {
+        cmd.arg("-s").arg(device);
     }
 
-    let mut child = Command::new(adb)
+    let mut child = cmd
         .arg("bugreport")
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -153,11 +700,10 @@ pub fn bugreport(args: &ArgMatches) {
             .expect("Failed to create outfile parent directory");
     }
 
-    let progress = ProgressBar::new(u64::MAX);
-    progress.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}")
-            .progress_chars(" • "),
+    let progress = Progress::new(
+        None,
+        "{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}",
+        " • ",
     );
     progress.set_message("Connecting");
 
@@ -167,6 +713,13 @@ pub fn bugreport(args: &ArgMatches) {
         Box::new(File::create(&filename).expect("Failed to craete file")) as Box<dyn Write>
     };
 
+    let mut extractor = extract.map(|dir| {
+        Extractor::new(Path::new(dir)).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(error::exit_code(&e))
+        })
+    });
+
     progress.set_message("Pulling bugreport line");
 
     // TODO: Migrate to tokio::fs::File
@@ -174,55 +727,71 @@ pub fn bugreport(args: &ArgMatches) {
         .for_each(|l| {
             write.write_all(l.as_bytes()).expect("Failed to write");
             write.write_all(b"\n").expect("Failed to write");
+            if let Some(ref mut extractor) = extractor {
+                extractor.line(&l).expect("Failed to write section");
+            }
             progress.inc(1);
             ok(())
         })
         .then(|r| {
-            progress.set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
             progress.finish_with_message(&format!("Finished {}.", filename_path.display()));
             r
         })
         .map_err(|e| {
             eprintln!("Failed to create bugreport: {e}");
-            exit(1);
+            exit(error::EXIT_DEVICE);
         });
 
     tokio::runtime::current_thread::block_on_all(output).expect("Runtime error");
+    if let (Some(extractor), Some(dir)) = (extractor, extract) {
+        match extractor.finish() {
+            Ok(sections) => extracted(&sections, dir),
+            Err(e) => {
+                eprintln!("{e}");
+                exit(error::exit_code(&e))
+            }
+        }
+    }
     exit(0);
 }
 
 pub fn devices() {
-    let mut child = Command::new(adb().expect("Failed to find adb"))
-        .arg("devices")
-        .stdout(Stdio::piped())
-        .spawn_async()
-        .expect("Failed to run adb devices");
-    let reader = BufReader::new(child.stdout().take().unwrap());
-    let result = lines(reader)
-        .skip(1)
-        .filter(|l| !l.is_empty())
-        .filter(|l| !l.starts_with("* daemon"))
-        .for_each(|l| {
-            let mut s = l.split_whitespace();
-            let id: &str = s.next().unwrap_or("unknown");
-            let name: &str = s.next().unwrap_or("unknown");
-            println!("{id} {name}");
-            Ok(())
-        });
+    // The device itself
+    if utils::on_device() {
+        let serial = utils::device_shell(None, false, ["getprop", "ro.serialno"])
+            .and_then(|mut cmd| Ok(cmd.output()?))
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+            .ok()
+            .filter(|serial| !serial.is_empty())
+            .unwrap_or_else(|| "localhost".to_owned());
+        println!("{serial} device");
+        exit(0)
+    }
+    match utils::devices() {
+        Ok(devices) => {
+            for device in devices {
+                println!("{} {}", device.serial, device.state);
+            }
+            exit(0)
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::EXIT_DEVICE)
+        }
+    }
+}
 
-    tokio::run(
-        result
-            .map_err(|e| {
-                eprintln!("Failed to run adb devices: {e}");
-                exit(1)
-            })
-            .map(|_| exit(0)),
-    );
+/// Quote `s` for the device shell that interprets the arguments of adb shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
 }
 
 struct Logger {
     tag: String,
     level: Level,
+    buffer: Option<String>,
+    /// Encoded key value pairs appended to every message
+    fields: String,
 }
 
 impl Logger {
@@ -235,6 +804,43 @@ impl Logger {
             Level::Error | Level::Fatal | Level::Assert => "e",
         }
     }
+
+    /// Encode `key=value` arguments as ` key=value key2="value with spaces"`
+    fn encode_fields<'a, I: Iterator<Item = &'a str>>(fields: I) -> Result<String, Error> {
+        let mut encoded = String::new();
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+                .ok_or_else(|| RogcatError::Config(format!("Invalid key value pair: {}", field)))?;
+            encoded.push(' ');
+            encoded.push_str(key);
+            encoded.push('=');
+            if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+                encoded.push_str(&format!("{value:?}"));
+            } else {
+                encoded.push_str(value);
+            }
+        }
+        Ok(encoded)
+    }
+
+    fn command(&self, message: &str) -> Result<Command, Error> {
+        let mut args = vec!["log".to_owned()];
+        if let Some(ref buffer) = self.buffer {
+            args.extend(["-b".to_owned(), buffer.clone()]);
+        }
+        args.extend([
+            "-p".to_owned(),
+            Self::level(&self.level).to_owned(),
+            "-t".to_owned(),
+            shell_quote(&self.tag),
+            shell_quote(&format!("{}{}", message, self.fields)),
+        ]);
+        let mut command = device_shell(None, false, args)?;
+        command.stdout(Stdio::piped());
+        Ok(command)
+    }
 }
 
 impl Sink for Logger {
@@ -242,15 +848,8 @@ impl Sink for Logger {
     type SinkError = Error;
 
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        let child = Command::new(adb()?)
-            .arg("shell")
-            .arg("log")
-            .arg("-p")
-            .arg(Self::level(&self.level))
-            .arg("-t")
-            .arg(format!("\"{}\"", &self.tag))
-            .arg(&item)
-            .stdout(Stdio::piped())
+        let child = self
+            .command(&item)?
             .output_async()
             .map(|_| ())
             .map_err(|_| ());
@@ -268,29 +867,34 @@ pub fn log(args: &ArgMatches) {
     let message = args.value_of("MESSAGE").unwrap_or("");
     let tag = args.value_of("tag").unwrap_or("Rogcat").to_owned();
     let level = Level::from(args.value_of("level").unwrap_or(""));
+    let buffer = args.value_of("buffer").map(ToOwned::to_owned);
+    let fields =
+        Logger::encode_fields(args.values_of("kv").unwrap_or_default()).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(error::exit_code(&e))
+        });
+    let logger = Logger {
+        tag,
+        level,
+        buffer,
+        fields,
+    };
     match message {
         "-" => {
-            let sink = Logger { tag, level };
-            let stream = stdin()
+            let stream = stdin(Framing::Newline)
                 .map(|d| match d {
                     StreamData::Line(l) => l,
                     _ => panic!("Received non line item during log"),
                 })
-                .forward(sink)
+                .forward(logger)
                 .map(|_| ())
                 .map_err(|_| ());
             tokio::run(stream);
         }
         _ => {
-            let child = Command::new(adb().expect("Failed to find adb"))
-                .arg("shell")
-                .arg("log")
-                .arg("-p")
-                .arg(Logger::level(&level))
-                .arg("-t")
-                .arg(&tag)
-                .arg(format!("\"{message}\""))
-                .stdout(Stdio::piped())
+            let child = logger
+                .command(message)
+                .expect("Failed to find adb")
                 .output_async()
                 .map(|_| ())
                 .map_err(|_| ());
@@ -301,6 +905,48 @@ pub fn log(args: &ArgMatches) {
     exit(0);
 }
 
+/// Run the full screen terminal ui
+pub fn tui(args: &ArgMatches) {
+    match crate::tui::run(args) {
+        Ok(_) => exit(0),
+        Err(e) => {
+            eprintln!("{e}");
+            exit(error::exit_code(&e));
+        }
+    }
+}
+
+/// Page a colored human output file with $PAGER or `less -R`. The file is copied
+/// to stdout if stdout is not a terminal.
+pub fn view(args: &ArgMatches) {
+    let file = value_t!(args, "FILE", String).unwrap_or_else(|e| e.exit());
+
+    let result = if atty::is(atty::Stream::Stdout) {
+        let pager = env::var("PAGER").unwrap_or_else(|_| "less -R".into());
+        let mut pager = pager.split_whitespace();
+        let cmd = pager.next().unwrap_or("less");
+        Command::new(cmd)
+            .args(pager)
+            .arg(&file)
+            .status()
+            .map_err(|e| RogcatError::Io(format!("Failed to run {}: {}", cmd, e)))
+            .map(|_| ())
+    } else {
+        File::open(&file)
+            .and_then(|mut f| io::copy(&mut f, &mut io::stdout()))
+            .map_err(|e| RogcatError::Io(format!("Failed to read {}: {}", file, e)))
+            .map(|_| ())
+    };
+
+    match result {
+        Ok(_) => exit(0),
+        Err(e) => {
+            eprintln!("{e}");
+            exit(e.exit_code());
+        }
+    }
+}
+
 /// Call adb logcat -c -b BUFFERS
 pub fn clear(args: &ArgMatches) {
     let buffer = args
@@ -309,8 +955,17 @@ pub fn clear(args: &ArgMatches) {
         .or_else(|| utils::config_get("buffer"))
         .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
         .join(" -b ");
-    let child = Command::new(adb().expect("Failed to find adb"))
-        .arg("logcat")
+    let mut cmd = if utils::on_device() {
+        Command::new(utils::logcat())
+    } else {
+        let mut cmd = adb().unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(error::EXIT_DEVICE)
+        });
+        cmd.arg("logcat");
+        cmd
+    };
+    let child = cmd
         .arg("-c")
         .arg("-b")
         .args(buffer.split(' '))
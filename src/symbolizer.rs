@@ -0,0 +1,151 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Symbolize the backtraces of native crashes with `llvm-symbolizer` or `addr2line`
+
+use crate::error::RogcatError;
+use clap::ArgMatches;
+use failure::Error;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::Record;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+use which::which_in;
+
+lazy_static! {
+    /// Backtrace frame of a tombstone e.g `#00 pc 000000000004f4b4  /system/lib64/libc.so (abort+164)`
+    static ref FRAME: Regex = Regex::new(r"#\d+ pc ([0-9a-fA-F]+)\s+(/\S+)").unwrap();
+}
+
+/// Maps backtrace addresses to function and source location with the unstripped
+/// libraries of a symbols directory
+pub struct Symbolizer {
+    dir: PathBuf,
+    tool: PathBuf,
+    /// Symbolized frames by library and address. None if the lookup failed.
+    cache: Mutex<HashMap<(PathBuf, String), Option<String>>>,
+}
+
+impl Symbolizer {
+    /// Create a symbolizer if `--symbols-dir` is passed. `llvm-symbolizer` is preferred
+    /// over `addr2line` unless `--symbolizer` selects a tool.
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Symbolizer>, Error> {
+        let dir = match args.value_of("symbols-dir") {
+            Some(dir) => PathBuf::from(dir),
+            None => return Ok(None),
+        };
+        if !dir.is_dir() {
+            return Err(
+                RogcatError::Config(format!("Cannot find symbols dir {}", dir.display())).into(),
+            );
+        }
+
+        let cwd = env::current_dir()?;
+        let find = |tool| which_in(tool, env::var_os("PATH"), &cwd);
+        let tool = match args.value_of("symbolizer") {
+            Some(tool) => find(tool),
+            None => find("llvm-symbolizer").or_else(|_| find("addr2line")),
+        }
+        .map_err(|e| {
+            RogcatError::Config(format!("Cannot find llvm-symbolizer or addr2line: {}", e))
+        })?;
+
+        Ok(Some(Symbolizer {
+            dir,
+            tool,
+            cache: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Unstripped library for `path` on the device. The directory layout of the device
+    /// is tried first and the file name second.
+    fn library(&self, path: &str) -> Option<PathBuf> {
+        let mirrored = self.dir.join(path.trim_start_matches('/'));
+        if mirrored.is_file() {
+            return Some(mirrored);
+        }
+        let flat = self.dir.join(Path::new(path).file_name()?);
+        Some(flat).filter(|f| f.is_file())
+    }
+
+    /// Run the symbolizer for `address` in `library`
+    fn lookup(&self, library: &Path, address: &str) -> Option<String> {
+        let address = format!("0x{:x}", u64::from_str_radix(address, 16).ok()?);
+        let mut cmd = Command::new(&self.tool);
+        if self.tool.file_stem().is_some_and(|t| t == "addr2line") {
+            cmd.args(["-C", "-f", "-e"]).arg(library).arg(&address);
+        } else {
+            cmd.args(["-C", "--inlining=false"])
+                .arg(format!("--obj={}", library.display()))
+                .arg(&address);
+        }
+        let output = cmd
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        let output = String::from_utf8_lossy(&output.stdout);
+        let mut lines = output.lines();
+        let function = lines.next().filter(|f| !f.starts_with("??"))?;
+        match lines.next().filter(|l| !l.starts_with("??")) {
+            Some(location) => Some(format!("{function} ({location})")),
+            None => Some(function.to_owned()),
+        }
+    }
+
+    /// Replace the address of a backtrace frame in the message and raw line of `record`
+    pub fn apply(&self, mut record: Record) -> Record {
+        let (range, address, path) = match FRAME.captures(&record.message) {
+            Some(captures) => (
+                captures.get(1).unwrap().range(),
+                captures[1].to_owned(),
+                captures[2].to_owned(),
+            ),
+            None => return record,
+        };
+        let library = match self.library(&path) {
+            Some(library) => library,
+            None => return record,
+        };
+
+        let symbol = self
+            .cache
+            .lock()
+            .expect("Failed to get symbol cache lock")
+            .entry((library.clone(), address.clone()))
+            .or_insert_with(|| self.lookup(&library, &address))
+            .clone();
+        if let Some(symbol) = symbol {
+            let mut message = record.message.clone();
+            message.replace_range(range, &symbol);
+            if let Some(header) = record.raw.strip_suffix(record.message.as_str()) {
+                record.raw = format!("{header}{message}");
+            }
+            record.message = message;
+        }
+        record
+    }
+}
@@ -19,26 +19,60 @@
 // SOFTWARE.
 
 use crate::{
-    profiles::Profile,
-    utils::{config_get, terminal_width},
+    console,
+    error::RogcatError,
+    interactive::Interactive,
+    internal, pipeline,
+    profiles::{self, Profile},
+    utils::{self, config_get, format_options, parse_duration, terminal_width},
     LogSink,
 };
 use clap::{values_t, ArgMatches};
-use failure::{err_msg, format_err, Error};
+use failure::{err_msg, format_err, Error, Fail};
 use futures::{Async, AsyncSink, Poll, Sink, StartSend};
-use itertools::intersperse;
 use regex::Regex;
-use rogcat::record::{Format, Level, Record};
+use rogcat::record::{Format, Level, Record, Timestamp};
 use std::{
     cmp::{max, min},
+    collections::HashMap,
     convert::Into,
-    io::{stdout, BufWriter, Write},
+    fmt::{self, Display, Formatter},
+    fs::read_to_string,
+    io::{self, stdout, BufWriter, Write},
+    ops::Range,
     str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const DIMM_COLOR: Color = Color::Ansi256(243);
 
+/// Number of buffered records that indicate that the terminal falls behind
+const CATCH_UP_THRESHOLD: usize = 1000;
+
+/// Minimum interval between two catch up status lines
+const CATCH_UP_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size of the rendered records that are printed at once
+const PRINT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Interval of terminal width checks
+const TERMINAL_WIDTH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The reader of stdout went away e.g `rogcat | head`
+#[derive(Debug)]
+pub struct BrokenPipe;
+
+impl Display for BrokenPipe {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Broken pipe")
+    }
+}
+
+impl Fail for BrokenPipe {}
+
 /// Construct a terminal sink for format from args with give profile
 pub fn try_from(args: &ArgMatches<'_>, profile: &Profile) -> Result<LogSink, Error> {
     let format = args
@@ -46,100 +80,308 @@ pub fn try_from(args: &ArgMatches<'_>, profile: &Profile) -> Result<LogSink, Err
         .ok_or_else(|| format_err!("Missing format argument"))
         .and_then(|f| Format::from_str(f).map_err(err_msg))
         .unwrap_or(Format::Human);
+    let format = format_options(format, args)?;
 
     if format == Format::Html {
-        return Err(format_err!("HTML format is only valid for file output"));
+        return Err(
+            RogcatError::Config("HTML format is only valid for file output".to_owned()).into(),
+        );
     }
 
     let sink = match format {
-        Format::Human => Box::new(Human::from(args, profile, format)) as LogSink,
-        format => Box::new(FormatSink::new(format, stdout())) as LogSink,
+        Format::Human => {
+            let mut human = Human::from(args, profile, format);
+            // Key handling and status lines need a terminal. Pipes and CI logs get plain output.
+            if args.is_present("interactive") {
+                if atty::is(atty::Stream::Stdout) {
+                    human.interactive = Some(Interactive::new()?);
+                } else {
+                    eprintln!("Interactive mode disabled: stdout is not a terminal");
+                }
+            }
+            Box::new(human) as LogSink
+        }
+        Format::Passthrough => Box::new(Passthrough::from(args, profile)) as LogSink,
+        format => Box::new(FormatSink::new(format, stdout(), Delimiting::from(args))?) as LogSink,
     };
 
-    Ok(Box::new(sink.sink_map_err(|e| {
-        failure::format_err!("Terminal error: {}", e)
-    })))
+    Ok(Box::new(sink.sink_map_err(
+        |e| match e.downcast::<io::Error>() {
+            Ok(ref e) if e.kind() == io::ErrorKind::BrokenPipe => BrokenPipe.into(),
+            Ok(e) => RogcatError::Io(format!("Terminal error: {}", e)).into(),
+            Err(e) => RogcatError::Io(format!("Terminal error: {}", e)).into(),
+        },
+    )))
+}
+
+/// Record delimiting options for viewer pipelines like fzf or xargs
+#[derive(Clone, Copy)]
+struct Delimiting {
+    /// Terminate records with NUL instead of newline
+    null: bool,
+    /// Escape newlines within records
+    no_multiline: bool,
+}
+
+impl Delimiting {
+    fn from(args: &ArgMatches<'_>) -> Delimiting {
+        Delimiting {
+            null: args.is_present("null"),
+            no_multiline: args.is_present("no-multiline"),
+        }
+    }
+
+    fn terminator(self) -> &'static [u8] {
+        if self.null {
+            b"\0"
+        } else {
+            b"\n"
+        }
+    }
+
+    fn escape(self, s: String) -> String {
+        if self.no_multiline && s.contains(['\n', '\r']) {
+            s.replace('\r', "\\r").replace('\n', "\\n")
+        } else {
+            s
+        }
+    }
+}
+
+/// Read a toml file that maps tags, processes or threads to colors e.g `Bluetooth = "cyan"`
+/// or `"1234" = "208"`
+fn color_map(file: &str) -> Result<HashMap<String, Color>, Error> {
+    let map = read_to_string(file)
+        .map_err(|e| RogcatError::Config(format!("Failed to read color map {}: {}", file, e)))?;
+    toml::from_str::<HashMap<String, String>>(&map)
+        .map_err(|e| RogcatError::Config(format!("Failed to parse color map {}: {}", file, e)))?
+        .into_iter()
+        .map(|(name, color)| {
+            Color::from_str(&color)
+                .map(|color| (name, color))
+                .map_err(|e| {
+                    RogcatError::Config(format!("Invalid color in {}: {}", file, e)).into()
+                })
+        })
+        .collect()
+}
+
+/// Highlight pattern. Styled patterns paint the matching text of tags and messages,
+/// plain ones color the timestamp of matching records.
+#[derive(Clone)]
+struct Highlight {
+    regex: Regex,
+    style: Option<ColorSpec>,
+}
+
+/// Highlight patterns of `profile` and the command line. Invalid patterns and colors are ignored.
+fn highlight(profile: &Profile, args: &[String]) -> Vec<Highlight> {
+    let args = args
+        .iter()
+        .map(|a| profiles::Highlight::pattern(a))
+        .collect::<Vec<_>>();
+    profile
+        .highlight
+        .iter()
+        .chain(&args)
+        .flat_map(|h| {
+            let regex = Regex::new(&h.pattern).ok()?;
+            let style = if h.is_styled() {
+                let mut spec = ColorSpec::new();
+                spec.set_fg(h.fg.as_ref().and_then(|c| c.parse().ok()))
+                    .set_bg(h.bg.as_ref().and_then(|c| c.parse().ok()))
+                    .set_bold(h.bold)
+                    .set_underline(h.underline);
+                Some(spec)
+            } else {
+                None
+            };
+            Some(Highlight { regex, style })
+        })
+        .collect()
+}
+
+/// Highlight patterns passed with `--highlight`
+fn highlight_args(args: &ArgMatches<'_>) -> Vec<String> {
+    if args.is_present("highlight") {
+        values_t!(args.values_of("highlight"), String).unwrap()
+    } else {
+        vec![]
+    }
+}
+
+/// Terminal color choice of `--color` or the configuration for output to `stream`
+fn color_choice(args: &ArgMatches<'_>, stream: atty::Stream) -> ColorChoice {
+    match args
+        .value_of("color")
+        .unwrap_or_else(|| config_get("terminal_color").unwrap_or("auto"))
+    {
+        "always" => ColorChoice::Always,
+        "never" => ColorChoice::Never,
+        // Pipes, files and legacy consoles without escape code support get unstyled output
+        _ => {
+            if atty::is(stream) && console::ansi() {
+                ColorChoice::Auto
+            } else {
+                ColorChoice::Never
+            }
+        }
+    }
+}
+
+/// Group names of the tags in the groups of `profile`
+fn tag_groups(profile: &Profile) -> HashMap<String, String> {
+    profile
+        .tag_groups
+        .iter()
+        .flat_map(|(name, tags)| tags.iter().map(move |tag| (tag.clone(), name.clone())))
+        .collect()
 }
 
 /// Human readable terminal output
-struct Human {
-    writer: BufferWriter,
-    date_format: Option<(&'static str, usize)>,
-    highlight: Vec<Regex>,
+#[derive(Clone)]
+pub struct Human {
+    /// Terminal writer. None if the output is rendered into buffers e.g for files.
+    writer: Option<Arc<BufferWriter>>,
+    /// Rendered records that are not yet printed
+    pending: Pending,
+    show_date: bool,
+    show_time: bool,
+    highlight: Vec<Highlight>,
+    /// Highlight patterns given on the command line
+    highlight_args: Vec<String>,
+    /// Generation of the last applied profile reload
+    profile_generation: usize,
+    /// Group names shown instead of the tags of the group
+    tag_groups: Arc<HashMap<String, String>>,
     process_width: usize,
     process_width_max: usize,
     tag_width: Option<usize>,
     thread_width: usize,
     thread_width_max: usize,
+    /// Width of the source column
+    source_width: usize,
+    /// Show the logcat buffer next to the source
+    show_buffer: bool,
     dimm_color: Option<Color>,
     bright_colors: bool,
+    delimiting: Delimiting,
+    /// Wrap messages at the terminal width
+    wrap: bool,
+    /// Mark messages that exceed the terminal width instead of wrapping them
+    wide: bool,
+    /// Print a separator if the gap between two records exceeds this
+    gap_indicator: Option<time::Duration>,
+    last_timestamp: Option<Timestamp>,
+    /// Render without colors and wrapping while catching up
+    catch_up_condensed: bool,
+    /// Time of the last catch up status while behind
+    catch_up_status: Option<Instant>,
+    /// Catch up status lines go to stderr to keep piped output clean
+    status_writer: Arc<BufferWriter>,
+    /// Keyboard controlled pause and scroll back
+    interactive: Option<Interactive>,
+    /// Seed of the hashed colors of tags, processes and threads
+    color_seed: u8,
+    /// Fixed colors of tags, processes and threads
+    color_map: Arc<HashMap<String, Color>>,
+    /// Last terminal width and the time it was checked
+    terminal_width: Option<(Option<usize>, Instant)>,
+}
+
+/// Reusable buffer of rendered records. Clones start with an empty buffer.
+#[derive(Default)]
+struct Pending(Option<Buffer>);
+
+impl Clone for Pending {
+    fn clone(&self) -> Pending {
+        Pending(None)
+    }
 }
 
 impl Human {
     pub fn from(args: &ArgMatches<'_>, profile: &Profile, _: Format) -> Human {
-        let mut hl = profile.highlight.clone();
-        if args.is_present("highlight") {
-            hl.extend(values_t!(args.values_of("highlight"), String).unwrap());
-        }
-        let highlight = hl.iter().flat_map(|h| Regex::new(h)).collect();
-
-        let color = {
-            match args
-                .value_of("color")
-                .unwrap_or_else(|| config_get("terminal_color").unwrap_or("auto"))
-            {
-                "always" => ColorChoice::Always,
-                "never" => ColorChoice::Never,
-                "auto" => {
-                    if atty::is(atty::Stream::Stdout) {
-                        ColorChoice::Auto
-                    } else {
-                        ColorChoice::Never
-                    }
-                }
-                _ => ColorChoice::Auto,
-            }
-        };
+        let highlight_args = highlight_args(args);
+        let highlight = highlight(profile, &highlight_args);
         let no_dimm = args.is_present("no-dimm") || config_get("terminal_no_dimm").unwrap_or(false);
         let tag_width = config_get("terminal_tag_width");
         let hide_timestamp = args.is_present("hide-timestamp")
             || config_get("terminal_hide_timestamp").unwrap_or(false);
         let show_date =
             args.is_present("show-date") || config_get("terminal_show_date").unwrap_or(false);
-        let date_format = if show_date {
-            if hide_timestamp {
-                Some(("%m-%d", 5))
-            } else {
-                Some(("%m-%d %H:%M:%S.%f", 12 + 1 + 5))
-            }
-        } else if hide_timestamp {
-            None
-        } else {
-            Some(("%H:%M:%S.%f", 12))
-        };
+        let show_buffer =
+            args.is_present("show-buffer") || config_get("terminal_show_buffer").unwrap_or(false);
+        let wide = args.is_present("wide") || config_get("terminal_wide").unwrap_or(false);
 
         let bright_colors = args.is_present("bright_colors")
             || config_get("terminal_bright_colors").unwrap_or(false);
         let thread_width_max = max(1, config_get("terminal_thread_width_max").unwrap_or(16));
         let process_width_max = max(1, config_get("terminal_process_width_max").unwrap_or(16));
+        let color_map = config_get::<String>("terminal_color_map")
+            .map(|file| {
+                color_map(&file).unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    HashMap::new()
+                })
+            })
+            .unwrap_or_default();
 
         Human {
-            writer: BufferWriter::stdout(color),
+            writer: Some(Arc::new(BufferWriter::stdout(color_choice(
+                args,
+                atty::Stream::Stdout,
+            )))),
             dimm_color: if no_dimm { None } else { Some(DIMM_COLOR) },
             highlight,
-            date_format,
+            highlight_args,
+            profile_generation: 0,
+            tag_groups: Arc::new(tag_groups(profile)),
+            pending: Pending::default(),
+            show_date,
+            show_time: !hide_timestamp,
             tag_width,
             process_width: 0,
             process_width_max,
             thread_width: 0,
             thread_width_max,
+            source_width: 0,
+            show_buffer,
             bright_colors,
+            // Records printed on one line must not break at newlines
+            delimiting: Delimiting {
+                no_multiline: wide || args.is_present("no-multiline"),
+                ..Delimiting::from(args)
+            },
+            wrap: !wide,
+            wide,
+            gap_indicator: args
+                .value_of("gap-indicator")
+                .and_then(|d| parse_duration(d).ok())
+                .and_then(|d| time::Duration::from_std(d).ok()),
+            last_timestamp: None,
+            catch_up_condensed: args.is_present("catch-up-condensed"),
+            catch_up_status: None,
+            status_writer: Arc::new(BufferWriter::stderr(color_choice(
+                args,
+                atty::Stream::Stderr,
+            ))),
+            interactive: None,
+            color_seed: config_get("terminal_color_seed").unwrap_or(42),
+            color_map: Arc::new(color_map),
+            terminal_width: None,
         }
     }
 
+    /// Detach from the terminal in order to render into buffers only
+    pub fn detach(mut self) -> Human {
+        self.writer = None;
+        self.wrap = false;
+        self.wide = false;
+        self
+    }
+
     // Dynamic tag width estimation according to terminal width
-    fn tag_width(&self) -> usize {
-        let terminal_width = terminal_width();
+    fn tag_width(&self, terminal_width: Option<usize>) -> usize {
         self.tag_width.unwrap_or(match terminal_width {
             Some(n) if n <= 80 => 15,
             Some(n) if n <= 90 => 20,
@@ -149,9 +391,17 @@ impl Human {
         })
     }
 
+    /// Color of a tag, process or thread from the color map or its hash
+    fn color(&self, i: &str) -> Color {
+        match self.color_map.get(i.trim()) {
+            Some(color) => *color,
+            None => Self::hashed_color(i, self.color_seed),
+        }
+    }
+
     #[cfg(target_os = "windows")]
-    fn hashed_color(i: &str) -> Color {
-        let v = i.bytes().fold(42u8, |c, x| c ^ x) % 7;
+    fn hashed_color(i: &str, seed: u8) -> Color {
+        let v = i.bytes().fold(seed, |c, x| c ^ x) % 7;
         match v {
             0 => Color::Blue,
             1 => Color::Green,
@@ -164,10 +414,10 @@ impl Human {
     }
 
     #[cfg(not(target_os = "windows"))]
-    fn hashed_color(i: &str) -> Color {
+    fn hashed_color(i: &str, seed: u8) -> Color {
         // Some colors are hard to read on (at least) dark terminals
         // and I consider some others as ugly.
-        Color::Ansi256(match i.bytes().fold(42u8, |c, x| c ^ x) {
+        Color::Ansi256(match i.bytes().fold(seed, |c, x| c ^ x) {
             c @ 0..=1 => c + 2,
             c @ 16..=21 => c + 6,
             c @ 52..=55 | c @ 126..=129 => c + 4,
@@ -178,65 +428,217 @@ impl Human {
         })
     }
 
-    fn print(&mut self, mut record: Record) -> Result<(), Error> {
-        let timestamp = if let Some((format, len)) = self.date_format {
-            if let Some(ref ts) = record.timestamp {
-                let mut ts = time::strftime(format, ts).expect("Date format error");
-                ts.truncate(len);
-                ts
-            } else {
-                " ".repeat(len)
+    pub fn print(&mut self, record: Record) -> Result<(), Error> {
+        let writer = self
+            .writer
+            .clone()
+            .ok_or_else(|| err_msg("Human output is detached from the terminal"))?;
+
+        if let Some(interactive) = self.interactive.clone() {
+            // The record is kept to apply filters edited at runtime to the scroll back
+            let buffer = self.render(record.clone(), writer.buffer())?;
+            return interactive.print(record, buffer.into_inner());
+        }
+
+        let backlog = pipeline::backlog();
+        let behind = backlog > CATCH_UP_THRESHOLD;
+        self.catch_up(backlog)?;
+
+        let mut buffer = self.pending.0.take().unwrap_or_else(|| writer.buffer());
+        if behind && self.catch_up_condensed {
+            let wrap = self.wrap;
+            self.wrap = false;
+            let condensed = self.render(record, Buffer::no_color());
+            self.wrap = wrap;
+            buffer.write_all(condensed?.as_slice())?;
+        } else {
+            buffer = self.render(record, buffer)?;
+        }
+        self.pending.0 = Some(buffer);
+
+        if self.pending.0.as_ref().map_or(0, Buffer::len) >= PRINT_BUFFER_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Print the rendered records that are pending
+    fn flush(&mut self) -> Result<(), Error> {
+        if let (Some(writer), Some(buffer)) = (&self.writer, &mut self.pending.0) {
+            if !buffer.is_empty() {
+                writer.print(buffer)?;
+                buffer.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Print `records` within a frame titled `title` e.g a crash
+    pub fn print_framed(&mut self, title: &str, records: Vec<Record>) -> Result<(), Error> {
+        self.flush()?;
+        let writer = self
+            .writer
+            .clone()
+            .ok_or_else(|| err_msg("Human output is detached from the terminal"))?;
+        let mut frame = ColorSpec::new();
+        frame.set_fg(Some(Color::Red)).set_bold(true);
+
+        let mut buffer = writer.buffer();
+        buffer.set_color(&frame)?;
+        write!(buffer, "┌─ {title}")?;
+        buffer.reset()?;
+        buffer.write_all(b"\n")?;
+
+        // The frame takes two columns that are missing for wrapping
+        let wrap = self.wrap;
+        self.wrap = false;
+        for record in records {
+            let rendered = self.render(record, writer.buffer())?;
+            let rendered = rendered.as_slice();
+            let rendered = rendered.strip_suffix(b"\n").unwrap_or(rendered);
+            for line in rendered.split(|b| *b == b'\n') {
+                buffer.set_color(&frame)?;
+                buffer.write_all("│ ".as_bytes())?;
+                buffer.reset()?;
+                buffer.write_all(line)?;
+                buffer.write_all(b"\n")?;
+            }
+        }
+        self.wrap = wrap;
+
+        buffer.set_color(&frame)?;
+        buffer.write_all("└─".as_bytes())?;
+        buffer.reset()?;
+        buffer.write_all(b"\n")?;
+        writer.print(&buffer).map_err(Into::into)
+    }
+
+    /// Print a status line to stderr if the terminal falls behind the ingestion
+    fn catch_up(&mut self, backlog: usize) -> Result<(), Error> {
+        let message = if backlog > CATCH_UP_THRESHOLD {
+            match self.catch_up_status {
+                Some(last) if last.elapsed() < CATCH_UP_STATUS_INTERVAL => return Ok(()),
+                _ => {
+                    self.catch_up_status = Some(Instant::now());
+                    format!("catching up, {}k records buffered", backlog / 1000)
+                }
             }
+        } else if self.catch_up_status.take().is_some() {
+            "caught up".to_owned()
         } else {
-            String::new()
+            return Ok(());
         };
 
-        // Calculate colors before truncation
-        let process_color = Self::hashed_color(&record.process);
-        let thread_color = Self::hashed_color(&record.thread);
-
-        /// Truncate `s` to width characters, adding "…" if necessary
-        fn format_trim(s: &mut String, width: usize) {
-            let len = s.chars().count();
-            if len > width {
-                s.truncate(width);
-                s.pop();
-                s.push('…');
+        self.flush()?;
+        let mut buffer = self.status_writer.buffer();
+        buffer.set_color(ColorSpec::new().set_fg(self.dimm_color).set_italic(true))?;
+        buffer.write_all(message.as_bytes())?;
+        buffer.set_color(&ColorSpec::new())?;
+        buffer.write_all(b"\n")?;
+        self.status_writer.print(&buffer).map_err(Into::into)
+    }
+
+    /// Write a separator into `buffer` if the gap to the previous record exceeds the gap indicator
+    fn gap(&mut self, record: &Record, buffer: &mut Buffer) -> Result<(), Error> {
+        let threshold = match self.gap_indicator {
+            Some(threshold) if !internal::is_internal(record) => threshold,
+            _ => return Ok(()),
+        };
+        let timestamp = match record.timestamp {
+            Some(ref timestamp) if timestamp.is_valid() => timestamp.clone(),
+            _ => return Ok(()),
+        };
+
+        if let Some(last) = self.last_timestamp.replace(timestamp.clone()) {
+            let gap = timestamp.tm - last.tm;
+            if gap > threshold {
+                let label = format!(
+                    " +{}.{:03}s ",
+                    gap.num_seconds(),
+                    gap.num_milliseconds() % 1000
+                );
+                let width = terminal_width().unwrap_or(80).max(label.len() + 4);
+                buffer.set_color(ColorSpec::new().set_fg(self.dimm_color))?;
+                buffer.write_all("──".as_bytes())?;
+                buffer.write_all(label.as_bytes())?;
+                buffer.write_all("─".repeat(width - label.len() - 2).as_bytes())?;
+                buffer.set_color(&ColorSpec::new())?;
+                buffer.write_all(b"\n")?;
             }
+        }
+        Ok(())
+    }
+
+    /// Render `record` into `buffer`
+    pub fn render(&mut self, mut record: Record, mut buffer: Buffer) -> Result<Buffer, Error> {
+        if let Some(profile) = profiles::reloaded(&mut self.profile_generation) {
+            self.highlight = highlight(&profile, &self.highlight_args);
+            self.tag_groups = Arc::new(tag_groups(&profile));
+        }
 
-            if len < width {
-                s.reserve(width - len);
-                for _ in 0..(width - len) {
-                    s.push(' ')
+        self.gap(&record, &mut buffer)?;
+
+        let timestamp_width = self.timestamp_width();
+        let timestamp = match record.timestamp.take() {
+            Some(timestamp) if timestamp.is_valid() => Some(timestamp),
+            Some(timestamp) => {
+                if timestamp_width > 0 {
+                    utils::invalid_timestamp(&timestamp);
                 }
+                None
             }
-        }
+            None => None,
+        };
+
+        // Prefix the source e.g the input file of watched directories or the device serial
+        let source = match (record.source.take(), record.buffer.take()) {
+            (Some(source), Some(buffer)) if self.show_buffer => Some(format!("{source} {buffer}")),
+            (None, Some(buffer)) if self.show_buffer => Some(buffer),
+            (source, _) => source,
+        };
+        let prefix_width = match source {
+            Some(ref source) => {
+                self.source_width = max(self.source_width, source.chars().count());
+                if timestamp_width == 0 {
+                    self.source_width
+                } else {
+                    self.source_width + 1 + timestamp_width
+                }
+            }
+            None => timestamp_width,
+        };
 
-        // Tag
-        let tag_width = self.tag_width();
+        // Calculate colors before truncation
+        let process_color = self.color(&record.process);
+        let thread_color = self.color(&record.thread);
+
+        let terminal_width = self.terminal_width();
+        let tag_width = self.tag_width(terminal_width);
 
-        // Process
+        // Process - show the name if resolved
+        if let Some(name) = record.process_name.take() {
+            record.process = name;
+        }
         self.process_width = min(
-            max(self.process_width, record.process.chars().count()),
+            max(self.process_width, record.process.width()),
             self.process_width_max,
         );
-        format_trim(&mut record.process, self.process_width);
 
         // Thread
         self.thread_width = min(
-            max(self.thread_width, record.thread.chars().count()),
+            max(self.thread_width, record.thread.width()),
             self.thread_width_max,
         );
-        format_trim(&mut record.thread, self.thread_width);
 
-        let highlight = !self.highlight.is_empty()
-            && (self
-                .highlight
-                .iter()
-                .any(|r| record.tags.iter().any(|t| r.is_match(t)))
-                || self.highlight.iter().any(|r| r.is_match(&record.message)));
+        let highlight = self
+            .highlight
+            .iter()
+            .filter(|h| h.style.is_none())
+            .any(|h| {
+                record.tags.iter().any(|t| h.regex.is_match(t)) || h.regex.is_match(&record.message)
+            });
 
-        let preamble_width = timestamp.chars().count()
+        let preamble_width = prefix_width
             + 1 // " "
             + tag_width
             + 2 // " ("
@@ -255,102 +657,145 @@ impl Human {
             Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
             _ => self.dimm_color,
         };
+        // Internal events are dimmed and telephony messages colored by direction
+        let message_color = if internal::is_internal(&record) {
+            self.dimm_color
+        } else {
+            match (record.direction.as_deref(), &record.level) {
+                (_, Level::Error | Level::Fatal | Level::Assert) => level_color,
+                (Some("TX"), _) => Some(Color::Cyan),
+                (Some("RX"), _) => Some(Color::Magenta),
+                _ => level_color,
+            }
+        };
+
+        let tags = if self.tag_groups.is_empty() {
+            record.tags
+        } else {
+            let mut tags = record
+                .tags
+                .into_iter()
+                .map(|tag| self.tag_groups.get(&tag).cloned().unwrap_or(tag))
+                .collect::<Vec<_>>();
+            tags.dedup();
+            tags
+        };
+        let tag_styles = tags
+            .iter()
+            .map(|tag| {
+                self.highlight
+                    .iter()
+                    .find(|h| h.style.is_some() && h.regex.is_match(tag))
+                    .and_then(|h| h.style.as_ref())
+            })
+            .collect::<Vec<_>>();
 
         let Record {
             message,
             level,
-            tags,
             process,
             thread,
+            annotations,
             ..
         } = record;
 
-        // Collect as many tags as needed
-        let mut tags: Vec<_> = {
-            let tags = tags.into_iter().map(|t| {
-                let chars = t.chars().count();
-                let color = Self::hashed_color(&t);
-                (t, chars, Some(color))
-            });
-
-            // Add spaces between the tags
-            let tags = intersperse(tags, (" ".to_string(), 1, None));
-
-            let mut space_left = tag_width;
-            let space_left = &mut space_left;
-            let mut tags = tags
-                .into_iter()
-                .map_while(|(mut tag, chars, color)| {
-                    // Break condition
-                    if *space_left == 0 {
-                        return None;
-                    }
-
-                    let left = *space_left;
-                    *space_left = space_left.saturating_sub(chars);
-
-                    // Truncate tag if necessary
-                    if *space_left == 0 {
-                        format_trim(&mut tag, left);
-                    }
-                    Some((tag, color))
-                })
-                .collect::<Vec<_>>();
-
-            // If the tags do not fill up the tag space, add spaces
-            if *space_left > 0 {
-                tags.push((" ".repeat(*space_left), None));
-            }
-            tags
-        };
+        // The padded thread column is empty only if there was never a thread
+        let show_thread = !thread.is_empty() || self.thread_width > 0;
 
-        let mut write_preamble = |buffer: &mut Buffer| -> Result<(), Error> {
+        let write_preamble = |buffer: &mut Buffer, paint: &mut Paint| -> Result<(), Error> {
             let mut spec = ColorSpec::new();
 
-            // Timestamp
-            buffer.set_color(spec.set_fg(timestamp_color))?;
-            buffer.write_all(timestamp.as_bytes())?;
+            // Source and timestamp
+            paint.set(buffer, spec.set_fg(timestamp_color))?;
+            if let Some(ref source) = source {
+                write!(buffer, "{:width$}", source, width = self.source_width)?;
+                if timestamp_width > 0 {
+                    buffer.write_all(b" ")?;
+                }
+            }
+            self.write_timestamp(buffer, timestamp.as_ref())?;
 
-            // Tags
+            // Tags separated by spaces as long as they fit
             buffer.write_all(b" ")?;
-            for (tag, color) in &mut tags {
-                buffer.set_color(spec.set_fg(*color))?;
-                buffer.write_all(tag.as_bytes())?;
+            let mut space_left = tag_width;
+            for (n, tag) in tags.iter().enumerate() {
+                if n > 0 && space_left > 0 {
+                    paint.set(buffer, spec.set_fg(None))?;
+                    buffer.write_all(b" ")?;
+                    space_left -= 1;
+                }
+                if space_left == 0 {
+                    break;
+                }
+                match tag_styles[n] {
+                    Some(style) => paint.set(buffer, style)?,
+                    None => paint.set(buffer, spec.set_fg(Some(self.color(tag))))?,
+                }
+                let width = tag.width();
+                if width >= space_left {
+                    write_trimmed(buffer, tag, space_left)?;
+                    space_left = 0;
+                } else {
+                    buffer.write_all(tag.as_bytes())?;
+                    space_left -= width;
+                }
+            }
+            if space_left > 0 {
+                paint.set(buffer, spec.set_fg(None))?;
+                write_spaces(buffer, space_left)?;
             }
 
             // Process and thread
-            buffer.set_color(spec.set_fg(None))?;
+            paint.set(buffer, spec.set_fg(None))?;
             buffer.write_all(b" (")?;
-            buffer.set_color(spec.set_fg(Some(process_color)))?;
-            buffer.write_all(process.as_bytes())?;
-            if !thread.is_empty() {
-                buffer.set_color(spec.set_fg(Some(thread_color)))?;
+            paint.set(buffer, spec.set_fg(Some(process_color)))?;
+            write_trimmed(buffer, &process, self.process_width)?;
+            if show_thread {
+                paint.set(buffer, spec.set_fg(Some(thread_color)))?;
                 buffer.write_all(b" ")?;
-                buffer.write_all(thread.as_bytes())?;
+                write_trimmed(buffer, &thread, self.thread_width)?;
             }
-            buffer.set_color(spec.set_fg(None))?;
+            paint.set(buffer, spec.set_fg(None))?;
             buffer.write_all(b") ")?;
 
             // Level
-            buffer.set_color(
+            paint.set(
+                buffer,
                 spec.set_bg(level_color)
                     .set_fg(level_color.map(|_| Color::Black)), // Set fg only if bg is set
             )?;
             write!(buffer, " {} ", level)?;
-            buffer.set_color(&ColorSpec::new())?;
+            paint.set(buffer, &ColorSpec::new())?;
 
             Ok(())
         };
 
-        let payload_len = terminal_width().unwrap_or(usize::MAX) - preamble_width - 3;
-        let message = message.replace('\t', "<TAB>");
-        let message_len = message.chars().count();
-        let chunks = message_len / payload_len + 1;
-
-        let mut buffer = self.writer.buffer();
+        let message = if message.contains('\t') {
+            message.replace('\t', "<TAB>")
+        } else {
+            message
+        };
+        let message = self.delimiting.escape(message);
+        let payload_len =
+            terminal_width.map(|width| width.saturating_sub(preamble_width + 3).max(1));
+        let overflow = self.wide && payload_len.is_some_and(|len| message.width() > len);
+        let lines = match payload_len.filter(|_| self.wrap) {
+            Some(len) => wrap(&message, len),
+            None => vec![Range {
+                start: 0,
+                end: message.len(),
+            }],
+        };
+        let chunks = lines.len();
 
-        for i in 0..chunks {
-            write_preamble(&mut buffer)?;
+        let mut message_spec = ColorSpec::new();
+        message_spec
+            .set_intense(self.bright_colors)
+            .set_fg(message_color);
+        let spans = self.highlight_spans(&message);
+        let mut paint = Paint::default();
+        for (i, line) in lines.iter().enumerate() {
+            write_preamble(&mut buffer, &mut paint)?;
 
             let c = if chunks == 1 {
                 "   "
@@ -362,44 +807,274 @@ impl Human {
                 " ├ "
             };
 
-            buffer.write_all(c.as_bytes())?;
-
-            let chunk = message
-                .chars()
-                .skip(i * payload_len)
-                .take(payload_len)
-                .collect::<String>();
-            buffer.set_color(
-                ColorSpec::new()
-                    .set_intense(self.bright_colors)
-                    .set_fg(level_color),
+            if overflow {
+                // Hint that the line continues beyond the terminal width
+                paint.set(&mut buffer, ColorSpec::new().set_fg(self.dimm_color))?;
+                buffer.write_all(" → ".as_bytes())?;
+            } else {
+                buffer.write_all(c.as_bytes())?;
+            }
+
+            let chunk = &message[line.clone()];
+            write_spans(
+                &mut buffer,
+                &mut paint,
+                chunk,
+                line.start,
+                &spans,
+                &message_spec,
             )?;
-            buffer.write_all(chunk.as_bytes())?;
-            buffer.write_all(b"\n")?;
+            if i == chunks - 1 {
+                if !annotations.is_empty() {
+                    paint.set(&mut buffer, ColorSpec::new().set_fg(self.dimm_color))?;
+                    buffer.write_all(b" ")?;
+                    for annotation in &annotations {
+                        buffer.write_all(b" ")?;
+                        buffer.write_all(annotation.as_bytes())?;
+                    }
+                }
+                buffer.write_all(self.delimiting.terminator())?;
+            } else {
+                buffer.write_all(b"\n")?;
+            }
         }
 
-        self.writer.print(&buffer).map_err(Into::into)
+        Ok(buffer)
+    }
+
+    /// Byte ranges of `message` matched by styled highlights. Overlapping matches are dropped.
+    fn highlight_spans(&self, message: &str) -> Vec<(Range<usize>, &ColorSpec)> {
+        let mut spans = self
+            .highlight
+            .iter()
+            .filter_map(|h| h.style.as_ref().map(|style| (&h.regex, style)))
+            .flat_map(|(regex, style)| regex.find_iter(message).map(move |m| (m.range(), style)))
+            .filter(|(range, _)| !range.is_empty())
+            .collect::<Vec<_>>();
+        spans.sort_by_key(|(range, _)| range.start);
+        let mut end = 0;
+        spans.retain(|(range, _)| {
+            let keep = range.start >= end;
+            if keep {
+                end = range.end;
+            }
+            keep
+        });
+        spans
+    }
+
+    /// Width of the timestamp column
+    fn timestamp_width(&self) -> usize {
+        match (self.show_date, self.show_time) {
+            (true, true) => 5 + 1 + 12,
+            (true, false) => 5,
+            (false, true) => 12,
+            (false, false) => 0,
+        }
+    }
+
+    /// Write the date and time or blanks if `timestamp` is missing or invalid
+    fn write_timestamp(
+        &self,
+        buffer: &mut Buffer,
+        timestamp: Option<&Timestamp>,
+    ) -> io::Result<()> {
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => return write_spaces(buffer, self.timestamp_width()),
+        };
+        if self.show_date {
+            write!(
+                buffer,
+                "{:02}-{:02}",
+                timestamp.tm_mon + 1,
+                timestamp.tm_mday
+            )?;
+            if self.show_time {
+                buffer.write_all(b" ")?;
+            }
+        }
+        if self.show_time {
+            write!(
+                buffer,
+                "{:02}:{:02}:{:02}.{:03}",
+                timestamp.tm_hour,
+                timestamp.tm_min,
+                timestamp.tm_sec,
+                timestamp.tm_nsec / 1_000_000
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Terminal width. Checked at most every `TERMINAL_WIDTH_INTERVAL`.
+    fn terminal_width(&mut self) -> Option<usize> {
+        match self.terminal_width {
+            Some((width, checked)) if checked.elapsed() < TERMINAL_WIDTH_INTERVAL => width,
+            _ => {
+                let width = terminal_width();
+                self.terminal_width = Some((width, Instant::now()));
+                width
+            }
+        }
+    }
+}
+
+/// Writes color changes unless the color is already set
+#[derive(Default)]
+struct Paint {
+    current: Option<ColorSpec>,
+}
+
+impl Paint {
+    fn set(&mut self, buffer: &mut Buffer, spec: &ColorSpec) -> io::Result<()> {
+        if self.current.as_ref() != Some(spec) {
+            buffer.set_color(spec)?;
+            self.current = Some(spec.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Write `chunk` that starts at byte `offset` of the message with `spec` and the highlighted
+/// `spans` with their style
+fn write_spans(
+    buffer: &mut Buffer,
+    paint: &mut Paint,
+    chunk: &str,
+    offset: usize,
+    spans: &[(Range<usize>, &ColorSpec)],
+    spec: &ColorSpec,
+) -> io::Result<()> {
+    let bytes = chunk.as_bytes();
+    let end = offset + bytes.len();
+    let mut pos = offset;
+    for (range, style) in spans
+        .iter()
+        .filter(|(r, _)| r.end > offset && r.start < end)
+    {
+        let start = max(range.start, offset);
+        if start > pos {
+            paint.set(buffer, spec)?;
+            buffer.write_all(&bytes[pos - offset..start - offset])?;
+        }
+        pos = min(range.end, end);
+        paint.set(buffer, style)?;
+        buffer.write_all(&bytes[start - offset..pos - offset])?;
+    }
+    if pos < end {
+        paint.set(buffer, spec)?;
+        buffer.write_all(&bytes[pos - offset..])?;
+    }
+    // Don't let a background bleed into the rest of the line
+    paint.set(buffer, spec)
+}
+
+fn write_spaces(buffer: &mut Buffer, mut n: usize) -> io::Result<()> {
+    const SPACES: &[u8] = b"                                ";
+    while n > 0 {
+        let chunk = min(n, SPACES.len());
+        buffer.write_all(&SPACES[..chunk])?;
+        n -= chunk;
+    }
+    Ok(())
+}
+
+/// Byte length and columns of the longest prefix of `s` that fits into `width` columns
+fn fit(s: &str, width: usize) -> (usize, usize) {
+    let mut columns = 0;
+    let mut joined = false;
+    for (n, c) in s.char_indices() {
+        // Wide characters joined with a zero width joiner render as one glyph e.g emoji sequences
+        let w = match c.width().unwrap_or(0) {
+            2 if joined => 0,
+            w => w,
+        };
+        if columns + w > width {
+            return (n, columns);
+        }
+        columns += w;
+        joined = c == '\u{200d}';
+    }
+    (s.len(), columns)
+}
+
+/// Split `message` into byte ranges of at most `width` columns. Lines break at the last
+/// whitespace that fits, which is dropped, and within a word only if the word is wider than a
+/// line. Zero width characters stay with the preceding character and emoji sequences are not
+/// split.
+fn wrap(message: &str, width: usize) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while message[start..].width() > width {
+        let rest = &message[start..];
+        let (fits, _) = fit(rest, width);
+        let space = |n: usize| rest[n..].chars().next().filter(|c| c.is_whitespace());
+        let (end, next) = match space(fits) {
+            Some(c) if fits > 0 => (fits, fits + c.len_utf8()),
+            _ => match rest[..fits].rfind(char::is_whitespace).filter(|n| *n > 0) {
+                Some(n) => (n, n + space(n).map_or(1, char::len_utf8)),
+                // Take at least one character e.g a wide one in a single column
+                None if fits == 0 => {
+                    let n = rest.chars().next().map_or(rest.len(), char::len_utf8);
+                    (n, n)
+                }
+                None => (fits, fits),
+            },
+        };
+        lines.push(start..start + end);
+        start += next;
+    }
+    if start < message.len() || lines.is_empty() {
+        lines.push(start..message.len());
+    }
+    lines
+}
+
+/// Write `s` with `width` columns. Longer strings are truncated and end with "…",
+/// shorter ones are padded with spaces.
+fn write_trimmed(buffer: &mut Buffer, s: &str, width: usize) -> io::Result<()> {
+    let len = s.width();
+    if len > width {
+        let (end, columns) = fit(s, width.saturating_sub(1));
+        buffer.write_all(&s.as_bytes()[..end])?;
+        buffer.write_all("…".as_bytes())?;
+        // A wide character may leave a column
+        write_spaces(buffer, width.saturating_sub(columns + 1))
+    } else {
+        buffer.write_all(s.as_bytes())?;
+        write_spaces(buffer, width - len)
     }
 }
 
 impl Drop for Human {
     fn drop(&mut self) {
-        let mut buffer = self.writer.buffer();
-        buffer.reset().and_then(|_| self.writer.print(&buffer)).ok();
+        self.flush().ok();
+        if let Some(ref writer) = self.writer {
+            let mut buffer = writer.buffer();
+            buffer.reset().and_then(|_| writer.print(&buffer)).ok();
+        }
     }
 }
 
 struct FormatSink<T: Write> {
     format: Format,
     sink: BufWriter<T>,
+    delimiting: Delimiting,
 }
 
 impl<T: Write> FormatSink<T> {
-    fn new(format: Format, sink: T) -> FormatSink<T> {
-        FormatSink {
-            format,
-            sink: BufWriter::new(sink),
+    fn new(format: Format, sink: T, delimiting: Delimiting) -> Result<FormatSink<T>, Error> {
+        let mut sink = BufWriter::new(sink);
+        if let Some(header) = format.header()? {
+            sink.write_all(header.as_bytes())?;
+            sink.write_all(delimiting.terminator())?;
         }
+        Ok(FormatSink {
+            format,
+            sink,
+            delimiting,
+        })
     }
 }
 
@@ -408,9 +1083,13 @@ impl<T: Write> Sink for FormatSink<T> {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        self.sink
-            .write_all(self.format.fmt_record(&record)?.as_bytes())?;
-        self.sink.write_all(b"\n")?;
+        // Invalid timestamps are written as missing
+        if let Some(timestamp) = record.timestamp.as_ref().filter(|t| !t.is_valid()) {
+            utils::invalid_timestamp(timestamp);
+        }
+        let line = self.delimiting.escape(self.format.fmt_record(&record)?);
+        self.sink.write_all(line.as_bytes())?;
+        self.sink.write_all(self.delimiting.terminator())?;
         self.sink.flush()?;
         Ok(AsyncSink::Ready)
     }
@@ -420,6 +1099,65 @@ impl<T: Write> Sink for FormatSink<T> {
     }
 }
 
+/// Prints the raw lines of records unchanged. Lines matching a highlight are colored with its
+/// style or yellow for plain patterns.
+struct Passthrough {
+    writer: BufferWriter,
+    highlight: Vec<Highlight>,
+    highlight_args: Vec<String>,
+    profile_generation: usize,
+    delimiting: Delimiting,
+}
+
+impl Passthrough {
+    fn from(args: &ArgMatches<'_>, profile: &Profile) -> Passthrough {
+        let highlight_args = highlight_args(args);
+        Passthrough {
+            writer: BufferWriter::stdout(color_choice(args, atty::Stream::Stdout)),
+            highlight: highlight(profile, &highlight_args),
+            highlight_args,
+            profile_generation: 0,
+            delimiting: Delimiting::from(args),
+        }
+    }
+}
+
+impl Sink for Passthrough {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if let Some(profile) = profiles::reloaded(&mut self.profile_generation) {
+            self.highlight = highlight(&profile, &self.highlight_args);
+        }
+
+        let mut buffer = self.writer.buffer();
+        let style = self
+            .highlight
+            .iter()
+            .find(|h| h.regex.is_match(&record.raw))
+            .map(|h| {
+                h.style
+                    .clone()
+                    .unwrap_or_else(|| ColorSpec::new().set_fg(Some(Color::Yellow)).clone())
+            });
+        if let Some(ref style) = style {
+            buffer.set_color(style)?;
+        }
+        buffer.write_all(record.raw.as_bytes())?;
+        if style.is_some() {
+            buffer.reset()?;
+        }
+        buffer.write_all(self.delimiting.terminator())?;
+        self.writer.print(&buffer)?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+}
+
 impl Sink for Human {
     type SinkItem = Record;
     type SinkError = Error;
@@ -429,6 +1167,91 @@ impl Sink for Human {
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.flush().map(Async::Ready)
+    }
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.flush()?;
+        if let Some(ref interactive) = self.interactive {
+            interactive.finish()?;
+        }
         Ok(Async::Ready(()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(message: &str, width: usize) -> Vec<&str> {
+        wrap(message, width)
+            .into_iter()
+            .map(|range| &message[range])
+            .collect()
+    }
+
+    #[test]
+    fn wrap_words() {
+        assert_eq!(lines("", 5), vec![""]);
+        assert_eq!(lines("hello world", 11), vec!["hello world"]);
+        assert_eq!(lines("aaa bbb ccc", 5), vec!["aaa", "bbb", "ccc"]);
+        // The space at the break is dropped
+        assert_eq!(lines("abcd efgh", 4), vec!["abcd", "efgh"]);
+        assert_eq!(lines("ab abcdefgh", 4), vec!["ab", "abcd", "efgh"]);
+    }
+
+    #[test]
+    fn wrap_long_words() {
+        assert_eq!(lines("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(lines("abc", 1), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn wrap_wide_characters() {
+        // CJK characters and emoji take two columns
+        assert_eq!(
+            lines("日本語テキスト", 5),
+            vec!["日本", "語テ", "キス", "ト"]
+        );
+        assert_eq!(lines("😀😀😀", 3), vec!["😀", "😀", "😀"]);
+        assert_eq!(lines("ab 日本語", 4), vec!["ab", "日本", "語"]);
+        // A wide character is placed alone if a line has a single column
+        assert_eq!(lines("日本", 1), vec!["日", "本"]);
+    }
+
+    #[test]
+    fn wrap_zero_width() {
+        // Joiners and combining marks stay with the preceding character
+        assert_eq!(lines("ab\u{200d}cd", 2), vec!["ab\u{200d}", "cd"]);
+        assert_eq!(
+            lines("e\u{301}e\u{301}e\u{301}", 2),
+            vec!["e\u{301}e\u{301}", "e\u{301}"]
+        );
+        // Emoji sequences are not split
+        assert_eq!(
+            lines("👩\u{200d}💻👩\u{200d}💻", 3),
+            vec!["👩\u{200d}💻", "👩\u{200d}💻"]
+        );
+    }
+
+    #[test]
+    fn wide() {
+        let args = crate::cli::cli().get_matches_from(vec!["rogcat", "--wide"]);
+        let mut human = Human::from(&args, &Profile::default(), Format::Human);
+        human.terminal_width = Some((Some(60), Instant::now()));
+        let mut render = |message: &str| {
+            let record = Record {
+                message: message.to_owned(),
+                ..Default::default()
+            };
+            let buffer = human.render(record, Buffer::no_color()).unwrap();
+            String::from_utf8(buffer.into_inner()).unwrap()
+        };
+
+        let short = render("short");
+        assert!(!short.contains('→'));
+        // Long messages stay on one line with a hint and newlines are escaped
+        let long = render(&format!("first\n{}", "x".repeat(60)));
+        assert_eq!(long.lines().count(), 1);
+        assert!(long.contains(" → first\\nxxx"));
+    }
+}
@@ -0,0 +1,372 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Full screen terminal ui with a live record table
+
+use crate::{error::RogcatError, internal, pipeline::LineParser, profiles, reader};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::Stream;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Cell, Row, Table},
+    DefaultTerminal, Frame,
+};
+use rogcat::record::{Level, Record};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    sync::mpsc::{channel, Receiver},
+    thread,
+    time::Duration,
+};
+use time::{now, strftime};
+use tokio::runtime::Runtime;
+
+/// Maximum number of records kept in memory
+const CAPACITY: usize = 100_000;
+
+/// Levels that can be toggled and their keys
+const LEVELS: [(char, Level); 9] = [
+    ('-', Level::None),
+    ('t', Level::Trace),
+    ('v', Level::Verbose),
+    ('d', Level::Debug),
+    ('i', Level::Info),
+    ('w', Level::Warn),
+    ('e', Level::Error),
+    ('f', Level::Fatal),
+    ('a', Level::Assert),
+];
+
+/// Text input prompts
+enum Input {
+    Search(String),
+    Export(String),
+}
+
+struct App {
+    /// Records and their lowercased message and tags for searching
+    records: VecDeque<(Record, String)>,
+    /// Records received while paused
+    pending: VecDeque<Record>,
+    levels: [bool; 9],
+    search: String,
+    paused: bool,
+    /// Number of rows scrolled up from the bottom
+    scroll: usize,
+    input: Option<Input>,
+    status: String,
+    done: bool,
+}
+
+/// Run the terminal ui
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    if !atty::is(atty::Stream::Stdout) {
+        return Err(RogcatError::Config(
+            "The terminal ui requires stdout to be a terminal".to_owned(),
+        )
+        .into());
+    }
+
+    let source = if args.is_present("watch") {
+        reader::watch(args)?
+    } else if args.is_present("input") {
+        reader::files(args)?
+    } else if args.is_present("COMMAND") {
+        reader::process(args)?
+    } else {
+        reader::logcat(args)?
+    };
+
+    let parser = LineParser::from_args(args, &profiles::from_args(args)?, None, None)?;
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let error = tx.clone();
+        let f = parser
+            .records(source)
+            .for_each(move |record| tx.send(record).map_err(|e| format_err!("{}", e)));
+        let result = Runtime::new()
+            .map_err(Into::into)
+            .and_then(|mut runtime| runtime.block_on(f));
+        if let Err(e) = result {
+            let message = format!("Source error: {e}");
+            error
+                .send(Record {
+                    level: Level::Error,
+                    tags: vec![internal::TAG.to_owned()],
+                    raw: message.clone(),
+                    message,
+                    ..Default::default()
+                })
+                .ok();
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    let result = App::new().run(&mut terminal, rx);
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn new() -> App {
+        App {
+            records: VecDeque::new(),
+            pending: VecDeque::new(),
+            levels: [true; 9],
+            search: String::new(),
+            paused: false,
+            scroll: 0,
+            input: None,
+            status: String::new(),
+            done: false,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal, rx: Receiver<Record>) -> Result<(), Error> {
+        while !self.done {
+            while let Ok(record) = rx.try_recv() {
+                self.push(record);
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.key(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, record: Record) {
+        if self.paused {
+            if self.pending.len() == CAPACITY {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(record);
+            return;
+        }
+        if self.records.len() == CAPACITY {
+            self.records.pop_front();
+        }
+        // The search input cannot contain newlines so matches never span fields
+        let mut text = record.message.to_lowercase();
+        for tag in &record.tags {
+            text.push('\n');
+            text.push_str(&tag.to_lowercase());
+        }
+        self.records.push_back((record, text));
+    }
+
+    fn level_index(level: &Level) -> usize {
+        LEVELS.iter().position(|(_, l)| l == level).unwrap_or(0)
+    }
+
+    fn visible(&self, (record, text): &(Record, String)) -> bool {
+        self.levels[Self::level_index(&record.level)]
+            && (self.search.is_empty() || text.contains(&self.search))
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        // Raw mode delivers Ctrl-C as a key instead of a signal
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.done = true;
+            return;
+        }
+        let code = key.code;
+        match self.input.take() {
+            Some(Input::Search(mut s)) => match code {
+                KeyCode::Enter => (),
+                KeyCode::Esc => self.search.clear(),
+                KeyCode::Backspace => {
+                    s.pop();
+                    self.search = s.to_lowercase();
+                    self.input = Some(Input::Search(s));
+                }
+                KeyCode::Char(c) => {
+                    s.push(c);
+                    self.search = s.to_lowercase();
+                    self.input = Some(Input::Search(s));
+                }
+                _ => self.input = Some(Input::Search(s)),
+            },
+            Some(Input::Export(mut s)) => match code {
+                KeyCode::Enter => {
+                    self.status = match self.export(&s) {
+                        Ok(n) => format!("Exported {n} records to {s}"),
+                        Err(e) => format!("Export failed: {e}"),
+                    }
+                }
+                KeyCode::Esc => (),
+                KeyCode::Backspace => {
+                    s.pop();
+                    self.input = Some(Input::Export(s));
+                }
+                KeyCode::Char(c) => {
+                    s.push(c);
+                    self.input = Some(Input::Export(s));
+                }
+                _ => self.input = Some(Input::Export(s)),
+            },
+            None => match code {
+                KeyCode::Char('q') | KeyCode::Esc => self.done = true,
+                KeyCode::Char(' ') => {
+                    self.paused = !self.paused;
+                    if !self.paused {
+                        for record in std::mem::take(&mut self.pending) {
+                            self.push(record);
+                        }
+                    }
+                }
+                KeyCode::Char('/') => self.input = Some(Input::Search(self.search.clone())),
+                KeyCode::Char('s') => {
+                    let filename = strftime("rogcat-%F-%H_%M_%S.log", &now()).unwrap_or_default();
+                    self.input = Some(Input::Export(filename));
+                }
+                KeyCode::Char(c) => {
+                    if let Some(n) = LEVELS.iter().position(|(k, _)| *k == c) {
+                        self.levels[n] = !self.levels[n];
+                    }
+                }
+                KeyCode::Up => self.scroll += 1,
+                KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+                KeyCode::PageUp => self.scroll += 20,
+                KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(20),
+                KeyCode::End => self.scroll = 0,
+                _ => (),
+            },
+        }
+    }
+
+    /// Write the raw lines of all visible records to `filename`
+    fn export(&self, filename: &str) -> Result<usize, Error> {
+        let mut file = BufWriter::new(File::create(filename)?);
+        let mut n = 0;
+        for (record, _) in self.records.iter().filter(|r| self.visible(r)) {
+            file.write_all(record.raw.as_bytes())?;
+            file.write_all(b"\n")?;
+            n += 1;
+        }
+        file.flush()?;
+        Ok(n)
+    }
+
+    fn level_color(level: &Level) -> Color {
+        match level {
+            Level::Info => Color::Green,
+            Level::Warn => Color::Yellow,
+            Level::Error | Level::Fatal | Level::Assert => Color::Red,
+            _ => Color::Gray,
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let height = table_area.height as usize;
+        let visible = self.records.iter().filter(|r| self.visible(r)).count();
+        self.scroll = self.scroll.min(visible.saturating_sub(height));
+
+        let mut rows = self
+            .records
+            .iter()
+            .rev()
+            .filter(|r| self.visible(r))
+            .skip(self.scroll)
+            .take(height)
+            .map(|(r, _)| {
+                let timestamp = r
+                    .timestamp
+                    .as_ref()
+                    .and_then(|ts| strftime("%H:%M:%S.%f", ts).ok())
+                    .map(|mut ts| {
+                        ts.truncate(12);
+                        ts
+                    })
+                    .unwrap_or_default();
+                let color = Self::level_color(&r.level);
+                Row::new(vec![
+                    Cell::from(timestamp).style(Style::default().fg(Color::DarkGray)),
+                    Cell::from(r.tags.join(" ")),
+                    Cell::from(r.process.clone()),
+                    Cell::from(r.thread.clone()),
+                    Cell::from(r.level.to_string()).style(Style::default().fg(color)),
+                    Cell::from(r.message.clone()).style(Style::default().fg(color)),
+                ])
+            })
+            .collect::<Vec<_>>();
+        rows.reverse();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(12),
+                Constraint::Length(24),
+                Constraint::Length(6),
+                Constraint::Length(6),
+                Constraint::Length(1),
+                Constraint::Min(10),
+            ],
+        );
+        frame.render_widget(table, table_area);
+
+        let levels = LEVELS
+            .iter()
+            .zip(self.levels.iter())
+            .map(|((k, _), enabled)| if *enabled { k.to_ascii_uppercase() } else { *k })
+            .collect::<String>();
+        let status = match self.input {
+            Some(Input::Search(ref s)) => format!("/{s}"),
+            Some(Input::Export(ref s)) => format!("Export to: {s}"),
+            None => format!(
+                "{} {}/{} records | levels [{}] | search \"{}\"{} | {}",
+                if self.paused { "PAUSED" } else { "LIVE" },
+                visible,
+                self.records.len(),
+                levels,
+                self.search,
+                if self.paused {
+                    format!(" | {} buffered", self.pending.len())
+                } else {
+                    String::new()
+                },
+                if self.status.is_empty() {
+                    "q quit, space pause, / search, s export, t v d i w e f a - toggle levels"
+                } else {
+                    &self.status
+                }
+            ),
+        };
+        frame.render_widget(
+            Line::from(status).style(Style::default().add_modifier(Modifier::REVERSED)),
+            status_area,
+        );
+    }
+}
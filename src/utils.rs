@@ -18,50 +18,631 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::{console, error::RogcatError, internal};
+use clap::ArgMatches;
 use config::{Config, File};
 use failure::Error;
 use lazy_static::lazy_static;
+use rogcat::{
+    parser::EventTags,
+    record::{CsvColumn, Format, Timestamp},
+};
 use serde::Deserialize;
-use std::{convert::Into, env, path::PathBuf, sync::RwLock};
+use std::{
+    convert::Into,
+    env, fmt,
+    io::{self, Write},
+    iter::once,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
 use which::which_in;
 
 lazy_static! {
-    static ref CONFIG: RwLock<Config> = RwLock::new({
-        Config::builder()
-            .add_source(File::from(config_dir().join("config.toml")).required(false))
-            .build()
-            .unwrap_or_default()
-    });
+    static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+    /// adb binary and global arguments
+    static ref ADB: RwLock<(Option<PathBuf>, Vec<String>)> = RwLock::new((None, Vec::new()));
+    /// Serial of the device selected by `select_device`
+    static ref SERIAL: RwLock<Option<Option<String>>> = RwLock::new(None);
 }
 
-/// Find adb binary
-pub fn adb() -> Result<PathBuf, Error> {
-    which_in("adb", env::var_os("PATH"), env::current_dir()?).map_err(Into::into)
+/// Location of the event log tags on Android devices
+const EVENT_TAGS_PATH: &str = "/system/etc/event-log-tags";
+
+/// Command of the adb binary with the global adb arguments
+pub fn adb() -> Result<Command, Error> {
+    let cmdline = adb_cmdline()?;
+    let mut cmd = Command::new(&cmdline[0]);
+    cmd.args(&cmdline[1..]);
+    Ok(cmd)
 }
 
-pub fn terminal_width() -> Option<usize> {
-    match term_size::dimensions() {
-        Some((width, _)) => Some(width),
-        None => env::var("COLUMNS")
-            .ok()
-            .and_then(|e| e.parse::<usize>().ok()),
+/// The adb binary followed by the global adb arguments e.g `-H remote-host`
+pub fn adb_cmdline() -> Result<Vec<String>, Error> {
+    if on_device() {
+        return Err(
+            RogcatError::Device("adb is not used on the device (--no-adb)".to_owned()).into(),
+        );
+    }
+    let mut cmdline = vec![find_adb()?.display().to_string()];
+    cmdline.extend(ADB.read().unwrap().1.iter().cloned());
+    Ok(cmdline)
+}
+
+/// The adb binary of `--adb-path`, `adb_path` in the config or on `PATH`
+fn find_adb() -> Result<PathBuf, Error> {
+    if let Some(ref path) = ADB.read().unwrap().0 {
+        return Ok(path.clone());
+    }
+    which_in("adb", env::var_os("PATH"), env::current_dir()?)
+        .map_err(|e| RogcatError::Device(format!("Cannot find adb: {}", e)).into())
+}
+
+/// Set the adb binary and the global adb arguments from `args` or the config
+fn adb_init(args: &ArgMatches) {
+    let path = args
+        .value_of("adb-path")
+        .map(ToOwned::to_owned)
+        .or_else(|| config_get::<String>("adb_path"))
+        .map(PathBuf::from);
+    let extra = args
+        .values_of("adb-arg")
+        .map(|args| args.map(ToOwned::to_owned).collect())
+        .or_else(|| config_get::<Vec<String>>("adb_args"))
+        .unwrap_or_default();
+    *ADB.write().unwrap() = (path, extra);
+}
+
+/// Set if rogcat runs on the device and calls logcat and friends directly
+static ON_DEVICE: AtomicBool = AtomicBool::new(false);
+
+/// True if rogcat runs on the device itself e.g in Termux or a rooted shell
+pub fn on_device() -> bool {
+    ON_DEVICE.load(Ordering::Relaxed)
+}
+
+/// Run on the device with `--no-adb`, `no_adb = true` in the config or if rogcat runs on
+/// Android without an adb on PATH
+fn detect_on_device(args: &ArgMatches) -> bool {
+    let android = cfg!(target_os = "android")
+        || (env::var_os("ANDROID_ROOT").is_some() && Path::new(LOGCAT).exists());
+    args.is_present("no-adb")
+        || config_get::<bool>("no_adb").unwrap_or(false)
+        || (android && find_adb().is_err())
+}
+
+/// Location of logcat on Android devices
+const LOGCAT: &str = "/system/bin/logcat";
+
+/// The logcat binary when running on the device
+pub fn logcat() -> String {
+    if Path::new(LOGCAT).exists() {
+        LOGCAT.to_owned()
+    } else {
+        "logcat".to_owned()
+    }
+}
+
+/// Command that runs `command` in the shell of `device` with `adb shell` or with `adb exec-out`
+/// that passes binary output unchanged. The arguments are joined like adb does and run with
+/// `sh -c` if rogcat runs on the device.
+pub fn device_shell<I, S>(
+    device: Option<String>,
+    exec_out: bool,
+    command: I,
+) -> Result<Command, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let command = command
+        .into_iter()
+        .map(|s| s.as_ref().to_owned())
+        .collect::<Vec<_>>();
+    if on_device() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command.join(" "));
+        return Ok(cmd);
+    }
+    let mut cmd = adb()?;
+    if let Some(device) = device {
+        cmd.arg("-s").arg(device);
     }
+    cmd.arg(if exec_out { "exec-out" } else { "shell" })
+        .args(command);
+    Ok(cmd)
+}
+
+/// A device listed by `adb devices -l`
+#[derive(Clone, Debug, Default)]
+pub struct Device {
+    pub serial: String,
+    pub state: String,
+    pub product: Option<String>,
+    pub model: Option<String>,
+    pub device: Option<String>,
+}
+
+impl Device {
+    /// Parse a line of `adb devices` or `adb devices -l`
+    pub fn parse(line: &str) -> Option<Device> {
+        if line.is_empty() || line.starts_with("* daemon") || line.starts_with("List of") {
+            return None;
+        }
+        let mut words = line.split_whitespace();
+        let mut device = Device {
+            serial: words.next()?.to_owned(),
+            ..Device::default()
+        };
+        let mut state = Vec::new();
+        for word in words {
+            match word.split_once(':') {
+                Some(("product", value)) => device.product = Some(value.to_owned()),
+                Some(("model", value)) => device.model = Some(value.to_owned()),
+                Some(("device", value)) => device.device = Some(value.to_owned()),
+                Some(_) => (),
+                None if device.model.is_none() => state.push(word),
+                None => (),
+            }
+        }
+        device.state = state.join(" ");
+        Some(device)
+    }
+
+    /// True if `selector` is part of the serial, model, product or device name. Case and
+    /// underscores for spaces are ignored.
+    fn matches(&self, selector: &str) -> bool {
+        let normalize = |s: &str| s.to_lowercase().replace(' ', "_");
+        let selector = normalize(selector);
+        once(&self.serial)
+            .chain(self.model.iter())
+            .chain(self.product.iter())
+            .chain(self.device.iter())
+            .any(|name| normalize(name).contains(&selector))
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.serial)?;
+        if let Some(ref model) = self.model {
+            write!(f, " {}", model)?;
+        }
+        if let Some(ref device) = self.device {
+            write!(f, " ({})", device)?;
+        }
+        if self.state != "device" {
+            write!(f, " [{}]", self.state)?;
+        }
+        Ok(())
+    }
+}
+
+/// List the devices known to adb
+pub fn devices() -> Result<Vec<Device>, Error> {
+    let output = adb()?
+        .args(["devices", "-l"])
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| RogcatError::Device(format!("Failed to run adb devices: {}", e)))?;
+    if !output.status.success() {
+        return Err(RogcatError::Device("Failed to run adb devices".to_owned()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(Device::parse)
+        .collect())
+}
+
+/// Select the device once for all adb invocations. A `--serial` that isn't a serial is matched
+/// against the model, product and device names. Without `--serial` and `ANDROID_SERIAL` the
+/// device is picked interactively if more than one is attached.
+pub fn select_device(args: &ArgMatches) -> Result<(), Error> {
+    if SERIAL.read().unwrap().is_some() {
+        return Ok(());
+    }
+    if on_device() {
+        *SERIAL.write().unwrap() = Some(None);
+        return Ok(());
+    }
+    let serial = match args.value_of("dev") {
+        Some(selector) => match devices() {
+            Ok(devices) if !devices.iter().any(|d| d.serial == selector) => {
+                let matching = devices
+                    .into_iter()
+                    .filter(|d| d.matches(selector))
+                    .collect::<Vec<_>>();
+                match matching.len() {
+                    // The device may not be attached yet
+                    0 => Some(selector.to_owned()),
+                    1 => Some(matching[0].serial.clone()),
+                    _ => Some(pick_device(&matching, selector)?),
+                }
+            }
+            _ => Some(selector.to_owned()),
+        },
+        None if env::var_os("ANDROID_SERIAL").is_some() => None,
+        None => {
+            let online = devices()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|d| d.state == "device")
+                .collect::<Vec<_>>();
+            if online.len() > 1 {
+                Some(pick_device(&online, "multiple devices attached")?)
+            } else {
+                None
+            }
+        }
+    };
+    *SERIAL.write().unwrap() = Some(serial);
+    Ok(())
+}
+
+/// Let the user choose one of `devices` or fail if stdin or stderr is not a terminal
+fn pick_device(devices: &[Device], reason: &str) -> Result<String, Error> {
+    let list = devices
+        .iter()
+        .enumerate()
+        .map(|(n, device)| format!("  {}) {}", n + 1, device))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stderr) {
+        return Err(RogcatError::Device(format!(
+            "Ambiguous device selection ({}). Select one with --serial:\n{}",
+            reason, list
+        ))
+        .into());
+    }
+    let mut stderr = io::stderr();
+    write!(
+        stderr,
+        "Select device ({}):\n{}\nDevice [1-{}]: ",
+        reason,
+        list,
+        devices.len()
+    )?;
+    stderr.flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    input
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|n| devices.get(n))
+        .or_else(|| devices.iter().find(|d| d.serial == input))
+        .map(|d| d.serial.clone())
+        .ok_or_else(|| RogcatError::Device(format!("Invalid device selection: {}", input)).into())
+}
+
+/// Select the device with `serial` for all adb invocations
+pub fn set_device(serial: String) {
+    *SERIAL.write().unwrap() = Some(Some(serial));
+}
+
+/// Serial of the selected device or the `--serial` argument
+pub fn selected_device(args: &ArgMatches) -> Option<String> {
+    match *SERIAL.read().unwrap() {
+        Some(ref serial) => serial.clone(),
+        None => args.value_of("dev").map(ToOwned::to_owned),
+    }
+}
+
+/// Apply format options from `args` e.g csv column selection
+pub fn format_options(format: Format, args: &ArgMatches) -> Result<Format, Error> {
+    Ok(match format {
+        Format::Csv(None) => Format::Csv(
+            args.value_of("csv-columns")
+                .map(CsvColumn::parse_list)
+                .transpose()
+                .map_err(|e| RogcatError::Config(e.to_string()))?,
+        ),
+        format => format,
+    })
 }
 
-/// Detect configuration directory
+/// Query the current year since 1900 like `Tm` and the utc offset in seconds from the device
+pub fn device_date(args: &ArgMatches) -> Option<(i32, i32)> {
+    let output = device_shell(selected_device(args), false, ["date", "+'%Y %z'"])
+        .ok()?
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_date(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse the output of `date +'%Y %z'`
+fn parse_date(output: &str) -> Option<(i32, i32)> {
+    let mut fields = output.split_whitespace();
+    let year = fields.next()?.parse::<i32>().ok()? - 1900;
+    let offset = fields.next()?;
+    if offset.len() != 5 {
+        return None;
+    }
+    let sign = if offset.starts_with('-') { -1 } else { 1 };
+    let hours = offset[1..3].parse::<i32>().ok()?;
+    let minutes = offset[3..5].parse::<i32>().ok()?;
+    Some((year, sign * (hours * 60 * 60 + minutes * 60)))
+}
+
+/// Query the boot time of the device in seconds since the epoch
+pub fn device_boot_time(args: &ArgMatches) -> Option<f64> {
+    let output = device_shell(
+        selected_device(args),
+        false,
+        ["date +%s.%N; cat /proc/uptime"],
+    )
+    .ok()?
+    .output()
+    .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut fields = output.split_whitespace();
+    // Older date implementations don't know %N
+    let now = fields.next()?;
+    let now = now
+        .parse::<f64>()
+        .or_else(|_| now.split('.').next().unwrap_or_default().parse::<f64>())
+        .ok()?;
+    let uptime = fields.next()?.parse::<f64>().ok()?;
+    Some(now - uptime)
+}
+
+/// Load the event log tags from `--event-tags` or the device if `device` is set
+pub fn event_tags(args: &ArgMatches, device: bool) -> Result<Option<EventTags>, Error> {
+    let content = match args.value_of("event-tags") {
+        Some(file) => std::fs::read_to_string(file)
+            .map_err(|e| RogcatError::Io(format!("Failed to read {}: {}", file, e)))?,
+        None if device => {
+            match device_shell(selected_device(args), true, ["cat", EVENT_TAGS_PATH])
+                .ok()
+                .and_then(|mut cmd| cmd.output().ok())
+                .filter(|output| output.status.success())
+            {
+                Some(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+                None => return Ok(None),
+            }
+        }
+        None => return Ok(None),
+    };
+    Ok(Some(EventTags::parse(&content)).filter(|tags| !tags.is_empty()))
+}
+
+/// Set once an invalid timestamp is reported
+static INVALID_TIMESTAMP: AtomicBool = AtomicBool::new(false);
+
+/// Report the first timestamp that cannot be formatted. These are rendered blank.
+pub fn invalid_timestamp(timestamp: &Timestamp) {
+    if !INVALID_TIMESTAMP.swap(true, Ordering::Relaxed) {
+        internal::emit(format!(
+            "Invalid timestamp {}-{:02}-{:02} {:02}:{:02}:{:02}. Invalid timestamps are blank",
+            timestamp.tm_year + 1900,
+            timestamp.tm_mon + 1,
+            timestamp.tm_mday,
+            timestamp.tm_hour,
+            timestamp.tm_min,
+            timestamp.tm_sec
+        ));
+    }
+}
+
+/// Parse a duration like `90s`, `5m`, `1h` or `500ms`. Plain numbers are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| RogcatError::Config(format!("Invalid duration: {}", s)))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "h" => Ok(Duration::from_secs(value * 60 * 60)),
+        _ => Err(RogcatError::Config(format!("Invalid duration unit: {}", s)).into()),
+    }
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number
+pub fn parse_number(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Parse a range of record indices like `1000..2000`, `1000..` or `..2000` into
+/// the start and the optional end
+pub fn parse_range(s: &str) -> Result<(u64, Option<u64>), Error> {
+    let invalid = || {
+        RogcatError::Config(format!(
+            "Invalid range: {}. Use e.g 1000..2000, 1000.. or ..2000",
+            s
+        ))
+    };
+    let (start, end) = s.trim().split_once("..").ok_or_else(invalid)?;
+    let start = match start {
+        "" => 0,
+        start => parse_number(start).ok_or_else(invalid)?,
+    };
+    let end = match end {
+        "" => None,
+        end => Some(
+            parse_number(end)
+                .filter(|end| *end >= start)
+                .ok_or_else(invalid)?,
+        ),
+    };
+    Ok((start, end))
+}
+
+/// Parse a date like `2024-03-01` or `2024-03-01 10:30` into the sortable form
+/// `2024-03-01 10:30:00`. A missing time is the start or, if `end` is set, the end of the day.
+pub fn parse_capture_date(s: &str, end: bool) -> Result<String, Error> {
+    let invalid = || {
+        RogcatError::Config(format!(
+            "Invalid date: {}. Use e.g 2024-03-01 or 2024-03-01 10:30",
+            s
+        ))
+    };
+    let (date, time) = match s.trim().split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time.trim())),
+        None => (s.trim(), None),
+    };
+    let digits = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+
+    let date = date.split('-').collect::<Vec<_>>();
+    if date.len() != 3 || !digits(date[0], 4) || !digits(date[1], 2) || !digits(date[2], 2) {
+        return Err(invalid().into());
+    }
+    let time = match time {
+        Some(time) => {
+            let mut time = time.split(':').collect::<Vec<_>>();
+            if time.len() == 2 {
+                time.push(if end { "59" } else { "00" });
+            }
+            if time.len() != 3 || time.iter().any(|t| !digits(t, 2)) {
+                return Err(invalid().into());
+            }
+            time.join(":")
+        }
+        None if end => "23:59:59".to_owned(),
+        None => "00:00:00".to_owned(),
+    };
+    Ok(format!("{} {}", date.join("-"), time))
+}
+
+/// True if stdin is a pipe or a file e.g `foo | rogcat` or `rogcat < trace.log`
+#[cfg(unix)]
+pub fn stdin_piped() -> bool {
+    use std::{
+        fs,
+        io::stdin,
+        os::{fd::AsFd, unix::fs::FileTypeExt},
+    };
+    stdin()
+        .as_fd()
+        .try_clone_to_owned()
+        .map(fs::File::from)
+        .and_then(|f| f.metadata())
+        .map(|m| {
+            let file_type = m.file_type();
+            file_type.is_fifo() || file_type.is_file() || file_type.is_socket()
+        })
+        .unwrap_or(false)
+}
+
+/// True if stdin is not a terminal
+#[cfg(not(unix))]
+pub fn stdin_piped() -> bool {
+    !atty::is(atty::Stream::Stdin)
+}
+
+pub fn terminal_width() -> Option<usize> {
+    term_size::dimensions()
+        .map(|(width, _)| width)
+        .or_else(console::width)
+        .or_else(|| {
+            env::var("COLUMNS")
+                .ok()
+                .and_then(|e| e.parse::<usize>().ok())
+        })
+}
+
+/// Detect configuration directory. `XDG_CONFIG_HOME` is respected on all platforms.
 pub fn config_dir() -> PathBuf {
-    directories::BaseDirs::new()
-        .unwrap()
-        .config_dir()
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| {
+            directories::BaseDirs::new()
+                .unwrap()
+                .config_dir()
+                .to_owned()
+        })
         .join("rogcat")
 }
 
+/// Return path to config file by checking cli argument, env and default to configdir
+fn config_file(args: &ArgMatches) -> Result<(PathBuf, bool), Error> {
+    if let Some(f) = args.value_of("config").map(PathBuf::from) {
+        if f.exists() {
+            Ok((f, true))
+        } else {
+            Err(RogcatError::Config(format!("Cannot find config file {}", f.display())).into())
+        }
+    } else if let Some(f) = env::var_os("ROGCAT_CONFIG").map(PathBuf::from) {
+        if f.exists() {
+            Ok((f, true))
+        } else {
+            Err(
+                RogcatError::Config(format!("Cannot find {} set in ROGCAT_CONFIG!", f.display()))
+                    .into(),
+            )
+        }
+    } else {
+        Ok((config_dir().join("config.toml"), false))
+    }
+}
+
 /// Read a value from the configuration file
 /// `config_dir/config.toml`
 pub fn config_get<'a, T: Deserialize<'a>>(key: &'a str) -> Option<T> {
     CONFIG.read().ok().and_then(|c| c.get::<T>(key).ok())
 }
 
-pub fn config_init() {
-    drop(CONFIG.read().expect("Failed to get config lock"));
+/// Load the config file selected by `args`
+pub fn config_init(args: &ArgMatches) -> Result<(), Error> {
+    let (file, required) = config_file(args)?;
+    if args.is_present("verbose") {
+        eprintln!("Config file: {}", file.display());
+    }
+    let config = Config::builder()
+        .add_source(File::from(file.as_path()).required(required))
+        .build()
+        .map_err(|e| RogcatError::Config(format!("Failed to read {}: {}", file.display(), e)))?;
+    *CONFIG.write().expect("Failed to get config lock") = config;
+    adb_init(args);
+    ON_DEVICE.store(detect_on_device(args), Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn date() {
+        assert_eq!(parse_date("2024 +0100\n"), Some((124, 3600)));
+        assert_eq!(parse_date("1999 -0530"), Some((99, -(5 * 60 + 30) * 60)));
+        assert_eq!(parse_date("2024 +0000"), Some((124, 0)));
+
+        for invalid in &["", "2024", "2024 +01", "x +0100", "2024 +01x0"] {
+            assert_eq!(parse_date(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("0s").unwrap(), Duration::from_secs(0));
+
+        for invalid in &["", "s", "5d", "1.5s", "-1s", "5 m", "m5"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
 }
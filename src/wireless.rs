@@ -0,0 +1,147 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! adb over the network: connect to devices and switch usb devices to tcp mode
+
+use crate::{
+    error::RogcatError,
+    utils::{self, adb, parse_duration, select_device, selected_device},
+};
+use clap::ArgMatches;
+use failure::Error;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Default port of adbd in tcp mode
+const DEFAULT_PORT: u16 = 5555;
+/// Default time to wait for a connected device to come online
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Interval of polling the device state
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Run adb with `args` and return its stdout and stderr
+fn run(args: &[&str]) -> Result<String, Error> {
+    let output = adb()?
+        .args(args)
+        .output()
+        .map_err(|e| RogcatError::Device(format!("Failed to run adb: {}", e)))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        return Err(RogcatError::Device(format!("adb {} failed: {}", args[0], text.trim())).into());
+    }
+    Ok(text)
+}
+
+/// Connect to `ADDRESS` with `adb connect` and wait until the device is online.
+/// Returns the serial of the device.
+pub fn connect(args: &ArgMatches) -> Result<String, Error> {
+    let address = args.value_of("ADDRESS").unwrap_or_default();
+    // adb names the device with the port even if it is omitted
+    let serial = if address
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        address.to_owned()
+    } else {
+        format!("{}:{}", address, DEFAULT_PORT)
+    };
+    let timeout = args
+        .value_of("timeout")
+        .map(parse_duration)
+        .transpose()?
+        .unwrap_or(DEFAULT_TIMEOUT);
+
+    let output = run(&["connect", &serial])?;
+    // adb connect exits with 0 on failures
+    if !output.contains("connected to") {
+        return Err(RogcatError::Device(format!(
+            "Failed to connect to {}: {}",
+            serial,
+            output.trim()
+        ))
+        .into());
+    }
+
+    let start = Instant::now();
+    loop {
+        let state = utils::devices()?
+            .into_iter()
+            .find(|device| device.serial == serial)
+            .map(|device| device.state);
+        match state.as_deref() {
+            Some("device") => break,
+            _ if start.elapsed() > timeout => {
+                return Err(RogcatError::Device(format!(
+                    "{} is not online after {}s ({})",
+                    serial,
+                    timeout.as_secs(),
+                    state.unwrap_or_else(|| "disconnected".to_owned())
+                ))
+                .into())
+            }
+            _ => thread::sleep(POLL_INTERVAL),
+        }
+    }
+    eprintln!("Connected to {}", serial);
+    Ok(serial)
+}
+
+/// Restart adbd of the selected usb device in tcp mode on `PORT` and print the
+/// address to connect to
+pub fn tcpip(args: &ArgMatches) -> Result<(), Error> {
+    let port = args
+        .value_of("PORT")
+        .map(|port| {
+            port.parse::<u16>()
+                .map_err(|_| RogcatError::Config(format!("Invalid port: {}", port)))
+        })
+        .transpose()?
+        .unwrap_or(DEFAULT_PORT)
+        .to_string();
+    select_device(args)?;
+    let device = selected_device(args);
+    let mut adb = Vec::new();
+    if let Some(ref device) = device {
+        adb.extend(["-s", device.as_str()]);
+    }
+
+    // Query the address before adbd restarts and drops the usb connection
+    let mut ip = adb.clone();
+    ip.extend(["shell", "ip", "-f", "inet", "addr", "show", "wlan0"]);
+    let address = run(&ip).ok().and_then(|output| {
+        output
+            .split_whitespace()
+            .skip_while(|word| *word != "inet")
+            .nth(1)
+            .and_then(|inet| inet.split('/').next())
+            .map(ToOwned::to_owned)
+    });
+
+    adb.extend(["tcpip", port.as_str()]);
+    run(&adb)?;
+    match address {
+        Some(address) => println!("rogcat connect {}:{}", address, port),
+        None => println!("adbd listens on port {}", port),
+    }
+    Ok(())
+}